@@ -0,0 +1,85 @@
+//! A window drawn through the `App` trait: a square follows the pointer,
+//! the left button paints it orange and the space bar cycles the background.
+
+use std::process::ExitCode;
+
+use rust_wayland::{
+    app::{App, Canvas, PointerEvent, BTN_LEFT},
+    color::Color,
+    keyboard::KeyEvent,
+    rect::Rect,
+    scene::cycle_color,
+    window::Window,
+};
+use wayland_client::Connection;
+
+const SIZE: usize = 48;
+
+#[derive(Default)]
+struct Demo {
+    pointer: Option<(f64, f64)>,
+    pressed: bool,
+    cycling: bool,
+}
+
+impl App for Demo {
+    fn on_draw(&mut self, canvas: &mut Canvas) {
+        let background = if self.cycling {
+            cycle_color(canvas.time())
+        } else {
+            Color::rgb(0x30, 0x30, 0x30)
+        };
+        canvas.fill(background);
+
+        let Some((x, y)) = self.pointer else {
+            return;
+        };
+        // Laid out in window pixels, drawn in buffer pixels
+        let scale = canvas.scale();
+        let (x, y) = (x as usize, y as usize);
+        let square = Rect::new(
+            x.saturating_sub(SIZE / 2),
+            y.saturating_sub(SIZE / 2),
+            SIZE,
+            SIZE,
+        );
+        let square = scale.rect_to_buffer(square).intersect(&canvas.bounds());
+        let color = if self.pressed {
+            Color::rgb(0xFF, 0x80, 0x00)
+        } else {
+            Color::rgb(0x40, 0xA0, 0xFF)
+        };
+        canvas.fill_rect(square, color);
+    }
+
+    fn on_key(&mut self, event: &KeyEvent) -> bool {
+        let space = event.utf8.as_deref() == Some(" ");
+        if space && !event.repeat {
+            self.cycling = !self.cycling;
+        }
+        space
+    }
+
+    fn on_pointer(&mut self, event: PointerEvent) -> bool {
+        match event {
+            PointerEvent::Motion { x, y } => self.pointer = Some((x, y)),
+            PointerEvent::Button { button, pressed } if button == BTN_LEFT => {
+                self.pressed = pressed
+            }
+            PointerEvent::Button { .. } => return false,
+            PointerEvent::Leave => self.pointer = None,
+        }
+        true
+    }
+
+    fn animates(&self) -> bool {
+        self.cycling
+    }
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    tracing_subscriber::fmt::init();
+    let conn = Connection::connect_to_env()?;
+    let window = Window::builder().title("App").size(640, 480).build(&conn)?;
+    Ok(window.run(Demo::default())?)
+}
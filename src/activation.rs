@@ -0,0 +1,133 @@
+//! xdg-activation: handing the focus over between applications, without
+//! letting any of them steal it.
+
+use std::{env, process::Command, thread};
+
+use tracing::{debug, info, warn};
+use wayland_client::{Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols::xdg::activation::v1::client::{
+    xdg_activation_token_v1::{self, XdgActivationTokenV1},
+    xdg_activation_v1::XdgActivationV1,
+};
+
+use crate::{state::AppState, window::WindowId};
+
+/// Where launchers pass the token to the applications they start
+pub(crate) const TOKEN_ENV: &str = "XDG_ACTIVATION_TOKEN";
+
+/// Called with a token once the compositor handed it out.
+pub(crate) type TokenCallback = Box<dyn FnOnce(&mut AppState, String)>;
+
+/// Asks for the focus for the window `id` with the token we were started
+/// with, if any. Does nothing without one, the compositor then decides on
+/// its own.
+pub(crate) fn activate_from_env(state: &AppState, id: WindowId) {
+    let Ok(token) = env::var(TOKEN_ENV) else {
+        return;
+    };
+    // Only good once, the processes we start must not inherit it
+    env::remove_var(TOKEN_ENV);
+
+    let (Some(activation), Some(window)) = (&state.activation, state.window(id)) else {
+        debug!("can't use the activation token we were started with");
+        return;
+    };
+    info!("activating the window with the token we were started with");
+    activation.activate(token, window.surface());
+}
+
+/// Asks the compositor for a token that lets another surface, ours or of an
+/// application we start, take the focus. It is tied to the last input event,
+/// without one the compositor is likely to refuse it. `done` is called with
+/// the token.
+pub fn request_token(state: &mut AppState, done: impl FnOnce(&mut AppState, String) + 'static) {
+    let qh = &state.queue_handle;
+    let Some(activation) = &state.activation else {
+        debug!("the compositor doesn't support xdg-activation");
+        return;
+    };
+
+    let token = activation.get_activation_token(qh, ());
+    if let Some((seat, serial)) = state
+        .seat
+        .as_ref()
+        .and_then(|seat| Some((&seat.seat, seat.last_serial?)))
+    {
+        token.set_serial(serial, seat);
+    }
+    // The window the user works with, if any
+    if let Some(window) = state.focused_window().and_then(|id| state.window(id)) {
+        token.set_app_id(window.options().app_id().to_owned());
+        token.set_surface(window.surface());
+    }
+    token.commit();
+
+    state.activation_tokens.push((token, Box::new(done)));
+}
+
+/// Starts another instance of ourselves with the same arguments, which gets
+/// the focus if the compositor agrees.
+pub(crate) fn launch_another(state: &mut AppState) {
+    request_token(state, |_, token| {
+        let exe = match env::current_exe() {
+            Ok(exe) => exe,
+            Err(err) => {
+                warn!(%err, "failed to find our own executable");
+                return;
+            }
+        };
+
+        let child = Command::new(exe)
+            .args(env::args_os().skip(1))
+            .env(TOKEN_ENV, token)
+            .spawn();
+        match child {
+            // Reaped in the background so that it doesn't linger as a zombie
+            Ok(mut child) => {
+                info!(pid = child.id(), "launched another instance");
+                thread::spawn(move || child.wait());
+            }
+            Err(err) => warn!(%err, "failed to launch another instance"),
+        }
+    });
+}
+
+impl Dispatch<XdgActivationV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &XdgActivationV1,
+        _event: <XdgActivationV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<XdgActivationTokenV1, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &XdgActivationTokenV1,
+        event: <XdgActivationTokenV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let xdg_activation_token_v1::Event::Done { token } = event else {
+            return;
+        };
+        proxy.destroy();
+
+        let Some(i) = state
+            .activation_tokens
+            .iter()
+            .position(|(pending, _)| pending == proxy)
+        else {
+            return;
+        };
+        let (_, done) = state.activation_tokens.swap_remove(i);
+        debug!("got an activation token");
+        done(state, token);
+    }
+}
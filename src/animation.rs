@@ -0,0 +1,586 @@
+//! Animated GIF, APNG and WebP images. Every frame is decoded and composited
+//! up front, then a timer steps through them at the delays the file asks for
+//! and stops stepping while no window can be seen.
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    color::{Color, ColorSpace, PremulColor},
+    pixel_buffer::PixelBuffer,
+    render::present_if_needed,
+    scene::Scene,
+    state::AppState,
+    svg::{fit, ImageError},
+    window::WindowState,
+};
+
+pub const BACKGROUND: Color = Color::rgb(0x30, 0x30, 0x30);
+
+// Like browsers, delays this short are taken as the file not setting one
+const MIN_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// The image formats that may be animated, told apart by the extension.
+pub fn is_animated_format(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        ["gif", "png", "apng", "webp"]
+            .iter()
+            .any(|format| extension.eq_ignore_ascii_case(format))
+    })
+}
+
+#[derive(Debug)]
+struct Frame {
+    pixels: Vec<PremulColor>,
+    delay: Duration,
+}
+
+/// A decoded image and where its playback is, drawn as large as fits in the
+/// window. Stills are animations of a single frame.
+#[derive(Debug, Clone)]
+pub struct Animation {
+    size: (usize, usize),
+    frames: Arc<[Frame]>,
+    // How many times to play the frames, `None` to loop forever
+    plays: Option<u32>,
+    current: usize,
+    played: u32,
+}
+
+impl Animation {
+    pub fn open(path: &Path) -> Result<Self, ImageError> {
+        let file = File::open(path).map_err(|source| ImageError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        let file = BufReader::new(file);
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_ascii_lowercase());
+        let decoded = match extension.as_ref().and_then(|e| e.to_str()) {
+            Some("gif") => decode_gif(file),
+            Some("png" | "apng") => decode_png(file),
+            Some("webp") => decode_webp(file),
+            _ => return Err(ImageError::Unsupported(path.to_owned())),
+        };
+        decoded.map_err(|source| ImageError::Decode {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    /// Whether there is more than one frame to show.
+    pub fn is_animated(&self) -> bool {
+        self.frames.len() > 1
+    }
+
+    /// The size of the image, and its current frame as rows of pixels.
+    pub(crate) fn frame(&self) -> ((usize, usize), &[PremulColor]) {
+        (self.size, &self.frames[self.current].pixels)
+    }
+
+    /// How long the current frame stays up.
+    pub fn delay(&self) -> Duration {
+        self.frames[self.current].delay
+    }
+
+    /// Moves on to the next frame, back to the first after the last one as
+    /// long as the file asks for another play. Returns whether it moved.
+    pub fn step(&mut self) -> bool {
+        if self.current + 1 < self.frames.len() {
+            self.current += 1;
+            return true;
+        }
+        self.played += 1;
+        if self.plays.is_some_and(|plays| self.played >= plays) || !self.is_animated() {
+            return false;
+        }
+        self.current = 0;
+        true
+    }
+
+    /// Draws the current frame into `pixels`, scaled to fit and centered.
+    pub(crate) fn draw(&self, pixels: &mut PixelBuffer) {
+        pixels.fill(BACKGROUND);
+        let bounds = pixels.bounds();
+        let (width, height) = self.size;
+        let (scale, dx, dy) = fit((width as f32, height as f32), (bounds.width, bounds.height));
+        let (left, top) = (dx.round() as usize, dy.round() as usize);
+        let scaled_width = ((width as f32 * scale).round() as usize).min(bounds.width - left);
+        let scaled_height = ((height as f32 * scale).round() as usize).min(bounds.height - top);
+
+        // Nearest neighbour, so that pixel art stays crisp
+        let frame = &self.frames[self.current];
+        for y in 0..scaled_height {
+            let source_y = ((y as f32 + 0.5) / scale) as usize;
+            let row = source_y.min(height - 1) * width;
+            for x in 0..scaled_width {
+                let source_x = (((x as f32 + 0.5) / scale) as usize).min(width - 1);
+                let color = frame.pixels[row + source_x];
+                if color.a == 0 {
+                    continue;
+                }
+                pixels.blend_pixel(left + x, top + y, color.unpremultiply(), ColorSpace::Srgb);
+            }
+        }
+    }
+}
+
+/// Shows the next frame of the animated scene and sets the timer for the
+/// one after. While every window is suspended the frame stays as it is and
+/// this checks again after its delay.
+pub(crate) fn advance(state: &mut AppState) {
+    let animation = match &mut state.scene {
+        Scene::Animation(animation) => animation,
+        // Holds still while a screenshot of it is annotated
+        Scene::Annotate(annotation) => {
+            if let Scene::Animation(animation) = &*annotation.previous {
+                let delay = animation.delay();
+                state.timers.after(delay, advance);
+            }
+            return;
+        }
+        _ => return,
+    };
+
+    let visible = state
+        .windows
+        .iter()
+        .any(|window| !window.state().contains(WindowState::SUSPENDED));
+    if visible {
+        if !animation.step() {
+            // Played as many times as the file asks, the last frame stays
+            return;
+        }
+        for window in &mut state.windows {
+            window.request_redraw();
+        }
+    }
+    let delay = animation.delay();
+    state.timers.after(delay, advance);
+    present_if_needed(state);
+}
+
+/// How a frame is laid over the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Blend {
+    /// Replaces what is under it, transparent pixels included
+    Source,
+    Over,
+}
+
+/// What becomes of a frame's area once it has been shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dispose {
+    Keep,
+    /// Cleared to transparent
+    Clear,
+    /// Put back as it was before the frame
+    Restore,
+}
+
+/// Where a frame goes on the canvas, for the formats whose frames only
+/// cover the part that changes.
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+}
+
+/// Lays the frames of a GIF or APNG over each other into whole frames.
+struct Compositor {
+    width: usize,
+    height: usize,
+    canvas: Vec<PremulColor>,
+    frames: Vec<Frame>,
+}
+
+impl Compositor {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            canvas: vec![PremulColor::default(); width * height],
+            frames: Vec::new(),
+        }
+    }
+
+    /// Adds a frame of `placement.width`x`placement.height` `pixels`.
+    fn add(
+        &mut self,
+        placement: Placement,
+        pixels: &[Color],
+        blend: Blend,
+        dispose: Dispose,
+        delay: Duration,
+    ) {
+        let previous = (dispose == Dispose::Restore).then(|| self.canvas.clone());
+
+        // Frames that stick out of the canvas are cut off
+        let rows = placement.top..(placement.top + placement.height).min(self.height);
+        let columns = placement.left..(placement.left + placement.width).min(self.width);
+        for y in rows.clone() {
+            for x in columns.clone() {
+                let color = pixels[(y - placement.top) * placement.width + x - placement.left];
+                let color = color.premultiply();
+                let dst = &mut self.canvas[y * self.width + x];
+                *dst = match blend {
+                    Blend::Source => color,
+                    Blend::Over => color.over(*dst, ColorSpace::Srgb),
+                };
+            }
+        }
+
+        self.frames.push(Frame {
+            pixels: self.canvas.clone(),
+            delay: if delay < MIN_DELAY {
+                DEFAULT_DELAY
+            } else {
+                delay
+            },
+        });
+
+        match (dispose, previous) {
+            (Dispose::Restore, Some(previous)) => self.canvas = previous,
+            (Dispose::Clear, _) => {
+                for y in rows {
+                    self.canvas[y * self.width..][columns.clone()].fill(PremulColor::default());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(self, plays: Option<u32>) -> Result<Animation, Box<dyn Error + Send + Sync>> {
+        if self.frames.is_empty() {
+            return Err("the image has no frames".into());
+        }
+        Ok(Animation {
+            size: (self.width, self.height),
+            frames: self.frames.into(),
+            plays,
+            current: 0,
+            played: 0,
+        })
+    }
+}
+
+/// 8-bit samples, as many per pixel as `channels`, to colors.
+fn to_colors(samples: &[u8], channels: usize) -> Vec<Color> {
+    samples
+        .chunks_exact(channels)
+        .map(|pixel| match *pixel {
+            [gray] => Color::rgb(gray, gray, gray),
+            [gray, a] => Color::rgba(gray, gray, gray, a),
+            [r, g, b] => Color::rgb(r, g, b),
+            [r, g, b, a, ..] => Color::rgba(r, g, b, a),
+            [] => unreachable!("no channels"),
+        })
+        .collect()
+}
+
+fn decode_gif(reader: impl Read) -> Result<Animation, Box<dyn Error + Send + Sync>> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(reader)?;
+    let mut compositor = Compositor::new(decoder.width().into(), decoder.height().into());
+
+    while let Some(frame) = decoder.read_next_frame()? {
+        let placement = Placement {
+            left: frame.left.into(),
+            top: frame.top.into(),
+            width: frame.width.into(),
+            height: frame.height.into(),
+        };
+        let dispose = match frame.dispose {
+            gif::DisposalMethod::Background => Dispose::Clear,
+            gif::DisposalMethod::Previous => Dispose::Restore,
+            gif::DisposalMethod::Any | gif::DisposalMethod::Keep => Dispose::Keep,
+        };
+        // In hundredths of a second
+        let delay = Duration::from_millis(u64::from(frame.delay) * 10);
+        compositor.add(
+            placement,
+            &to_colors(&frame.buffer, 4),
+            Blend::Over,
+            dispose,
+            delay,
+        );
+    }
+
+    // Without a loop count the frames play once, with one they play once
+    // more than it says
+    let plays = match decoder.repeat() {
+        gif::Repeat::Infinite => None,
+        gif::Repeat::Finite(repeats) => Some(u32::from(repeats) + 1),
+    };
+    compositor.finish(plays)
+}
+
+fn decode_png(reader: impl Read) -> Result<Animation, Box<dyn Error + Send + Sync>> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let info = reader.info();
+    let mut compositor = Compositor::new(info.width as usize, info.height as usize);
+    let animation = info.animation_control;
+    // The default image may be left out of the animation, as a still for
+    // what can't play it
+    let default_is_frame = animation.is_none() || info.frame_control.is_some();
+    let count = match animation {
+        Some(animation) => animation.num_frames as usize + usize::from(!default_is_frame),
+        None => 1,
+    };
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    for i in 0..count {
+        let output = reader.next_frame(&mut buffer)?;
+        if i == 0 && !default_is_frame {
+            continue;
+        }
+        let channels = output.color_type.samples();
+        let pixels = to_colors(
+            &buffer[..output.width as usize * output.height as usize * channels],
+            channels,
+        );
+
+        let Some(control) = reader.info().frame_control else {
+            // A still
+            let placement = Placement {
+                left: 0,
+                top: 0,
+                width: output.width as usize,
+                height: output.height as usize,
+            };
+            compositor.add(
+                placement,
+                &pixels,
+                Blend::Source,
+                Dispose::Keep,
+                DEFAULT_DELAY,
+            );
+            continue;
+        };
+        let placement = Placement {
+            left: control.x_offset as usize,
+            top: control.y_offset as usize,
+            width: control.width as usize,
+            height: control.height as usize,
+        };
+        let blend = match control.blend_op {
+            png::BlendOp::Source => Blend::Source,
+            png::BlendOp::Over => Blend::Over,
+        };
+        let dispose = match control.dispose_op {
+            png::DisposeOp::None => Dispose::Keep,
+            png::DisposeOp::Background => Dispose::Clear,
+            png::DisposeOp::Previous => Dispose::Restore,
+        };
+        // A fraction of a second, where 0 stands for 100
+        let denominator = match control.delay_den {
+            0 => 100,
+            denominator => denominator,
+        };
+        let delay = Duration::from_secs(control.delay_num.into()) / denominator.into();
+        compositor.add(placement, &pixels, blend, dispose, delay);
+    }
+
+    let plays = animation
+        .map(|animation| animation.num_plays)
+        .filter(|&plays| plays != 0);
+    compositor.finish(plays)
+}
+
+fn decode_webp(reader: impl BufRead + Seek) -> Result<Animation, Box<dyn Error + Send + Sync>> {
+    let mut decoder = image_webp::WebPDecoder::new(reader)?;
+    let (width, height) = decoder.dimensions();
+    let channels = if decoder.has_alpha() { 4 } else { 3 };
+    let placement = Placement {
+        left: 0,
+        top: 0,
+        width: width as usize,
+        height: height as usize,
+    };
+    let mut compositor = Compositor::new(placement.width, placement.height);
+    let mut buffer = vec![
+        0;
+        decoder
+            .output_buffer_size()
+            .ok_or("the image is too large")?
+    ];
+
+    if !decoder.is_animated() {
+        decoder.read_image(&mut buffer)?;
+        let pixels = to_colors(&buffer, channels);
+        compositor.add(
+            placement,
+            &pixels,
+            Blend::Source,
+            Dispose::Keep,
+            DEFAULT_DELAY,
+        );
+        return compositor.finish(None);
+    }
+
+    // The decoder composites the frames itself
+    for _ in 0..decoder.num_frames() {
+        let delay = decoder.read_frame(&mut buffer)?;
+        let pixels = to_colors(&buffer, channels);
+        let delay = Duration::from_millis(delay.into());
+        compositor.add(placement, &pixels, Blend::Source, Dispose::Keep, delay);
+    }
+
+    let plays = match decoder.loop_count() {
+        image_webp::LoopCount::Forever => None,
+        image_webp::LoopCount::Times(plays) => Some(plays.get().into()),
+    };
+    compositor.finish(plays)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color::rgb(0xFF, 0x00, 0x00);
+    const BLUE: Color = Color::rgb(0x00, 0x00, 0xFF);
+    const CLEAR: PremulColor = PremulColor {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 0,
+    };
+
+    #[test]
+    fn frames_are_laid_over_each_other() {
+        let mut compositor = Compositor::new(2, 1);
+        let whole = Placement {
+            left: 0,
+            top: 0,
+            width: 2,
+            height: 1,
+        };
+        let right = Placement {
+            left: 1,
+            top: 0,
+            width: 1,
+            height: 1,
+        };
+        let delay = Duration::from_millis(50);
+
+        compositor.add(whole, &[RED, RED], Blend::Source, Dispose::Keep, delay);
+        // Over a kept frame, then cleared
+        compositor.add(right, &[BLUE], Blend::Over, Dispose::Clear, delay);
+        // See-through over the cleared pixel, then put back
+        let transparent = Color::rgba(0, 0, 0, 0);
+        compositor.add(right, &[transparent], Blend::Over, Dispose::Restore, delay);
+        compositor.add(
+            whole,
+            &[transparent, BLUE],
+            Blend::Over,
+            Dispose::Keep,
+            Duration::ZERO,
+        );
+
+        let red = RED.premultiply();
+        let blue = BLUE.premultiply();
+        let frames = &compositor.frames;
+        assert_eq!(frames[0].pixels, [red, red]);
+        assert_eq!(frames[1].pixels, [red, blue]);
+        assert_eq!(frames[2].pixels, [red, CLEAR]);
+        assert_eq!(frames[3].pixels, [red, blue]);
+        assert_eq!(frames[3].delay, DEFAULT_DELAY);
+    }
+
+    #[test]
+    fn animations_play_as_many_times_as_asked() {
+        let mut compositor = Compositor::new(1, 1);
+        let placement = Placement {
+            left: 0,
+            top: 0,
+            width: 1,
+            height: 1,
+        };
+        for color in [RED, BLUE] {
+            let delay = Duration::from_millis(50);
+            compositor.add(placement, &[color], Blend::Source, Dispose::Keep, delay);
+        }
+        let mut animation = compositor.finish(Some(2)).unwrap();
+
+        let mut steps = 0;
+        while animation.step() {
+            steps += 1;
+        }
+        // To the second frame, back to the first and to the second again
+        assert_eq!(steps, 3);
+        assert_eq!(animation.current, 1);
+    }
+
+    #[test]
+    fn gifs_are_decoded() {
+        let mut data = Vec::new();
+        {
+            let palette = [0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF];
+            let mut encoder = gif::Encoder::new(&mut data, 2, 1, &palette).unwrap();
+            encoder.set_repeat(gif::Repeat::Infinite).unwrap();
+            for (indices, dispose) in [
+                ([0, 0], gif::DisposalMethod::Keep),
+                ([1, 1], gif::DisposalMethod::Keep),
+            ] {
+                let frame = gif::Frame {
+                    width: 2,
+                    height: 1,
+                    delay: 5,
+                    dispose,
+                    buffer: indices.to_vec().into(),
+                    ..gif::Frame::default()
+                };
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+
+        let animation = decode_gif(data.as_slice()).unwrap();
+        assert_eq!(animation.size, (2, 1));
+        assert_eq!(animation.plays, None);
+        let red = RED.premultiply();
+        let blue = BLUE.premultiply();
+        assert_eq!(animation.frames[0].pixels, [red, red]);
+        assert_eq!(animation.frames[1].pixels, [blue, blue]);
+        assert_eq!(animation.frames[1].delay, Duration::from_millis(50));
+    }
+
+    #[test]
+    fn apngs_are_decoded() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut data, 2, 1);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_animated(2, 3).unwrap();
+            encoder.set_frame_delay(1, 20).unwrap();
+            let mut writer = encoder.write_header().unwrap();
+            writer
+                .write_image_data(&[0xFF, 0, 0, 0xFF, 0xFF, 0, 0, 0xFF])
+                .unwrap();
+            // Only the right pixel changes
+            writer.set_frame_dimension(1, 1).unwrap();
+            writer.set_frame_position(1, 0).unwrap();
+            writer.write_image_data(&[0, 0, 0xFF, 0xFF]).unwrap();
+        }
+
+        let animation = decode_png(data.as_slice()).unwrap();
+        assert_eq!(animation.plays, Some(3));
+        let red = RED.premultiply();
+        let blue = BLUE.premultiply();
+        assert_eq!(animation.frames[0].pixels, [red, red]);
+        assert_eq!(animation.frames[1].pixels, [red, blue]);
+        assert_eq!(animation.frames[1].delay, Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,706 @@
+//! Annotating a screenshot: after Print the window shows the frame it just
+//! saved, to draw arrows, rectangles and freehand strokes on with the left
+//! button and to type labels on. Ctrl+S saves the result next to the
+//! screenshot, Ctrl+C copies it to the clipboard as a PNG and Escape goes
+//! back to what was shown before.
+
+use std::{mem, sync::Arc};
+
+use tracing::{info, warn};
+use xkbcommon_dl::keysyms;
+
+use crate::{
+    clipboard,
+    color::{Color, ColorSpace},
+    keyboard::KeyEvent,
+    pixel_buffer::PixelBuffer,
+    pixel_format::PixelFormat,
+    rect::Rect,
+    render::present_if_needed,
+    scale::Scale,
+    scene::Scene,
+    screenshot::{self, Capture},
+    seat::BTN_LEFT,
+    state::AppState,
+    svg::fit,
+    text::{self, Font},
+    window::WindowId,
+};
+
+pub const BACKGROUND: Color = Color::rgb(0x30, 0x30, 0x30);
+const INK: Color = Color::rgb(0xE0, 0x30, 0x30);
+const LABEL_BACKGROUND: Color = Color::rgba(0x00, 0x00, 0x00, 0xA0);
+const LABEL_TEXT: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+const PNG_MIME_TYPE: &str = "image/png";
+
+// In pixels of the screenshot, so that they come out the same in the file
+const STROKE_WIDTH: f32 = 4.0;
+const LABEL_FONT_SIZE: f32 = 20.0;
+const LABEL_PADDING: usize = 4;
+const CARET_WIDTH: usize = 2;
+// The sides of an arrow head, and how far they spread from the shaft
+const ARROW_HEAD_LENGTH: f32 = 18.0;
+const ARROW_HEAD_ANGLE: f32 = 0.5;
+
+// The help line along the top, in window pixels
+const HELP_FONT_SIZE: f32 = 13.0;
+const HELP_PADDING: usize = 6;
+
+/// A point on the screenshot, in its pixels.
+type Point = (f32, f32);
+
+/// What the left button draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Arrow,
+    Rectangle,
+    Freehand,
+    /// Places a label to type into
+    Label,
+}
+
+impl Tool {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Arrow => "Arrow",
+            Self::Rectangle => "Rectangle",
+            Self::Freehand => "Freehand",
+            Self::Label => "Label",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Shape {
+    Arrow { from: Point, to: Point },
+    Rectangle { from: Point, to: Point },
+    Freehand(Vec<Point>),
+    Label { at: Point, text: String },
+}
+
+/// A screenshot and what was drawn on it, the last shape being the one the
+/// pointer or the keyboard changes.
+#[derive(Clone)]
+pub struct Annotation {
+    width: usize,
+    height: usize,
+    image: Arc<[u32]>,
+    format: PixelFormat,
+    shapes: Vec<Shape>,
+    tool: Tool,
+    // Whether the last shape follows the pointer, while the button is held
+    drawing: bool,
+    // Whether the last shape is a label taking what is typed
+    typing: bool,
+    pointer: Point,
+    // No labels without it
+    font: Option<Arc<Font>>,
+    /// What the window showed before, back once done
+    pub(crate) previous: Box<Scene>,
+}
+
+impl Annotation {
+    /// Annotates `capture`, coming back to `previous` once done.
+    fn new(capture: Capture, previous: Scene) -> Self {
+        let font = Font::find(text::SANS)
+            .inspect_err(|err| warn!(%err, "no font for labels"))
+            .ok()
+            .map(Arc::new);
+        Self {
+            width: capture.width,
+            height: capture.height,
+            image: capture.pixels.into(),
+            format: capture.format,
+            shapes: Vec::new(),
+            tool: Tool::Arrow,
+            drawing: false,
+            typing: false,
+            pointer: (0.0, 0.0),
+            font,
+            previous: Box::new(previous),
+        }
+    }
+
+    /// Where `position` in a window of `size` is on the screenshot, drawn
+    /// as large as fits in it.
+    fn to_image(&self, (x, y): (f64, f64), size: (usize, usize)) -> Point {
+        let (scale, dx, dy) = fit((self.width as f32, self.height as f32), size);
+        ((x as f32 - dx) / scale, (y as f32 - dy) / scale)
+    }
+
+    /// Starts a shape with the current tool where the pointer is.
+    fn press(&mut self) {
+        self.finish_label();
+        let at = self.pointer;
+        let shape = match self.tool {
+            Tool::Arrow => Shape::Arrow { from: at, to: at },
+            Tool::Rectangle => Shape::Rectangle { from: at, to: at },
+            Tool::Freehand => Shape::Freehand(vec![at]),
+            Tool::Label => {
+                if self.font.is_none() {
+                    return;
+                }
+                self.typing = true;
+                Shape::Label {
+                    at,
+                    text: String::new(),
+                }
+            }
+        };
+        self.drawing = self.tool != Tool::Label;
+        self.shapes.push(shape);
+    }
+
+    /// Moves the end of the shape being drawn to `point`.
+    fn extend(&mut self, point: Point) {
+        self.pointer = point;
+        if !self.drawing {
+            return;
+        }
+        match self.shapes.last_mut() {
+            Some(Shape::Arrow { to, .. } | Shape::Rectangle { to, .. }) => *to = point,
+            Some(Shape::Freehand(points)) if points.last() != Some(&point) => points.push(point),
+            _ => {}
+        }
+    }
+
+    /// Ends the shape being drawn, dropping it if the button was released
+    /// where it was pressed.
+    fn release(&mut self) {
+        if !mem::take(&mut self.drawing) {
+            return;
+        }
+        if let Some(Shape::Arrow { from, to } | Shape::Rectangle { from, to }) = self.shapes.last()
+        {
+            if from == to {
+                self.shapes.pop();
+            }
+        }
+    }
+
+    fn type_text(&mut self, typed: &str) {
+        if let Some(Shape::Label { text, .. }) = self.shapes.last_mut().filter(|_| self.typing) {
+            text.push_str(typed);
+        }
+    }
+
+    fn backspace(&mut self) {
+        if let Some(Shape::Label { text, .. }) = self.shapes.last_mut().filter(|_| self.typing) {
+            text.pop();
+        }
+    }
+
+    /// Stops typing into the last label, dropping it if nothing was typed.
+    fn finish_label(&mut self) {
+        if !mem::take(&mut self.typing) {
+            return;
+        }
+        if let Some(Shape::Label { text, .. }) = self.shapes.last() {
+            if text.is_empty() {
+                self.shapes.pop();
+            }
+        }
+    }
+
+    fn undo(&mut self) {
+        self.finish_label();
+        self.drawing = false;
+        self.shapes.pop();
+    }
+
+    /// The screenshot with the annotations drawn over it, at its own size.
+    /// With `caret`, the label being typed into shows where text goes.
+    fn render(&self, caret: bool) -> Vec<u32> {
+        let mut data = self.image.to_vec();
+        let mut pixels = PixelBuffer::new(
+            &mut data,
+            self.width,
+            self.height,
+            self.width * 4,
+            self.format,
+        );
+        let mut coverage = Coverage::new(self.width, self.height);
+        let last = self.shapes.len().saturating_sub(1);
+        for (i, shape) in self.shapes.iter().enumerate() {
+            match shape {
+                Shape::Arrow { from, to } => {
+                    coverage.segment(*from, *to);
+                    let angle = (from.1 - to.1).atan2(from.0 - to.0);
+                    for side in [-ARROW_HEAD_ANGLE, ARROW_HEAD_ANGLE] {
+                        let (sin, cos) = (angle + side).sin_cos();
+                        let end = (
+                            to.0 + cos * ARROW_HEAD_LENGTH,
+                            to.1 + sin * ARROW_HEAD_LENGTH,
+                        );
+                        coverage.segment(*to, end);
+                    }
+                }
+                Shape::Rectangle { from, to } => {
+                    let corners = [*from, (to.0, from.1), *to, (from.0, to.1)];
+                    for (j, corner) in corners.iter().enumerate() {
+                        coverage.segment(*corner, corners[(j + 1) % corners.len()]);
+                    }
+                }
+                Shape::Freehand(points) => {
+                    // A click leaves a dot
+                    coverage.segment(points[0], points[0]);
+                    for pair in points.windows(2) {
+                        coverage.segment(pair[0], pair[1]);
+                    }
+                }
+                Shape::Label { at, text } => {
+                    let typing = caret && self.typing && i == last;
+                    self.draw_label(&mut pixels, *at, text, typing);
+                    continue;
+                }
+            }
+            coverage.blend(&mut pixels, INK);
+        }
+        data
+    }
+
+    fn draw_label(&self, pixels: &mut PixelBuffer, (x, y): Point, text: &str, caret: bool) {
+        let Some(font) = &self.font else {
+            return;
+        };
+        let (x, y) = (x.max(0.0) as usize, y.max(0.0) as usize);
+        let text_width = font.width(text, LABEL_FONT_SIZE);
+        let caret_width = if caret { CARET_WIDTH } else { 0 };
+        let line_height = font.line_height(LABEL_FONT_SIZE);
+        let background = Rect::new(
+            x,
+            y,
+            text_width + caret_width + 2 * LABEL_PADDING,
+            line_height + 2 * LABEL_PADDING,
+        );
+        let bounds = pixels.bounds();
+        pixels.blend_rect(
+            background.intersect(&bounds),
+            LABEL_BACKGROUND,
+            ColorSpace::Srgb,
+        );
+        let (text_x, text_y) = (x + LABEL_PADDING, y + LABEL_PADDING);
+        font.draw(pixels, text, (text_x, text_y), LABEL_FONT_SIZE, LABEL_TEXT);
+        if caret {
+            let caret = Rect::new(text_x + text_width, text_y, caret_width, line_height);
+            pixels.fill_rect(caret.intersect(&bounds), INK);
+        }
+    }
+
+    /// Draws the annotated screenshot into `pixels`, scaled to fit and
+    /// centered, with what the keys do along the top.
+    pub(crate) fn draw(&self, pixels: &mut PixelBuffer, scale: Scale) {
+        pixels.fill(BACKGROUND);
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+        let bounds = pixels.bounds();
+        let (width, height) = (self.width, self.height);
+        let (image_scale, dx, dy) =
+            fit((width as f32, height as f32), (bounds.width, bounds.height));
+        let (left, top) = (dx.round() as usize, dy.round() as usize);
+        let scaled_width = ((width as f32 * image_scale).round() as usize).min(bounds.width - left);
+        let scaled_height =
+            ((height as f32 * image_scale).round() as usize).min(bounds.height - top);
+
+        // Usually drawn at its own size, since it is a frame of this window
+        let rendered = self.render(true);
+        for y in 0..scaled_height {
+            let source_y = (((y as f32 + 0.5) / image_scale) as usize).min(height - 1);
+            for x in 0..scaled_width {
+                let source_x = (((x as f32 + 0.5) / image_scale) as usize).min(width - 1);
+                let color = self.format.unpack(rendered[source_y * width + source_x]);
+                if color.a == 0 {
+                    continue;
+                }
+                pixels.blend_pixel(left + x, top + y, color.unpremultiply(), ColorSpace::Srgb);
+            }
+        }
+
+        let Some(font) = &self.font else {
+            return;
+        };
+        let help = format!(
+            "{}: A arrow, R rectangle, F freehand, T label, \
+             Ctrl+Z undo, Ctrl+S save, Ctrl+C copy, Escape done",
+            self.tool.name()
+        );
+        let font_size = HELP_FONT_SIZE * scale.as_f64() as f32;
+        let padding = scale.to_buffer(HELP_PADDING);
+        let bar = Rect::new(
+            0,
+            0,
+            bounds.width,
+            font.line_height(font_size) + 2 * padding,
+        );
+        pixels.blend_rect(bar.intersect(&bounds), LABEL_BACKGROUND, ColorSpace::Srgb);
+        font.draw(pixels, &help, (padding, padding), font_size, LABEL_TEXT);
+    }
+}
+
+/// How much of each pixel a stroke covers. Its segments overlap at the
+/// joints, taking the most any of them covers draws those only once.
+struct Coverage {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+    // What the stroke touched, cleared again once blended
+    touched: Option<Rect>,
+}
+
+impl Coverage {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            values: vec![0.0; width * height],
+            touched: None,
+        }
+    }
+
+    /// Adds a segment from `a` to `b`, as wide as a stroke with round ends.
+    fn segment(&mut self, a: Point, b: Point) {
+        let radius = STROKE_WIDTH / 2.0;
+        // Smoothed over a pixel on either edge
+        let reach = radius + 1.0;
+        let clamp = |v: f32, max: usize| (v.max(0.0) as usize).min(max);
+        let (left, right) = (
+            clamp(a.0.min(b.0) - reach, self.width),
+            clamp(a.0.max(b.0) + reach + 1.0, self.width),
+        );
+        let (top, bottom) = (
+            clamp(a.1.min(b.1) - reach, self.height),
+            clamp(a.1.max(b.1) + reach + 1.0, self.height),
+        );
+        let area = Rect::new(left, top, right - left, bottom - top);
+        if area.is_empty() {
+            return;
+        }
+        self.touched = Some(self.touched.map_or(area, |touched| touched.union(&area)));
+
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length = dx * dx + dy * dy;
+        for y in top..bottom {
+            for x in left..right {
+                let (px, py) = (x as f32 + 0.5 - a.0, y as f32 + 0.5 - a.1);
+                // How far along the segment the closest point on it is
+                let t = if length > 0.0 {
+                    ((px * dx + py * dy) / length).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let (ex, ey) = (px - t * dx, py - t * dy);
+                let distance = (ex * ex + ey * ey).sqrt();
+                let covered = (radius - distance + 0.5).clamp(0.0, 1.0);
+                let value = &mut self.values[y * self.width + x];
+                *value = value.max(covered);
+            }
+        }
+    }
+
+    /// Draws the stroke in `color` and starts over.
+    fn blend(&mut self, pixels: &mut PixelBuffer, color: Color) {
+        let Some(touched) = self.touched.take() else {
+            return;
+        };
+        for y in touched.y..touched.bottom() {
+            for x in touched.x..touched.right() {
+                let value = mem::take(&mut self.values[y * self.width + x]);
+                if value > 0.0 {
+                    pixels.blend_pixel(x, y, color.with_coverage(value), ColorSpace::Srgb);
+                }
+            }
+        }
+    }
+}
+
+/// Redraws every window after the annotation changed.
+fn redraw(state: &mut AppState) {
+    for window in &mut state.windows {
+        window.request_redraw();
+    }
+    present_if_needed(state);
+}
+
+/// Shows the frame the window `id` last showed, to annotate it. Does
+/// nothing while annotating already.
+pub(crate) fn start(state: &mut AppState, id: WindowId) {
+    if matches!(state.scene, Scene::Annotate(_)) {
+        return;
+    }
+    let capture = match screenshot::capture(state, id) {
+        Ok(capture) => capture,
+        Err(err) => {
+            warn!(%err, "nothing to annotate");
+            return;
+        }
+    };
+    let previous = mem::take(&mut state.scene);
+    state.scene = Scene::Annotate(Annotation::new(capture, previous));
+    redraw(state);
+}
+
+/// Goes back to what was shown before annotating.
+fn finish(state: &mut AppState) {
+    if let Scene::Annotate(annotation) = &mut state.scene {
+        state.scene = mem::take(&mut *annotation.previous);
+        redraw(state);
+    }
+}
+
+/// The annotated screenshot, as it is saved.
+fn annotated(annotation: &Annotation) -> Capture {
+    Capture {
+        width: annotation.width,
+        height: annotation.height,
+        pixels: annotation.render(false),
+        format: annotation.format,
+    }
+}
+
+/// Saves the annotated screenshot next to the others.
+fn save(state: &AppState) {
+    let Scene::Annotate(annotation) = &state.scene else {
+        return;
+    };
+    match screenshot::save_png(&annotated(annotation), "-annotated") {
+        Ok(path) => info!(path = %path.display(), "saved the annotated screenshot"),
+        Err(err) => warn!(%err, "failed to save the annotated screenshot"),
+    }
+}
+
+/// Puts the annotated screenshot on the clipboard as a PNG.
+fn copy(state: &mut AppState) {
+    let Scene::Annotate(annotation) = &state.scene else {
+        return;
+    };
+    let Capture {
+        width,
+        height,
+        pixels,
+        format,
+    } = annotated(annotation);
+    let mut png = Vec::new();
+    if let Err(err) = screenshot::encode_png(&mut png, width, height, &pixels, format) {
+        warn!(%err, "failed to encode the annotated screenshot");
+        return;
+    }
+    if clipboard::set_selection(state, &[PNG_MIME_TYPE], png) {
+        info!("copied the annotated screenshot");
+    }
+}
+
+/// The pointer moved to `x`, `y` over the window `id`.
+pub(crate) fn pointer_moved(state: &mut AppState, id: Option<WindowId>, x: f64, y: f64) {
+    let Some(window) = id.and_then(|id| state.windows.iter().find(|w| w.id() == id)) else {
+        return;
+    };
+    let Scene::Annotate(annotation) = &mut state.scene else {
+        return;
+    };
+    let point = annotation.to_image((x, y), window.size());
+    let drawing = annotation.drawing;
+    annotation.extend(point);
+    if drawing {
+        redraw(state);
+    }
+}
+
+/// Draws with the left button over the window `id`. Returns whether it
+/// was the left one, while annotating.
+pub(crate) fn pointer_button(
+    state: &mut AppState,
+    id: Option<WindowId>,
+    button: u32,
+    pressed: bool,
+) -> bool {
+    let (Some(_), Scene::Annotate(annotation)) = (id, &mut state.scene) else {
+        return false;
+    };
+    if button != BTN_LEFT {
+        return false;
+    }
+
+    if pressed {
+        annotation.press();
+    } else {
+        annotation.release();
+    }
+    redraw(state);
+    true
+}
+
+/// Switches tools, types into labels, saves and copies while annotating.
+/// Returns whether the key was taken.
+pub(crate) fn handle_key(state: &mut AppState, event: &KeyEvent) -> bool {
+    let mods = state
+        .keyboard_mut()
+        .map(|keyboard| keyboard.modifiers)
+        .unwrap_or_default();
+    let Scene::Annotate(annotation) = &mut state.scene else {
+        return false;
+    };
+
+    if annotation.typing {
+        match event.keysym {
+            keysyms::Return | keysyms::KP_Enter | keysyms::Escape => annotation.finish_label(),
+            keysyms::BackSpace => annotation.backspace(),
+            _ => match event.utf8.as_deref() {
+                Some(typed) if !mods.ctrl && !typed.chars().any(char::is_control) => {
+                    annotation.type_text(typed)
+                }
+                // Shortcuts still work while typing
+                _ => return handle_shortcut(state, event, mods.ctrl),
+            },
+        }
+        redraw(state);
+        return true;
+    }
+
+    let plain = !(mods.ctrl || mods.alt || mods.logo);
+    let tool = match event.keysym {
+        keysyms::a if plain => Tool::Arrow,
+        keysyms::r if plain => Tool::Rectangle,
+        keysyms::f if plain => Tool::Freehand,
+        keysyms::t if plain => Tool::Label,
+        keysyms::Escape => {
+            finish(state);
+            return true;
+        }
+        _ => return handle_shortcut(state, event, mods.ctrl),
+    };
+    annotation.tool = tool;
+    redraw(state);
+    true
+}
+
+/// Ctrl+Z, Ctrl+S and Ctrl+C while annotating.
+fn handle_shortcut(state: &mut AppState, event: &KeyEvent, ctrl: bool) -> bool {
+    let Scene::Annotate(annotation) = &mut state.scene else {
+        return false;
+    };
+    if !ctrl {
+        return false;
+    }
+    match event.keysym {
+        keysyms::z => {
+            annotation.undo();
+            redraw(state);
+        }
+        keysyms::s if !event.repeat => save(state),
+        keysyms::c if !event.repeat => copy(state),
+        _ => return false,
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+
+    fn blank(width: usize, height: usize) -> Annotation {
+        let format = PixelFormat::Argb8888;
+        let capture = Capture {
+            width,
+            height,
+            pixels: vec![format.pack(WHITE.premultiply()); width * height],
+            format,
+        };
+        Annotation {
+            font: None,
+            ..Annotation::new(capture, Scene::TestPattern)
+        }
+    }
+
+    #[test]
+    fn shapes_follow_the_pointer() {
+        let mut annotation = blank(100, 100);
+        // A window twice as large shows it at twice the size
+        assert_eq!(annotation.to_image((50.0, 100.0), (200, 200)), (25.0, 50.0));
+
+        annotation.tool = Tool::Rectangle;
+        annotation.extend((10.0, 10.0));
+        annotation.press();
+        annotation.extend((20.0, 30.0));
+        annotation.release();
+        assert_eq!(
+            annotation.shapes,
+            [Shape::Rectangle {
+                from: (10.0, 10.0),
+                to: (20.0, 30.0)
+            }]
+        );
+        // Moving without the button leaves it be
+        annotation.extend((50.0, 50.0));
+
+        // A click is no arrow
+        annotation.tool = Tool::Arrow;
+        annotation.press();
+        annotation.release();
+        assert_eq!(annotation.shapes.len(), 1);
+
+        annotation.tool = Tool::Freehand;
+        annotation.press();
+        annotation.extend((51.0, 50.0));
+        annotation.extend((51.0, 50.0));
+        annotation.extend((52.0, 52.0));
+        annotation.release();
+        assert_eq!(
+            annotation.shapes[1],
+            Shape::Freehand(vec![(50.0, 50.0), (51.0, 50.0), (52.0, 52.0)])
+        );
+
+        annotation.undo();
+        assert_eq!(annotation.shapes.len(), 1);
+    }
+
+    #[test]
+    fn labels_take_what_is_typed() {
+        let mut annotation = blank(100, 100);
+        annotation.typing = true;
+        annotation.shapes.push(Shape::Label {
+            at: (5.0, 5.0),
+            text: String::new(),
+        });
+        annotation.type_text("hi");
+        annotation.type_text("!");
+        annotation.backspace();
+        annotation.finish_label();
+        assert!(!annotation.typing);
+        assert_eq!(
+            annotation.shapes,
+            [Shape::Label {
+                at: (5.0, 5.0),
+                text: "hi".to_owned()
+            }]
+        );
+
+        // Typing nothing leaves no label behind
+        annotation.typing = true;
+        annotation.shapes.push(Shape::Label {
+            at: (9.0, 9.0),
+            text: String::new(),
+        });
+        annotation.finish_label();
+        assert_eq!(annotation.shapes.len(), 1);
+    }
+
+    #[test]
+    fn strokes_are_drawn_into_the_screenshot() {
+        let mut annotation = blank(40, 40);
+        annotation.shapes.push(Shape::Rectangle {
+            from: (10.0, 10.0),
+            to: (30.0, 30.0),
+        });
+        let pixels = annotation.render(false);
+        let color = |x: usize, y: usize| annotation.format.unpack(pixels[y * 40 + x]);
+
+        assert_eq!(color(10, 20), INK.premultiply());
+        assert_eq!(color(20, 30), INK.premultiply());
+        // Inside and outside of it
+        assert_eq!(color(20, 20), WHITE.premultiply());
+        assert_eq!(color(2, 2), WHITE.premultiply());
+    }
+}
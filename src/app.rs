@@ -0,0 +1,167 @@
+//! Windows whose content and input are up to the user of the library: an
+//! [`App`] draws and reacts to events, [`run`] does the Wayland plumbing.
+//!
+//! Everything the demo does around its scenes still applies, like the
+//! decorations, the context menu or moving the window with Alt held.
+
+use std::{
+    ops::{Deref, DerefMut},
+    process::ExitCode,
+};
+
+use calloop::EventLoop;
+use tracing::warn;
+use wayland_client::Connection;
+
+use crate::{
+    error::Error,
+    event_loop::{self, Flusher},
+    keyboard::KeyEvent,
+    pixel_buffer::PixelBuffer,
+    scale::Scale,
+    scene::{Scene, Unfocused},
+    state::AppState,
+    window::WindowOptions,
+};
+
+pub use crate::seat::{BTN_LEFT, BTN_RIGHT};
+
+/// What the pointer did over the window. Positions are in window pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointerEvent {
+    Motion {
+        x: f64,
+        y: f64,
+    },
+    /// `button` is a Linux input event code like [`BTN_LEFT`]
+    Button {
+        button: u32,
+        pressed: bool,
+    },
+    Leave,
+}
+
+/// The part of a buffer an [`App`] draws into, in buffer pixels. Dereferences
+/// to the [`PixelBuffer`], clipped to what has to be redrawn.
+pub struct Canvas<'a, 'b> {
+    pixels: &'a mut PixelBuffer<'b>,
+    scale: Scale,
+    time: u32,
+}
+
+impl<'a, 'b> Canvas<'a, 'b> {
+    pub(crate) fn new(pixels: &'a mut PixelBuffer<'b>, scale: Scale, time: u32) -> Self {
+        Self {
+            pixels,
+            scale,
+            time,
+        }
+    }
+
+    /// How many buffer pixels make up a window pixel.
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    /// How far animations are, in milliseconds. Moves on by the refresh
+    /// cycles that passed while [`App::animates`].
+    pub fn time(&self) -> u32 {
+        self.time
+    }
+}
+
+impl<'b> Deref for Canvas<'_, 'b> {
+    type Target = PixelBuffer<'b>;
+
+    fn deref(&self) -> &Self::Target {
+        self.pixels
+    }
+}
+
+impl DerefMut for Canvas<'_, '_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.pixels
+    }
+}
+
+/// What a window shows and how it reacts to input. Every window [`run`]
+/// opens shares it.
+pub trait App: 'static {
+    /// The window got its size, `width`x`height` window pixels, or a new
+    /// one. It is redrawn right after.
+    fn on_configure(&mut self, _width: usize, _height: usize) {}
+
+    /// Draws the whole window. Drawing is clipped to what has to be redrawn.
+    fn on_draw(&mut self, canvas: &mut Canvas);
+
+    /// A key was pressed or repeated in the focused window. Returns whether
+    /// it was handled, the window is redrawn then. Unhandled keys do what
+    /// they do in the demo, e.g. Escape closes the window.
+    fn on_key(&mut self, _event: &KeyEvent) -> bool {
+        false
+    }
+
+    /// The pointer moved or a button was pressed over the window. Returns
+    /// whether the window has to be redrawn.
+    fn on_pointer(&mut self, _event: PointerEvent) -> bool {
+        false
+    }
+
+    /// The user asked to close the window. Returns whether it may close.
+    fn on_close(&mut self) -> bool {
+        true
+    }
+
+    /// Whether the window is redrawn every frame, for animations.
+    fn animates(&self) -> bool {
+        false
+    }
+
+    /// What the animation does while the window isn't focused.
+    fn when_unfocused(&self) -> Unfocused {
+        Unfocused::default()
+    }
+}
+
+/// A window [`WindowBuilder::build`] opened, empty until an [`App`] is
+/// [`run`](OpenWindow::run) in it.
+///
+/// [`WindowBuilder::build`]: crate::window::WindowBuilder::build
+pub struct OpenWindow {
+    state: AppState,
+    event_loop: EventLoop<'static, AppState>,
+    flusher: Flusher<AppState>,
+}
+
+impl OpenWindow {
+    pub(crate) fn open(conn: Connection, mut options: WindowOptions) -> Result<Self, Error> {
+        // The app stays on the main thread
+        if options.render_thread {
+            warn!("an app can't draw on the render thread, drawing on the main one");
+            options.render_thread = false;
+        }
+
+        let (state, event_loop, flusher) = event_loop::start_on(conn, Scene::default(), options)?;
+        Ok(Self {
+            state,
+            event_loop,
+            flusher,
+        })
+    }
+
+    /// Shows `app` in the window until it is closed. Returns how the
+    /// process should exit.
+    pub fn run<A: App>(mut self, app: A) -> Result<ExitCode, Error> {
+        self.state.app = Some(Box::new(app));
+        if !event_loop::run_loop(&mut self.state, &mut self.event_loop, &self.flusher) {
+            return Ok(ExitCode::FAILURE);
+        }
+        event_loop::shut_down(self.state, self.flusher.connection())
+    }
+}
+
+/// Shows `app` in a window set up with `options` until it is closed.
+/// Returns how the process should exit.
+pub fn run<A: App>(app: A, options: WindowOptions) -> Result<ExitCode, Error> {
+    OpenWindow::open(Connection::connect_to_env()?, options)?.run(app)
+}
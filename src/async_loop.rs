@@ -0,0 +1,108 @@
+//! The main loop on tokio, with the `tokio` feature and `--async`.
+//!
+//! The calloop loop carries the connection along with the pipes and the
+//! signals, and its fd is readable whenever one of them is ready. Tokio
+//! waits on that fd next to async timers and channels, and the loop is
+//! dispatched without blocking once it woke up. Application logic can then
+//! be written as tasks that send their results over a channel.
+
+use std::{
+    future,
+    os::fd::{AsFd, OwnedFd},
+    process::ExitCode,
+    time::Duration,
+};
+
+use tokio::{
+    io::{unix::AsyncFd, Interest},
+    runtime,
+    sync::mpsc::{self, UnboundedSender},
+    time,
+};
+
+use crate::{
+    error::Error,
+    event_loop::{self, STATS_INTERVAL},
+    scene::Scene,
+    watchdog,
+    window::WindowOptions,
+};
+
+/// What tasks send the main loop.
+#[derive(Debug)]
+enum Message {
+    LogStats,
+}
+
+/// Asks for the stats to be logged every [`STATS_INTERVAL`], until the
+/// main loop is gone.
+async fn stats_ticker(messages: UnboundedSender<Message>) {
+    let mut interval = time::interval(STATS_INTERVAL);
+    // The first tick is right away
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if messages.send(Message::LogStats).is_err() {
+            return;
+        }
+    }
+}
+
+async fn run_async(scene: Scene, options: WindowOptions) -> Result<ExitCode, Error> {
+    let (mut state, mut event_loop, flusher) = event_loop::start(scene, options)?;
+    // A copy of the fd, the loop itself is borrowed to dispatch it
+    let fd: OwnedFd = event_loop
+        .as_fd()
+        .try_clone_to_owned()
+        .map_err(Error::Runtime)?;
+    let ready = AsyncFd::with_interest(fd, Interest::READABLE).map_err(Error::Runtime)?;
+
+    let (sender, mut messages) = mpsc::unbounded_channel();
+    tokio::spawn(stats_ticker(sender));
+
+    while state.running {
+        // Waiting for room in the socket makes the loop's fd readable too
+        flusher.flush()?;
+        let timeout = state.timers.next_timeout();
+        let timers = async {
+            match timeout {
+                Some(timeout) => time::sleep(timeout).await,
+                None => future::pending().await,
+            }
+        };
+
+        watchdog::idle();
+        tokio::select! {
+            guard = ready.readable() => {
+                // Whatever becomes ready from now on wakes us up again
+                guard.map_err(Error::Runtime)?.clear_ready();
+            }
+            Some(message) = messages.recv() => match message {
+                Message::LogStats => event_loop::log_stats(&state),
+            },
+            () = timers => {}
+        }
+        watchdog::busy();
+
+        // The pipes and signals handle their own errors, only reading from
+        // the connection fails
+        if let Err(err) = event_loop.dispatch(Duration::ZERO, &mut state) {
+            flusher.report(err.into());
+            return Ok(ExitCode::FAILURE);
+        }
+        event_loop::after_dispatch(&mut state);
+    }
+
+    event_loop::shut_down(state, flusher.connection())
+}
+
+/// Like [`event_loop::run`], with the main loop on a single threaded tokio
+/// runtime.
+pub fn run(scene: Scene, options: WindowOptions) -> Result<ExitCode, Error> {
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()
+        .map_err(Error::Runtime)?;
+    runtime.block_on(run_async(scene, options))
+}
@@ -0,0 +1,209 @@
+//! `bench`: animates the test pattern for a while, as fast as the
+//! compositor takes frames, and reports how long they took to reach the
+//! screen. `bench present` does so through each way of presenting frames in
+//! turn, to compare them.
+
+use std::{mem, process::ExitCode, time::Duration};
+
+use tracing::warn;
+
+use crate::{
+    app::{App, Canvas},
+    error::Error,
+    event_loop,
+    presentation::PresentationStats,
+    scene::{draw_scene, Scene, Unfocused},
+    window::{PresentPath, WindowOptions},
+};
+
+/// How long the benchmark runs if not told otherwise
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+
+/// Redraws the whole test pattern every frame.
+struct Bench;
+
+impl App for Bench {
+    fn on_draw(&mut self, canvas: &mut Canvas) {
+        let (time, scale) = (canvas.time(), canvas.scale());
+        draw_scene(canvas, &Scene::TestPattern, false, false, time, scale);
+    }
+
+    fn animates(&self) -> bool {
+        true
+    }
+
+    // Measures the same thing whatever has the focus
+    fn when_unfocused(&self) -> Unfocused {
+        Unfocused::Animate
+    }
+}
+
+/// Prints how the frames of a window fared over `duration`.
+fn report(stats: &PresentationStats, duration: Duration) {
+    let ms = |latency: Duration| latency.as_secs_f64() * 1000.0;
+    println!(
+        "{} frames shown in {:.1}s, {:.1} fps",
+        stats.presented,
+        duration.as_secs_f64(),
+        stats.presented as f64 / duration.as_secs_f64()
+    );
+    println!(
+        "{} discarded, {} shown late, {} refresh cycles dropped",
+        stats.discarded, stats.missed, stats.dropped
+    );
+    if let (Some(average), Some(min)) = (stats.average_latency(), stats.min_latency) {
+        println!(
+            "commit to present latency: {:.2}ms average, {:.2}ms min, {:.2}ms max",
+            ms(average),
+            ms(min),
+            ms(stats.max_latency)
+        );
+    } else {
+        println!("commit to present latency: unknown, no presentation feedback");
+    }
+    if !stats.refresh.is_zero() {
+        println!("refresh cycle: {:.2}ms", ms(stats.refresh));
+    }
+}
+
+/// What became of a run of the benchmark.
+enum Outcome {
+    /// How the frames fared, and how much CPU time the process took
+    Measured(Box<PresentationStats>, Duration),
+    /// The window was closed before the time was up
+    Closed,
+    Failed,
+    /// The compositor can't present that way, and why
+    Unavailable(&'static str),
+}
+
+/// Runs the test pattern in a window set up with `options` for `duration`.
+fn measure(duration: Duration, mut options: WindowOptions) -> Result<Outcome, Error> {
+    // Drawn through an app, which stays on the main thread
+    if options.render_thread {
+        warn!("the benchmark can't draw on the render thread, drawing on the main one");
+        options.render_thread = false;
+    }
+    let single_pixel = options.present_path == PresentPath::SinglePixel;
+
+    let (mut state, mut event_loop, flusher) = event_loop::start(Scene::default(), options)?;
+    if single_pixel && (state.single_pixel_buffer_manager.is_none() || state.viewporter.is_none()) {
+        event_loop::shut_down(state, flusher.connection())?;
+        return Ok(Outcome::Unavailable(
+            "the compositor lacks wp_single_pixel_buffer_manager_v1 or wp_viewporter",
+        ));
+    }
+
+    state.app = Some(Box::new(Bench));
+    state.timers.after(duration, |state| state.running = false);
+    let cpu_start = cpu_time();
+    if !event_loop::run_loop(&mut state, &mut event_loop, &flusher) {
+        return Ok(Outcome::Failed);
+    }
+    let cpu = cpu_time().saturating_sub(cpu_start);
+
+    let outcome = match state.windows.first_mut() {
+        Some(window) => Outcome::Measured(Box::new(mem::take(&mut window.presentation_stats)), cpu),
+        None => Outcome::Closed,
+    };
+    event_loop::shut_down(state, flusher.connection())?;
+    Ok(outcome)
+}
+
+/// Runs the benchmark in a window set up with `options` for `duration`,
+/// then prints the results. Returns how the process should exit.
+pub fn run(duration: Duration, options: WindowOptions) -> Result<ExitCode, Error> {
+    match measure(duration, options)? {
+        Outcome::Measured(stats, _) => report(&stats, duration),
+        Outcome::Closed => println!("the window was closed before the benchmark was over"),
+        Outcome::Failed => return Ok(ExitCode::FAILURE),
+        Outcome::Unavailable(reason) => println!("{reason}"),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The ways frames can reach the compositor, and those we have no way to
+/// present with, with why.
+const PATHS: [(&str, Result<PresentPath, &str>); 6] = [
+    ("shm single buffer", Ok(PresentPath::SingleBuffer)),
+    ("shm swapchain", Ok(PresentPath::Swapchain)),
+    ("single pixel buffer", Ok(PresentPath::SinglePixel)),
+    (
+        "dmabuf",
+        Err("not implemented, frames are only drawn on the CPU"),
+    ),
+    (
+        "GL",
+        Err("not implemented, frames are only drawn on the CPU"),
+    ),
+    (
+        "wgpu",
+        Err("not implemented, frames are only drawn on the CPU"),
+    ),
+];
+
+/// `bench present`: runs the benchmark for `duration` through each way of
+/// presenting frames in turn, each in a window of its own set up with
+/// `options`, and prints how they compare.
+pub fn run_present(duration: Duration, options: WindowOptions) -> Result<ExitCode, Error> {
+    let ms = |latency: Duration| latency.as_secs_f64() * 1000.0;
+    let mut rows = Vec::new();
+    for (name, path) in PATHS {
+        let path = match path {
+            Ok(path) => path,
+            Err(reason) => {
+                rows.push(format!("{name:<20} {reason}"));
+                continue;
+            }
+        };
+        let options = WindowOptions {
+            present_path: path,
+            ..options.clone()
+        };
+        let row = match measure(duration, options)? {
+            Outcome::Measured(stats, cpu) => {
+                let seconds = duration.as_secs_f64();
+                let latency = match (stats.average_latency(), stats.min_latency) {
+                    (Some(average), Some(min)) => format!(
+                        "{:.2} / {:.2} / {:.2}",
+                        ms(average),
+                        ms(min),
+                        ms(stats.max_latency)
+                    ),
+                    _ => "unknown".to_owned(),
+                };
+                format!(
+                    "{name:<20} {:>8.1} {:>7.1}% {latency:>24}",
+                    stats.presented as f64 / seconds,
+                    cpu.as_secs_f64() / seconds * 100.0,
+                )
+            }
+            Outcome::Closed => format!("{name:<20} the window was closed before it was over"),
+            Outcome::Failed => return Ok(ExitCode::FAILURE),
+            Outcome::Unavailable(reason) => format!("{name:<20} {reason}"),
+        };
+        rows.push(row);
+    }
+
+    println!(
+        "{:<20} {:>8} {:>8} {:>24}",
+        "path", "fps", "cpu", "latency avg/min/max ms"
+    );
+    for row in rows {
+        println!("{row}");
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// How much CPU time the process has taken so far, on every thread.
+fn cpu_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `time` is a valid timespec to write to
+    if unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut time) } != 0 {
+        return Duration::ZERO;
+    }
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
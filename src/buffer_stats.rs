@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+// This many frames in a row without a free buffer means we need more of them
+const STARVED_WARN_THRESHOLD: u32 = 30;
+
+/// Counters describing how well the shm buffers keep up with the compositor.
+#[derive(Debug, Default)]
+pub struct BufferStats {
+    pub created: u64,
+    pub destroyed: u64,
+    pub reused: u64,
+    pub in_flight: usize,
+    pub peak_in_flight: usize,
+    /// Frames that found no buffer released by the compositor
+    pub starved_frames: u64,
+    /// Total time between wanting a free buffer and getting one back
+    pub wait_time: Duration,
+
+    waiting_since: Option<Instant>,
+    starved_in_a_row: u32,
+}
+
+impl BufferStats {
+    pub fn created(&mut self) {
+        self.created += 1;
+    }
+
+    /// `in_flight` if it was destroyed before the compositor released it.
+    pub fn destroyed(&mut self, in_flight: bool) {
+        self.destroyed += 1;
+        if in_flight {
+            self.in_flight = self.in_flight.saturating_sub(1);
+        }
+    }
+
+    pub fn reused(&mut self) {
+        self.reused += 1;
+    }
+
+    /// A buffer was handed over to the compositor.
+    pub fn attached(&mut self) {
+        // Whether it was reused or new, the streak is over
+        self.starved_in_a_row = 0;
+        self.in_flight += 1;
+        self.peak_in_flight = self.peak_in_flight.max(self.in_flight);
+    }
+
+    /// The compositor gave a buffer back.
+    pub fn released(&mut self) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        if let Some(since) = self.waiting_since.take() {
+            self.wait_time += since.elapsed();
+        }
+    }
+
+    /// We needed a buffer but all of them are still in use by the compositor.
+    pub fn starved(&mut self) {
+        self.starved_frames += 1;
+        self.starved_in_a_row += 1;
+        self.waiting_since.get_or_insert_with(Instant::now);
+
+        if self.starved_in_a_row == STARVED_WARN_THRESHOLD {
+            warn!(
+                frames = self.starved_in_a_row,
+                in_flight = self.in_flight,
+                "consistently buffer-starved, an extra buffer is needed"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_buffers_in_flight() {
+        let mut stats = BufferStats::default();
+        stats.created();
+        stats.attached();
+        stats.starved();
+        stats.created();
+        stats.attached();
+        assert_eq!((stats.in_flight, stats.peak_in_flight), (2, 2));
+        // A new buffer ends the streak as much as a reused one
+        assert_eq!(stats.starved_in_a_row, 0);
+
+        stats.released();
+        stats.destroyed(true);
+        stats.destroyed(false);
+        assert_eq!((stats.in_flight, stats.destroyed), (0, 2));
+    }
+}
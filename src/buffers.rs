@@ -0,0 +1,510 @@
+//! The buffers we draw into.
+//!
+//! A swapchain of two shm buffers: we draw into the free one and attach it,
+//! and only touch a buffer again once the compositor released it. Drawing
+//! into a buffer the compositor may still be reading from shows up as
+//! tearing and half drawn frames.
+//!
+//! All buffers live in one long-lived pool that only ever grows, so a resize
+//! costs a `wl_shm_pool.resize` at most instead of a new file and mapping.
+
+use tracing::{debug, warn};
+use wayland_client::{
+    protocol::{wl_buffer::WlBuffer, wl_output::Transform, wl_shm::WlShm, wl_shm_pool::WlShmPool},
+    Dispatch, QueueHandle,
+};
+
+use crate::{
+    buffer_stats::BufferStats,
+    damage::Damage,
+    pixel_buffer::PixelBuffer,
+    pixel_format::PixelFormat,
+    rect::Rect,
+    shm::{page_size, SharedMapping, ShmError, ShmPool},
+};
+
+// One on screen, one to draw the next frame into
+const DEFAULT_LEN: usize = 2;
+
+// Don't bother going smaller than this when allocations fail
+const MIN_FALLBACK_SIZE: usize = 64;
+
+/// A buffer carved out of the pool. Kept around between frames so we don't
+/// have to create a new one for every frame.
+pub struct ShmBuffer {
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    // The part of the pool reserved for this buffer, may be bigger than
+    // the buffer itself after it shrank
+    offset: usize,
+    len: usize,
+    buffer: WlBuffer,
+    // Attached and not released by the compositor yet
+    busy: bool,
+    // What changed since this buffer was last drawn to
+    damage: Damage,
+}
+
+impl ShmBuffer {
+    fn new<D>(
+        pool: &ShmPool,
+        format: PixelFormat,
+        offset: usize,
+        len: usize,
+        width: usize,
+        height: usize,
+        qh: &QueueHandle<D>,
+    ) -> Result<Self, ShmError>
+    where
+        D: Dispatch<WlBuffer, ()> + 'static,
+    {
+        let stride = width * 4; // 4 bytes per pixel
+        let buffer = pool.create_buffer(offset, width, height, stride, format.wl_format(), qh)?;
+
+        let mut damage = Damage::default();
+        damage.add(Rect::new(0, 0, width, height));
+
+        Ok(Self {
+            width,
+            height,
+            format,
+            offset,
+            len,
+            buffer,
+            busy: false,
+            damage,
+        })
+    }
+
+    /// Replaces the buffer with one that still fits in its part of the pool.
+    fn resize_in_place<D>(
+        &mut self,
+        pool: &ShmPool,
+        width: usize,
+        height: usize,
+        qh: &QueueHandle<D>,
+    ) -> Result<(), ShmError>
+    where
+        D: Dispatch<WlBuffer, ()> + 'static,
+    {
+        let stride = width * 4;
+        let buffer = pool.create_buffer(
+            self.offset,
+            width,
+            height,
+            stride,
+            self.format.wl_format(),
+            qh,
+        )?;
+
+        self.buffer.destroy();
+        self.buffer = buffer;
+        self.busy = false;
+        self.width = width;
+        self.height = height;
+        pool.discard(self.offset + stride * height, self.len - stride * height);
+
+        // The contents don't line up anymore
+        self.damage.take();
+        self.damage.add(Rect::new(0, 0, width, height));
+
+        Ok(())
+    }
+
+    fn fits(&self, width: usize, height: usize) -> bool {
+        self.len >= width * 4 * height
+    }
+}
+
+impl Drop for ShmBuffer {
+    fn drop(&mut self) {
+        debug!(
+            width = self.width,
+            height = self.height,
+            "destroying shm buffer"
+        );
+        self.buffer.destroy();
+    }
+}
+
+/// A buffer handed out to draw the next frame into.
+pub struct Frame<'a> {
+    buffer: &'a mut ShmBuffer,
+    pool: &'a mut ShmPool,
+}
+
+impl Frame<'_> {
+    pub fn wl_buffer(&self) -> &WlBuffer {
+        &self.buffer.buffer
+    }
+
+    /// The size of the buffer, smaller than asked for if allocating fell
+    /// back to a smaller one.
+    pub fn size(&self) -> (usize, usize) {
+        (self.buffer.width, self.buffer.height)
+    }
+
+    /// Takes what needs to be redrawn to bring this buffer up to date.
+    pub fn take_damage(&mut self) -> Vec<Rect> {
+        self.buffer.damage.take()
+    }
+
+    /// The memory of the buffer in a mapping of its own, to draw into on
+    /// another thread while this one carries on. Only to be used until the
+    /// buffer is attached, nothing else draws into it meanwhile.
+    pub fn share(&self) -> Result<SharedMapping, ShmError> {
+        let b = &self.buffer;
+        self.pool.map_shared(b.offset, b.width * 4 * b.height)
+    }
+
+    pub fn pixels(&mut self) -> PixelBuffer<'_> {
+        let b = &self.buffer;
+        let stride = b.width * 4;
+        let start = b.offset / 4;
+        let end = start + stride * b.height / 4;
+        PixelBuffer::new(
+            &mut self.pool.memory().as_mut_slice()[start..end],
+            b.width,
+            b.height,
+            stride,
+            b.format,
+        )
+    }
+}
+
+pub struct Swapchain {
+    format: PixelFormat,
+    buffers: Vec<ShmBuffer>,
+    // Declared after the buffers so it outlives them
+    pool: Option<ShmPool>,
+    // How many buffers there may be at most
+    len: usize,
+    // Size of the last buffer handed out
+    size: (usize, usize),
+    // The size last asked for, larger than `size` after falling back to
+    // smaller buffers
+    requested: (usize, usize),
+    // The buffer last committed and the transform it was drawn with, which
+    // is what the window shows
+    last: Option<(WlBuffer, Transform)>,
+    pub stats: BufferStats,
+}
+
+impl Default for Swapchain {
+    fn default() -> Self {
+        Self::new(PixelFormat::default())
+    }
+}
+
+impl Swapchain {
+    pub fn new(format: PixelFormat) -> Self {
+        Self {
+            format,
+            buffers: Vec::new(),
+            pool: None,
+            len: DEFAULT_LEN,
+            size: (0, 0),
+            requested: (0, 0),
+            last: None,
+            stats: BufferStats::default(),
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    /// Keeps at most `len` buffers, e.g. 1 to draw into the one on screen
+    /// once the compositor is done with it. Takes effect before the first
+    /// buffer is made.
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len.max(1);
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Returns a `width`x`height` buffer to draw the next frame into, or
+    /// `None` if the compositor still holds all of them. The buffer may be
+    /// smaller if allocating fell back to a smaller one, it keeps being
+    /// used until a different size is asked for. `damage` is what
+    /// changed since the last frame, every buffer keeps track of it until
+    /// it is drawn to again. `pool_size` is how big a buffer we expect to
+    /// need at most, the pool is made big enough for that up front.
+    ///
+    /// The buffer is marked busy, the caller is expected to attach it right
+    /// away.
+    pub fn acquire<D>(
+        &mut self,
+        shm: &WlShm,
+        width: usize,
+        height: usize,
+        pool_size: usize,
+        damage: &[Rect],
+        qh: &QueueHandle<D>,
+    ) -> Result<Option<Frame<'_>>, ShmError>
+    where
+        D: Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, ()> + 'static,
+    {
+        let free = self.buffers.iter().position(|b| !b.busy);
+        if free.is_none() && self.buffers.len() >= self.len {
+            self.stats.starved();
+            return Ok(None);
+        }
+
+        for b in &mut self.buffers {
+            for rect in damage {
+                b.damage.add(*rect);
+            }
+        }
+        // Not worth another failed allocation every frame
+        let (width, height) = if (width, height) == self.requested {
+            self.size
+        } else {
+            self.requested = (width, height);
+            (width, height)
+        };
+
+        let index = match free {
+            Some(i) if (self.buffers[i].width, self.buffers[i].height) == (width, height) => {
+                self.stats.reused();
+                i
+            }
+            Some(i) if self.buffers[i].fits(width, height) => {
+                self.stats.destroyed(self.buffers[i].busy);
+                let pool = self.pool.as_ref().unwrap();
+                self.buffers[i].resize_in_place(pool, width, height, qh)?;
+                self.stats.created();
+                i
+            }
+            Some(i) => {
+                // Free its part of the pool so the new buffer can take it
+                self.remove(i);
+                self.allocate(shm, width, height, pool_size, qh)?
+            }
+            None => self.allocate(shm, width, height, pool_size, qh)?,
+        };
+
+        let buffer = &mut self.buffers[index];
+        self.size = (buffer.width, buffer.height);
+        buffer.busy = true;
+        self.stats.attached();
+
+        Ok(Some(Frame {
+            buffer,
+            pool: self.pool.as_mut().unwrap(),
+        }))
+    }
+
+    /// `buffer` was committed, drawn with `transform`.
+    pub fn committed(&mut self, buffer: &WlBuffer, transform: Transform) {
+        self.last = Some((buffer.clone(), transform));
+    }
+
+    /// The pixels of the buffer last committed, as its width, height and
+    /// rows without padding, along with the transform it was drawn with.
+    /// `None` if it was destroyed since, e.g. by a resize.
+    pub fn last_frame(&mut self) -> Option<(usize, usize, &[u32], Transform)> {
+        let (last, transform) = self.last.as_ref()?;
+        let b = self.buffers.iter().find(|b| &b.buffer == last)?;
+        let start = b.offset / 4;
+        let pixels =
+            &self.pool.as_mut()?.memory().as_mut_slice()[start..start + b.width * b.height];
+        Some((b.width, b.height, pixels, *transform))
+    }
+
+    /// The compositor is done reading from `buffer`. Returns whether it is
+    /// one of ours.
+    pub fn release(&mut self, buffer: &WlBuffer) -> bool {
+        let Some(i) = self.buffers.iter().position(|b| &b.buffer == buffer) else {
+            return false;
+        };
+
+        self.buffers[i].busy = false;
+        self.stats.released();
+
+        // Left over from before a resize, give its memory back
+        let b = &self.buffers[i];
+        if (b.width, b.height) != self.size && !b.fits(self.size.0, self.size.1) {
+            self.remove(i);
+        }
+        true
+    }
+
+    fn remove(&mut self, index: usize) {
+        let b = self.buffers.swap_remove(index);
+        if let Some(pool) = &self.pool {
+            pool.discard(b.offset, b.len);
+        }
+        self.stats.destroyed(b.busy);
+    }
+
+    /// Allocates a buffer, falling back to smaller buffers if that fails,
+    /// e.g. when we are out of memory. A smaller window beats a dead one.
+    /// Returns its index, the buffer has the size it actually got.
+    fn allocate<D>(
+        &mut self,
+        shm: &WlShm,
+        width: usize,
+        height: usize,
+        pool_size: usize,
+        qh: &QueueHandle<D>,
+    ) -> Result<usize, ShmError>
+    where
+        D: Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, ()> + 'static,
+    {
+        let mut attempts = vec![(width, height)];
+        let (mut w, mut h) = (width / 2, height / 2);
+        while w >= MIN_FALLBACK_SIZE && h >= MIN_FALLBACK_SIZE {
+            attempts.push((w, h));
+            (w, h) = (w / 2, h / 2);
+        }
+
+        let mut last_err = None;
+        for (w, h) in attempts {
+            let len = (w * 4 * h).next_multiple_of(page_size());
+            let res = self.reserve(shm, len, pool_size, qh).and_then(|offset| {
+                let pool = self.pool.as_ref().unwrap();
+                ShmBuffer::new(pool, self.format, offset, len, w, h, qh)
+            });
+
+            match res {
+                Ok(buffer) => {
+                    if (w, h) != (width, height) {
+                        warn!(width = w, height = h, "fell back to a smaller buffer");
+                    }
+                    self.buffers.push(buffer);
+                    self.stats.created();
+                    return Ok(self.buffers.len() - 1);
+                }
+                Err(err) => {
+                    warn!(width = w, height = h, %err, "failed to allocate a buffer");
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.unwrap())
+    }
+
+    /// Finds `len` bytes in the pool that no buffer uses, growing the pool
+    /// if there is no such gap. Returns the offset.
+    fn reserve<D>(
+        &mut self,
+        shm: &WlShm,
+        len: usize,
+        pool_size: usize,
+        qh: &QueueHandle<D>,
+    ) -> Result<usize, ShmError>
+    where
+        D: Dispatch<WlShmPool, ()> + 'static,
+    {
+        let pool = match &mut self.pool {
+            Some(pool) => pool,
+            None => {
+                let size = pool_size.next_multiple_of(page_size()).max(len) * self.len;
+                let pool = match ShmPool::new(shm, size, qh) {
+                    Ok(pool) => pool,
+                    // Don't give up just because the spare room didn't fit
+                    Err(err) if size > len => {
+                        warn!(size, %err, "failed to create a shm pool, retrying smaller");
+                        ShmPool::new(shm, len, qh)?
+                    }
+                    Err(err) => return Err(err),
+                };
+                // The first buffer goes at the start, and the first frame
+                // draws all of it
+                pool.prefault(0, len);
+                self.pool.insert(pool)
+            }
+        };
+
+        let mut used: Vec<_> = self.buffers.iter().map(|b| (b.offset, b.len)).collect();
+        used.sort_unstable();
+
+        // First fit, there are only ever a couple of buffers
+        let mut offset = 0;
+        for (start, used_len) in used {
+            if start >= offset + len {
+                return Ok(offset);
+            }
+            offset = offset.max(start + used_len);
+        }
+
+        if offset + len > pool.size() {
+            debug!(from = pool.size(), to = offset + len, "growing shm pool");
+            pool.grow(offset + len)?;
+        }
+
+        Ok(offset)
+    }
+}
+
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        // Those the compositor still holds are destroyed along with them
+        for b in &self.buffers {
+            self.stats.destroyed(b.busy);
+        }
+        debug!(stats = ?self.stats, "dropping swapchain");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use wayland_client::{protocol::wl_shm::WlShm, Connection, EventQueue};
+
+    use super::*;
+    use crate::state::AppState;
+
+    /// A wl_shm on a connection nobody answers on, requests pile up on the
+    /// other end of the socket. Enough to create pools and buffers.
+    fn fake_shm() -> (Connection, EventQueue<AppState>, WlShm, UnixStream) {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let conn = Connection::from_socket(ours).unwrap();
+        let queue = conn.new_event_queue();
+        let registry = conn.display().get_registry(&queue.handle(), ());
+        let shm = registry.bind(1, 1, &queue.handle(), ());
+        (conn, queue, shm, theirs)
+    }
+
+    #[test]
+    fn sticks_to_a_smaller_buffer_after_falling_back() {
+        let (_conn, queue, shm, _server) = fake_shm();
+        let qh = queue.handle();
+        let mut swapchain = Swapchain::new(PixelFormat::default());
+
+        // Too large for a wl_shm pool, the size is an i32
+        let (width, height) = (24_000, 24_000);
+        let all = [Rect::new(0, 0, width, height)];
+        let frame = swapchain
+            .acquire(&shm, width, height, 0, &all, &qh)
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.size(), (12_000, 12_000));
+        let buffer = frame.wl_buffer().clone();
+        assert_eq!(swapchain.size(), (12_000, 12_000));
+
+        // Kept once released, and reused rather than allocating again
+        assert!(swapchain.release(&buffer));
+        let frame = swapchain
+            .acquire(&shm, width, height, 0, &all, &qh)
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.wl_buffer(), &buffer);
+        assert_eq!(swapchain.stats.created, 1);
+        assert_eq!(swapchain.stats.reused, 1);
+
+        // A new size is tried at full size again
+        assert!(swapchain.release(&buffer));
+        let frame = swapchain
+            .acquire(&shm, 100, 100, 0, &all, &qh)
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame.size(), (100, 100));
+    }
+}
@@ -0,0 +1,375 @@
+//! Copy and paste through wl_data_device: what other clients put on the
+//! clipboard, pasted with Ctrl+V, and the color of the window, copied with
+//! Ctrl+C. Annotated screenshots are copied as PNG images.
+
+use std::os::fd::AsFd;
+
+use tracing::{debug, info, warn};
+use wayland_client::{
+    event_created_child,
+    protocol::{
+        wl_data_device::{self, WlDataDevice},
+        wl_data_device_manager::{DndAction, WlDataDeviceManager},
+        wl_data_offer::{self, WlDataOffer},
+        wl_data_source::{self, WlDataSource},
+        wl_seat::WlSeat,
+        wl_surface::WlSurface,
+    },
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+
+use crate::{
+    dnd::{self, Drag, DragSource},
+    pipes,
+    state::AppState,
+};
+
+/// The MIME types we paste, most preferred first. We copy in all of them.
+pub(crate) const TEXT_MIME_TYPES: [&str; 3] =
+    ["text/plain;charset=utf-8", "UTF8_STRING", "text/plain"];
+
+/// The MIME type out of `mime_types` to paste as text, if there is one.
+pub fn text_mime_type(mime_types: &[String]) -> Option<&'static str> {
+    TEXT_MIME_TYPES
+        .into_iter()
+        .find(|m| mime_types.iter().any(|t| t == m))
+}
+
+/// Data another client offers us, along with the MIME types it has it in.
+pub struct Offer {
+    pub(crate) offer: WlDataOffer,
+    pub mime_types: Vec<String>,
+    /// What dropping it does, as picked by the compositor
+    pub action: Option<DndAction>,
+}
+
+impl Offer {
+    pub(crate) fn destroy(self) {
+        self.offer.destroy();
+    }
+}
+
+/// What we put on the clipboard, until another client replaces it.
+struct Source {
+    source: WlDataSource,
+    // The same in each MIME type offered
+    data: Vec<u8>,
+}
+
+/// The data device of our seat.
+pub struct DataDevice {
+    device: WlDataDevice,
+    // Offers still learning their MIME types, until the compositor tells
+    // what they are for
+    new_offers: Vec<Offer>,
+    /// What is on the clipboard, if anything we can see
+    pub selection: Option<Offer>,
+    // What we copied, while it is on the clipboard
+    source: Option<Source>,
+    // What is dragged over our windows, if anything
+    pub(crate) drag: Option<Drag>,
+    // What we drag out of them, if anything
+    pub(crate) drag_source: Option<DragSource>,
+}
+
+impl DataDevice {
+    pub fn new<D>(manager: &WlDataDeviceManager, seat: &WlSeat, qh: &QueueHandle<D>) -> Self
+    where
+        D: Dispatch<WlDataDevice, ()> + 'static,
+    {
+        Self {
+            device: manager.get_data_device(seat, qh, ()),
+            new_offers: Vec::new(),
+            selection: None,
+            source: None,
+            drag: None,
+            drag_source: None,
+        }
+    }
+
+    /// The offer `offer`, if it is one we know about.
+    fn offer_mut(&mut self, offer: &WlDataOffer) -> Option<&mut Offer> {
+        self.new_offers
+            .iter_mut()
+            .chain(self.selection.as_mut())
+            .chain(self.drag.as_mut().map(|d| &mut d.offer))
+            .find(|o| &o.offer == offer)
+    }
+
+    /// Takes `offer` out of the new ones.
+    fn take_offer(&mut self, offer: &WlDataOffer) -> Option<Offer> {
+        let i = self.new_offers.iter().position(|o| &o.offer == offer)?;
+        Some(self.new_offers.remove(i))
+    }
+
+    /// Starts dragging `source` out of `origin`, with `icon` following the
+    /// pointer. `serial` is that of the button press that started it.
+    pub(crate) fn start_drag(
+        &self,
+        source: Option<&WlDataSource>,
+        origin: &WlSurface,
+        icon: Option<&WlSurface>,
+        serial: u32,
+    ) {
+        self.device.start_drag(source, origin, icon, serial);
+    }
+
+    pub(crate) fn release(mut self) {
+        dnd::leave(&mut self);
+        for offer in self.new_offers.into_iter().chain(self.selection) {
+            offer.destroy();
+        }
+        if let Some(source) = self.source {
+            source.source.destroy();
+        }
+        if let Some(drag) = self.drag_source {
+            drag.destroy();
+        }
+        // Only has a destructor since version 2
+        if self.device.version() >= 2 {
+            self.device.release();
+        }
+    }
+}
+
+pub(crate) fn data_device(state: &mut AppState) -> Option<&mut DataDevice> {
+    state.seat.as_mut()?.data_device.as_mut()
+}
+
+/// Reads the text on the clipboard, logs it and shows its first line as
+/// the title of the focused window.
+pub(crate) fn paste(state: &mut AppState) {
+    let Some(offer) = data_device(state).and_then(|d| d.selection.as_ref()) else {
+        info!("nothing to paste");
+        return;
+    };
+    let Some(mime_type) = text_mime_type(&offer.mime_types) else {
+        info!(mime_types = ?offer.mime_types, "no text to paste");
+        return;
+    };
+
+    let (read, write) = match pipes::pipe() {
+        Ok(pipe) => pipe,
+        Err(err) => {
+            warn!(%err, "failed to create a pipe to paste through");
+            return;
+        }
+    };
+    offer.offer.receive(mime_type.to_owned(), write.as_fd());
+    // Ours has to be closed too for the pipe to close once the other client
+    // is done writing
+    drop(write);
+
+    let focused = state.focused_window();
+    let res = state.pipes.read_to_end(read, move |state, res| {
+        let text = match res {
+            Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+            Err(err) => {
+                warn!(%err, "failed to read the clipboard");
+                return;
+            }
+        };
+        info!(%text, "pasted");
+
+        let line = text.lines().next().unwrap_or_default();
+        if let Some(window) = focused.and_then(|id| state.window(id)) {
+            window.set_title(line);
+        }
+    });
+    if let Err(err) = res {
+        warn!(%err, "failed to read the clipboard");
+    }
+}
+
+/// Puts the color of the focused window on the clipboard, as a hex string
+/// like `#ff8000`.
+pub(crate) fn copy(state: &mut AppState) {
+    let Some(window) = state.focused_window().and_then(|id| state.window(id)) else {
+        return;
+    };
+    let time = window.animation_time.as_millis() as u32;
+    let text = state.scene.background(window.highlighted, time).to_hex();
+
+    if set_selection(state, &TEXT_MIME_TYPES, text.clone().into_bytes()) {
+        info!(%text, "copied");
+    }
+}
+
+/// Puts `data` on the clipboard, offered as each of `mime_types`. Returns
+/// whether it is there.
+pub(crate) fn set_selection(state: &mut AppState, mime_types: &[&str], data: Vec<u8>) -> bool {
+    let qh = &state.queue_handle;
+    let (Some(manager), Some(seat)) = (&state.data_device_manager, state.seat.as_mut()) else {
+        debug!("the compositor doesn't support copy and paste");
+        return false;
+    };
+    // Proves that the user asked for it
+    let (Some(serial), Some(device)) = (seat.last_serial, seat.data_device.as_mut()) else {
+        return false;
+    };
+
+    let source = manager.create_data_source(qh, ());
+    for mime_type in mime_types {
+        source.offer((*mime_type).to_owned());
+    }
+    device.device.set_selection(Some(&source), serial);
+
+    if let Some(old) = device.source.replace(Source { source, data }) {
+        old.source.destroy();
+    }
+    true
+}
+
+impl Dispatch<WlDataDeviceManager, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlDataDeviceManager,
+        _event: <WlDataDeviceManager as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<WlDataDevice, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlDataDevice,
+        event: <WlDataDevice as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(device) = data_device(state) else {
+            return;
+        };
+
+        match event {
+            // Its MIME types follow, then what it is for
+            wl_data_device::Event::DataOffer { id } => device.new_offers.push(Offer {
+                offer: id,
+                mime_types: Vec::new(),
+                action: None,
+            }),
+            wl_data_device::Event::Selection { id } => {
+                if let Some(old) = device.selection.take() {
+                    old.destroy();
+                }
+                device.selection = id.and_then(|id| device.take_offer(&id));
+                debug!(
+                    mime_types = ?device.selection.as_ref().map(|o| &o.mime_types),
+                    "clipboard changed"
+                );
+            }
+            wl_data_device::Event::Enter {
+                serial,
+                surface,
+                id,
+                ..
+            } => {
+                let offer = id.and_then(|id| device.take_offer(&id));
+                dnd::enter(state, serial, &surface, offer);
+            }
+            wl_data_device::Event::Leave => dnd::leave(device),
+            // Files are taken anywhere in the window
+            wl_data_device::Event::Motion { .. } => {}
+            wl_data_device::Event::Drop => dnd::dropped(state),
+            event => debug!(?event, "ignoring unknown data device event"),
+        }
+    }
+
+    event_created_child!(AppState, WlDataDevice, [
+        wl_data_device::EVT_DATA_OFFER_OPCODE => (WlDataOffer, ()),
+    ]);
+}
+
+impl Dispatch<WlDataOffer, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlDataOffer,
+        event: <WlDataOffer as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(offer) = data_device(state).and_then(|d| d.offer_mut(proxy)) else {
+            return;
+        };
+
+        match event {
+            wl_data_offer::Event::Offer { mime_type } => offer.mime_types.push(mime_type),
+            wl_data_offer::Event::Action {
+                dnd_action: WEnum::Value(action),
+            } => offer.action = Some(action),
+            // We only ever copy, whatever else the other client supports
+            wl_data_offer::Event::SourceActions { .. } | wl_data_offer::Event::Action { .. } => {}
+            event => debug!(?event, "ignoring unknown data offer event"),
+        }
+    }
+}
+
+impl Dispatch<WlDataSource, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlDataSource,
+        event: <WlDataSource as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let device = data_device(state);
+        match event {
+            // Someone pastes what we copied
+            wl_data_source::Event::Send { mime_type, fd } => {
+                let Some(data) = device
+                    .and_then(|d| d.source.as_ref())
+                    .filter(|s| &s.source == proxy)
+                    .map(|s| s.data.clone())
+                else {
+                    return;
+                };
+                debug!(%mime_type, "sending the clipboard");
+                if let Err(err) = state.pipes.write_all(fd, data) {
+                    warn!(%err, "failed to send the clipboard");
+                }
+            }
+            // Something else was copied since
+            wl_data_source::Event::Cancelled => {
+                let ours = |s: &Source| &s.source == proxy;
+                if let Some(device) = device.filter(|d| d.source.as_ref().is_some_and(ours)) {
+                    device.source = None;
+                }
+                proxy.destroy();
+            }
+            // Only matter for drag and drop
+            wl_data_source::Event::Target { .. }
+            | wl_data_source::Event::Action { .. }
+            | wl_data_source::Event::DndDropPerformed
+            | wl_data_source::Event::DndFinished => {}
+            event => debug!(?event, "ignoring unknown data source event"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_utf8_text() {
+        let mime_types =
+            |types: &[&str]| -> Vec<String> { types.iter().map(|t| t.to_string()).collect() };
+
+        assert_eq!(
+            text_mime_type(&mime_types(&["text/plain", "text/plain;charset=utf-8"])),
+            Some("text/plain;charset=utf-8")
+        );
+        assert_eq!(
+            text_mime_type(&mime_types(&["image/png", "UTF8_STRING"])),
+            Some("UTF8_STRING")
+        );
+        assert_eq!(text_mime_type(&mime_types(&["image/png"])), None);
+    }
+}
@@ -0,0 +1,316 @@
+//! `--clipboard-watch`: prints what is copied, by any client, as it is
+//! copied. wlr-data-control lets clipboard managers see the clipboard
+//! without a window or keyboard focus, which wl_data_device requires.
+
+use std::{
+    io::{self, Write},
+    ops::RangeInclusive,
+    os::fd::AsFd,
+    process::ExitCode,
+};
+
+use calloop::EventLoop;
+use tracing::{debug, info, warn};
+use wayland_client::{
+    event_created_child,
+    protocol::{
+        wl_registry::{self, WlRegistry},
+        wl_seat::WlSeat,
+    },
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+use wayland_protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+    zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+};
+
+use crate::{
+    clipboard::text_mime_type,
+    error::Error,
+    event_loop::{insert_signals, insert_wayland},
+    pipes::{self, Pipes},
+    registry::GlobalManager,
+};
+
+const SEAT_VERSIONS: RangeInclusive<u32> = 1..=7;
+// The primary selection is only sent since version 2
+const DATA_CONTROL_VERSIONS: RangeInclusive<u32> = 1..=2;
+
+/// Which of the two clipboards changed.
+#[derive(Debug, Clone, Copy)]
+enum Selection {
+    /// Ctrl+C and Ctrl+V
+    Clipboard,
+    /// Selecting text and pasting it with the middle button
+    Primary,
+}
+
+impl Selection {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Clipboard => "clipboard",
+            Self::Primary => "primary",
+        }
+    }
+}
+
+/// Data copied by some client, along with the MIME types it has it in.
+struct Offer {
+    offer: ZwlrDataControlOfferV1,
+    mime_types: Vec<String>,
+}
+
+#[derive(Default)]
+struct Watcher {
+    globals: GlobalManager,
+    seat: Option<WlSeat>,
+    manager: Option<ZwlrDataControlManagerV1>,
+    device: Option<ZwlrDataControlDeviceV1>,
+    // Offers still learning their MIME types, until the compositor tells
+    // which clipboard they are on
+    new_offers: Vec<Offer>,
+    pipes: Pipes<Watcher>,
+    running: bool,
+}
+
+impl Watcher {
+    fn take_offer(&mut self, offer: &ZwlrDataControlOfferV1) -> Option<Offer> {
+        let i = self.new_offers.iter().position(|o| &o.offer == offer)?;
+        Some(self.new_offers.remove(i))
+    }
+
+    /// Prints `line`, stops once nobody reads what we print anymore.
+    fn print(&mut self, line: &str) {
+        if let Err(err) = writeln!(io::stdout().lock(), "{line}") {
+            warn!(%err, "failed to print, exiting");
+            self.running = false;
+        }
+    }
+
+    /// What was copied to `selection` changed to `offer`, or nothing.
+    /// Prints it as text if it is, and only its MIME types otherwise.
+    fn selection_changed(&mut self, selection: Selection, offer: Option<Offer>) {
+        let name = selection.name();
+        let Some(offer) = offer else {
+            self.print(&format!("{name}: cleared"));
+            return;
+        };
+        let Some(mime_type) = text_mime_type(&offer.mime_types) else {
+            self.print(&format!("{name}: {}", offer.mime_types.join(", ")));
+            offer.offer.destroy();
+            return;
+        };
+
+        let (read, write) = match pipes::pipe() {
+            Ok(pipe) => pipe,
+            Err(err) => {
+                warn!(%err, "failed to create a pipe to read the {name} through");
+                offer.offer.destroy();
+                return;
+            }
+        };
+        offer.offer.receive(mime_type.to_owned(), write.as_fd());
+        // Ours has to be closed too for the pipe to close once the other
+        // client is done writing. The offer isn't needed to read it.
+        drop(write);
+        offer.offer.destroy();
+
+        let res = self.pipes.read_to_end(read, move |watcher, res| match res {
+            Ok(data) => watcher.print(&format!("{name}: {}", String::from_utf8_lossy(&data))),
+            Err(err) => warn!(%err, "failed to read the {name}"),
+        });
+        if let Err(err) = res {
+            warn!(%err, "failed to read the {name}");
+        }
+    }
+
+    fn destroy(&mut self) {
+        for offer in self.new_offers.drain(..) {
+            offer.offer.destroy();
+        }
+        if let Some(device) = self.device.take() {
+            device.destroy();
+        }
+        if let Some(manager) = self.manager.take() {
+            manager.destroy();
+        }
+        // Only has a destructor since version 5
+        if let Some(seat) = self.seat.take().filter(|s| s.version() >= 5) {
+            seat.release();
+        }
+    }
+}
+
+/// Prints what is copied to the clipboard and the primary selection until
+/// the compositor stops telling us, or nobody reads what we print. Returns
+/// how the process should exit.
+pub fn run() -> Result<ExitCode, Error> {
+    let conn = Connection::connect_to_env()?;
+    let mut event_loop = EventLoop::try_new()?;
+    insert_signals(&event_loop.handle(), |watcher: &mut Watcher| {
+        watcher.running = false
+    })?;
+    let mut event_queue = conn.new_event_queue::<Watcher>();
+    let qh = event_queue.handle();
+
+    let mut watcher = Watcher {
+        globals: GlobalManager::new(conn.display().get_registry(&qh, ())),
+        pipes: Pipes::new(event_loop.handle()),
+        // Unless a signal stops it first
+        running: true,
+        ..Default::default()
+    };
+    event_queue.roundtrip(&mut watcher)?;
+
+    let globals = &mut watcher.globals;
+    globals.require(&[
+        WlSeat::interface().name,
+        ZwlrDataControlManagerV1::interface().name,
+    ])?;
+    let seat: WlSeat = globals.bind(SEAT_VERSIONS, &qh, ())?;
+    let manager: ZwlrDataControlManagerV1 = globals.bind(DATA_CONTROL_VERSIONS, &qh, ())?;
+    watcher.device = Some(manager.get_data_device(&seat, &qh, ()));
+    watcher.seat = Some(seat);
+    watcher.manager = Some(manager);
+
+    let flusher = insert_wayland(&event_loop.handle(), conn, event_queue)?;
+
+    info!("watching the clipboard");
+    while watcher.running {
+        // The pipes and signals handle their own errors, only the
+        // connection fails
+        let res = flusher
+            .flush()
+            .and_then(|()| Ok(event_loop.dispatch(None, &mut watcher)?));
+        if let Err(err) = res {
+            flusher.report(err);
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
+    watcher.destroy();
+    flusher.connection().flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+impl Dispatch<WlRegistry, ()> for Watcher {
+    fn event(
+        watcher: &mut Self,
+        _registry: &WlRegistry,
+        event: <WlRegistry as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } => watcher.globals.add(name, interface, version),
+            wl_registry::Event::GlobalRemove { name } => {
+                // Only the seat and the manager are bound, and there is
+                // nothing to watch without them
+                if let Some(global) = watcher.globals.remove(name) {
+                    warn!(
+                        interface = global.interface,
+                        "a global we depend on was removed, exiting"
+                    );
+                    watcher.running = false;
+                }
+            }
+            event => debug!(?event, "ignoring unknown registry event"),
+        }
+    }
+}
+
+impl Dispatch<WlSeat, ()> for Watcher {
+    fn event(
+        _watcher: &mut Self,
+        _proxy: &WlSeat,
+        _event: <WlSeat as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // Its clipboard doesn't depend on its devices
+    }
+}
+
+impl Dispatch<ZwlrDataControlManagerV1, ()> for Watcher {
+    fn event(
+        _watcher: &mut Self,
+        _proxy: &ZwlrDataControlManagerV1,
+        _event: <ZwlrDataControlManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for Watcher {
+    fn event(
+        watcher: &mut Self,
+        _proxy: &ZwlrDataControlDeviceV1,
+        event: <ZwlrDataControlDeviceV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            // Its MIME types follow, then which clipboard it is on
+            zwlr_data_control_device_v1::Event::DataOffer { id } => {
+                watcher.new_offers.push(Offer {
+                    offer: id,
+                    mime_types: Vec::new(),
+                })
+            }
+            zwlr_data_control_device_v1::Event::Selection { id } => {
+                let offer = id.and_then(|id| watcher.take_offer(&id));
+                watcher.selection_changed(Selection::Clipboard, offer);
+            }
+            zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
+                let offer = id.and_then(|id| watcher.take_offer(&id));
+                watcher.selection_changed(Selection::Primary, offer);
+            }
+            // E.g. the seat is gone
+            zwlr_data_control_device_v1::Event::Finished => {
+                info!("the compositor stopped sending the clipboard, exiting");
+                if let Some(device) = watcher.device.take() {
+                    device.destroy();
+                }
+                watcher.running = false;
+            }
+            event => debug!(?event, "ignoring unknown data control device event"),
+        }
+    }
+
+    event_created_child!(Watcher, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, ()> for Watcher {
+    fn event(
+        watcher: &mut Self,
+        proxy: &ZwlrDataControlOfferV1,
+        event: <ZwlrDataControlOfferV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(offer) = watcher.new_offers.iter_mut().find(|o| &o.offer == proxy) else {
+            return;
+        };
+
+        match event {
+            zwlr_data_control_offer_v1::Event::Offer { mime_type } => {
+                offer.mime_types.push(mime_type)
+            }
+            event => debug!(?event, "ignoring unknown data control offer event"),
+        }
+    }
+}
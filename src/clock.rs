@@ -0,0 +1,13 @@
+//! The wall clock, for what is shown or named after the time of day.
+
+/// The local wall clock time, broken down. `None` if the time zone can't
+/// be figured out.
+pub fn local_time() -> Option<libc::tm> {
+    // SAFETY: time(NULL) only returns the time
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    // SAFETY: all zeroes is a valid tm, which localtime_r fills in
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    // SAFETY: both point to valid values
+    let res = unsafe { libc::localtime_r(&now, &mut tm) };
+    (!res.is_null()).then_some(tm)
+}
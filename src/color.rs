@@ -0,0 +1,358 @@
+use std::sync::LazyLock;
+
+/// An sRGB color with straight (not premultiplied) alpha.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+/// A color whose channels are already multiplied by its alpha.
+///
+/// This is what wl_shm's ARGB formats expect, and what makes blending a
+/// simple multiply-add.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PremulColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+/// How colors are mixed when blending or interpolating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Mix the encoded sRGB bytes directly. Cheap, but midtones come out
+    /// too dark.
+    Srgb,
+    /// Decode to linear light, mix, and encode back.
+    Linear,
+}
+
+// Resolution of the linear -> sRGB table, plenty for 8-bit output
+const ENCODE_STEPS: usize = 4096;
+
+static SRGB_TO_LINEAR: LazyLock<[f32; 256]> = LazyLock::new(|| {
+    std::array::from_fn(|i| {
+        let c = i as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    })
+});
+
+static LINEAR_TO_SRGB: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    (0..ENCODE_STEPS)
+        .map(|i| {
+            let l = i as f32 / (ENCODE_STEPS - 1) as f32;
+            let c = if l <= 0.0031308 {
+                l * 12.92
+            } else {
+                1.055 * l.powf(1.0 / 2.4) - 0.055
+            };
+            (c * 255.0).round() as u8
+        })
+        .collect()
+});
+
+fn decode(c: u8) -> f32 {
+    SRGB_TO_LINEAR[c as usize]
+}
+
+fn encode(l: f32) -> u8 {
+    let i = (l.clamp(0.0, 1.0) * (ENCODE_STEPS - 1) as f32).round() as usize;
+    LINEAR_TO_SRGB[i]
+}
+
+/// `a * b / 255`, rounded
+fn mul_div255(a: u8, b: u8) -> u8 {
+    let x = a as u32 * b as u32 + 128;
+    ((x + (x >> 8)) >> 8) as u8
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self::rgba(r, g, b, 0xFF)
+    }
+
+    pub const fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+
+    /// Parses `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`, the `#` is optional.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if !hex.is_ascii() {
+            return None;
+        }
+
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).ok();
+        let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+
+        match hex.len() {
+            3 | 4 => {
+                // #abc is short for #aabbcc
+                let mut channels = [0xFF; 4];
+                for (i, channel) in channels.iter_mut().take(hex.len()).enumerate() {
+                    *channel = digit(i)? * 0x11;
+                }
+                let [r, g, b, a] = channels;
+                Some(Self::rgba(r, g, b, a))
+            }
+            6 => Some(Self::rgb(byte(0)?, byte(2)?, byte(4)?)),
+            8 => Some(Self::rgba(byte(0)?, byte(2)?, byte(4)?, byte(6)?)),
+            _ => None,
+        }
+    }
+
+    /// Formats the color as `#rrggbb`, or `#rrggbbaa` if it isn't opaque.
+    pub fn to_hex(self) -> String {
+        let Self { r, g, b, a } = self;
+        if a == 0xFF {
+            format!("#{r:02x}{g:02x}{b:02x}")
+        } else {
+            format!("#{r:02x}{g:02x}{b:02x}{a:02x}")
+        }
+    }
+
+    /// Linear interpolation between two colors, `t` is clamped to `0..=1`.
+    pub fn lerp(self, other: Self, t: f32, space: ColorSpace) -> Self {
+        let t = t.clamp(0.0, 1.0);
+        let mix_bytes = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        let mix = |a: u8, b: u8| match space {
+            ColorSpace::Srgb => mix_bytes(a, b),
+            ColorSpace::Linear => {
+                let (a, b) = (decode(a), decode(b));
+                encode(a + (b - a) * t)
+            }
+        };
+
+        // Alpha is linear already
+        Self::rgba(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+            mix_bytes(self.a, other.a),
+        )
+    }
+
+    /// The color over `coverage` of a pixel, for anti-aliased edges.
+    pub fn with_coverage(self, coverage: f32) -> Self {
+        let alpha = (f32::from(self.a) * coverage).round() as u8;
+        Self::rgba(self.r, self.g, self.b, alpha)
+    }
+
+    pub fn premultiply(self) -> PremulColor {
+        PremulColor {
+            r: mul_div255(self.r, self.a),
+            g: mul_div255(self.g, self.a),
+            b: mul_div255(self.b, self.a),
+            a: self.a,
+        }
+    }
+}
+
+impl PremulColor {
+    /// Porter-Duff "over": `self` drawn on top of `dst`.
+    pub fn over(self, dst: Self, space: ColorSpace) -> Self {
+        if space == ColorSpace::Linear {
+            return self.over_linear(dst);
+        }
+
+        let inv = 0xFF - self.a;
+        Self {
+            r: self.r.saturating_add(mul_div255(dst.r, inv)),
+            g: self.g.saturating_add(mul_div255(dst.g, inv)),
+            b: self.b.saturating_add(mul_div255(dst.b, inv)),
+            a: self.a.saturating_add(mul_div255(dst.a, inv)),
+        }
+    }
+
+    fn over_linear(self, dst: Self) -> Self {
+        let (src, dst) = (self.unpremultiply(), dst.unpremultiply());
+        let src_a = src.a as f32 / 255.0;
+        let dst_a = dst.a as f32 / 255.0 * (1.0 - src_a);
+        let a = src_a + dst_a;
+        if a <= 0.0 {
+            return Self::default();
+        }
+
+        let mix = |s: u8, d: u8| encode((decode(s) * src_a + decode(d) * dst_a) / a);
+        Color::rgba(
+            mix(src.r, dst.r),
+            mix(src.g, dst.g),
+            mix(src.b, dst.b),
+            (a * 255.0).round() as u8,
+        )
+        .premultiply()
+    }
+
+    pub fn unpremultiply(self) -> Color {
+        if self.a == 0 {
+            return Color::rgba(0, 0, 0, 0);
+        }
+
+        let div = |c: u8| ((c as u32 * 255 + self.a as u32 / 2) / self.a as u32).min(255) as u8;
+        Color::rgba(div(self.r), div(self.g), div(self.b), self.a)
+    }
+}
+
+impl From<Color> for PremulColor {
+    fn from(color: Color) -> Self {
+        color.premultiply()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_round_trips_through_linear() {
+        for c in 0..=255 {
+            assert_eq!(encode(decode(c)), c);
+        }
+    }
+
+    #[test]
+    fn srgb_decodes_to_known_values() {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+        assert_eq!(decode(0), 0.0);
+        assert_eq!(decode(255), 1.0);
+        // On the straight part of the curve near black
+        assert!(close(decode(10), 0.0030353));
+        // On the power part
+        assert!(close(decode(128), 0.2158605));
+        assert!(close(decode(188), 0.5028865));
+
+        assert_eq!(encode(0.0), 0);
+        assert_eq!(encode(1.0), 255);
+        assert_eq!(encode(0.5), 188);
+        assert_eq!(encode(0.2158605), 128);
+        // Clamped
+        assert_eq!(encode(-1.0), 0);
+        assert_eq!(encode(2.0), 255);
+    }
+
+    #[test]
+    fn hex_colors_are_parsed() {
+        assert_eq!(
+            Color::from_hex("#ff8000"),
+            Some(Color::rgb(0xFF, 0x80, 0x00))
+        );
+        assert_eq!(
+            Color::from_hex("FF800080"),
+            Some(Color::rgba(0xFF, 0x80, 0x00, 0x80))
+        );
+        assert_eq!(Color::from_hex("#f80"), Some(Color::rgb(0xFF, 0x88, 0x00)));
+        assert_eq!(
+            Color::from_hex("#f808"),
+            Some(Color::rgba(0xFF, 0x88, 0x00, 0x88))
+        );
+        assert_eq!(Color::from_hex("#00000000"), Some(Color::rgba(0, 0, 0, 0)));
+
+        for bad in [
+            "", "#", "#12", "#12345", "#1234567", "#ggg", "#12345g", "#ff80é", "##fff",
+        ] {
+            assert_eq!(Color::from_hex(bad), None, "{bad:?}");
+        }
+
+        let color = Color::rgba(0x12, 0xAB, 0xEF, 0x34);
+        assert_eq!(Color::from_hex(&color.to_hex()), Some(color));
+        assert_eq!(Color::rgb(0x12, 0xAB, 0xEF).to_hex(), "#12abef");
+    }
+
+    #[test]
+    fn lerp_mixes_in_either_space() {
+        let (black, white) = (Color::rgb(0, 0, 0), Color::rgb(0xFF, 0xFF, 0xFF));
+        assert_eq!(black.lerp(white, 0.0, ColorSpace::Srgb), black);
+        assert_eq!(black.lerp(white, 1.0, ColorSpace::Linear), white);
+        // Clamped
+        assert_eq!(black.lerp(white, -1.0, ColorSpace::Srgb), black);
+        assert_eq!(black.lerp(white, 2.0, ColorSpace::Linear), white);
+
+        assert_eq!(
+            black.lerp(white, 0.5, ColorSpace::Srgb),
+            Color::rgb(0x80, 0x80, 0x80)
+        );
+        // Half the light is brighter than the middle byte
+        assert_eq!(
+            black.lerp(white, 0.5, ColorSpace::Linear),
+            Color::rgb(0xBC, 0xBC, 0xBC)
+        );
+
+        // Alpha is mixed the same way in both
+        let clear = Color::rgba(0, 0, 0, 0);
+        assert_eq!(clear.lerp(white, 0.5, ColorSpace::Linear).a, 0x80);
+        assert_eq!(clear.lerp(white, 0.5, ColorSpace::Srgb).a, 0x80);
+    }
+
+    #[test]
+    fn over_blends_by_alpha() {
+        let dst = Color::rgb(0x00, 0x00, 0xFF).premultiply();
+        for space in [ColorSpace::Srgb, ColorSpace::Linear] {
+            // Opaque covers, transparent leaves alone
+            let opaque = Color::rgb(0xFF, 0x00, 0x00).premultiply();
+            assert_eq!(opaque.over(dst, space), opaque);
+            assert_eq!(PremulColor::default().over(dst, space), dst);
+            assert_eq!(opaque.over(PremulColor::default(), space), opaque);
+        }
+
+        let half = Color::rgba(0xFF, 0x00, 0x00, 0x80).premultiply();
+        assert_eq!(
+            half.over(dst, ColorSpace::Srgb),
+            PremulColor {
+                r: 0x80,
+                g: 0x00,
+                b: 0x7F,
+                a: 0xFF
+            }
+        );
+        assert_eq!(
+            half.over(dst, ColorSpace::Linear),
+            Color::rgb(0xBC, 0x00, 0xBB).premultiply()
+        );
+
+        // Both see-through, more so than either
+        let result = half.over(
+            Color::rgba(0, 0, 0xFF, 0x80).premultiply(),
+            ColorSpace::Srgb,
+        );
+        assert_eq!(result.a, 0xC0);
+    }
+
+    #[test]
+    fn premultiplying_round_trips() {
+        for c in 0..=255 {
+            let color = Color::rgba(c, 255 - c, c / 2, 0xFF);
+            assert_eq!(color.premultiply().unpremultiply(), color);
+        }
+        // Nothing is left of the color at alpha 0
+        assert_eq!(
+            Color::rgba(0xFF, 0x80, 0x40, 0).premultiply(),
+            PremulColor::default()
+        );
+        assert_eq!(
+            PremulColor::default().unpremultiply(),
+            Color::rgba(0, 0, 0, 0)
+        );
+
+        // Every premultiplied color comes back the same, even though the
+        // straight one can only be approximated
+        for a in 1..=255 {
+            for c in 0..=a {
+                let color = PremulColor {
+                    r: c,
+                    g: a - c,
+                    b: c / 2,
+                    a,
+                };
+                assert_eq!(color.unpremultiply().premultiply(), color);
+            }
+        }
+    }
+}
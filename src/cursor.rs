@@ -0,0 +1,282 @@
+//! The pointer cursor.
+//!
+//! The compositor leaves the cursor alone when it enters our surface, so
+//! unless we set one it keeps whatever the previous client showed, or
+//! nothing at all. With cursor-shape-v1 we only name the shape and the
+//! compositor draws it, otherwise we draw it from the user's Xcursor theme.
+
+use std::{
+    mem,
+    time::{Duration, Instant},
+};
+
+use tracing::warn;
+use wayland_client::{
+    backend::InvalidId,
+    protocol::{
+        wl_compositor::WlCompositor, wl_pointer::WlPointer, wl_shm::WlShm, wl_surface::WlSurface,
+    },
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+use wayland_cursor::CursorTheme;
+use wayland_protocols::wp::cursor_shape::v1::client::{
+    wp_cursor_shape_device_v1::{Shape, WpCursorShapeDeviceV1},
+    wp_cursor_shape_manager_v1::WpCursorShapeManagerV1,
+};
+
+// Used unless XCURSOR_SIZE says otherwise
+const DEFAULT_SIZE: u32 = 24;
+
+/// The cursor shapes we use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Default,
+    Pointer,
+    Text,
+    Move,
+    NResize,
+    NeResize,
+    EResize,
+    SeResize,
+    SResize,
+    SwResize,
+    WResize,
+    NwResize,
+}
+
+impl CursorShape {
+    fn wp_shape(self) -> Shape {
+        match self {
+            Self::Default => Shape::Default,
+            Self::Pointer => Shape::Pointer,
+            Self::Text => Shape::Text,
+            Self::Move => Shape::Move,
+            Self::NResize => Shape::NResize,
+            Self::NeResize => Shape::NeResize,
+            Self::EResize => Shape::EResize,
+            Self::SeResize => Shape::SeResize,
+            Self::SResize => Shape::SResize,
+            Self::SwResize => Shape::SwResize,
+            Self::WResize => Shape::WResize,
+            Self::NwResize => Shape::NwResize,
+        }
+    }
+
+    /// Xcursor names, the CSS one first and then the legacy X11 one that
+    /// older themes only have.
+    fn theme_names(self) -> [&'static str; 2] {
+        match self {
+            Self::Default => ["default", "left_ptr"],
+            Self::Pointer => ["pointer", "hand2"],
+            Self::Text => ["text", "xterm"],
+            Self::Move => ["move", "fleur"],
+            Self::NResize => ["n-resize", "top_side"],
+            Self::NeResize => ["ne-resize", "top_right_corner"],
+            Self::EResize => ["e-resize", "right_side"],
+            Self::SeResize => ["se-resize", "bottom_right_corner"],
+            Self::SResize => ["s-resize", "bottom_side"],
+            Self::SwResize => ["sw-resize", "bottom_left_corner"],
+            Self::WResize => ["w-resize", "left_side"],
+            Self::NwResize => ["nw-resize", "top_left_corner"],
+        }
+    }
+}
+
+enum Backend {
+    /// The compositor draws the cursor
+    Shape {
+        manager: WpCursorShapeManagerV1,
+        device: Option<WpCursorShapeDeviceV1>,
+    },
+    /// We draw it on our own surface
+    Theme {
+        theme: CursorTheme,
+        surface: WlSurface,
+        // When the current cursor was shown, animations are timed from it
+        shown_at: Instant,
+    },
+}
+
+impl Backend {
+    fn destroy(self) {
+        match self {
+            Self::Shape { manager, device } => {
+                if let Some(device) = device {
+                    device.destroy();
+                }
+                manager.destroy();
+            }
+            Self::Theme { surface, .. } => surface.destroy(),
+        }
+    }
+}
+
+pub struct Cursor {
+    backend: Backend,
+    shape: CursorShape,
+    // The pointer we last set the cursor for, and the serial of its enter
+    // event, needed to change the cursor later on.
+    pointer: Option<(WlPointer, u32)>,
+}
+
+impl Cursor {
+    /// Lets the compositor draw the cursor.
+    pub fn with_shapes(manager: WpCursorShapeManagerV1) -> Self {
+        Self::new(Backend::Shape {
+            manager,
+            device: None,
+        })
+    }
+
+    /// Loads the theme from `XCURSOR_THEME`, or the default one.
+    pub fn with_theme<D>(
+        conn: &Connection,
+        shm: &WlShm,
+        compositor: &WlCompositor,
+        qh: &QueueHandle<D>,
+    ) -> Result<Self, InvalidId>
+    where
+        D: Dispatch<WlSurface, ()> + 'static,
+    {
+        let theme = CursorTheme::load(conn, shm.clone(), DEFAULT_SIZE)?;
+        let surface = compositor.create_surface(qh, ());
+
+        Ok(Self::new(Backend::Theme {
+            theme,
+            surface,
+            shown_at: Instant::now(),
+        }))
+    }
+
+    fn new(backend: Backend) -> Self {
+        Self {
+            backend,
+            shape: CursorShape::Default,
+            pointer: None,
+        }
+    }
+
+    /// Shows the cursor for `pointer`, `serial` is the one of the enter
+    /// event. Returns when the next animation frame is due, if animated.
+    pub fn show<D>(
+        &mut self,
+        pointer: &WlPointer,
+        serial: u32,
+        qh: &QueueHandle<D>,
+    ) -> Option<Duration>
+    where
+        D: Dispatch<WpCursorShapeDeviceV1, ()> + 'static,
+    {
+        if let Backend::Shape { manager, device } = &mut self.backend {
+            if self.pointer.as_ref().is_none_or(|(p, _)| p != pointer) {
+                if let Some(device) = device.take() {
+                    device.destroy();
+                }
+                *device = Some(manager.get_pointer(pointer, qh, ()));
+            }
+        }
+
+        self.pointer = Some((pointer.clone(), serial));
+        self.apply(true)
+    }
+
+    pub fn shape(&self) -> CursorShape {
+        self.shape
+    }
+
+    /// Switches to another shape. Returns when the next animation frame is
+    /// due, if animated.
+    pub fn set_shape(&mut self, shape: CursorShape) -> Option<Duration> {
+        self.shape = shape;
+        self.apply(true)
+    }
+
+    /// Shows the animation frame that is due now. Returns when the next one
+    /// is due.
+    pub fn update(&mut self) -> Option<Duration> {
+        self.apply(false)
+    }
+
+    /// Draws the cursor ourselves from now on, with the theme of
+    /// `fallback`, e.g. once the compositor no longer offers shapes. Keeps
+    /// the shape and the pointer it is shown for. Returns when the next
+    /// animation frame is due, if animated.
+    pub fn fall_back(&mut self, fallback: Cursor) -> Option<Duration> {
+        mem::replace(&mut self.backend, fallback.backend).destroy();
+        self.apply(true)
+    }
+
+    pub fn destroy(self) {
+        self.backend.destroy();
+    }
+
+    fn apply(&mut self, restart: bool) -> Option<Duration> {
+        let (pointer, serial) = self.pointer.as_ref()?;
+
+        match &mut self.backend {
+            Backend::Shape { device, .. } => {
+                device.as_ref()?.set_shape(*serial, self.shape.wp_shape());
+                None
+            }
+            Backend::Theme {
+                theme,
+                surface,
+                shown_at,
+            } => {
+                if restart {
+                    *shown_at = Instant::now();
+                }
+
+                let (next_frame, (x, y)) =
+                    draw_theme_cursor(theme, surface, self.shape, *shown_at)?;
+                if restart {
+                    pointer.set_cursor(*serial, Some(surface), x, y);
+                }
+                next_frame
+            }
+        }
+    }
+}
+
+/// Attaches the frame of `shape` that is due now. Returns when the next
+/// frame is due and the hotspot.
+fn draw_theme_cursor(
+    theme: &mut CursorTheme,
+    surface: &WlSurface,
+    shape: CursorShape,
+    shown_at: Instant,
+) -> Option<(Option<Duration>, (i32, i32))> {
+    // Looked up by name twice, the first successful lookup can't be kept
+    // around while trying the next name.
+    let names = shape.theme_names();
+    let Some(name) = names
+        .into_iter()
+        .find(|name| theme.get_cursor(name).is_some())
+    else {
+        warn!(?shape, "cursor missing from the theme");
+        return None;
+    };
+    let cursor = theme.get_cursor(name)?;
+
+    let elapsed = shown_at.elapsed().as_millis() as u32;
+    let frame = cursor.frame_and_duration(elapsed);
+    let image = &cursor[frame.frame_index];
+
+    let (width, height) = image.dimensions();
+    let (x, y) = image.hotspot();
+    surface.attach(Some(image), 0, 0);
+    if surface.version() >= 4 {
+        surface.damage_buffer(0, 0, width as i32, height as i32);
+    } else {
+        surface.damage(0, 0, width as i32, height as i32);
+    }
+    surface.commit();
+
+    // frame_duration is how far we are into the frame
+    let next_frame = (cursor.image_count() > 1).then(|| {
+        let left = image.delay().saturating_sub(frame.frame_duration);
+        Duration::from_millis(left.max(1) as u64)
+    });
+
+    Some((next_frame, (x as i32, y as i32)))
+}
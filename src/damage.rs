@@ -0,0 +1,116 @@
+//! Collects the parts of a buffer that changed since it was last presented.
+//!
+//! Whatever draws into the window reports the rectangles it touched. The
+//! renderer then only redraws those, and passes the same rectangles to
+//! `wl_surface.damage_buffer` so the compositor only recomposites them.
+
+use crate::rect::Rect;
+
+// Past this, tracking rectangles individually costs more than it saves
+const MAX_RECTS: usize = 16;
+
+#[derive(Debug, Default)]
+pub struct Damage {
+    rects: Vec<Rect>,
+}
+
+impl Damage {
+    pub fn add(&mut self, rect: Rect) {
+        if rect.is_empty() || self.rects.iter().any(|r| r.contains_rect(&rect)) {
+            return;
+        }
+
+        // Fold in everything it overlaps, repeating since the union may now
+        // overlap rectangles that the original one didn't.
+        let mut rect = rect;
+        loop {
+            let len = self.rects.len();
+            self.rects.retain(|r| {
+                if r.intersect(&rect).is_empty() {
+                    return true;
+                }
+
+                rect = rect.union(r);
+                false
+            });
+
+            if self.rects.len() == len {
+                break;
+            }
+        }
+
+        self.rects.push(rect);
+
+        if self.rects.len() > MAX_RECTS {
+            let bounds = self.bounds();
+            self.rects.clear();
+            self.rects.push(bounds);
+        }
+    }
+
+    /// The smallest rectangle containing all of the damage.
+    pub fn bounds(&self) -> Rect {
+        self.rects
+            .iter()
+            .fold(Rect::default(), |bounds, r| bounds.union(r))
+    }
+
+    /// Returns the damaged rectangles and resets the tracker.
+    pub fn take(&mut self) -> Vec<Rect> {
+        std::mem::take(&mut self.rects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_rects_are_merged() {
+        let mut damage = Damage::default();
+        damage.add(Rect::new(0, 0, 10, 10));
+        damage.add(Rect::new(20, 0, 10, 10));
+        // Already covered
+        damage.add(Rect::new(2, 2, 4, 4));
+        damage.add(Rect::new(40, 40, 0, 10));
+        assert_eq!(damage.rects.len(), 2);
+
+        // Bridges both, so all three end up as one
+        damage.add(Rect::new(5, 5, 20, 2));
+        assert_eq!(damage.take(), [Rect::new(0, 0, 30, 10)]);
+    }
+
+    #[test]
+    fn merging_repeats_until_nothing_overlaps() {
+        let mut damage = Damage::default();
+        damage.add(Rect::new(10, 6, 4, 4));
+        damage.add(Rect::new(0, 0, 12, 4));
+        // Overlaps the second only, but their union overlaps the first
+        damage.add(Rect::new(2, 2, 4, 6));
+        assert_eq!(damage.take(), [Rect::new(0, 0, 14, 10)]);
+    }
+
+    #[test]
+    fn take_clears_the_damage() {
+        let mut damage = Damage::default();
+        damage.add(Rect::new(0, 0, 4, 4));
+        assert_eq!(damage.take().len(), 1);
+        assert!(damage.take().is_empty());
+        assert_eq!(damage.bounds(), Rect::default());
+    }
+
+    #[test]
+    fn too_many_rects_become_their_bounds() {
+        let mut damage = Damage::default();
+        for i in 0..MAX_RECTS {
+            damage.add(Rect::new(i * 10, 0, 5, 5));
+        }
+        assert_eq!(damage.rects.len(), MAX_RECTS);
+
+        damage.add(Rect::new(0, 100, 5, 5));
+        assert_eq!(
+            damage.take(),
+            [Rect::new(0, 0, (MAX_RECTS - 1) * 10 + 5, 105)]
+        );
+    }
+}
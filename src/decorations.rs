@@ -0,0 +1,190 @@
+//! Minimal client-side decorations, for compositors that won't draw them for
+//! us: a title bar with window buttons, and a thin border.
+
+use crate::{
+    color::Color,
+    pixel_buffer::PixelBuffer,
+    rect::Rect,
+    scale::Scale,
+    window::{WindowState, WmCapabilities},
+};
+
+/// Dragging the window by this many pixels at the top moves it
+pub const TITLE_BAR_HEIGHT: usize = 32;
+
+const BUTTON_SIZE: usize = 20;
+const BUTTON_SPACING: usize = 8;
+const ICON_INSET: usize = 6;
+
+const TITLE_BAR_ACTIVE: Color = Color::rgb(0x3C, 0x3C, 0x3C);
+const TITLE_BAR_INACTIVE: Color = Color::rgb(0x2A, 0x2A, 0x2A);
+const BORDER: Color = Color::rgb(0x1E, 0x1E, 0x1E);
+const CLOSE: Color = Color::rgb(0xC0, 0x39, 0x2B);
+const BUTTON: Color = Color::rgb(0x50, 0x50, 0x50);
+const ICON: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+
+/// A title bar button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Close,
+    Maximize,
+    Minimize,
+}
+
+impl Button {
+    // From the right edge
+    const ALL: [Self; 3] = [Self::Close, Self::Maximize, Self::Minimize];
+
+    /// Whether the compositor lets us do what the button does.
+    fn available(self, capabilities: WmCapabilities) -> bool {
+        match self {
+            Self::Close => true,
+            Self::Maximize => capabilities.contains(WmCapabilities::MAXIMIZE),
+            Self::Minimize => capabilities.contains(WmCapabilities::MINIMIZE),
+        }
+    }
+}
+
+/// The buttons shown in a window `width` pixels wide and where they are.
+/// Those the compositor doesn't support are left out.
+pub fn buttons(width: usize, capabilities: WmCapabilities) -> impl Iterator<Item = (Button, Rect)> {
+    Button::ALL
+        .into_iter()
+        .filter(move |button| button.available(capabilities))
+        .enumerate()
+        .map(move |(i, button)| {
+            let x = width.saturating_sub((i + 1) * (BUTTON_SIZE + BUTTON_SPACING));
+            let y = (TITLE_BAR_HEIGHT - BUTTON_SIZE) / 2;
+            (button, Rect::new(x, y, BUTTON_SIZE, BUTTON_SIZE))
+        })
+}
+
+/// The title bar button at `(x, y)` in a window `width` pixels wide, if any.
+pub fn button_at(x: f64, y: f64, width: usize, capabilities: WmCapabilities) -> Option<Button> {
+    if x < 0.0 || y < 0.0 {
+        return None;
+    }
+
+    let (x, y) = (x as usize, y as usize);
+    buttons(width, capabilities)
+        .find(|(_, rect)| {
+            (rect.x..rect.right()).contains(&x) && (rect.y..rect.bottom()).contains(&y)
+        })
+        .map(|(button, _)| button)
+}
+
+/// Draws the decorations over the top of the window, into a buffer `scale`
+/// times as large as the window.
+pub(crate) fn draw(
+    pixels: &mut PixelBuffer,
+    state: WindowState,
+    capabilities: WmCapabilities,
+    scale: Scale,
+) {
+    let bounds = pixels.bounds();
+    let (width, height) = (
+        scale.to_surface(bounds.width),
+        scale.to_surface(bounds.height),
+    );
+    let mut fill = |rect: Rect, color| pixels.fill_rect(scale.rect_to_buffer(rect), color);
+
+    let title_bar = if state.contains(WindowState::ACTIVATED) {
+        TITLE_BAR_ACTIVE
+    } else {
+        TITLE_BAR_INACTIVE
+    };
+    fill(Rect::new(0, 0, width, TITLE_BAR_HEIGHT), title_bar);
+
+    // Nothing to tell apart from the neighbours when the window fills its
+    // area
+    if state.is_floating() {
+        fill(Rect::new(0, 0, width, 1), BORDER);
+        fill(Rect::new(0, height.saturating_sub(1), width, 1), BORDER);
+        fill(Rect::new(0, 0, 1, height), BORDER);
+        fill(Rect::new(width.saturating_sub(1), 0, 1, height), BORDER);
+    }
+
+    for (button, rect) in buttons(width, capabilities) {
+        draw_button(pixels, button, scale.rect_to_buffer(rect));
+    }
+}
+
+/// Draws `button` with its icon into `rect`.
+pub(crate) fn draw_button(pixels: &mut PixelBuffer, button: Button, rect: Rect) {
+    let background = match button {
+        Button::Close => CLOSE,
+        Button::Maximize | Button::Minimize => BUTTON,
+    };
+    pixels.fill_rect(rect, background);
+
+    // Scaled along with the button
+    let inset = rect.width * ICON_INSET / BUTTON_SIZE;
+    let icon = Rect::new(
+        rect.x + inset,
+        rect.y + inset,
+        rect.width - 2 * inset,
+        rect.height - 2 * inset,
+    );
+    match button {
+        // An X, two pixels thick
+        Button::Close => {
+            for i in 0..icon.width - 1 {
+                let y = icon.y + i;
+                let (down, up) = (icon.x + i, icon.right() - 1 - i);
+                for x in [down, down + 1, up, up - 1] {
+                    pixels.put_pixel(x, y, ICON);
+                }
+            }
+        }
+        // A window outline with a thicker top
+        Button::Maximize => {
+            pixels.fill_rect(Rect::new(icon.x, icon.y, icon.width, 2), ICON);
+            pixels.fill_rect(Rect::new(icon.x, icon.bottom() - 1, icon.width, 1), ICON);
+            pixels.fill_rect(Rect::new(icon.x, icon.y, 1, icon.height), ICON);
+            pixels.fill_rect(Rect::new(icon.right() - 1, icon.y, 1, icon.height), ICON);
+        }
+        // A line at the bottom
+        Button::Minimize => {
+            pixels.fill_rect(Rect::new(icon.x, icon.bottom() - 2, icon.width, 2), ICON);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_are_hit_from_the_right() {
+        let width = 300;
+        let all = WmCapabilities::all();
+        let rects: Vec<_> = buttons(width, all).collect();
+        let [(Button::Close, close), (Button::Maximize, maximize), (Button::Minimize, _)] =
+            rects[..]
+        else {
+            panic!("unexpected buttons {rects:?}");
+        };
+        assert!(maximize.right() <= close.x);
+        assert!(close.right() <= width);
+
+        let center = |r: Rect| ((r.x + r.width / 2) as f64, (r.y + r.height / 2) as f64);
+        let (x, y) = center(close);
+        assert_eq!(button_at(x, y, width, all), Some(Button::Close));
+        let (x, y) = center(maximize);
+        assert_eq!(button_at(x, y, width, all), Some(Button::Maximize));
+        assert_eq!(button_at(10.0, 10.0, width, all), None);
+        assert_eq!(button_at(x, 100.0, width, all), None);
+    }
+
+    #[test]
+    fn unsupported_buttons_are_left_out() {
+        let capabilities = WmCapabilities::MINIMIZE;
+        let shown: Vec<_> = buttons(300, capabilities).map(|(b, _)| b).collect();
+        assert_eq!(shown, [Button::Close, Button::Minimize]);
+
+        // Minimize takes the place of maximize
+        let (_, rect) = buttons(300, WmCapabilities::all()).nth(1).unwrap();
+        let (x, y) = (rect.x as f64 + 1.0, rect.y as f64 + 1.0);
+        assert_eq!(button_at(x, y, 300, capabilities), Some(Button::Minimize));
+    }
+}
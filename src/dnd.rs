@@ -0,0 +1,358 @@
+//! Drag and drop through wl_data_device: files dropped on a window, as a
+//! `text/uri-list`, and the color of a window dragged out of it with Ctrl
+//! held, as text.
+
+use std::{
+    ffi::OsString,
+    os::{fd::AsFd, unix::ffi::OsStringExt},
+    path::PathBuf,
+};
+
+use tracing::{debug, error, info, warn};
+use wayland_client::{
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_data_device_manager::DndAction,
+        wl_data_source::{self, WlDataSource},
+        wl_surface::WlSurface,
+    },
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+
+use crate::{
+    buffers::Swapchain,
+    clipboard::{data_device, DataDevice, Offer, TEXT_MIME_TYPES},
+    color::Color,
+    pipes,
+    rect::Rect,
+    render,
+    state::{required, AppState},
+    window::WindowId,
+};
+
+/// The MIME type of dropped files, one URI per line.
+const URI_LIST: &str = "text/uri-list";
+
+/// Something being dragged over one of our windows.
+pub struct Drag {
+    pub(crate) offer: Offer,
+    // The window it is over, the one it is dropped on
+    window: Option<WindowId>,
+    // Whether we told the other client we take it
+    accepted: bool,
+}
+
+/// User data of the data source of a drag we started, tells it from the
+/// one on the clipboard.
+pub struct DragRole;
+
+/// A drag we started, until it is dropped or cancelled.
+pub struct DragSource {
+    source: WlDataSource,
+    // Follows the pointer around
+    icon: WlSurface,
+    icon_buffers: Swapchain,
+    text: String,
+}
+
+impl DragSource {
+    pub(crate) fn destroy(self) {
+        self.source.destroy();
+        self.icon.destroy();
+    }
+}
+
+const ICON_SIZE: usize = 32;
+const ICON_BORDER: Color = Color::rgb(0x10, 0x10, 0x10);
+
+/// Starts dragging the color of the window `id` out of it, as a hex
+/// string. `serial` is that of the button press that started it.
+pub(crate) fn start_drag(state: &mut AppState, id: WindowId, serial: u32) {
+    let Some(window) = state.window(id) else {
+        return;
+    };
+    let time = window.animation_time.as_millis() as u32;
+    let color = state.scene.background(window.highlighted, time);
+    let origin = window.surface().clone();
+
+    let qh = &state.queue_handle;
+    let (Ok(compositor), Ok(shm), Some(manager)) = (
+        required(&state.compositor),
+        required(&state.shm),
+        &state.data_device_manager,
+    ) else {
+        return;
+    };
+    let Some(device) = state.seat.as_mut().and_then(|s| s.data_device.as_mut()) else {
+        debug!("the compositor doesn't support drag and drop");
+        return;
+    };
+
+    let source = manager.create_data_source(qh, DragRole);
+    for mime_type in TEXT_MIME_TYPES {
+        source.offer(mime_type.to_owned());
+    }
+    // Version 3 asks what dropping it may do
+    if source.version() >= 3 {
+        source.set_actions(DndAction::Copy);
+    }
+    let icon = compositor.create_surface(qh, ());
+    device.start_drag(Some(&source), &origin, Some(&icon), serial);
+
+    // Only drawn once it has its role
+    let mut icon_buffers = Swapchain::new(state.format);
+    let drawn = render::present_whole(
+        &icon,
+        &mut icon_buffers,
+        (ICON_SIZE, ICON_SIZE),
+        shm,
+        qh,
+        |pixels| {
+            pixels.fill(ICON_BORDER);
+            pixels.fill_rect(Rect::new(2, 2, ICON_SIZE - 4, ICON_SIZE - 4), color);
+        },
+    );
+    if let Err(err) = drawn {
+        error!(%err, "failed to draw the drag icon");
+    }
+
+    let text = color.to_hex();
+    info!(%text, "dragging");
+    let old = device.drag_source.replace(DragSource {
+        source,
+        icon,
+        icon_buffers,
+        text,
+    });
+    // Older compositors never tell when a drop is done with
+    if let Some(old) = old {
+        old.destroy();
+    }
+}
+
+/// Hands `buffer` back to the drag icon, if it is one of its buffers.
+pub(crate) fn buffer_released(state: &mut AppState, buffer: &WlBuffer) {
+    if let Some(drag) = data_device(state).and_then(|d| d.drag_source.as_mut()) {
+        drag.icon_buffers.release(buffer);
+    }
+}
+
+/// The drag we started is over, one way or the other.
+fn end_drag(state: &mut AppState, source: &WlDataSource) {
+    let Some(device) = data_device(state) else {
+        source.destroy();
+        return;
+    };
+    match device.drag_source.take_if(|d| &d.source == source) {
+        Some(drag) => drag.destroy(),
+        None => source.destroy(),
+    }
+}
+
+/// Something is dragged into `surface`. Takes it if it is files.
+pub(crate) fn enter(state: &mut AppState, serial: u32, surface: &WlSurface, offer: Option<Offer>) {
+    let window = state.window_for_surface(surface);
+    let Some(device) = data_device(state) else {
+        return;
+    };
+    leave(device);
+    // A drag from within our own windows, with nothing to drop
+    let Some(offer) = offer else {
+        return;
+    };
+
+    let accepted = offer.mime_types.iter().any(|m| m == URI_LIST);
+    debug!(mime_types = ?offer.mime_types, accepted, "drag entered");
+    offer
+        .offer
+        .accept(serial, accepted.then(|| URI_LIST.to_owned()));
+    // Version 3 asks which actions we support, the compositor picks one of
+    // them along with the other client
+    if offer.offer.version() >= 3 {
+        let actions = if accepted {
+            DndAction::Copy
+        } else {
+            DndAction::empty()
+        };
+        offer.offer.set_actions(actions, actions);
+    }
+
+    device.drag = Some(Drag {
+        offer,
+        window,
+        accepted,
+    });
+}
+
+/// The drag left our windows, or was cancelled.
+pub(crate) fn leave(device: &mut DataDevice) {
+    if let Some(drag) = device.drag.take() {
+        drag.offer.destroy();
+    }
+}
+
+/// The drag was dropped. Reads the dropped URIs, logs them and shows the
+/// file names as the title of the window they were dropped on.
+pub(crate) fn dropped(state: &mut AppState) {
+    let Some(drag) = data_device(state).and_then(|d| d.drag.take()) else {
+        return;
+    };
+    if !drag.accepted {
+        drag.offer.destroy();
+        return;
+    }
+
+    let (read, write) = match pipes::pipe() {
+        Ok(pipe) => pipe,
+        Err(err) => {
+            warn!(%err, "failed to create a pipe to read the drop through");
+            drag.offer.destroy();
+            return;
+        }
+    };
+    drag.offer.offer.receive(URI_LIST.to_owned(), write.as_fd());
+    // Ours has to be closed too for the pipe to close once the other client
+    // is done writing
+    drop(write);
+
+    let Drag { offer, window, .. } = drag;
+    let res = state.pipes.read_to_end(read, move |state, res| {
+        match res {
+            Ok(data) => {
+                let text = String::from_utf8_lossy(&data);
+                let uris = parse_uri_list(&text);
+                for uri in &uris {
+                    info!(uri, path = ?file_path(uri), "dropped");
+                }
+
+                // There is no image decoding to show them with yet
+                let names: Vec<_> = uris
+                    .iter()
+                    .filter_map(|uri| file_path(uri)?.file_name()?.to_str().map(str::to_owned))
+                    .collect();
+                if let Some(window) = window.and_then(|id| state.window(id)) {
+                    window.set_title(names.join(", "));
+                }
+            }
+            Err(err) => warn!(%err, "failed to read the drop"),
+        }
+        finish(offer);
+    });
+    if let Err(err) = res {
+        warn!(%err, "failed to read the drop");
+    }
+}
+
+/// Tells the other client we are done with the drop, so that it may e.g.
+/// delete moved files.
+fn finish(offer: Offer) {
+    // Only allowed since version 3, and if an action was picked
+    if offer.offer.version() >= 3 && offer.action.is_some_and(|a| !a.is_empty()) {
+        offer.offer.finish();
+    }
+    offer.destroy();
+}
+
+impl Dispatch<WlDataSource, DragRole> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlDataSource,
+        event: <WlDataSource as Proxy>::Event,
+        _data: &DragRole,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            // The window it was dropped on reads it
+            wl_data_source::Event::Send { mime_type, fd } => {
+                let Some(text) = data_device(state)
+                    .and_then(|d| d.drag_source.as_ref())
+                    .filter(|d| &d.source == proxy)
+                    .map(|d| d.text.clone())
+                else {
+                    return;
+                };
+                debug!(%mime_type, "sending the drop");
+                if let Err(err) = state.pipes.write_all(fd, text.into_bytes()) {
+                    warn!(%err, "failed to send the drop");
+                }
+            }
+            // Whether what is under the pointer takes it
+            wl_data_source::Event::Target { mime_type } => debug!(?mime_type, "drag target"),
+            wl_data_source::Event::Action {
+                dnd_action: WEnum::Value(action),
+            } => debug!(?action, "drag action"),
+            wl_data_source::Event::DndDropPerformed => info!("dropped"),
+            // Dropped and read, or dropped nowhere or on something that
+            // doesn't take it
+            wl_data_source::Event::DndFinished | wl_data_source::Event::Cancelled => {
+                end_drag(state, proxy);
+            }
+            event => debug!(?event, "ignoring unknown drag source event"),
+        }
+    }
+}
+
+/// The URIs in a `text/uri-list`, without the comments.
+pub fn parse_uri_list(text: &str) -> Vec<&str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+/// The local path a `file://` URI points at, percent-decoded.
+pub fn file_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    // Only local files, which have an empty host or localhost
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .filter(|_| byte == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_uris_become_paths() {
+        let list = "# from a file manager\r\nfile:///home/me/My%20Photo.png\r\n\r\nhttps://example.com/\r\n";
+        let uris = parse_uri_list(list);
+        assert_eq!(
+            uris,
+            ["file:///home/me/My%20Photo.png", "https://example.com/"]
+        );
+
+        assert_eq!(
+            file_path(uris[0]),
+            Some(PathBuf::from("/home/me/My Photo.png"))
+        );
+        assert_eq!(
+            file_path("file://localhost/tmp/a"),
+            Some(PathBuf::from("/tmp/a"))
+        );
+        assert_eq!(file_path("file://remote/tmp/a"), None);
+        assert_eq!(file_path(uris[1]), None);
+    }
+}
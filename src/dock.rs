@@ -0,0 +1,615 @@
+//! A dock: the icons of applications along the bottom of the output, taken
+//! from their .desktop files. The icons near the pointer grow, and a click
+//! launches the application with an activation token so that it gets the
+//! focus.
+
+use std::{
+    env, fs, mem,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+    thread,
+};
+
+use tracing::{debug, info, warn};
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::Layer,
+    zwlr_layer_surface_v1::{Anchor, KeyboardInteractivity},
+};
+
+use crate::{
+    activation::{self, TOKEN_ENV},
+    animation::Animation,
+    color::{Color, ColorSpace, PremulColor},
+    layer::LayerOptions,
+    pixel_buffer::PixelBuffer,
+    rect::Rect,
+    render::present_if_needed,
+    scale::Scale,
+    scene::Scene,
+    seat::BTN_LEFT,
+    state::AppState,
+    svg::SvgImage,
+    text::{self, Font},
+    window::WindowId,
+};
+
+const ICON_SIZE: usize = 48;
+const PADDING: usize = 8;
+// The icon under the pointer is this much larger, those around it less so
+const MAGNIFICATION: f64 = 1.75;
+// How far from the pointer icons still grow
+const MAGNIFIED_RANGE: f64 = 2.5 * (ICON_SIZE + PADDING) as f64;
+// Without magnification, what the other windows are kept clear of
+const BAR_HEIGHT: usize = ICON_SIZE + 2 * PADDING;
+// With room above the bar for the icons to grow into
+const HEIGHT: usize = (ICON_SIZE as f64 * MAGNIFICATION) as usize + 2 * PADDING;
+// The icons are drawn from images this big, the largest they get
+const ICON_IMAGE_SIZE: usize = 128;
+// With no applications asked for, only this many of them
+const MAX_APPS: usize = 12;
+
+const SHELF: Color = Color::rgba(0x20, 0x20, 0x20, 0xC0);
+const NO_ICON: Color = Color::rgb(0x50, 0x50, 0x50);
+const TEXT: Color = Color::rgb(0xE0, 0xE0, 0xE0);
+
+// The sizes of the hicolor theme worth looking in, largest first
+const ICON_SIZES: [&str; 6] = ["scalable", "256x256", "128x128", "96x96", "64x64", "48x48"];
+
+/// A strip along the bottom of the output, under the windows' way, which
+/// never takes the keyboard.
+pub fn layer_options() -> LayerOptions {
+    LayerOptions {
+        layer: Layer::Top,
+        anchor: Anchor::Bottom | Anchor::Left | Anchor::Right,
+        exclusive_zone: BAR_HEIGHT as i32,
+        keyboard_interactivity: KeyboardInteractivity::None,
+        size: (0, HEIGHT as u32),
+    }
+}
+
+/// What a .desktop file says about an application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DesktopEntry {
+    name: String,
+    exec: String,
+    icon: Option<String>,
+}
+
+/// Parses the `[Desktop Entry]` group of a .desktop file. `None` for what
+/// isn't an application to show, e.g. links and hidden entries.
+fn parse_desktop_entry(contents: &str) -> Option<DesktopEntry> {
+    let mut in_entry = false;
+    let (mut name, mut exec, mut icon) = (None, None, None);
+    let mut application = false;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=').filter(|_| in_entry) else {
+            continue;
+        };
+        // Only the untranslated keys, which have no [locale]
+        match (key.trim(), value.trim()) {
+            ("Type", kind) => application = kind == "Application",
+            ("Name", value) => name = Some(value.to_owned()),
+            ("Exec", value) => exec = Some(value.to_owned()),
+            ("Icon", value) if !value.is_empty() => icon = Some(value.to_owned()),
+            ("NoDisplay" | "Hidden", "true") => return None,
+            _ => {}
+        }
+    }
+
+    Some(DesktopEntry {
+        name: name?,
+        exec: exec?,
+        icon,
+    })
+    .filter(|_| application)
+}
+
+/// Splits the `Exec` key of a .desktop file into arguments. The field codes
+/// for files and URLs are left out since there are none to pass, and so
+/// are the deprecated ones.
+fn exec_args(exec: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    // Whether there is an argument, even an empty quoted one
+    let mut started = false;
+    let mut quoted = false;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            }
+            '\\' if quoted => arg.extend(chars.next()),
+            ' ' | '\t' if !quoted => {
+                if started {
+                    args.push(mem::take(&mut arg));
+                    started = false;
+                }
+            }
+            // Anything but %% is a field code, expanded to nothing
+            '%' => {
+                if chars.next() == Some('%') {
+                    arg.push('%');
+                    started = true;
+                }
+            }
+            c => {
+                arg.push(c);
+                started = true;
+            }
+        }
+    }
+    if started {
+        args.push(arg);
+    }
+    args
+}
+
+/// Where applications and icons are looked for, in order of preference:
+/// `XDG_DATA_HOME` and then `XDG_DATA_DIRS`, with their defaults.
+fn data_dirs() -> Vec<PathBuf> {
+    let home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    let dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_owned());
+    home.into_iter()
+        .chain(dirs.split(':').map(PathBuf::from))
+        .collect()
+}
+
+/// The .desktop files of the applications `ids`, e.g. `firefox` for
+/// firefox.desktop, or of every application if there are none.
+fn desktop_files(ids: &[String]) -> Vec<PathBuf> {
+    let dirs: Vec<_> = data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("applications"))
+        .collect();
+    if !ids.is_empty() {
+        return ids
+            .iter()
+            .filter_map(|id| {
+                let file = dirs
+                    .iter()
+                    .map(|dir| dir.join(format!("{id}.desktop")))
+                    .find(|file| file.exists());
+                if file.is_none() {
+                    warn!(id, "no such application");
+                }
+                file
+            })
+            .collect();
+    }
+
+    // The first directory with a file of the name wins
+    let mut files: Vec<PathBuf> = Vec::new();
+    for dir in &dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let taken = files
+                .iter()
+                .any(|file| file.file_name() == path.file_name());
+            if path.extension().is_some_and(|e| e == "desktop") && !taken {
+                files.push(path);
+            }
+        }
+    }
+    files
+}
+
+/// Looks `icon` up in the hicolor theme and the pixmaps, unless it is a
+/// path already.
+fn find_icon(icon: &str) -> Option<PathBuf> {
+    let path = Path::new(icon);
+    if path.is_absolute() {
+        return Some(path.to_owned()).filter(|path| path.exists());
+    }
+
+    let dirs = data_dirs();
+    let themed = ICON_SIZES.iter().flat_map(|size| {
+        let extension = if *size == "scalable" { "svg" } else { "png" };
+        dirs.iter()
+            .map(move |dir| dir.join(format!("icons/hicolor/{size}/apps/{icon}.{extension}")))
+    });
+    let pixmaps = ["png", "svg"]
+        .into_iter()
+        .map(|extension| PathBuf::from(format!("/usr/share/pixmaps/{icon}.{extension}")));
+    themed.chain(pixmaps).find(|path| path.exists())
+}
+
+/// Square pixels to draw an icon from, at any size.
+#[derive(Debug)]
+struct Icon {
+    size: usize,
+    pixels: Vec<PremulColor>,
+}
+
+impl Icon {
+    /// Loads and rasterizes the SVG or PNG at `path`.
+    fn load(path: &Path) -> Option<Self> {
+        let size = ICON_IMAGE_SIZE;
+        let loaded = match path.extension().and_then(|e| e.to_str()) {
+            Some("svg") => SvgImage::open(path).map(|image| image.rasterize(size, size)),
+            _ => Animation::open(path).map(|image| {
+                let ((width, height), pixels) = image.frame();
+                Self::square(width, height, pixels)
+            }),
+        };
+        match loaded {
+            Ok(pixels) => Some(Self { size, pixels }),
+            Err(err) => {
+                warn!(%err, "failed to load an icon");
+                None
+            }
+        }
+    }
+
+    /// `width`x`height` `pixels` scaled to fit in a square of
+    /// [`ICON_IMAGE_SIZE`].
+    fn square(width: usize, height: usize, pixels: &[PremulColor]) -> Vec<PremulColor> {
+        let size = ICON_IMAGE_SIZE;
+        let scale = width.max(height) as f32 / size as f32;
+        let (dx, dy) = (
+            (width as f32 - size as f32 * scale) / 2.0,
+            (height as f32 - size as f32 * scale) / 2.0,
+        );
+        let mut square = Vec::with_capacity(size * size);
+        for y in 0..size {
+            for x in 0..size {
+                let source_x = dx + (x as f32 + 0.5) * scale;
+                let source_y = dy + (y as f32 + 0.5) * scale;
+                let inside = (0.0..width as f32).contains(&source_x)
+                    && (0.0..height as f32).contains(&source_y);
+                square.push(if inside {
+                    pixels[source_y as usize * width + source_x as usize]
+                } else {
+                    PremulColor::default()
+                });
+            }
+        }
+        square
+    }
+
+    /// The color at `u`, `v` from 0 to 1, between the pixels around it.
+    fn sample(&self, u: f32, v: f32) -> PremulColor {
+        let max = (self.size - 1) as f32;
+        let (x, y) = (
+            (u * self.size as f32 - 0.5).clamp(0.0, max),
+            (v * self.size as f32 - 0.5).clamp(0.0, max),
+        );
+        let (x0, y0) = (x as usize, y as usize);
+        let (x1, y1) = ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+        let (fx, fy) = (x.fract(), y.fract());
+
+        let pixel = |x: usize, y: usize| self.pixels[y * self.size + x];
+        let mix = |a: u8, b: u8, c: u8, d: u8| {
+            let top = f32::from(a) * (1.0 - fx) + f32::from(b) * fx;
+            let bottom = f32::from(c) * (1.0 - fx) + f32::from(d) * fx;
+            (top * (1.0 - fy) + bottom * fy).round() as u8
+        };
+        let (a, b, c, d) = (pixel(x0, y0), pixel(x1, y0), pixel(x0, y1), pixel(x1, y1));
+        PremulColor {
+            r: mix(a.r, b.r, c.r, d.r),
+            g: mix(a.g, b.g, c.g, d.g),
+            b: mix(a.b, b.b, c.b, d.b),
+            a: mix(a.a, b.a, c.a, d.a),
+        }
+    }
+
+    fn draw(&self, pixels: &mut PixelBuffer, rect: Rect) {
+        let rect = rect.intersect(&pixels.bounds());
+        for y in rect.y..rect.bottom() {
+            let v = (y - rect.y) as f32 / rect.height as f32;
+            for x in rect.x..rect.right() {
+                let u = (x - rect.x) as f32 / rect.width as f32;
+                let color = self.sample(u, v);
+                if color.a != 0 {
+                    pixels.blend_pixel(x, y, color.unpremultiply(), ColorSpace::Srgb);
+                }
+            }
+        }
+    }
+}
+
+/// An application in the dock.
+#[derive(Debug)]
+struct DockApp {
+    name: String,
+    args: Vec<String>,
+    icon: Option<Icon>,
+}
+
+/// The applications in the dock, and where the pointer is over it.
+#[derive(Clone)]
+pub struct Dock {
+    apps: Arc<[DockApp]>,
+    // Across the dock, in window pixels, None while the pointer is elsewhere
+    pointer: Option<f64>,
+    // To write the initials of the applications without an icon
+    font: Option<Arc<Font>>,
+}
+
+impl Dock {
+    /// A dock of the applications `ids`, the names of their .desktop files,
+    /// or of the first applications there are if none are given.
+    pub fn new(ids: &[String]) -> Self {
+        let mut entries: Vec<_> = desktop_files(ids)
+            .iter()
+            .filter_map(|file| {
+                let entry = parse_desktop_entry(&fs::read_to_string(file).ok()?);
+                if entry.is_none() {
+                    debug!(file = %file.display(), "not an application to show");
+                }
+                entry
+            })
+            .collect();
+        if ids.is_empty() {
+            // Those that show up as blank squares are likely no one's pick
+            entries.retain(|entry| entry.icon.is_some());
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            entries.truncate(MAX_APPS);
+        }
+
+        let apps: Vec<_> = entries
+            .into_iter()
+            .map(|entry| DockApp {
+                args: exec_args(&entry.exec),
+                icon: entry
+                    .icon
+                    .as_deref()
+                    .and_then(find_icon)
+                    .and_then(|path| Icon::load(&path)),
+                name: entry.name,
+            })
+            .collect();
+        info!(apps = apps.len(), "loaded the applications of the dock");
+
+        let font = Font::find(text::SANS)
+            .inspect_err(
+                |err| warn!(%err, "failed to load a font, applications without an icon stay blank"),
+            )
+            .ok()
+            .map(Arc::new);
+        Self {
+            apps: apps.into(),
+            pointer: None,
+            font,
+        }
+    }
+
+    /// Draws the shelf and the icons on it into `pixels`, laid out in
+    /// window pixels.
+    pub(crate) fn draw(&self, pixels: &mut PixelBuffer, scale: Scale) {
+        pixels.fill(Color::rgba(0x00, 0x00, 0x00, 0x00));
+        let bounds = pixels.bounds();
+        let size = (
+            scale.to_surface(bounds.width),
+            scale.to_surface(bounds.height),
+        );
+        let icons = layout(self.apps.len(), size, self.pointer);
+        let (Some(first), Some(last)) = (icons.first(), icons.last()) else {
+            return;
+        };
+
+        let shelf = Rect::new(
+            first.x.saturating_sub(PADDING),
+            size.1.saturating_sub(BAR_HEIGHT),
+            last.right() + PADDING - first.x.saturating_sub(PADDING),
+            BAR_HEIGHT,
+        );
+        pixels.blend_rect(scale.rect_to_buffer(shelf), SHELF, ColorSpace::Srgb);
+
+        for (app, rect) in self.apps.iter().zip(icons) {
+            let rect = scale.rect_to_buffer(rect);
+            if let Some(icon) = &app.icon {
+                icon.draw(pixels, rect);
+                continue;
+            }
+            pixels.fill_rect(rect, NO_ICON);
+            let (Some(font), Some(initial)) = (&self.font, app.name.chars().next()) else {
+                continue;
+            };
+            let initial = initial.to_uppercase().to_string();
+            let font_size = rect.height as f32 / 2.0;
+            let x = rect.x + rect.width.saturating_sub(font.width(&initial, font_size)) / 2;
+            let y = rect.y + rect.height.saturating_sub(font.line_height(font_size)) / 2;
+            font.draw(pixels, &initial, (x, y), font_size, TEXT);
+        }
+    }
+}
+
+/// How much an icon whose middle is at `center` grows with the pointer at
+/// `pointer`.
+fn magnification(center: f64, pointer: Option<f64>) -> f64 {
+    let Some(pointer) = pointer else {
+        return 1.0;
+    };
+    let closeness = (1.0 - (pointer - center).abs() / MAGNIFIED_RANGE).max(0.0);
+    1.0 + (MAGNIFICATION - 1.0) * closeness
+}
+
+/// Where the icons of `count` applications go in a dock of `width`x`height`
+/// window pixels: in a row in the middle of the bottom edge, growing with
+/// the pointer at `pointer` across the dock.
+fn layout(count: usize, (width, height): (usize, usize), pointer: Option<f64>) -> Vec<Rect> {
+    let slot = ICON_SIZE + PADDING;
+    let row = (count * slot).saturating_sub(PADDING);
+    // Where the icons would be without magnification, which is what the
+    // distance to the pointer is measured from so that they don't slide
+    // away from under it
+    let left = width.saturating_sub(row) as f64 / 2.0;
+    let sizes: Vec<usize> = (0..count)
+        .map(|i| {
+            let center = left + (i * slot) as f64 + ICON_SIZE as f64 / 2.0;
+            (ICON_SIZE as f64 * magnification(center, pointer)).round() as usize
+        })
+        .collect();
+
+    let grown_row = sizes.iter().sum::<usize>() + PADDING * count.saturating_sub(1);
+    let mut x = width.saturating_sub(grown_row) / 2;
+    sizes
+        .into_iter()
+        .map(|size| {
+            let rect = Rect::new(x, height.saturating_sub(PADDING + size), size, size);
+            x += size + PADDING;
+            rect
+        })
+        .collect()
+}
+
+/// Starts `app`, with `token` to hand it the focus.
+fn launch(app: &DockApp, token: Option<String>) {
+    let Some((program, args)) = app.args.split_first() else {
+        warn!(name = app.name, "the application has nothing to run");
+        return;
+    };
+    let mut command = Command::new(program);
+    command.args(args);
+    if let Some(token) = token {
+        // The older name of the same, for X11 and startup-notification
+        command.env("DESKTOP_STARTUP_ID", &token);
+        command.env(TOKEN_ENV, token);
+    }
+    match command.spawn() {
+        // Reaped in the background so that it doesn't linger as a zombie
+        Ok(mut child) => {
+            info!(name = app.name, pid = child.id(), "launched");
+            thread::spawn(move || child.wait());
+        }
+        Err(err) => warn!(name = app.name, %err, "failed to launch"),
+    }
+}
+
+/// Grows the icons around the pointer, at `position` over the window `id`
+/// or gone if `None`.
+pub(crate) fn pointer_moved(
+    state: &mut AppState,
+    id: Option<WindowId>,
+    position: Option<(f64, f64)>,
+) {
+    let (Scene::Dock(dock), Some(window)) = (
+        &mut state.scene,
+        id.and_then(|id| state.windows.iter_mut().find(|w| w.id() == id)),
+    ) else {
+        return;
+    };
+
+    let pointer = position.map(|(x, _)| x);
+    if dock.pointer != pointer {
+        dock.pointer = pointer;
+        window.request_redraw();
+        present_if_needed(state);
+    }
+}
+
+/// Launches the application whose icon is clicked with the left button in
+/// the window `id`. Returns whether the button was the left one, in the
+/// dock.
+pub(crate) fn pointer_button(
+    state: &mut AppState,
+    id: Option<WindowId>,
+    position: Option<(f64, f64)>,
+    button: u32,
+    pressed: bool,
+) -> bool {
+    let (Scene::Dock(dock), Some(window)) = (&state.scene, id.and_then(|id| state.window(id)))
+    else {
+        return false;
+    };
+    if button != BTN_LEFT {
+        return false;
+    }
+    let Some((x, y)) = position.filter(|_| pressed) else {
+        return true;
+    };
+
+    let icons = layout(dock.apps.len(), window.size(), dock.pointer);
+    let Some(i) = icons.iter().position(|rect| {
+        (rect.x as f64..rect.right() as f64).contains(&x)
+            && (rect.y as f64..rect.bottom() as f64).contains(&y)
+    }) else {
+        return true;
+    };
+
+    // Handed over along with the token, which is up to the compositor
+    let apps = Arc::clone(&dock.apps);
+    if state.activation.is_none() {
+        launch(&apps[i], None);
+        return true;
+    }
+    activation::request_token(state, move |_, token| launch(&apps[i], Some(token)));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn desktop_entries_are_parsed() {
+        let contents = "\
+[Desktop Entry]
+Type=Application
+Name=Terminal
+Name[de]=Konsole
+Exec=foot --title \"My Term\" %U
+Icon=foot
+
+[Desktop Action new-window]
+Name=New Window
+Exec=foot --new
+";
+        let entry = parse_desktop_entry(contents).unwrap();
+        assert_eq!(entry.name, "Terminal");
+        assert_eq!(entry.icon.as_deref(), Some("foot"));
+        assert_eq!(exec_args(&entry.exec), ["foot", "--title", "My Term"]);
+
+        let hidden = format!("{contents}\n[Desktop Entry]\nNoDisplay=true");
+        assert_eq!(parse_desktop_entry(&hidden), None);
+        let link = contents.replace("Type=Application", "Type=Link");
+        assert_eq!(parse_desktop_entry(&link), None);
+
+        assert_eq!(
+            exec_args("sh -c \"echo 100%%\" %f"),
+            ["sh", "-c", "echo 100%"]
+        );
+        assert_eq!(exec_args("run \"\" \"a\\\"b\""), ["run", "", "a\"b"]);
+    }
+
+    #[test]
+    fn icons_grow_around_the_pointer() {
+        let size = (1000, HEIGHT);
+        let still = layout(3, size, None);
+        let row = 3 * ICON_SIZE + 2 * PADDING;
+        assert_eq!(
+            still[0],
+            Rect::new((1000 - row) / 2, HEIGHT - PADDING - ICON_SIZE, 48, 48)
+        );
+        assert!(still.iter().all(|rect| rect.width == ICON_SIZE));
+
+        // Right over the middle one
+        let grown = layout(3, size, Some(500.0));
+        let largest = (ICON_SIZE as f64 * MAGNIFICATION).round() as usize;
+        assert_eq!(grown[1].width, largest);
+        assert_eq!(grown[1].bottom(), HEIGHT - PADDING);
+        assert!(grown[0].width > ICON_SIZE && grown[0].width < largest);
+        assert_eq!(grown[0].width, grown[2].width);
+        // Still in the middle, and not overlapping
+        assert_eq!(grown[1].x + grown[1].width / 2, 500);
+        assert_eq!(grown[0].right() + PADDING, grown[1].x);
+
+        // Too far away to make a difference
+        let far = layout(3, size, Some(0.0));
+        assert_eq!(far, still);
+    }
+}
@@ -0,0 +1,64 @@
+//! What can go wrong talking to the compositor.
+
+use thiserror::Error;
+use wayland_client::{
+    backend::WaylandError, protocol::wl_shm::Format, ConnectError, DispatchError,
+};
+
+use crate::{
+    pam::PamError,
+    registry::{BindError, MissingGlobals},
+    shm::ShmError,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to connect to the compositor: {0}")]
+    Connect(#[from] ConnectError),
+    #[error(transparent)]
+    MissingGlobal(#[from] MissingGlobals),
+    #[error(transparent)]
+    ProtocolVersion(#[from] BindError),
+    #[error("the compositor supports none of our pixel formats, only {0:?}")]
+    UnsupportedFormats(Vec<Format>),
+    #[error("failed to allocate a buffer: {0}")]
+    ShmAllocation(#[from] ShmError),
+    /// The connection is broken, or the compositor sent something we can't
+    /// make sense of.
+    #[error(transparent)]
+    Dispatch(#[from] DispatchError),
+    /// Window options that contradict each other.
+    #[error("invalid window options: {0}")]
+    InvalidOptions(String),
+    #[error("failed to set up the main loop: {0}")]
+    EventLoop(#[from] calloop::Error),
+    /// Passwords can't be checked, so the session isn't locked.
+    #[error("refusing to lock the session: {0}")]
+    Pam(#[from] PamError),
+    #[cfg(feature = "tokio")]
+    #[error("failed to run the async runtime: {0}")]
+    Runtime(#[source] std::io::Error),
+}
+
+impl Error {
+    /// A global that should have been bound at startup isn't.
+    pub(crate) fn missing(interface: &'static str) -> Self {
+        Self::MissingGlobal(MissingGlobals(vec![interface]))
+    }
+
+    /// Returns the underlying Wayland error if this means the connection to
+    /// the compositor is gone. Both I/O errors (EPIPE, ECONNRESET, EOF) and
+    /// protocol errors are fatal for the connection.
+    pub fn connection_error(&self) -> Option<&WaylandError> {
+        match self {
+            Self::Dispatch(DispatchError::Backend(err)) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<WaylandError> for Error {
+    fn from(err: WaylandError) -> Self {
+        Self::Dispatch(DispatchError::Backend(err))
+    }
+}
@@ -0,0 +1,344 @@
+//! Connects to the compositor and runs until the window is closed.
+
+use std::{io, os::fd::OwnedFd, process::ExitCode, time::Duration};
+
+use calloop::{
+    generic::Generic,
+    signals::{Signal, Signals},
+    EventLoop, Interest, LoopHandle, Mode, PostAction, RegistrationToken,
+};
+use calloop_wayland_source::WaylandSource;
+use tracing::{debug, error, info, warn};
+use wayland_client::{backend::WaylandError, Connection, EventQueue};
+
+use crate::{
+    animation,
+    cursor::Cursor,
+    error::Error,
+    ipc, lock, monitor,
+    pipes::Pipes,
+    pixel_format::PixelFormat,
+    pomodoro,
+    registry::{self, GlobalManager},
+    render,
+    render_thread::RenderThread,
+    scene::Scene,
+    snake,
+    state::{required, AppState},
+    systemd,
+    timer::Timers,
+    watchdog::{self, Phase},
+    window::{self, resize_border, WindowOptions},
+};
+
+/// Sends out our requests, see [`insert_wayland`].
+pub(crate) struct Flusher<S: 'static> {
+    conn: Connection,
+    handle: LoopHandle<'static, S>,
+    // Waits for the socket to have room again, only enabled while requests
+    // are waiting for it
+    writable: RegistrationToken,
+}
+
+impl<S: 'static> Flusher<S> {
+    pub(crate) fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Sends out our requests, to be called before the main loop waits.
+    /// Those that don't fit in the socket buffer, e.g. during a resize
+    /// storm, are sent as soon as it has room again.
+    pub(crate) fn flush(&self) -> Result<(), Error> {
+        match self.conn.flush() {
+            Err(WaylandError::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => {
+                debug!("socket buffer is full, delaying flush");
+                Ok(self.handle.enable(&self.writable)?)
+            }
+            res => Ok(res?),
+        }
+    }
+
+    /// Logs `err`, which stopped the main loop. The Wayland source only
+    /// hands on the I/O error, so what the connection itself ran into is
+    /// asked of it to tell a lost connection apart from the loop failing.
+    pub(crate) fn report(&self, err: Error) {
+        let err = self.conn.backend().last_error().map_or(err, Error::from);
+        match err.connection_error() {
+            Some(err) => error!(%err, "lost the connection to the compositor, exiting"),
+            None => error!(%err, "the main loop failed, exiting"),
+        }
+    }
+}
+
+/// Dispatches the events of `event_queue` from the main loop as they
+/// arrive. Returns what sends out our requests.
+pub(crate) fn insert_wayland<S: 'static>(
+    handle: &LoopHandle<'static, S>,
+    conn: Connection,
+    event_queue: EventQueue<S>,
+) -> Result<Flusher<S>, Error> {
+    let fd: OwnedFd = conn
+        .backend()
+        .poll_fd()
+        .try_clone_to_owned()
+        .map_err(calloop::Error::IoError)?;
+    // The source flushes before waiting too, but gives up on a full
+    // socket until something else wakes the loop up
+    let source = WaylandSource::new(conn.clone(), event_queue);
+    handle
+        .insert_source(source, |_, queue, state| {
+            watchdog::busy();
+            queue.dispatch_pending(state)
+        })
+        .map_err(|err| err.error)?;
+
+    let writer = conn.clone();
+    let writable = Generic::new(fd, Interest::WRITE, Mode::Level);
+    let writable = handle
+        .insert_source(writable, move |_, _, _| match writer.flush() {
+            Err(WaylandError::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => {
+                Ok(PostAction::Continue)
+            }
+            // Reading from the connection reports it if it is broken
+            res => {
+                if let Err(err) = res {
+                    debug!(%err, "failed to flush");
+                }
+                Ok(PostAction::Disable)
+            }
+        })
+        .map_err(|err| err.error)?;
+    handle.disable(&writable)?;
+
+    Ok(Flusher {
+        conn,
+        handle: handle.clone(),
+        writable,
+    })
+}
+
+/// Calls `stop` on SIGINT and SIGTERM instead of getting killed, so that we
+/// get to clean up. Has to be called before spawning any thread, or the
+/// signals may go to that one.
+pub(crate) fn insert_signals<S: 'static>(
+    handle: &LoopHandle<'static, S>,
+    stop: fn(&mut S),
+) -> Result<(), Error> {
+    let signals = Signals::new(&[Signal::SIGINT, Signal::SIGTERM])?;
+    handle
+        .insert_source(signals, move |event, _, state| {
+            info!(signal = ?event.signal(), "exiting");
+            stop(state);
+        })
+        .map_err(|err| err.error)?;
+    Ok(())
+}
+
+/// Connects to the compositor and opens a window showing `scene`, set up
+/// with `options`. Returns the main loop to run, with the connection in it,
+/// and what sends out our requests.
+pub(crate) fn start(
+    scene: Scene,
+    options: WindowOptions,
+) -> Result<(AppState, EventLoop<'static, AppState>, Flusher<AppState>), Error> {
+    start_on(Connection::connect_to_env()?, scene, options)
+}
+
+/// Like [`start`], on a connection made already.
+pub(crate) fn start_on(
+    conn: Connection,
+    scene: Scene,
+    options: WindowOptions,
+) -> Result<(AppState, EventLoop<'static, AppState>, Flusher<AppState>), Error> {
+    let mut event_queue = conn.new_event_queue::<AppState>();
+    let qh = event_queue.handle();
+    let mut state = AppState::new(scene, qh.clone());
+    state.resize_border = resize_border();
+
+    let event_loop = EventLoop::try_new()?;
+    insert_signals(&event_loop.handle(), |state: &mut AppState| {
+        state.running = false
+    })?;
+    state.pipes = Pipes::new(event_loop.handle());
+    state.watchdog = watchdog::spawn(conn.backend().poll_fd());
+    if options.render_thread {
+        match RenderThread::spawn(&event_loop.handle()) {
+            Ok(thread) => state.render_thread = Some(thread),
+            Err(err) => warn!(%err, "failed to start the render thread, drawing on the main one"),
+        }
+    }
+
+    let display = conn.display();
+    let registry = display.get_registry(&qh, ());
+    state.set_display(display);
+    state.globals = GlobalManager::new(registry);
+
+    watchdog::set_phase(Phase::Roundtrip);
+    event_queue.roundtrip(&mut state)?;
+    registry::bind_globals(&mut state, &qh)?;
+    // Delivers the events the globals send right away, like the wl_shm
+    // formats
+    event_queue.roundtrip(&mut state)?;
+    watchdog::set_phase(Phase::Startup);
+
+    let needs_alpha = state.scene.needs_alpha() || options.transparent;
+    let Some(format) = PixelFormat::choose(&state.shm_formats, needs_alpha) else {
+        // Both are mandatory, the compositor is broken or the formats got
+        // lost on the way
+        warn!(advertised = ?state.shm_formats, "wl_shm advertised neither ARGB8888 nor XRGB8888");
+        return Err(Error::UnsupportedFormats(state.shm_formats.clone()));
+    };
+    info!(?format, "picked a pixel format");
+    state.format = format;
+
+    if matches!(state.scene, Scene::Pan(_)) && state.viewporter.is_none() {
+        warn!("the compositor doesn't support wp_viewporter, the image can't be panned or zoomed");
+    }
+    if matches!(state.scene, Scene::Badge) && state.subcompositor.is_none() {
+        warn!("the compositor doesn't support wl_subcompositor, there is no badge to show");
+    }
+    if matches!(state.scene, Scene::Snake(_)) {
+        state.timers.every(snake::STEP_INTERVAL, snake::step);
+    }
+    if let Scene::Animation(image) = &state.scene {
+        if image.is_animated() {
+            state.timers.after(image.delay(), animation::advance);
+        }
+    }
+    if matches!(state.scene, Scene::Pomodoro(_)) {
+        state
+            .timers
+            .every(pomodoro::TICK_INTERVAL, pomodoro::update);
+        let handle = event_loop.handle();
+        match ipc::listen(&handle, pomodoro::SOCKET_NAME, pomodoro::command) {
+            Ok(socket) => state.control_socket = Some(socket),
+            Err(err) => {
+                warn!(%err, "failed to open the control socket, the pomodoro can't be paused")
+            }
+        }
+    }
+    if matches!(state.scene, Scene::Monitor(_)) {
+        state
+            .timers
+            .every(monitor::UPDATE_INTERVAL, monitor::update);
+    }
+    if options.tearing && state.tearing_control_manager.is_none() {
+        warn!("the compositor doesn't support wp_tearing_control_v1, frames wait for the refresh");
+    }
+    if options.content_type.is_some() && state.content_type_manager.is_none() {
+        warn!("the compositor doesn't support wp_content_type_v1, ignoring the content type");
+    }
+
+    // Prefer letting the compositor draw the cursor
+    if let Some(manager) = state.cursor_shape_manager.take() {
+        state.cursor = Some(Cursor::with_shapes(manager));
+    } else {
+        let compositor = required(&state.compositor)?;
+        match Cursor::with_theme(&conn, required(&state.shm)?, compositor, &qh) {
+            Ok(cursor) => state.cursor = Some(cursor),
+            Err(err) => warn!(%err, "failed to load the cursor theme"),
+        }
+    }
+
+    // Layer surfaces and the lock screen are what runs as a service, a
+    // plain window has no business answering the watchdog
+    let daemon = options.layer.is_some() || options.lock;
+    if options.lock {
+        lock::lock(&mut state)?;
+    } else {
+        window::open(&mut state, options)?;
+    }
+
+    watchdog::set_phase(Phase::WaitingForConfigure);
+
+    if let Some(interval) = systemd::watchdog_interval().filter(|_| daemon) {
+        // Ping at half the interval, as recommended by sd_watchdog_enabled(3)
+        state
+            .timers
+            .every(interval / 2, |_| systemd::notify_watchdog());
+    }
+
+    let flusher = insert_wayland(&event_loop.handle(), conn, event_queue)?;
+    Ok((state, event_loop, flusher))
+}
+
+/// How often [`log_stats`] is worth calling.
+pub(crate) const STATS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Logs how the buffers and frames of each window fared so far.
+pub(crate) fn log_stats(state: &AppState) {
+    for window in &state.windows {
+        debug!(id = ?window.id(), stats = ?window.buffers.stats, "shm buffer stats");
+        let stats = &window.presentation_stats;
+        debug!(
+            id = ?window.id(),
+            presented = stats.presented,
+            discarded = stats.discarded,
+            missed = stats.missed,
+            dropped = stats.dropped,
+            latency = ?stats.average_latency(),
+            min_latency = ?stats.min_latency,
+            max_latency = ?stats.max_latency,
+            refresh = ?stats.refresh,
+            flags = ?stats.flags,
+            "presentation stats"
+        );
+    }
+}
+
+/// Runs the timers that are due and draws what changed, after the events
+/// that arrived were handled.
+pub(crate) fn after_dispatch(state: &mut AppState) {
+    Timers::dispatch(state, |state| &mut state.timers);
+    // Redraws requested through the window since the last frame
+    render::present_if_needed(state);
+}
+
+/// Cleans up once the window is closed.
+pub(crate) fn shut_down(mut state: AppState, conn: &Connection) -> Result<ExitCode, Error> {
+    info!("window closed, exiting");
+    state.destroy();
+    conn.flush()?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Shows `scene` in a window set up with `options` until it is closed.
+/// Returns how the process should exit.
+pub fn run(scene: Scene, options: WindowOptions) -> Result<ExitCode, Error> {
+    let (mut state, mut event_loop, flusher) = start(scene, options)?;
+    if !run_loop(&mut state, &mut event_loop, &flusher) {
+        return Ok(ExitCode::FAILURE);
+    }
+    shut_down(state, flusher.connection())
+}
+
+/// Runs the main loop [`start`] returned until the window is closed.
+/// Returns false if it failed first, e.g. because the connection was lost.
+pub(crate) fn run_loop(
+    state: &mut AppState,
+    event_loop: &mut EventLoop<'static, AppState>,
+    flusher: &Flusher<AppState>,
+) -> bool {
+    state.timers.every(STATS_INTERVAL, |state| log_stats(state));
+
+    while state.running {
+        let timeout = state.timers.next_timeout();
+        // The pipes and signals handle their own errors, only the
+        // connection fails
+        let res = flusher.flush().and_then(|()| {
+            watchdog::idle();
+            let res = event_loop.dispatch(timeout, state);
+            watchdog::busy();
+            Ok(res?)
+        });
+        if let Err(err) = res {
+            flusher.report(err);
+            return false;
+        }
+
+        after_dispatch(state);
+    }
+
+    true
+}
@@ -0,0 +1,169 @@
+//! Font preview: a specimen of a font family, with its alphabet, a range of
+//! sizes and a paragraph wrapped to the window. `w` goes through the
+//! weights of the family, `h` toggles grid fitting.
+
+use std::sync::Arc;
+
+use tracing::debug;
+use xkbcommon_dl::keysyms;
+
+use crate::{
+    color::Color,
+    pixel_buffer::PixelBuffer,
+    rect::Rect,
+    render::present_if_needed,
+    scale::Scale,
+    scene::Scene,
+    state::AppState,
+    text::{Font, FontError},
+};
+
+pub const BACKGROUND: Color = Color::rgb(0xFA, 0xFA, 0xF5);
+const TEXT: Color = Color::rgb(0x20, 0x20, 0x20);
+const CAPTION: Color = Color::rgb(0x80, 0x80, 0x80);
+
+// Asked of fontconfig for each family, those it has no face for map to the
+// closest one and are left out
+const WEIGHTS: [&str; 9] = [
+    "thin",
+    "extralight",
+    "light",
+    "regular",
+    "medium",
+    "semibold",
+    "bold",
+    "extrabold",
+    "black",
+];
+
+const MARGIN: usize = 24;
+const TITLE_SIZE: f32 = 28.0;
+const CAPTION_SIZE: f32 = 12.0;
+const ALPHABET_SIZE: f32 = 20.0;
+const ALPHABET: [&str; 3] = [
+    "ABCDEFGHIJKLMNOPQRSTUVWXYZ",
+    "abcdefghijklmnopqrstuvwxyz",
+    "0123456789 .,;:!?&@#%*()[]{}",
+];
+const SIZES: [f32; 8] = [9.0, 11.0, 13.0, 16.0, 20.0, 24.0, 32.0, 48.0];
+const SAMPLE: &str = "The quick brown fox jumps over the lazy dog";
+const PARAGRAPH_SIZE: f32 = 16.0;
+const PARAGRAPH: &str = "Typography is the craft of endowing human language with a durable \
+    visual form. Text set at small sizes on a screen has few pixels to work with: each stem \
+    either lines up with the pixel grid and looks crisp, or falls between two pixels and \
+    turns into a blurry grey. Grid fitting trades the exact shapes and spacing of the \
+    outlines for sharper stems.\n\
+    Pack my box with five dozen liquor jugs. How vexingly quick daft zebras jump!";
+
+/// The faces of a family and how they are drawn.
+#[derive(Clone)]
+pub struct FontView {
+    family: String,
+    // One per weight the family has, lightest first
+    faces: Vec<Arc<Font>>,
+    current: usize,
+    grid_fit: bool,
+}
+
+impl FontView {
+    /// Looks up the faces of `family` in its weights.
+    pub fn new(family: &str) -> Result<Self, FontError> {
+        let mut faces: Vec<Arc<Font>> = Vec::new();
+        // Starts out with the regular face
+        let mut current = 0;
+        for weight in WEIGHTS {
+            let face = Font::find(&format!("{family}:weight={weight}"))?;
+            let index = match faces.iter().position(|f| f.path() == face.path()) {
+                Some(index) => index,
+                None => {
+                    debug!(weight, path = %face.path().display(), "found a face");
+                    faces.push(Arc::new(face));
+                    faces.len() - 1
+                }
+            };
+            if weight == "regular" {
+                current = index;
+            }
+        }
+
+        Ok(Self {
+            family: family.to_owned(),
+            faces,
+            current,
+            grid_fit: false,
+        })
+    }
+
+    fn face(&self) -> &Font {
+        &self.faces[self.current]
+    }
+
+    /// Draws the specimen into `pixels`, laid out in window pixels.
+    pub(crate) fn draw(&self, pixels: &mut PixelBuffer, scale: Scale) {
+        pixels.fill(BACKGROUND);
+        let bounds = pixels.bounds();
+        let font = self.face();
+        let px = |length| scale.to_buffer(length);
+        let size = |size: f32| size * scale.as_f64() as f32;
+        let (x, width) = (px(MARGIN), bounds.width.saturating_sub(px(2 * MARGIN)));
+        let mut y = px(MARGIN);
+
+        let line = |pixels: &mut PixelBuffer, y: &mut usize, text: &str, size, color| {
+            font.draw_line(pixels, text, (x, *y), size, color, self.grid_fit);
+            *y += font.line_height(size);
+        };
+
+        let title = font.name().unwrap_or(&self.family);
+        line(pixels, &mut y, title, size(TITLE_SIZE), TEXT);
+        let caption = format!(
+            "{}, face {} of {}, grid fitting {} (w: next weight, h: grid fitting)",
+            font.path().display(),
+            self.current + 1,
+            self.faces.len(),
+            if self.grid_fit { "on" } else { "off" }
+        );
+        line(pixels, &mut y, &caption, size(CAPTION_SIZE), CAPTION);
+        y += px(MARGIN / 2);
+
+        for text in ALPHABET {
+            line(pixels, &mut y, text, size(ALPHABET_SIZE), TEXT);
+        }
+        y += px(MARGIN / 2);
+
+        for points in SIZES {
+            line(
+                pixels,
+                &mut y,
+                &format!("{points:.0}"),
+                size(CAPTION_SIZE),
+                CAPTION,
+            );
+            line(pixels, &mut y, SAMPLE, size(points), TEXT);
+        }
+        y += px(MARGIN / 2);
+
+        let rest = Rect::new(x, y, width, bounds.height.saturating_sub(y));
+        let paragraph = size(PARAGRAPH_SIZE);
+        font.draw_paragraph(pixels, PARAGRAPH, rest, paragraph, TEXT, self.grid_fit);
+    }
+}
+
+/// Goes to the next weight with `w` and toggles grid fitting with `h`.
+/// Returns whether `keysym` was one of those.
+pub(crate) fn handle_key(state: &mut AppState, keysym: u32) -> bool {
+    let Scene::FontView(view) = &mut state.scene else {
+        return false;
+    };
+
+    match keysym {
+        keysyms::w => view.current = (view.current + 1) % view.faces.len(),
+        keysyms::h => view.grid_fit = !view.grid_fit,
+        _ => return false,
+    }
+
+    for window in &mut state.windows {
+        window.request_redraw();
+    }
+    present_if_needed(state);
+    true
+}
@@ -0,0 +1,93 @@
+//! Finds out which part of the window the pointer is over, so that a button
+//! press on a border resizes the window instead of reaching the contents.
+
+use wayland_protocols::xdg::shell::client::xdg_toplevel::ResizeEdge;
+
+use crate::cursor::CursorShape;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Edge {
+    pub fn resize_edge(self) -> ResizeEdge {
+        match self {
+            Self::Top => ResizeEdge::Top,
+            Self::Bottom => ResizeEdge::Bottom,
+            Self::Left => ResizeEdge::Left,
+            Self::Right => ResizeEdge::Right,
+            Self::TopLeft => ResizeEdge::TopLeft,
+            Self::TopRight => ResizeEdge::TopRight,
+            Self::BottomLeft => ResizeEdge::BottomLeft,
+            Self::BottomRight => ResizeEdge::BottomRight,
+        }
+    }
+
+    pub fn cursor(self) -> CursorShape {
+        match self {
+            Self::Top => CursorShape::NResize,
+            Self::Bottom => CursorShape::SResize,
+            Self::Left => CursorShape::WResize,
+            Self::Right => CursorShape::EResize,
+            Self::TopLeft => CursorShape::NwResize,
+            Self::TopRight => CursorShape::NeResize,
+            Self::BottomLeft => CursorShape::SwResize,
+            Self::BottomRight => CursorShape::SeResize,
+        }
+    }
+}
+
+/// The edge or corner within `border` pixels of `(x, y)` in a window of
+/// `width`x`height`, if any.
+pub fn edge_at(x: f64, y: f64, width: usize, height: usize, border: f64) -> Option<Edge> {
+    let top = y < border;
+    let bottom = y >= height as f64 - border;
+    let left = x < border;
+    let right = x >= width as f64 - border;
+
+    match (top, bottom, left, right) {
+        (true, _, true, _) => Some(Edge::TopLeft),
+        (true, _, _, true) => Some(Edge::TopRight),
+        (_, true, true, _) => Some(Edge::BottomLeft),
+        (_, true, _, true) => Some(Edge::BottomRight),
+        (true, ..) => Some(Edge::Top),
+        (_, true, ..) => Some(Edge::Bottom),
+        (_, _, true, _) => Some(Edge::Left),
+        (.., true) => Some(Edge::Right),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edges_and_corners() {
+        let edge = |x, y| edge_at(x, y, 100, 50, 5.0);
+
+        assert_eq!(edge(50.0, 25.0), None);
+        assert_eq!(edge(50.0, 0.0), Some(Edge::Top));
+        assert_eq!(edge(50.0, 49.5), Some(Edge::Bottom));
+        assert_eq!(edge(4.9, 25.0), Some(Edge::Left));
+        assert_eq!(edge(95.0, 25.0), Some(Edge::Right));
+        assert_eq!(edge(0.0, 0.0), Some(Edge::TopLeft));
+        assert_eq!(edge(99.0, 2.0), Some(Edge::TopRight));
+        assert_eq!(edge(1.0, 48.0), Some(Edge::BottomLeft));
+        assert_eq!(edge(99.0, 49.0), Some(Edge::BottomRight));
+    }
+
+    #[test]
+    fn border_wider_than_window() {
+        // Corners win when the borders overlap
+        assert_eq!(edge_at(1.0, 1.0, 4, 4, 8.0), Some(Edge::TopLeft));
+    }
+}
@@ -0,0 +1,152 @@
+//! An overlay graphing how long the last frames took from being committed
+//! to reaching the screen, one bar per frame.
+
+use std::time::Duration;
+
+use crate::{
+    color::{Color, ColorSpace},
+    pixel_buffer::PixelBuffer,
+    rect::Rect,
+    scale::Scale,
+};
+
+/// How many frames the graph goes back
+pub const FRAMES: usize = 64;
+
+const BAR_WIDTH: usize = 2;
+const HEIGHT: usize = 48;
+const MARGIN: usize = 8;
+
+// What the full height stands for: three refresh cycles, or this if the
+// refresh rate is unknown
+const FALLBACK_RANGE: Duration = Duration::from_millis(50);
+const RANGE_CYCLES: u32 = 3;
+
+const BACKGROUND: Color = Color::rgba(0x00, 0x00, 0x00, 0xA0);
+const REFRESH_LINE: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+const ON_TIME: Color = Color::rgb(0x40, 0xC0, 0x40);
+const LATE: Color = Color::rgb(0xFF, 0xA0, 0x00);
+const VERY_LATE: Color = Color::rgb(0xE0, 0x30, 0x30);
+
+/// The latencies of the last [`FRAMES`] frames shown, oldest first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyGraph {
+    // Zero for frames we have yet to see
+    latencies: [Duration; FRAMES],
+    refresh: Duration,
+}
+
+impl Default for LatencyGraph {
+    fn default() -> Self {
+        Self {
+            latencies: [Duration::ZERO; FRAMES],
+            refresh: Duration::ZERO,
+        }
+    }
+}
+
+impl LatencyGraph {
+    /// A frame was shown `latency` after being committed, on an output
+    /// refreshing every `refresh`, zero if unknown.
+    pub fn push(&mut self, latency: Duration, refresh: Duration) {
+        self.latencies.rotate_left(1);
+        self.latencies[FRAMES - 1] = latency;
+        self.refresh = refresh;
+    }
+
+    /// Where the graph goes in a window of `width`x`height` window pixels:
+    /// its bottom left corner.
+    pub fn rect((width, height): (usize, usize)) -> Rect {
+        let y = height.saturating_sub(MARGIN + HEIGHT);
+        let window = Rect::new(0, 0, width, height);
+        Rect::new(MARGIN, y, FRAMES * BAR_WIDTH, HEIGHT).intersect(&window)
+    }
+
+    /// How tall the bar of a frame shown `latency` after being committed
+    /// is, out of `height`.
+    fn bar_height(&self, latency: Duration, height: usize) -> usize {
+        let range = if self.refresh.is_zero() {
+            FALLBACK_RANGE
+        } else {
+            self.refresh * RANGE_CYCLES
+        };
+        let ratio = (latency.as_secs_f64() / range.as_secs_f64()).min(1.0);
+        (ratio * height as f64).round() as usize
+    }
+
+    fn bar_color(&self, latency: Duration) -> Color {
+        if self.refresh.is_zero() || latency <= self.refresh {
+            ON_TIME
+        } else if latency <= self.refresh * 2 {
+            LATE
+        } else {
+            VERY_LATE
+        }
+    }
+
+    /// Draws the graph over `pixels`, laid out in window pixels.
+    pub fn draw(&self, pixels: &mut PixelBuffer, scale: Scale) {
+        let bounds = pixels.bounds();
+        let size = (
+            scale.to_surface(bounds.width),
+            scale.to_surface(bounds.height),
+        );
+        let area = scale.rect_to_buffer(Self::rect(size)).intersect(&bounds);
+        if area.is_empty() {
+            return;
+        }
+        pixels.blend_rect(area, BACKGROUND, ColorSpace::Srgb);
+
+        let bar_width = scale.to_buffer(BAR_WIDTH).max(1);
+        for (i, latency) in self.latencies.iter().enumerate() {
+            if latency.is_zero() {
+                continue;
+            }
+            let height = self.bar_height(*latency, area.height);
+            let bar = Rect::new(
+                area.x + i * bar_width,
+                area.bottom() - height,
+                bar_width,
+                height,
+            );
+            pixels.fill_rect(bar.intersect(&area), self.bar_color(*latency));
+        }
+
+        // Anything above the line missed a refresh
+        if !self.refresh.is_zero() {
+            let y = area.bottom() - self.bar_height(self.refresh, area.height);
+            let line = Rect::new(area.x, y, area.width, 1).intersect(&area);
+            pixels.fill_rect(line, REFRESH_LINE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bars_scale_with_the_refresh_rate() {
+        let mut graph = LatencyGraph::default();
+        let at = Duration::from_millis;
+
+        // Unknown refresh rate, the full height is 50ms
+        assert_eq!(graph.bar_height(at(25), 48), 24);
+        assert_eq!(graph.bar_color(at(40)), ON_TIME);
+
+        graph.push(at(8), at(10));
+        assert_eq!(graph.latencies[FRAMES - 1], at(8));
+        assert_eq!(graph.latencies[FRAMES - 2], Duration::ZERO);
+        assert_eq!(graph.bar_height(at(15), 48), 24);
+        assert_eq!(graph.bar_height(at(100), 48), 48);
+        assert_eq!(graph.bar_color(at(10)), ON_TIME);
+        assert_eq!(graph.bar_color(at(15)), LATE);
+        assert_eq!(graph.bar_color(at(25)), VERY_LATE);
+    }
+
+    #[test]
+    fn sits_in_the_bottom_left_corner() {
+        assert_eq!(LatencyGraph::rect((640, 480)), Rect::new(8, 424, 128, 48));
+        assert_eq!(LatencyGraph::rect((100, 30)), Rect::new(8, 0, 92, 30));
+    }
+}
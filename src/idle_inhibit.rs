@@ -0,0 +1,76 @@
+//! Keeps the screen from blanking while our window is visible, e.g. to watch
+//! the animation without touching the keyboard.
+
+use tracing::{debug, info};
+use wayland_client::{Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols::wp::idle_inhibit::zv1::client::{
+    zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1, zwp_idle_inhibitor_v1::ZwpIdleInhibitorV1,
+};
+
+use crate::state::AppState;
+
+/// Whether the screen is kept on. Only while the window the inhibitor is
+/// attached to is visible, the compositor ignores it otherwise.
+pub fn is_idle_inhibited(state: &AppState) -> bool {
+    state.idle_inhibitor.is_some()
+}
+
+/// Keeps the screen on or lets it blank again. Does nothing if the
+/// compositor doesn't support idle inhibition.
+pub fn set_idle_inhibited(state: &mut AppState, inhibited: bool) {
+    if inhibited == is_idle_inhibited(state) {
+        return;
+    }
+
+    if !inhibited {
+        if let Some((_, inhibitor)) = state.idle_inhibitor.take() {
+            info!("letting the screen blank again");
+            inhibitor.destroy();
+        }
+        return;
+    }
+
+    // The window the user works with, any will do otherwise
+    let window = state
+        .focused_window()
+        .and_then(|id| state.window(id))
+        .or(state.windows.first());
+    let qh = &state.queue_handle;
+    let (Some(manager), Some(window)) = (&state.idle_inhibit_manager, window) else {
+        debug!("the compositor doesn't support idle inhibition");
+        return;
+    };
+    info!(id = ?window.id(), "keeping the screen on");
+    let inhibitor = manager.create_inhibitor(window.surface(), qh, ());
+    state.idle_inhibitor = Some((window.id(), inhibitor));
+}
+
+pub fn toggle_idle_inhibit(state: &mut AppState) {
+    set_idle_inhibited(state, !is_idle_inhibited(state));
+}
+
+impl Dispatch<ZwpIdleInhibitManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwpIdleInhibitManagerV1,
+        _event: <ZwpIdleInhibitManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<ZwpIdleInhibitorV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwpIdleInhibitorV1,
+        _event: <ZwpIdleInhibitorV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
@@ -0,0 +1,690 @@
+//! Pointer and keyboard input.
+
+use std::time::Duration;
+
+use tracing::{debug, info, warn};
+use wayland_client::{
+    protocol::{
+        wl_keyboard::{self, WlKeyboard},
+        wl_pointer::{self, WlPointer},
+        wl_seat::{self, WlSeat},
+    },
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+use wayland_protocols::wp::cursor_shape::v1::client::{
+    wp_cursor_shape_device_v1::WpCursorShapeDeviceV1,
+    wp_cursor_shape_manager_v1::WpCursorShapeManagerV1,
+};
+use xkbcommon_dl::keysyms;
+
+use crate::{
+    activation, annotate,
+    app::PointerEvent,
+    clipboard,
+    cursor::{Cursor, CursorShape},
+    decorations::{self, TITLE_BAR_HEIGHT},
+    dnd, dock, fontview,
+    hit_test::{self, Edge},
+    idle_inhibit,
+    keyboard::{KeyEvent, Keyboard, RepeatInfo},
+    lock, menu,
+    render::present_if_needed,
+    screenshot,
+    seat::{Pointer, Seat, BTN_LEFT, BTN_RIGHT},
+    select, snake,
+    state::AppState,
+    transform, viewport,
+    window::{self, Window, WindowId},
+};
+
+impl Dispatch<WlSeat, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlSeat,
+        event: <WlSeat as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        // Events of a seat we dropped, or never used
+        let Some(seat) = state.seat.as_mut().filter(|seat| seat.seat == *proxy) else {
+            return;
+        };
+
+        match event {
+            wl_seat::Event::Capabilities {
+                capabilities: WEnum::Value(capabilities),
+            } => {
+                let (added, removed) = seat.set_capabilities(capabilities);
+                info!(name = ?seat.name, ?added, ?removed, "seat capabilities changed");
+
+                if added.contains(wl_seat::Capability::Keyboard) {
+                    let keyboard = seat.seat.get_keyboard(qh, ());
+                    seat.keyboard = Some(Keyboard::new(keyboard));
+                }
+                if removed.contains(wl_seat::Capability::Keyboard) {
+                    if let Some(keyboard) = seat.keyboard.take() {
+                        if let Some((_, id)) = keyboard.repeating {
+                            state.timers.cancel(id);
+                        }
+                        release_keyboard(keyboard);
+                    }
+                }
+
+                if added.contains(wl_seat::Capability::Pointer) {
+                    let pointer = seat.seat.get_pointer(qh, ());
+                    seat.pointer = Some(Pointer::new(pointer));
+                }
+                if removed.contains(wl_seat::Capability::Pointer) {
+                    if let Some(pointer) = seat.pointer.take() {
+                        release_pointer(pointer);
+                    }
+                    stop_cursor_animation(state);
+                    set_highlighted(state, None);
+                }
+            }
+            wl_seat::Event::Name { name } => {
+                debug!(?name, "seat name");
+                seat.name = Some(name);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Releases the seat and the devices we got from it.
+pub(crate) fn release_seat(seat: Seat) {
+    if let Some(pointer) = seat.pointer {
+        release_pointer(pointer);
+    }
+    if let Some(keyboard) = seat.keyboard {
+        release_keyboard(keyboard);
+    }
+    if let Some(data_device) = seat.data_device {
+        data_device.release();
+    }
+    // Only has a destructor since version 5
+    if seat.seat.version() >= 5 {
+        seat.seat.release();
+    }
+}
+
+/// Forgets about our seat, e.g. because the compositor removed it.
+pub(crate) fn remove_seat(state: &mut AppState) {
+    stop_key_repeat(state);
+    stop_cursor_animation(state);
+    if let Some(seat) = state.seat.take() {
+        release_seat(seat);
+    }
+    set_highlighted(state, None);
+}
+
+/// Highlights the window `id`, the one the left button is held down in,
+/// and no other.
+fn set_highlighted(state: &mut AppState, id: Option<WindowId>) {
+    for window in &mut state.windows {
+        let highlighted = Some(window.id()) == id;
+        if window.highlighted != highlighted {
+            window.highlighted = highlighted;
+            window.request_redraw();
+        }
+    }
+    present_if_needed(state);
+}
+
+pub(crate) fn release_pointer(pointer: Pointer) {
+    // Only has a destructor since version 3
+    if pointer.pointer.version() >= 3 {
+        pointer.pointer.release();
+    }
+}
+
+/// Hands `event` to the app, if it happened over the window `id`, and
+/// redraws the window if the app asks for it.
+fn app_pointer_event(state: &mut AppState, id: Option<WindowId>, event: PointerEvent) {
+    let (Some(id), Some(app)) = (id, &mut state.app) else {
+        return;
+    };
+    if app.on_pointer(event) {
+        if let Some(window) = state.window_mut(id) {
+            window.request_redraw();
+        }
+        present_if_needed(state);
+    }
+}
+
+/// The window the pointer is over, if it is one of ours.
+fn pointer_window(state: &AppState) -> Option<&Window> {
+    state.window(state.pointer_window()?)
+}
+
+/// The border of the window the pointer is over, if any.
+pub(crate) fn edge_at(state: &AppState, position: Option<(f64, f64)>) -> Option<Edge> {
+    let (x, y) = position?;
+    // Only regular windows can be resized
+    let window = pointer_window(state).filter(|w| w.is_toplevel())?;
+    let (width, height) = window.size();
+    hit_test::edge_at(x, y, width, height, state.resize_border)
+}
+
+/// Shows a resize cursor over the borders and the default one elsewhere.
+pub(crate) fn update_cursor_shape(state: &mut AppState, position: Option<(f64, f64)>) {
+    let shape = edge_at(state, position).map_or(CursorShape::Default, Edge::cursor);
+    let Some(cursor) = state.cursor.as_mut().filter(|c| c.shape() != shape) else {
+        return;
+    };
+
+    let next_frame = cursor.set_shape(shape);
+    stop_cursor_animation(state);
+    if let Some(next_frame) = next_frame {
+        schedule_cursor_frame(state, next_frame);
+    }
+}
+
+/// The title bar button the pointer is over, if we draw the decorations.
+pub(crate) fn decoration_button_at(
+    state: &AppState,
+    position: Option<(f64, f64)>,
+) -> Option<decorations::Button> {
+    let (x, y) = position?;
+    let window = pointer_window(state)?;
+    if !window.client_side_decorations() {
+        return None;
+    }
+    decorations::button_at(x, y, window.size().0, window.capabilities())
+}
+
+/// Shows the compositor's window menu for a right click in the title bar
+/// of our decorations. Returns false if the click was elsewhere, or there is
+/// no such menu.
+pub(crate) fn show_window_menu(state: &AppState, (x, y): (f64, f64), serial: u32) -> bool {
+    let (Some(window), Some(seat)) = (pointer_window(state), &state.seat) else {
+        return false;
+    };
+    let in_title_bar = y < TITLE_BAR_HEIGHT as f64;
+
+    window.client_side_decorations()
+        && in_title_bar
+        && window.show_window_menu(&seat.seat, serial, (x, y))
+}
+
+/// Whether a left button press starts dragging the color of the window out
+/// of it: with Ctrl held.
+pub(crate) fn wants_drag(state: &mut AppState) -> bool {
+    state.keyboard_mut().is_some_and(|k| k.modifiers.ctrl)
+}
+
+/// Whether pressing the left button should move the window: in the title
+/// bar area, or anywhere while Alt or Super is held down.
+pub(crate) fn wants_move(state: &mut AppState, position: Option<(f64, f64)>) -> bool {
+    let modifier = state
+        .keyboard_mut()
+        .is_some_and(|k| k.modifiers.alt || k.modifiers.logo);
+    let in_title_bar = position.is_some_and(|(_, y)| y < TITLE_BAR_HEIGHT as f64);
+
+    modifier || in_title_bar
+}
+
+/// Sets our cursor when the pointer enters the surface.
+pub(crate) fn show_cursor(
+    state: &mut AppState,
+    pointer: &WlPointer,
+    serial: u32,
+    qh: &QueueHandle<AppState>,
+) {
+    stop_cursor_animation(state);
+    if let Some(next_frame) = state
+        .cursor
+        .as_mut()
+        .and_then(|c| c.show(pointer, serial, qh))
+    {
+        schedule_cursor_frame(state, next_frame);
+    }
+}
+
+pub(crate) fn schedule_cursor_frame(state: &mut AppState, delay: Duration) {
+    let id = state.timers.after(delay, |state| {
+        state.cursor_animation = None;
+        if let Some(next_frame) = state.cursor.as_mut().and_then(|c| c.update()) {
+            schedule_cursor_frame(state, next_frame);
+        }
+    });
+    state.cursor_animation = Some(id);
+}
+
+/// Draws the cursor ourselves from the theme once the compositor no longer
+/// offers cursor shapes, in the shape it had. Without a theme there is no
+/// cursor of ours anymore.
+pub(crate) fn fall_back_to_theme_cursor(
+    state: &mut AppState,
+    conn: &Connection,
+    qh: &QueueHandle<AppState>,
+) {
+    stop_cursor_animation(state);
+    let Some(mut cursor) = state.cursor.take() else {
+        return;
+    };
+
+    let theme = match (&state.shm, &state.compositor) {
+        (Some(shm), Some(compositor)) => Cursor::with_theme(conn, shm, compositor, qh)
+            .inspect_err(|err| warn!(%err, "failed to load the cursor theme"))
+            .ok(),
+        _ => None,
+    };
+    let Some(theme) = theme else {
+        cursor.destroy();
+        return;
+    };
+
+    let next_frame = cursor.fall_back(theme);
+    state.cursor = Some(cursor);
+    if let Some(next_frame) = next_frame {
+        schedule_cursor_frame(state, next_frame);
+    }
+}
+
+pub(crate) fn stop_cursor_animation(state: &mut AppState) {
+    if let Some(id) = state.cursor_animation.take() {
+        state.timers.cancel(id);
+    }
+}
+
+impl Dispatch<WlPointer, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlPointer,
+        event: <WlPointer as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let Some(pointer) = state.pointer_mut() else {
+            return;
+        };
+
+        match event {
+            wl_pointer::Event::Enter {
+                serial,
+                surface,
+                surface_x,
+                surface_y,
+            } => {
+                debug!(?surface_x, ?surface_y, "pointer entered");
+                pointer.position = Some((surface_x, surface_y));
+                pointer.surface = Some(surface);
+                show_cursor(state, proxy, serial, qh);
+                if menu::has_pointer(state) {
+                    update_cursor_shape(state, None);
+                    menu::pointer_moved(state, Some((surface_x, surface_y)));
+                    return;
+                }
+                update_cursor_shape(state, Some((surface_x, surface_y)));
+                let (x, y) = (surface_x, surface_y);
+                app_pointer_event(state, state.pointer_window(), PointerEvent::Motion { x, y });
+                select::pointer_moved(state, state.pointer_window(), x, y);
+                annotate::pointer_moved(state, state.pointer_window(), x, y);
+                dock::pointer_moved(state, state.pointer_window(), Some((x, y)));
+            }
+            wl_pointer::Event::Leave { surface, .. } => {
+                debug!("pointer left");
+                pointer.position = None;
+                pointer.surface = None;
+                // We won't hear about these being released anymore
+                pointer.pressed.clear();
+                stop_cursor_animation(state);
+                if menu::is_menu_surface(state, &surface) {
+                    menu::pointer_moved(state, None);
+                    return;
+                }
+                let id = state.window_for_surface(&surface);
+                app_pointer_event(state, id, PointerEvent::Leave);
+                dock::pointer_moved(state, id, None);
+            }
+            wl_pointer::Event::Motion {
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                pointer.position = Some((surface_x, surface_y));
+                if menu::has_pointer(state) {
+                    menu::pointer_moved(state, Some((surface_x, surface_y)));
+                    return;
+                }
+                update_cursor_shape(state, Some((surface_x, surface_y)));
+                let (x, y) = (surface_x, surface_y);
+                app_pointer_event(state, state.pointer_window(), PointerEvent::Motion { x, y });
+                select::pointer_moved(state, state.pointer_window(), x, y);
+                annotate::pointer_moved(state, state.pointer_window(), x, y);
+                dock::pointer_moved(state, state.pointer_window(), Some((x, y)));
+            }
+            wl_pointer::Event::Button {
+                serial,
+                button,
+                state: WEnum::Value(button_state),
+                ..
+            } => {
+                let position = pointer.position;
+                debug!(button, ?button_state, ?position, "pointer button");
+                let pressed = button_state == wl_pointer::ButtonState::Pressed;
+                if let Some(seat) = state.seat.as_mut().filter(|_| pressed) {
+                    seat.last_serial = Some(serial);
+                }
+                if menu::has_pointer(state) {
+                    menu::pointer_button(state, button, pressed);
+                    return;
+                }
+                let target = state.pointer_window();
+                app_pointer_event(state, target, PointerEvent::Button { button, pressed });
+                // Dragging selects rather than moves in the selection mode
+                if select::pointer_button(state, target, button, pressed) {
+                    return;
+                }
+                // Draws on the screenshot rather than moves the window
+                if annotate::pointer_button(state, target, button, pressed) {
+                    return;
+                }
+                // Clicks launch rather than move in the dock
+                if dock::pointer_button(state, target, position, button, pressed) {
+                    return;
+                }
+                if let Some((position, id)) = position
+                    .zip(target)
+                    .filter(|_| pressed && button == BTN_RIGHT)
+                {
+                    // The title bar we draw gets the usual window menu
+                    if !show_window_menu(state, position, serial) {
+                        menu::open(state, id, position, serial);
+                    }
+                    return;
+                }
+                let left_press = pressed && button == BTN_LEFT;
+                if let Some(id) = target.filter(|_| left_press && wants_drag(state)) {
+                    dnd::start_drag(state, id, serial);
+                    return;
+                }
+                let decoration_button =
+                    decoration_button_at(state, position).filter(|_| left_press);
+                let edge = edge_at(state, position).filter(|_| left_press);
+                let moving = left_press && wants_move(state, position);
+                let grab = match (pointer_window(state), &state.seat) {
+                    (Some(window), Some(seat)) => Some((window, &seat.seat)),
+                    _ => None,
+                };
+                if let Some((button, id)) = decoration_button.zip(target) {
+                    match button {
+                        decorations::Button::Close => window::request_close(state, id),
+                        decorations::Button::Maximize => {
+                            if let Some(window) = state.window(id) {
+                                window.toggle_maximized();
+                            }
+                        }
+                        decorations::Button::Minimize => {
+                            if let Some(window) = state.window(id) {
+                                window.minimize();
+                            }
+                        }
+                    }
+                } else if let Some(((window, seat), edge)) = grab.zip(edge) {
+                    window.start_resize(seat, serial, edge);
+                } else if let Some((window, seat)) = grab.filter(|_| moving) {
+                    window.start_move(seat, serial);
+                } else if let Some(pointer) = state.pointer_mut() {
+                    pointer.pressed.retain(|&b| b != button);
+                    if pressed {
+                        pointer.pressed.push(button);
+                    }
+                }
+            }
+            _ => return,
+        }
+
+        let Some(pointer) = state.pointer_mut() else {
+            return;
+        };
+        let highlighted = pointer.position.is_some() && pointer.is_pressed(BTN_LEFT);
+        let target = state.pointer_window().filter(|_| highlighted);
+        set_highlighted(state, target);
+    }
+}
+
+pub(crate) fn release_keyboard(keyboard: Keyboard) {
+    // Only has a destructor since version 3
+    if keyboard.keyboard.version() >= 3 {
+        keyboard.keyboard.release();
+    }
+}
+
+/// What the application does with a key press, real or repeated.
+pub(crate) fn handle_key(state: &mut AppState, event: KeyEvent) {
+    debug!(keysym = event.keysym, utf8 = ?event.utf8, repeat = event.repeat, "key pressed");
+
+    // Typed into the password prompt
+    if state.session_lock.is_some() {
+        lock::handle_key(state, &event);
+        return;
+    }
+    if state.app.as_mut().is_some_and(|app| app.on_key(&event)) {
+        if let Some(window) = state.focused_window().and_then(|id| state.window_mut(id)) {
+            window.request_redraw();
+        }
+        present_if_needed(state);
+        return;
+    }
+    // Draws on a screenshot, types its labels
+    if annotate::handle_key(state, &event) {
+        return;
+    }
+    // Moves around in the pan demo
+    if viewport::handle_key(state, event.keysym) {
+        return;
+    }
+    // Steers in the snake game
+    if snake::handle_key(state, event.keysym) {
+        return;
+    }
+    // Changes how the font preview draws
+    if !event.repeat && fontview::handle_key(state, event.keysym) {
+        return;
+    }
+
+    let Some(keyboard) = state.keyboard_mut() else {
+        return;
+    };
+    let mods = keyboard.modifiers;
+    let plain = !(mods.ctrl || mods.alt || mods.logo);
+    let focused = state.focused_window();
+    match event.keysym {
+        // Closes the context menu first, if it is open
+        keysyms::Escape if state.menu.is_some() => menu::close(state),
+        keysyms::Escape => match focused {
+            Some(id) => window::request_close(state, id),
+            None => state.running = false,
+        },
+        keysyms::q if plain => state.running = false,
+        keysyms::i if plain && !event.repeat => idle_inhibit::toggle_idle_inhibit(state),
+        // Turns the buffers by another 90 degrees, the compositor turns
+        // them back
+        keysyms::t if plain && !event.repeat => {
+            if let Some(window) = focused.and_then(|id| state.window_mut(id)) {
+                window.set_transform(transform::rotate(window.transform()));
+                present_if_needed(state);
+            }
+        }
+        keysyms::c if mods.ctrl && !event.repeat => clipboard::copy(state),
+        keysyms::v if mods.ctrl && !event.repeat => clipboard::paste(state),
+        keysyms::n if mods.ctrl && !event.repeat => {
+            // Set up like the one the user works with
+            let options = focused
+                .and_then(|id| state.window(id))
+                .or(state.windows.first())
+                .map(|w| w.options().clone())
+                .unwrap_or_default();
+            if let Err(err) = window::open(state, options) {
+                warn!(%err, "failed to open another window");
+            }
+        }
+        // Shift+N: hands the focus over to the new instance
+        keysyms::N if mods.ctrl && !event.repeat => activation::launch_another(state),
+        // The new size arrives with the next configure
+        keysyms::F11 if !event.repeat => {
+            if let Some(window) = focused.and_then(|id| state.window(id)) {
+                window.toggle_fullscreen();
+            }
+        }
+        keysyms::F10 if !event.repeat => {
+            if let Some(window) = focused.and_then(|id| state.window(id)) {
+                window.toggle_maximized();
+            }
+        }
+        keysyms::Print if !event.repeat => {
+            if let Some(id) = focused {
+                screenshot::take(state, id);
+                annotate::start(state, id);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Starts repeating `key` once it has been held for the repeat delay.
+pub(crate) fn start_key_repeat(state: &mut AppState, key: u32) {
+    stop_key_repeat(state);
+
+    let Some(keyboard) = state.keyboard_mut() else {
+        return;
+    };
+    let Some(interval) = keyboard.repeat_info.interval() else {
+        return;
+    };
+    if !keyboard.key_repeats(key) {
+        return;
+    }
+
+    let delay = keyboard.repeat_info.delay;
+    let id = state.timers.after(delay, move |state| {
+        let id = state
+            .timers
+            .every(interval, move |state| repeat_key(state, key));
+        if let Some(keyboard) = state.keyboard_mut() {
+            keyboard.repeating = Some((key, id));
+        }
+        repeat_key(state, key);
+    });
+
+    if let Some(keyboard) = state.keyboard_mut() {
+        keyboard.repeating = Some((key, id));
+    }
+}
+
+pub(crate) fn stop_key_repeat(state: &mut AppState) {
+    if let Some((_, id)) = state.keyboard_mut().and_then(|k| k.repeating.take()) {
+        state.timers.cancel(id);
+    }
+}
+
+pub(crate) fn repeat_key(state: &mut AppState, key: u32) {
+    // Translated again, the modifiers may have changed in the meantime
+    if let Some(event) = state.keyboard_mut().and_then(|k| k.key(key, true)) {
+        handle_key(state, event);
+    }
+}
+
+impl Dispatch<WlKeyboard, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlKeyboard,
+        event: <WlKeyboard as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(keyboard) = state.keyboard_mut() else {
+            return;
+        };
+
+        match event {
+            wl_keyboard::Event::Keymap { format, fd, size } => {
+                debug!(?format, size, "keymap");
+                if let Err(err) = keyboard.set_keymap(format, fd, size) {
+                    warn!(%err, "failed to load the keymap, ignoring key presses");
+                }
+                stop_key_repeat(state);
+            }
+            wl_keyboard::Event::Enter { surface, .. } => {
+                debug!("keyboard focus gained");
+                keyboard.focus = Some(surface);
+            }
+            wl_keyboard::Event::Leave { .. } => {
+                debug!("keyboard focus lost");
+                keyboard.focus = None;
+                stop_key_repeat(state);
+            }
+            wl_keyboard::Event::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+                ..
+            } => {
+                keyboard.update_modifiers(mods_depressed, mods_latched, mods_locked, group);
+            }
+            wl_keyboard::Event::RepeatInfo { rate, delay } => {
+                debug!(rate, delay, "key repeat info");
+                keyboard.repeat_info = RepeatInfo {
+                    rate: rate.max(0) as u32,
+                    delay: Duration::from_millis(delay.max(0) as u64),
+                };
+            }
+            wl_keyboard::Event::Key {
+                serial,
+                key,
+                state: WEnum::Value(key_state),
+                ..
+            } => match key_state {
+                wl_keyboard::KeyState::Pressed => {
+                    let Some(event) = keyboard.key(key, false) else {
+                        return;
+                    };
+                    if let Some(seat) = state.seat.as_mut() {
+                        seat.last_serial = Some(serial);
+                    }
+                    start_key_repeat(state, key);
+                    handle_key(state, event);
+                }
+                wl_keyboard::KeyState::Released
+                    if keyboard.repeating.is_some_and(|(k, _)| k == key) =>
+                {
+                    stop_key_repeat(state);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WpCursorShapeManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpCursorShapeManagerV1,
+        _event: <WpCursorShapeManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<WpCursorShapeDeviceV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpCursorShapeDeviceV1,
+        _event: <WpCursorShapeDeviceV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
@@ -0,0 +1,140 @@
+//! The control socket: a Unix socket in `$XDG_RUNTIME_DIR` that another
+//! instance, started with `--send`, writes a command to. Each connection
+//! carries one command, and gets a line of text back once the sender shut
+//! down its side.
+
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::{
+        fd::OwnedFd,
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+};
+
+use calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction};
+use tracing::{debug, info, warn};
+
+use crate::state::AppState;
+
+/// Handles a command and returns the answer to it.
+pub(crate) type CommandHandler = fn(&mut AppState, &str) -> String;
+
+/// Where the socket called `name` lives, `None` without a runtime directory.
+fn socket_path(name: &str) -> Option<PathBuf> {
+    let dir = env::var_os("XDG_RUNTIME_DIR")?;
+    Some(PathBuf::from(dir).join(format!("rust-wayland-{name}.sock")))
+}
+
+fn no_runtime_dir() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set")
+}
+
+/// A socket we listen on, removed again when dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            debug!(path = %self.path.display(), %err, "failed to remove the control socket");
+        }
+    }
+}
+
+/// Listens on the socket called `name` from the main loop, and answers the
+/// commands that arrive with `handler`. Fails if another instance listens
+/// on it already.
+pub(crate) fn listen(
+    handle: &LoopHandle<'static, AppState>,
+    name: &str,
+    handler: CommandHandler,
+) -> io::Result<ControlSocket> {
+    let path = socket_path(name).ok_or_else(no_runtime_dir)?;
+    // Left behind by an instance that didn't get to clean up
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another instance listens on {}", path.display()),
+            ));
+        }
+        fs::remove_file(&path)?;
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    // Removes the socket if anything below fails
+    let socket = ControlSocket { path };
+    listener.set_nonblocking(true)?;
+
+    let source = Generic::new(listener, Interest::READ, Mode::Level);
+    handle
+        .insert_source(source, move |_, listener, state| {
+            loop {
+                match listener.accept() {
+                    Ok((stream, _)) => receive(state, stream, handler),
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => {
+                        warn!(%err, "failed to accept a control connection");
+                        break;
+                    }
+                }
+            }
+            Ok(PostAction::Continue)
+        })
+        .map_err(|err| err.error)?;
+
+    info!(path = %socket.path.display(), "listening for commands");
+    Ok(socket)
+}
+
+/// Reads the command sent over `stream` and writes back the answer.
+fn receive(state: &mut AppState, stream: UnixStream, handler: CommandHandler) {
+    let reader = match stream.try_clone() {
+        Ok(reader) => OwnedFd::from(reader),
+        Err(err) => {
+            warn!(%err, "failed to read a command");
+            return;
+        }
+    };
+    let res = state.pipes.read_to_end(reader, move |state, res| {
+        let command = match res.map(String::from_utf8) {
+            Ok(Ok(command)) => command,
+            Ok(Err(err)) => {
+                warn!(%err, "ignoring a command that isn't UTF-8");
+                return;
+            }
+            Err(err) => {
+                warn!(%err, "failed to read a command");
+                return;
+            }
+        };
+        let command = command.trim();
+        debug!(command, "got a command");
+        let mut answer = handler(state, command);
+        answer.push('\n');
+        if let Err(err) = state.pipes.write_all(stream.into(), answer.into_bytes()) {
+            warn!(%err, "failed to answer a command");
+        }
+    });
+    if let Err(err) = res {
+        warn!(%err, "failed to read a command");
+    }
+}
+
+/// Sends `command` to the instance listening on the socket called `name`,
+/// and returns its answer.
+pub fn send(name: &str, command: &str) -> io::Result<String> {
+    let path = socket_path(name).ok_or_else(no_runtime_dir)?;
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(command.as_bytes())?;
+    // Tells the other end that the command is complete
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer)?;
+    Ok(answer)
+}
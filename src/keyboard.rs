@@ -0,0 +1,283 @@
+//! Keyboard input: turns the compositor's keycodes into keysyms and text
+//! using the keymap it sends us.
+//!
+//! libxkbcommon is loaded at runtime with dlopen(), so we still build and run
+//! without it, we just can't make sense of key presses then.
+
+use std::{
+    ffi::c_char,
+    io,
+    os::fd::{AsRawFd, OwnedFd},
+    ptr, slice,
+    time::Duration,
+};
+
+use crate::timer::TimerId;
+use thiserror::Error;
+use wayland_client::{
+    protocol::{
+        wl_keyboard::{KeymapFormat, WlKeyboard},
+        wl_surface::WlSurface,
+    },
+    WEnum,
+};
+use xkbcommon_dl::{
+    xkb_context, xkb_context_flags, xkb_keymap, xkb_keymap_compile_flags, xkb_keymap_format,
+    xkb_keysym_t, xkb_state, xkb_state_component, xkbcommon_option, XkbCommon, XKB_MOD_NAME_ALT,
+    XKB_MOD_NAME_CTRL, XKB_MOD_NAME_LOGO, XKB_MOD_NAME_SHIFT,
+};
+
+// Keycodes on the wire are evdev codes, xkb ones are offset by 8 for
+// historical X11 reasons.
+const EVDEV_OFFSET: u32 = 8;
+
+#[derive(Debug, Error)]
+pub enum KeymapError {
+    #[error("libxkbcommon could not be loaded")]
+    Library,
+    #[error("unsupported keymap format {0:?}")]
+    Format(WEnum<KeymapFormat>),
+    #[error("failed to map the keymap: {0}")]
+    Map(#[source] io::Error),
+    #[error("failed to compile the keymap")]
+    Compile,
+}
+
+/// Modifiers that are currently in effect.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub logo: bool,
+}
+
+/// A key press translated with the current keymap and modifiers.
+#[derive(Debug)]
+pub struct KeyEvent {
+    pub keysym: xkb_keysym_t,
+    /// The text the key produces, if any
+    pub utf8: Option<String>,
+    /// Generated by us because the key is held down
+    pub repeat: bool,
+}
+
+/// How held keys repeat, from `wl_keyboard.repeat_info`.
+#[derive(Debug, Clone, Copy)]
+pub struct RepeatInfo {
+    /// Keys per second, 0 disables repeat
+    pub rate: u32,
+    pub delay: Duration,
+}
+
+impl Default for RepeatInfo {
+    // What most compositors use, until we are told otherwise
+    fn default() -> Self {
+        Self {
+            rate: 25,
+            delay: Duration::from_millis(600),
+        }
+    }
+}
+
+impl RepeatInfo {
+    /// Time between two repeats, `None` if keys don't repeat.
+    pub fn interval(&self) -> Option<Duration> {
+        (self.rate > 0).then(|| Duration::from_secs(1) / self.rate)
+    }
+}
+
+/// A compiled keymap and the modifier state that goes with it.
+struct Xkb {
+    lib: &'static XkbCommon,
+    context: *mut xkb_context,
+    keymap: *mut xkb_keymap,
+    state: *mut xkb_state,
+}
+
+impl Xkb {
+    fn new(keymap: &[u8]) -> Result<Self, KeymapError> {
+        let lib = xkbcommon_option().ok_or(KeymapError::Library)?;
+
+        // The keymap is NUL terminated, libxkbcommon doesn't want to see that
+        let len = keymap.iter().position(|&b| b == 0).unwrap_or(keymap.len());
+
+        unsafe {
+            let context = (lib.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return Err(KeymapError::Compile);
+            }
+
+            let keymap = (lib.xkb_keymap_new_from_buffer)(
+                context,
+                keymap.as_ptr().cast(),
+                len,
+                xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            );
+            if keymap.is_null() {
+                (lib.xkb_context_unref)(context);
+                return Err(KeymapError::Compile);
+            }
+
+            let state = (lib.xkb_state_new)(keymap);
+            if state.is_null() {
+                (lib.xkb_keymap_unref)(keymap);
+                (lib.xkb_context_unref)(context);
+                return Err(KeymapError::Compile);
+            }
+
+            Ok(Self {
+                lib,
+                context,
+                keymap,
+                state,
+            })
+        }
+    }
+
+    fn is_active(&self, name: &[u8]) -> bool {
+        let active = unsafe {
+            (self.lib.xkb_state_mod_name_is_active)(
+                self.state,
+                name.as_ptr().cast(),
+                xkb_state_component::XKB_STATE_MODS_EFFECTIVE,
+            )
+        };
+        active > 0
+    }
+
+    fn utf8(&self, keycode: u32) -> Option<String> {
+        let get_utf8 = &self.lib.xkb_state_key_get_utf8;
+
+        // Ask for the length first, it doesn't include the NUL terminator
+        let len = unsafe { get_utf8(self.state, keycode, ptr::null_mut(), 0) };
+        if len <= 0 {
+            return None;
+        }
+
+        let mut buf = vec![0u8; len as usize + 1];
+        unsafe {
+            get_utf8(
+                self.state,
+                keycode,
+                buf.as_mut_ptr().cast::<c_char>(),
+                buf.len(),
+            )
+        };
+        buf.truncate(len as usize);
+
+        String::from_utf8(buf).ok()
+    }
+}
+
+impl Drop for Xkb {
+    fn drop(&mut self) {
+        unsafe {
+            (self.lib.xkb_state_unref)(self.state);
+            (self.lib.xkb_keymap_unref)(self.keymap);
+            (self.lib.xkb_context_unref)(self.context);
+        }
+    }
+}
+
+pub struct Keyboard {
+    pub keyboard: WlKeyboard,
+    pub modifiers: Modifiers,
+    pub repeat_info: RepeatInfo,
+    // The key being repeated and the timer doing it
+    pub repeating: Option<(u32, TimerId)>,
+    // The surface with keyboard focus, if it is one of ours
+    pub focus: Option<WlSurface>,
+    // None until the compositor sent a keymap we could load
+    xkb: Option<Xkb>,
+}
+
+impl Keyboard {
+    pub fn new(keyboard: WlKeyboard) -> Self {
+        Self {
+            keyboard,
+            modifiers: Modifiers::default(),
+            repeat_info: RepeatInfo::default(),
+            repeating: None,
+            focus: None,
+            xkb: None,
+        }
+    }
+
+    /// Loads the keymap the compositor sent, replacing the previous one.
+    pub fn set_keymap(
+        &mut self,
+        format: WEnum<KeymapFormat>,
+        fd: OwnedFd,
+        size: u32,
+    ) -> Result<(), KeymapError> {
+        self.xkb = None;
+        if format != WEnum::Value(KeymapFormat::XkbV1) {
+            return Err(KeymapError::Format(format));
+        }
+
+        let size = size as usize;
+        // Must be MAP_PRIVATE, the compositor may hand out the same fd to
+        // every client.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(KeymapError::Map(io::Error::last_os_error()));
+        }
+
+        let res = Xkb::new(unsafe { slice::from_raw_parts(ptr.cast::<u8>(), size) });
+        unsafe { libc::munmap(ptr, size) };
+
+        self.xkb = Some(res?);
+        Ok(())
+    }
+
+    /// Applies the modifier state from a `wl_keyboard.modifiers` event.
+    pub fn update_modifiers(&mut self, depressed: u32, latched: u32, locked: u32, group: u32) {
+        let Some(xkb) = &self.xkb else {
+            return;
+        };
+
+        unsafe {
+            (xkb.lib.xkb_state_update_mask)(xkb.state, depressed, latched, locked, 0, 0, group);
+        }
+
+        self.modifiers = Modifiers {
+            shift: xkb.is_active(XKB_MOD_NAME_SHIFT),
+            ctrl: xkb.is_active(XKB_MOD_NAME_CTRL),
+            alt: xkb.is_active(XKB_MOD_NAME_ALT),
+            logo: xkb.is_active(XKB_MOD_NAME_LOGO),
+        };
+    }
+
+    /// Translates the evdev keycode of a `wl_keyboard.key` event.
+    pub fn key(&self, key: u32, repeat: bool) -> Option<KeyEvent> {
+        let xkb = self.xkb.as_ref()?;
+        let keycode = key + EVDEV_OFFSET;
+
+        let keysym = unsafe { (xkb.lib.xkb_state_key_get_one_sym)(xkb.state, keycode) };
+        Some(KeyEvent {
+            keysym,
+            utf8: xkb.utf8(keycode),
+            repeat,
+        })
+    }
+
+    /// Whether the keymap says `key` repeats when held, modifiers don't.
+    pub fn key_repeats(&self, key: u32) -> bool {
+        let Some(xkb) = &self.xkb else {
+            return false;
+        };
+
+        unsafe { (xkb.lib.xkb_keymap_key_repeats)(xkb.keymap, key + EVDEV_OFFSET) > 0 }
+    }
+}
@@ -0,0 +1,176 @@
+//! Layer surfaces from wlr-layer-shell: panels, overlays and wallpapers that
+//! the compositor stacks in layers around the regular windows.
+
+use tracing::{debug, info};
+use wayland_client::{Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::{Layer, ZwlrLayerShellV1},
+    zwlr_layer_surface_v1::{self, Anchor, KeyboardInteractivity, ZwlrLayerSurfaceV1},
+};
+
+use crate::{
+    state::AppState,
+    window::{self, WindowId, WindowState, DEFAULT_SIZE},
+};
+
+// Height of the bar placed in the top and bottom layers
+const BAR_HEIGHT: u32 = 32;
+
+/// Where a layer surface goes and how it behaves.
+#[derive(Debug, Clone)]
+pub struct LayerOptions {
+    pub layer: Layer,
+    /// The edges of the output the surface sticks to
+    pub anchor: Anchor,
+    /// How far from the anchored edge other surfaces should stay. 0 to
+    /// be placed among them, -1 to extend under panels too.
+    pub exclusive_zone: i32,
+    pub keyboard_interactivity: KeyboardInteractivity,
+    /// 0 stretches the surface between the opposite edges it is anchored to
+    pub size: (u32, u32),
+}
+
+impl LayerOptions {
+    /// What a surface in `layer` usually is: a wallpaper filling the output
+    /// in the background, a bar along the top of the output in the bottom
+    /// and top layers, and a window in the middle of the screen as an
+    /// overlay.
+    pub fn for_layer(layer: Layer) -> Self {
+        let everywhere = Anchor::Top | Anchor::Bottom | Anchor::Left | Anchor::Right;
+        let bar = Anchor::Top | Anchor::Left | Anchor::Right;
+
+        match layer {
+            Layer::Background => Self {
+                layer,
+                anchor: everywhere,
+                exclusive_zone: -1,
+                keyboard_interactivity: KeyboardInteractivity::None,
+                size: (0, 0),
+            },
+            Layer::Overlay => Self {
+                layer,
+                anchor: Anchor::empty(),
+                exclusive_zone: 0,
+                keyboard_interactivity: KeyboardInteractivity::OnDemand,
+                size: (DEFAULT_SIZE.0 as u32, DEFAULT_SIZE.1 as u32),
+            },
+            _ => Self {
+                layer,
+                anchor: bar,
+                exclusive_zone: BAR_HEIGHT as i32,
+                keyboard_interactivity: KeyboardInteractivity::OnDemand,
+                size: (0, BAR_HEIGHT),
+            },
+        }
+    }
+
+    /// Sets the initial state of `surface`, before its first commit.
+    pub(crate) fn apply(&self, surface: &ZwlrLayerSurfaceV1) {
+        // on_demand is only known since version 4, exclusive is the
+        // closest to it before
+        let keyboard_interactivity = match self.keyboard_interactivity {
+            KeyboardInteractivity::OnDemand if surface.version() < 4 => {
+                KeyboardInteractivity::Exclusive
+            }
+            interactivity => interactivity,
+        };
+
+        surface.set_size(self.size.0, self.size.1);
+        surface.set_anchor(self.anchor);
+        surface.set_exclusive_zone(self.exclusive_zone);
+        surface.set_keyboard_interactivity(keyboard_interactivity);
+    }
+}
+
+/// Parses the name of a layer, as in the protocol.
+pub fn parse_layer(name: &str) -> Option<Layer> {
+    match name {
+        "background" => Some(Layer::Background),
+        "bottom" => Some(Layer::Bottom),
+        "top" => Some(Layer::Top),
+        "overlay" => Some(Layer::Overlay),
+        _ => None,
+    }
+}
+
+/// Parses a comma separated list of edges, e.g. `top,left,right`. An empty
+/// list centers the surface.
+pub fn parse_anchor(edges: &str) -> Option<Anchor> {
+    edges
+        .split(',')
+        .filter(|edge| !edge.is_empty())
+        .map(|edge| match edge {
+            "top" => Some(Anchor::Top),
+            "bottom" => Some(Anchor::Bottom),
+            "left" => Some(Anchor::Left),
+            "right" => Some(Anchor::Right),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|anchors| anchors.into_iter().fold(Anchor::empty(), |all, a| all | a))
+}
+
+impl Dispatch<ZwlrLayerShellV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwlrLayerShellV1,
+        _event: <ZwlrLayerShellV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<ZwlrLayerSurfaceV1, WindowId> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrLayerSurfaceV1,
+        event: <ZwlrLayerSurfaceV1 as Proxy>::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_layer_surface_v1::Event::Configure {
+                serial,
+                width,
+                height,
+            } => {
+                info!(serial, width, height, "layer surface configure event");
+                proxy.ack_configure(serial);
+
+                let Some(window) = state.window_mut(*id) else {
+                    return;
+                };
+                // Layer surfaces have no focus to lose, never dim them
+                window.configure(width as i32, height as i32, WindowState::ACTIVATED);
+                window::configured(state, *id);
+            }
+            // E.g. the output went away
+            zwlr_layer_surface_v1::Event::Closed => {
+                debug!(?id, "layer surface closed");
+                window::close(state, *id);
+            }
+            event => debug!(?event, "ignoring unknown layer surface event"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anchors_are_parsed() {
+        assert_eq!(
+            parse_anchor("top,left,right"),
+            Some(Anchor::Top | Anchor::Left | Anchor::Right)
+        );
+        assert_eq!(parse_anchor(""), Some(Anchor::empty()));
+        assert_eq!(parse_anchor("top,middle"), None);
+        assert_eq!(parse_layer("overlay"), Some(Layer::Overlay));
+        assert_eq!(parse_layer("window"), None);
+    }
+}
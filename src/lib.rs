@@ -0,0 +1,66 @@
+#![warn(clippy::all)]
+//! A small Wayland client: one window drawn into shm buffers, with pointer
+//! and keyboard input.
+
+pub mod activation;
+pub mod animation;
+pub mod annotate;
+pub mod app;
+#[cfg(feature = "tokio")]
+pub mod async_loop;
+pub mod bench;
+pub mod buffer_stats;
+pub mod buffers;
+pub mod clipboard;
+pub mod clipboard_watch;
+pub mod clock;
+pub mod color;
+pub mod cursor;
+pub mod damage;
+pub mod decorations;
+pub mod dnd;
+pub mod dock;
+pub mod error;
+pub mod event_loop;
+pub mod fontview;
+pub mod hit_test;
+pub mod hud;
+pub mod idle_inhibit;
+mod input;
+pub mod ipc;
+pub mod keyboard;
+pub mod layer;
+pub mod lock;
+pub mod menu;
+pub mod monitor;
+pub mod output;
+pub mod pam;
+pub mod pipes;
+pub mod pixel_buffer;
+pub mod pixel_format;
+pub mod pomodoro;
+pub mod popup;
+pub mod presentation;
+pub mod qr;
+pub mod rect;
+pub mod registry;
+mod render;
+mod render_thread;
+pub mod scale;
+pub mod scene;
+pub mod screenshot;
+pub mod seat;
+pub mod select;
+pub mod shm;
+mod single_pixel;
+pub mod snake;
+mod state;
+pub mod subsurface;
+pub mod svg;
+pub mod systemd;
+pub mod text;
+pub mod timer;
+pub mod transform;
+pub mod viewport;
+pub mod watchdog;
+pub mod window;
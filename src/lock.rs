@@ -0,0 +1,627 @@
+//! A screen locker on top of ext-session-lock: covers every output with a
+//! lock surface showing a password prompt, and unlocks once PAM accepts the
+//! password typed into it. Wrong passwords shake the prompt, and after a few
+//! of them in a row the next one has to wait.
+
+use std::{
+    f32::consts::TAU,
+    fs::File,
+    io::Write,
+    mem,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, error, info, warn};
+use wayland_client::{
+    protocol::{wl_buffer::WlBuffer, wl_output::WlOutput, wl_surface::WlSurface},
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+use wayland_protocols::ext::session_lock::v1::client::{
+    ext_session_lock_manager_v1::ExtSessionLockManagerV1,
+    ext_session_lock_surface_v1::{self, ExtSessionLockSurfaceV1},
+    ext_session_lock_v1::{self, ExtSessionLockV1},
+};
+
+use xkbcommon_dl::keysyms;
+
+use crate::{
+    buffers::Swapchain,
+    color::Color,
+    error::Error,
+    keyboard::KeyEvent,
+    pam::Pam,
+    pipes,
+    pixel_buffer::PixelBuffer,
+    rect::Rect,
+    render,
+    state::{required, AppState},
+    systemd,
+    text::{self, Font},
+    watchdog::{self, Phase},
+};
+
+const BACKGROUND: Color = Color::rgb(0x1B, 0x26, 0x33);
+const PROMPT: Color = Color::rgb(0x2E, 0x3B, 0x4A);
+const PROMPT_BORDER: Color = Color::rgb(0x5A, 0x6E, 0x84);
+const FAILED_BORDER: Color = Color::rgb(0xE0, 0x60, 0x50);
+const DOT: Color = Color::rgb(0xE0, 0xE0, 0xE0);
+const STATUS: Color = Color::rgb(0xB0, 0xB8, 0xC0);
+
+const PROMPT_SIZE: (usize, usize) = (320, 48);
+// One for each character typed, as many as fit
+const DOT_SIZE: usize = 10;
+const DOT_SPACING: usize = 2 * DOT_SIZE;
+const STATUS_FONT_SIZE: f32 = 16.0;
+const STATUS_GAP: usize = 16;
+
+// Wrong passwords in a row that cost nothing more than PAM's own delay
+const FREE_ATTEMPTS: u32 = 3;
+// The wait after the next one, doubling with each one after that
+const FIRST_LOCKOUT: Duration = Duration::from_secs(5);
+const MAX_LOCKOUT: Duration = Duration::from_secs(5 * 60);
+
+const SHAKE_DURATION: Duration = Duration::from_millis(400);
+const SHAKE_FRAME: Duration = Duration::from_millis(16);
+const SHAKE_AMPLITUDE: f32 = 16.0;
+// Back and forth this many times
+const SHAKE_SWINGS: f32 = 4.0;
+
+// What the thread checking a password writes back
+const ACCEPTED: u8 = 1;
+const REJECTED: u8 = 0;
+
+/// How long to wait before checking another password, after `failures`
+/// wrong ones in a row.
+fn lockout(failures: u32) -> Duration {
+    let Some(over) = failures.checked_sub(FREE_ATTEMPTS) else {
+        return Duration::ZERO;
+    };
+    FIRST_LOCKOUT
+        .saturating_mul(2u32.saturating_pow(over))
+        .min(MAX_LOCKOUT)
+}
+
+/// How far the prompt is moved sideways `elapsed` into shaking it.
+fn shake_offset(elapsed: Duration) -> isize {
+    let t = elapsed.as_secs_f32() / SHAKE_DURATION.as_secs_f32();
+    if t >= 1.0 {
+        return 0;
+    }
+    // Swings less and less wide
+    let swing = (t * SHAKE_SWINGS * TAU).sin();
+    (swing * SHAKE_AMPLITUDE * (1.0 - t)).round() as isize
+}
+
+/// What was typed into the prompt, zeroed once no longer needed.
+#[derive(Default)]
+struct Password(String);
+
+impl Password {
+    /// How many dots it shows as.
+    fn len(&self) -> usize {
+        self.0.chars().count()
+    }
+
+    fn clear(&mut self) {
+        // SAFETY: zeros are valid UTF-8
+        unsafe { self.0.as_mut_vec() }.fill(0);
+        self.0.clear();
+    }
+}
+
+impl Drop for Password {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// The lock surface covering one output.
+struct LockSurface {
+    output: WlOutput,
+    surface: WlSurface,
+    lock_surface: ExtSessionLockSurfaceV1,
+    buffers: Swapchain,
+    // The size of the output, known from the first configure on
+    size: Option<(usize, usize)>,
+    // All buffers were with the compositor when the last frame was due
+    needs_redraw: bool,
+}
+
+impl LockSurface {
+    /// Destroys the role before the surface, as the protocol requires.
+    fn destroy(self) {
+        self.lock_surface.destroy();
+        self.surface.destroy();
+    }
+}
+
+/// The session lock, from asking for it until we unlock.
+pub struct SessionLock {
+    lock: ExtSessionLockV1,
+    surfaces: Vec<LockSurface>,
+    // Set once the compositor confirmed that the session is locked
+    locked: bool,
+    pam: Arc<Pam>,
+    password: Password,
+    // A password is being checked, on a thread of its own
+    checking: bool,
+    // Wrong passwords in a row
+    failures: u32,
+    // No password is taken before then
+    locked_out_until: Option<Instant>,
+    shake_started: Option<Instant>,
+    // No status line without it
+    font: Option<Arc<Font>>,
+}
+
+impl SessionLock {
+    fn is_locked_out(&self, now: Instant) -> bool {
+        self.locked_out_until.is_some_and(|until| now < until)
+    }
+
+    /// What the line under the prompt says, if anything.
+    fn status(&self, now: Instant) -> Option<String> {
+        if self.checking {
+            return Some("Checking…".to_owned());
+        }
+        if let Some(left) = self
+            .locked_out_until
+            .and_then(|until| until.checked_duration_since(now))
+        {
+            // Rounded up, so that it never says 0
+            let seconds = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+            return Some(format!("Too many attempts, try again in {seconds} s"));
+        }
+        (self.failures > 0 && self.password.0.is_empty()).then(|| "Wrong password".to_owned())
+    }
+
+    /// Lets go of the lock. Unlocking before the compositor confirmed the
+    /// lock is a protocol error, destroying it then just gives up on it.
+    pub(crate) fn destroy(self) {
+        if self.locked {
+            info!("unlocking the session");
+            self.lock.unlock_and_destroy();
+        } else {
+            self.lock.destroy();
+        }
+        for surface in self.surfaces {
+            surface.destroy();
+        }
+    }
+}
+
+/// Locks the session and covers every output we know about. The compositor
+/// confirms with the `locked` event once all of them are covered.
+pub(crate) fn lock(state: &mut AppState) -> Result<(), Error> {
+    let manager = required(&state.session_lock_manager)?;
+    let qh = &state.queue_handle;
+    // Without it the session could never be unlocked again
+    let pam = Pam::load()?;
+    let font = Font::find(text::SANS)
+        .inspect_err(|err| warn!(%err, "no font for the lock screen"))
+        .ok()
+        .map(Arc::new);
+
+    info!(outputs = state.outputs.len(), "locking the session");
+    state.session_lock = Some(SessionLock {
+        lock: manager.lock(qh, ()),
+        surfaces: Vec::new(),
+        locked: false,
+        pam: Arc::new(pam),
+        password: Password::default(),
+        checking: false,
+        failures: 0,
+        locked_out_until: None,
+        shake_started: None,
+        font,
+    });
+
+    let outputs: Vec<_> = state.outputs.iter().map(|o| o.wl_output.clone()).collect();
+    for output in outputs {
+        output_added(state, &output);
+    }
+    Ok(())
+}
+
+/// Covers an output plugged in while locked. It stays black until then, the
+/// compositor never shows what's behind the lock.
+pub(crate) fn output_added(state: &mut AppState, output: &WlOutput) {
+    let qh = &state.queue_handle;
+    let (Some(session_lock), Ok(compositor)) =
+        (state.session_lock.as_mut(), required(&state.compositor))
+    else {
+        return;
+    };
+
+    let surface = compositor.create_surface(qh, ());
+    let lock_surface = session_lock.lock.get_lock_surface(&surface, output, qh, ());
+    session_lock.surfaces.push(LockSurface {
+        output: output.clone(),
+        surface,
+        lock_surface,
+        buffers: Swapchain::new(state.format),
+        size: None,
+        needs_redraw: false,
+    });
+}
+
+/// Drops the lock surface of an unplugged output.
+pub(crate) fn output_removed(state: &mut AppState, output: &WlOutput) {
+    let Some(session_lock) = state.session_lock.as_mut() else {
+        return;
+    };
+    if let Some(i) = session_lock
+        .surfaces
+        .iter()
+        .position(|s| &s.output == output)
+    {
+        session_lock.surfaces.swap_remove(i).destroy();
+    }
+}
+
+/// Unlocks the session and quits, we have nothing else to show.
+pub(crate) fn unlock(state: &mut AppState) {
+    state.running = false;
+    if let Some(session_lock) = state.session_lock.take() {
+        session_lock.destroy();
+    }
+}
+
+/// Types into the password prompt, and checks the password on Enter.
+pub(crate) fn handle_key(state: &mut AppState, event: &KeyEvent) {
+    let Some(session_lock) = state.session_lock.as_mut() else {
+        return;
+    };
+    if session_lock.checking || session_lock.is_locked_out(Instant::now()) {
+        return;
+    }
+
+    let password = &mut session_lock.password;
+    match event.keysym {
+        keysyms::Return | keysyms::KP_Enter if !event.repeat => {
+            authenticate(state);
+            return;
+        }
+        keysyms::BackSpace => {
+            password.0.pop();
+        }
+        keysyms::Escape => password.clear(),
+        _ => match event.utf8.as_deref() {
+            Some(typed) if !typed.chars().any(char::is_control) => password.0.push_str(typed),
+            _ => return,
+        },
+    }
+    redraw(state);
+}
+
+/// Checks the password typed on a thread, PAM takes its time, and unlocks
+/// if it is right.
+fn authenticate(state: &mut AppState) {
+    if state
+        .session_lock
+        .as_ref()
+        .is_none_or(|session_lock| session_lock.password.0.is_empty())
+    {
+        return;
+    }
+
+    let (read, write) = match pipes::pipe() {
+        Ok(pipe) => pipe,
+        Err(err) => {
+            warn!(%err, "failed to create a pipe to check the password through");
+            return;
+        }
+    };
+    let res = state.pipes.read_to_end(read, |state, res| {
+        let accepted = matches!(res.as_deref(), Ok([ACCEPTED]));
+        checked(state, accepted);
+    });
+    if let Err(err) = res {
+        warn!(%err, "failed to check the password");
+        return;
+    }
+
+    let Some(session_lock) = state.session_lock.as_mut() else {
+        return;
+    };
+    let password = mem::take(&mut session_lock.password);
+    let pam = session_lock.pam.clone();
+    session_lock.checking = true;
+    thread::spawn(move || {
+        let verdict = match pam.authenticate(&password.0) {
+            Ok(()) => ACCEPTED,
+            Err(err) => {
+                info!(%err, "the password was rejected");
+                REJECTED
+            }
+        };
+        drop(password);
+        // Only fails if the lock is gone already, and closing it tells the
+        // main loop all the same
+        let _ = File::from(write).write_all(&[verdict]);
+    });
+    redraw(state);
+}
+
+/// PAM is done with the password, unlocks if it was right and shakes the
+/// prompt if not.
+fn checked(state: &mut AppState, accepted: bool) {
+    let Some(session_lock) = state.session_lock.as_mut() else {
+        return;
+    };
+    session_lock.checking = false;
+    if accepted {
+        info!("the password was accepted");
+        unlock(state);
+        return;
+    }
+
+    session_lock.failures += 1;
+    let now = Instant::now();
+    let wait = lockout(session_lock.failures);
+    warn!(failures = session_lock.failures, ?wait, "wrong password");
+    if !wait.is_zero() {
+        session_lock.locked_out_until = Some(now + wait);
+        state.timers.after(Duration::ZERO, count_down);
+    }
+    session_lock.shake_started = Some(now);
+    shake(state);
+}
+
+/// Draws the next frame of the shaking prompt, until it is done.
+fn shake(state: &mut AppState) {
+    let Some(session_lock) = state.session_lock.as_mut() else {
+        return;
+    };
+    if session_lock
+        .shake_started
+        .is_some_and(|started| started.elapsed() < SHAKE_DURATION)
+    {
+        state.timers.after(SHAKE_FRAME, shake);
+    } else {
+        session_lock.shake_started = None;
+    }
+    redraw(state);
+}
+
+/// Shows how long is left to wait, every second until it is over.
+fn count_down(state: &mut AppState) {
+    let Some(session_lock) = state.session_lock.as_mut() else {
+        return;
+    };
+    let now = Instant::now();
+    match session_lock
+        .locked_out_until
+        .and_then(|until| until.checked_duration_since(now))
+    {
+        Some(left) if !left.is_zero() => {
+            // On the next change of the seconds shown
+            let next = match left.subsec_nanos() {
+                0 => Duration::from_secs(1),
+                nanos => Duration::from_nanos(nanos.into()),
+            };
+            state.timers.after(next, count_down);
+        }
+        _ => session_lock.locked_out_until = None,
+    }
+    redraw(state);
+}
+
+/// Redraws every lock surface after the prompt changed.
+fn redraw(state: &mut AppState) {
+    let count = state
+        .session_lock
+        .as_ref()
+        .map_or(0, |session_lock| session_lock.surfaces.len());
+    for i in 0..count {
+        present(state, i);
+    }
+}
+
+/// The compositor is done reading from `buffer`, if it is one of a lock
+/// surface's. A frame may have been waiting for it.
+pub(crate) fn buffer_released(state: &mut AppState, buffer: &WlBuffer) {
+    let Some(session_lock) = state.session_lock.as_mut() else {
+        return;
+    };
+    let Some(i) = session_lock
+        .surfaces
+        .iter_mut()
+        .position(|s| s.buffers.release(buffer))
+    else {
+        return;
+    };
+    if session_lock.surfaces[i].needs_redraw {
+        present(state, i);
+    }
+}
+
+/// Draws the lock surface at `index` and commits it.
+fn present(state: &mut AppState, index: usize) {
+    let qh = &state.queue_handle;
+    let (Some(session_lock), Ok(shm)) = (state.session_lock.as_mut(), required(&state.shm)) else {
+        return;
+    };
+    let now = Instant::now();
+    let shake = session_lock
+        .shake_started
+        .map(|started| shake_offset(now - started));
+    let prompt = Prompt {
+        dots: session_lock.password.len(),
+        offset: shake.unwrap_or(0),
+        border: if shake.is_some() {
+            FAILED_BORDER
+        } else {
+            PROMPT_BORDER
+        },
+        status: session_lock.status(now),
+        font: session_lock.font.clone(),
+    };
+    let surface = &mut session_lock.surfaces[index];
+    let Some(size) = surface.size else {
+        return;
+    };
+
+    let draw = |pixels: &mut PixelBuffer| draw(pixels, &prompt);
+    match render::present_whole(&surface.surface, &mut surface.buffers, size, shm, qh, draw) {
+        Ok(drawn) => surface.needs_redraw = !drawn,
+        Err(err) => error!(%err, "failed to draw the lock surface"),
+    }
+}
+
+/// What the prompt shows, the same on every output.
+struct Prompt {
+    // One for each character typed
+    dots: usize,
+    // Sideways, while it shakes
+    offset: isize,
+    border: Color,
+    status: Option<String>,
+    font: Option<Arc<Font>>,
+}
+
+/// A password prompt in the middle of the output, with a line under it
+/// saying what is going on.
+fn draw(pixels: &mut PixelBuffer, prompt: &Prompt) {
+    pixels.fill(BACKGROUND);
+
+    let bounds = pixels.bounds();
+    let (width, height) = PROMPT_SIZE;
+    let x = (bounds.width.saturating_sub(width) / 2).saturating_add_signed(prompt.offset);
+    let rect = Rect::new(x, bounds.height.saturating_sub(height) / 2, width, height);
+    pixels.fill_rect(rect.intersect(&bounds), prompt.border);
+    pixels.fill_rect(
+        Rect::new(rect.x + 1, rect.y + 1, width - 2, height - 2).intersect(&bounds),
+        PROMPT,
+    );
+
+    let dots = prompt.dots.min((width - DOT_SIZE) / DOT_SPACING);
+    let dots_width = (dots * DOT_SPACING).saturating_sub(DOT_SIZE);
+    let x = rect.x + (width - dots_width) / 2;
+    let y = rect.y + (height - DOT_SIZE) / 2;
+    for i in 0..dots {
+        let dot = Rect::new(x + i * DOT_SPACING, y, DOT_SIZE, DOT_SIZE);
+        pixels.fill_rect(dot.intersect(&bounds), DOT);
+    }
+
+    let (Some(status), Some(font)) = (&prompt.status, &prompt.font) else {
+        return;
+    };
+    let status_width = font.width(status, STATUS_FONT_SIZE);
+    let position = (
+        bounds.width.saturating_sub(status_width) / 2,
+        rect.bottom() + STATUS_GAP,
+    );
+    font.draw(pixels, status, position, STATUS_FONT_SIZE, STATUS);
+}
+
+impl Dispatch<ExtSessionLockManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ExtSessionLockManagerV1,
+        _event: <ExtSessionLockManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<ExtSessionLockV1, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ExtSessionLockV1,
+        event: <ExtSessionLockV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_session_lock_v1::Event::Locked => {
+                info!("session locked");
+                if let Some(session_lock) = state.session_lock.as_mut() {
+                    session_lock.locked = true;
+                }
+                watchdog::set_phase(Phase::Running);
+                if !state.ready_notified {
+                    systemd::notify_ready();
+                    state.ready_notified = true;
+                }
+            }
+            // Another client holds the lock, or it was taken from us
+            ext_session_lock_v1::Event::Finished => {
+                warn!("the compositor refused or ended the session lock");
+                if let Some(session_lock) = state.session_lock.take() {
+                    session_lock.destroy();
+                }
+                state.running = false;
+            }
+            event => debug!(?event, "ignoring unknown session lock event"),
+        }
+    }
+}
+
+impl Dispatch<ExtSessionLockSurfaceV1, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &ExtSessionLockSurfaceV1,
+        event: <ExtSessionLockSurfaceV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let ext_session_lock_surface_v1::Event::Configure {
+            serial,
+            width,
+            height,
+        } = event
+        else {
+            return;
+        };
+        debug!(serial, width, height, "lock surface configure");
+        proxy.ack_configure(serial);
+
+        let Some(i) = state.session_lock.as_mut().and_then(|session_lock| {
+            let i = session_lock
+                .surfaces
+                .iter()
+                .position(|s| &s.lock_surface == proxy)?;
+            // The buffer has to be exactly this size
+            session_lock.surfaces[i].size = Some((width as usize, height as usize));
+            Some(i)
+        }) else {
+            return;
+        };
+        present(state, i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_longer_after_each_wrong_password() {
+        assert_eq!(lockout(0), Duration::ZERO);
+        assert_eq!(lockout(FREE_ATTEMPTS), FIRST_LOCKOUT);
+        assert_eq!(lockout(FREE_ATTEMPTS + 2), FIRST_LOCKOUT * 4);
+        assert_eq!(lockout(FREE_ATTEMPTS + 40), MAX_LOCKOUT);
+    }
+
+    #[test]
+    fn shaking_settles_down() {
+        assert_eq!(shake_offset(Duration::ZERO), 0);
+        assert_eq!(shake_offset(SHAKE_DURATION), 0);
+        let offsets: Vec<_> = (0..25)
+            .map(|i| shake_offset(SHAKE_DURATION * i / 25))
+            .collect();
+        assert!(offsets.iter().any(|&offset| offset < 0));
+        assert!(offsets.iter().any(|&offset| offset > 0));
+        assert!(offsets
+            .iter()
+            .all(|offset| offset.unsigned_abs() <= SHAKE_AMPLITUDE as usize));
+    }
+}
@@ -1,417 +1,267 @@
-#![warn(clippy::all)]
-use std::{
-    fs::File,
-    os::fd::{AsFd, AsRawFd},
-    ptr,
+use std::{env, path::Path, process::ExitCode, time::Duration};
+
+use anyhow::{bail, Context};
+use qrcodegen::{QrCode, QrCodeEcc};
+use rust_wayland::{
+    animation::{self, Animation},
+    bench, clipboard_watch,
+    dock::{self, Dock},
+    event_loop,
+    fontview::FontView,
+    ipc,
+    layer::{self, LayerOptions},
+    monitor::{self, Monitor},
+    pomodoro::{self, Pomodoro},
+    scene::Scene,
+    select::{self, Selection},
+    snake::Snake,
+    svg::SvgImage,
+    transform,
+    viewport::PanZoom,
+    window::{self, Prefer, Window, WindowOptions},
 };
+use tracing::info;
+
+const USAGE: &str = "usage: rust-wayland [--title TEXT] [--app-id ID] [--size WxH] [--fixed-size] \
+    [--min-size WxH] [--max-size WxH] [--csd] \
+    [--layer background|bottom|top|overlay [--anchor EDGE,...] [--exclusive-zone N]] \
+    [--lock] [--transform normal|90|180|270|flipped|flipped-90|flipped-180|flipped-270] \
+    [--tearing] [--content-type none|photo|video|game] [--click-through] [--render-thread] [--async] [--hud] \
+    [qr <text> | square | pan | badge | snake | select | monitor | fontview <family> | view <file.svg|gif|png|webp> | pomodoro [MINUTES] | dock [DESKTOP_ID...] | bench [present] [SECONDS]]
+       rust-wayland --clipboard-watch
+       rust-wayland --send pomodoro start|pause|reset";
+
+/// Parses a size given as `WIDTHxHEIGHT`.
+fn parse_size(size: &str) -> anyhow::Result<(usize, usize)> {
+    let (width, height) = size
+        .split_once('x')
+        .with_context(|| format!("expected a size like 640x480, got {size:?}"))?;
+    Ok((width.parse()?, height.parse()?))
+}
 
-use anyhow::{bail, Ok};
-use tempfile::tempfile;
-use tracing::{debug, info};
-use wayland_client::{
-    protocol::{
-        wl_buffer::WlBuffer,
-        wl_compositor::WlCompositor,
-        wl_display::WlDisplay,
-        wl_registry::{self, WlRegistry},
-        wl_shm::{Format, WlShm},
-        wl_shm_pool::WlShmPool,
-        wl_surface::WlSurface,
-    },
-    Connection, Dispatch, Proxy, QueueHandle,
-};
-use wayland_protocols::xdg::{
-    decoration::zv1::client::{
-        zxdg_decoration_manager_v1::ZxdgDecorationManagerV1,
-        zxdg_toplevel_decoration_v1::{self, Mode, ZxdgToplevelDecorationV1},
-    },
-    shell::client::{
-        xdg_surface::{self, XdgSurface},
-        xdg_toplevel::XdgToplevel,
-        xdg_wm_base::{self, XdgWmBase},
-    },
-};
-
-struct AppState {
-    // Globals
-    display: Option<WlDisplay>,
-    compositor: Option<WlCompositor>,
-    registry: Option<WlRegistry>,
-    shm: Option<WlShm>,
-    xdg_wm_base: Option<XdgWmBase>,
-    xdg_decoration_manager: Option<ZxdgDecorationManagerV1>,
-
-    // Objects
-    surface: Option<WlSurface>,
-    xdg_surface: Option<XdgSurface>,
-    xdg_toplevel: Option<XdgToplevel>,
-
-    queue_handle: Option<QueueHandle<Self>>,
+/// What to do with the window.
+enum Mode {
+    Show(Scene),
+    /// Animates for this long and reports the frame timings
+    Bench(Duration),
+    /// The same through each way of presenting frames, for this long each
+    BenchPresent(Duration),
 }
 
-impl AppState {
-    fn handle_global_add(
-        &mut self,
-        registry: &WlRegistry,
-        name: u32,
-        interface: &str,
-        version: u32,
-        qh: &QueueHandle<Self>,
-    ) {
-        match interface {
-            "wl_compositor" => {
-                debug!(?interface, ?name, ?version, "Adding compositor");
-                let compositor = registry.bind(name, version, qh, ());
-                self.compositor = Some(compositor);
+/// Picks what to do and how from the command line, and whether to run the
+/// main loop on tokio.
+fn parse_args() -> anyhow::Result<(Mode, WindowOptions, bool)> {
+    let mut builder = Window::builder();
+    let mut async_loop = false;
+    let mut positional = Vec::new();
+    // Only make sense with a layer, applied once we know which one
+    let mut layer = None;
+    let mut anchor = None;
+    let mut exclusive_zone = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("{arg} needs a value\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "--layer" => {
+                let name = value()?;
+                layer = Some(
+                    layer::parse_layer(&name)
+                        .with_context(|| format!("unknown layer {name:?}\n{USAGE}"))?,
+                );
             }
-            "wl_shm" => {
-                debug!(?interface, ?name, ?version, "Adding shm");
-                let shm = registry.bind(name, version, qh, ());
-                self.shm = Some(shm);
+            "--anchor" => {
+                let edges = value()?;
+                anchor = Some(
+                    layer::parse_anchor(&edges)
+                        .with_context(|| format!("unknown edge in {edges:?}\n{USAGE}"))?,
+                );
             }
-            "xdg_wm_base" => {
-                debug!(?interface, ?name, ?version, "Adding xdg_wm_base");
-                let xdg_wm_base = registry.bind(name, version, qh, ());
-                self.xdg_wm_base = Some(xdg_wm_base);
+            "--exclusive-zone" => exclusive_zone = Some(value()?.parse()?),
+            "--title" => builder = builder.title(value()?),
+            "--size" => {
+                let (width, height) = parse_size(&value()?)?;
+                builder = builder.size(width, height);
             }
-            "zxdg_decoration_manager_v1" => {
-                debug!(?interface, ?name, ?version, "Adding decoration manager");
-                let decoration_manager = registry.bind(name, version.min(1), qh, ());
-                self.xdg_decoration_manager = Some(decoration_manager);
+            "--fixed-size" => builder = builder.resizable(false),
+            "--min-size" => {
+                let (width, height) = parse_size(&value()?)?;
+                builder = builder.min_size(width, height);
             }
-            _ => {}
+            "--max-size" => {
+                let (width, height) = parse_size(&value()?)?;
+                builder = builder.max_size(width, height);
+            }
+            "--csd" => builder = builder.decorations(Prefer::ClientSide),
+            "--app-id" => builder = builder.app_id(value()?),
+            "--lock" => builder = builder.lock(true),
+            "--tearing" => builder = builder.tearing(true),
+            "--click-through" => builder = builder.click_through(true),
+            "--render-thread" => builder = builder.render_thread(true),
+            "--hud" => builder = builder.hud(true),
+            "--async" if cfg!(feature = "tokio") => async_loop = true,
+            "--async" => bail!("--async needs the tokio feature"),
+            "--content-type" => {
+                let name = value()?;
+                let content_type = window::parse_content_type(&name)
+                    .with_context(|| format!("unknown content type {name:?}\n{USAGE}"))?;
+                builder = builder.content_type(content_type);
+            }
+            "--transform" => {
+                let name = value()?;
+                let transform = transform::parse_transform(&name)
+                    .with_context(|| format!("unknown transform {name:?}\n{USAGE}"))?;
+                builder = builder.transform(transform);
+            }
+            _ => positional.push(arg),
         }
     }
 
-    fn set_display(&mut self, display: WlDisplay) {
-        self.display = Some(display);
-    }
-
-    fn set_compositor(&mut self, compositor: WlCompositor) {
-        self.compositor = Some(compositor);
-    }
-
-    fn set_registry(&mut self, registry: WlRegistry) {
-        self.registry = Some(registry);
-    }
-
-    fn set_surface(&mut self, surface: WlSurface) {
-        self.surface = Some(surface);
-    }
-
-    fn set_xdg_surface(&mut self, xdg_surface: XdgSurface) {
-        self.xdg_surface = Some(xdg_surface);
-    }
-
-    fn set_xdg_toplevel(&mut self, xdg_toplevel: XdgToplevel) {
-        self.xdg_toplevel = Some(xdg_toplevel);
-    }
-
-    fn set_queue_handle(&mut self, qh: QueueHandle<Self>) {
-        self.queue_handle = Some(qh);
-    }
-}
-
-impl Default for AppState {
-    fn default() -> Self {
-        Self {
-            display: None,
-            registry: None,
-            compositor: None,
-            shm: None,
-            xdg_wm_base: None,
-            xdg_decoration_manager: None,
-            surface: None,
-            xdg_surface: None,
-            xdg_toplevel: None,
-            queue_handle: None,
+    // Modes that make a layer surface of their own
+    let own_layer = match positional.first().map(String::as_str) {
+        Some("select") => Some(select::layer_options()),
+        Some("monitor") => Some(monitor::layer_options()),
+        Some("pomodoro") => Some(pomodoro::layer_options()),
+        Some("dock") => Some(dock::layer_options()),
+        _ => None,
+    };
+    if let Some(own_layer) = own_layer {
+        if layer.is_some() {
+            bail!(
+                "{} picks its own layer, it takes no --layer\n{USAGE}",
+                positional[0]
+            );
         }
+        builder = builder.layer(own_layer);
     }
-}
-
-fn create_shm_pool(size: usize) -> anyhow::Result<(File, *mut u8)> {
-    let tmpfile = tempfile()?;
-    tmpfile.set_len(size as u64)?;
-
-    // WARN: what happens to this fd when tmpfile goes out of scope?
-    let fd = tmpfile.as_raw_fd();
-    unsafe {
-        let res = libc::mmap(
-            ptr::null_mut(),
-            size,
-            libc::PROT_READ | libc::PROT_WRITE,
-            libc::MAP_SHARED,
-            fd,
-            0,
-        );
-
-        if res == libc::MAP_FAILED {
-            bail!("failed to mmap memory");
+    match layer {
+        Some(layer) => {
+            let mut layer = LayerOptions::for_layer(layer);
+            layer.anchor = anchor.unwrap_or(layer.anchor);
+            layer.exclusive_zone = exclusive_zone.unwrap_or(layer.exclusive_zone);
+            builder = builder.layer(layer);
         }
-
-        Ok((tmpfile, res as *mut u8))
+        None if anchor.is_some() || exclusive_zone.is_some() => {
+            bail!("--anchor and --exclusive-zone need --layer\n{USAGE}");
+        }
+        None => {}
     }
-}
-
-fn draw_frame(state: &AppState) -> anyhow::Result<WlBuffer> {
-    let qh = state.queue_handle.as_ref().unwrap();
-
-    let width = 500;
-    let height = 500;
-    let stride = width * 4; // 4 bytes per pixel
-    let size = stride * height;
-    let (shm_file, shm_ptr) = create_shm_pool(size)?;
-
-    let pool = state.shm.as_ref().unwrap().create_pool(
-        shm_file.as_fd(),
-        size.try_into().unwrap(),
-        &qh,
-        (),
-    );
-
-    let buffer = pool.create_buffer(
-        0,
-        width.try_into().unwrap(),
-        height.try_into().unwrap(),
-        stride.try_into().unwrap(),
-        Format::Argb8888,
-        &qh,
-        (),
-    );
-
-    unsafe {
-        for y in 0..height {
-            for x in 0..width {
-                let offset = (y * width + x) * 4;
-                let pixel = shm_ptr.add(offset);
-
-                // ARGB format
-                *pixel = 0xFF; // Alpha
-                *pixel.add(1) = 0x00; // Red
-                *pixel.add(2) = 0x00; // Green
-                *pixel.add(3) = 0xFF; // Blue
+    let options = builder.options()?;
+
+    let mut args = positional.into_iter();
+    let scene = match args.next().as_deref() {
+        Some("bench") if async_loop => bail!("bench doesn't run on tokio"),
+        Some("bench") => {
+            let mut next = args.next();
+            let present = next.as_deref() == Some("present");
+            if present {
+                next = args.next();
             }
+            let duration = match next {
+                Some(seconds) => Duration::from_secs_f64(
+                    seconds
+                        .parse()
+                        .with_context(|| format!("expected seconds, got {seconds:?}\n{USAGE}"))?,
+                ),
+                None => bench::DEFAULT_DURATION,
+            };
+            let mode = if present {
+                Mode::BenchPresent(duration)
+            } else {
+                Mode::Bench(duration)
+            };
+            return Ok((mode, options, false));
         }
-    }
-
-    Ok(buffer)
-}
-
-fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
-    info!("Starting the application");
-
-    let mut state = AppState::default();
-
-    let conn = Connection::connect_to_env()?;
-    let display = conn.display();
-    state.set_display(display);
-
-    let mut event_queue = conn.new_event_queue::<AppState>();
-    state.set_queue_handle(event_queue.handle());
-    let qh = event_queue.handle();
-
-    let registry = state.display.as_ref().unwrap().get_registry(&qh, ());
-    state.set_registry(registry);
-
-    event_queue.roundtrip(&mut state)?;
-
-    let surface = state.compositor.as_ref().unwrap().create_surface(&qh, ());
-    state.set_surface(surface);
-
-    let xdg_wm_base = state.xdg_wm_base.as_ref().unwrap();
-    let xdg_surface = xdg_wm_base.get_xdg_surface(state.surface.as_ref().unwrap(), &qh, ());
-    state.set_xdg_surface(xdg_surface);
-
-    let toplevel = state.xdg_surface.as_ref().unwrap().get_toplevel(&qh, ());
-    toplevel.set_title(String::from("Hello, world!"));
-    let decoration_manager = state.xdg_decoration_manager.as_ref().unwrap();
-    let decoration = decoration_manager.get_toplevel_decoration(&toplevel, &qh, ());
-    decoration.set_mode(Mode::ServerSide);
-
-    state.set_xdg_toplevel(toplevel);
-
-    state.surface.as_ref().unwrap().commit();
-
-    loop {
-        event_queue.blocking_dispatch(&mut state)?;
-    }
-}
-
-impl Dispatch<WlSurface, ()> for AppState {
-    fn event(
-        _state: &mut Self,
-        _proxy: &WlSurface,
-        _event: <WlSurface as Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
-    }
-}
-
-impl Dispatch<WlCompositor, ()> for AppState {
-    fn event(
-        _state: &mut Self,
-        _proxy: &WlCompositor,
-        _event: <WlCompositor as Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qhandle: &QueueHandle<Self>,
-    ) {
-        // This interface does not generates any events AFAIK
-    }
-}
-
-impl Dispatch<WlShm, ()> for AppState {
-    fn event(
-        _state: &mut Self,
-        _proxy: &WlShm,
-        _event: <WlShm as Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
-        // AFAIK, this interface only sends events advertising the supported
-        // pixel formats.
-    }
-}
-
-impl Dispatch<WlShmPool, ()> for AppState {
-    fn event(
-        _state: &mut Self,
-        _proxy: &WlShmPool,
-        _event: <WlShmPool as Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
-        // AFAIK, this interface does not emit any events
-    }
-}
-
-impl Dispatch<WlBuffer, ()> for AppState {
-    fn event(
-        _state: &mut Self,
-        _proxy: &WlBuffer,
-        _event: <WlBuffer as Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
-        // TODO: when the compositor is done using the buffer, it will emit a `release` event.
-        // I need to release ro re-use the buffer after receiving that event.
-        // wayland_client::protocol::wl_buffer::Event::Release
-    }
-}
+        None => Scene::TestPattern,
+        Some("qr") => {
+            let text = args.collect::<Vec<_>>().join(" ");
+            if text.is_empty() {
+                bail!(USAGE);
+            }
 
-impl Dispatch<XdgWmBase, ()> for AppState {
-    fn event(
-        _state: &mut Self,
-        proxy: &XdgWmBase,
-        event: <XdgWmBase as Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
-        if let xdg_wm_base::Event::Ping { serial } = event {
-            debug!(?serial, "xdg ping");
-            proxy.pong(serial);
+            let code = QrCode::encode_text(&text, QrCodeEcc::Medium)?;
+            Scene::Qr(code)
         }
-    }
-}
-
-impl Dispatch<XdgSurface, ()> for AppState {
-    fn event(
-        state: &mut Self,
-        proxy: &XdgSurface,
-        event: <XdgSurface as Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
-        if let xdg_surface::Event::Configure { serial } = event {
-            info!(?serial, "xdg surface configure event");
-            proxy.ack_configure(serial);
-
-            let toplevel = state.xdg_toplevel.as_ref().unwrap();
-            let qh = state.queue_handle.as_ref().unwrap();
-            let surface = state.surface.as_ref().unwrap();
-            let buffer = draw_frame(state).expect("failed to draw frame");
-
-            surface.attach(Some(&buffer), 0, 0);
-            surface.commit();
+        Some("square") => Scene::Square,
+        Some("pan") => Scene::Pan(PanZoom::default()),
+        Some("badge") => Scene::Badge,
+        Some("snake") => Scene::Snake(Snake::default()),
+        Some("select") => Scene::Select(Selection::new()),
+        Some("monitor") => Scene::Monitor(Monitor::new()?),
+        Some("dock") => Scene::Dock(Dock::new(&args.collect::<Vec<_>>())),
+        Some("pomodoro") => {
+            let duration = match args.next() {
+                Some(minutes) => Duration::from_secs_f64(
+                    minutes
+                        .parse::<f64>()
+                        .with_context(|| format!("expected minutes, got {minutes:?}\n{USAGE}"))?
+                        * 60.0,
+                ),
+                None => pomodoro::DEFAULT_DURATION,
+            };
+            Scene::Pomodoro(Pomodoro::new(duration)?)
         }
-    }
-}
+        Some("fontview") => {
+            let family = args.collect::<Vec<_>>().join(" ");
+            if family.is_empty() {
+                bail!(USAGE);
+            }
+            Scene::FontView(FontView::new(&family)?)
+        }
+        Some("view") => {
+            let path = args.next().context(USAGE)?;
+            let path = Path::new(&path);
+            if animation::is_animated_format(path) {
+                Scene::Animation(Animation::open(path)?)
+            } else {
+                Scene::Svg(SvgImage::open(path)?)
+            }
+        }
+        Some(mode) => bail!("unknown mode {mode:?}\n{USAGE}"),
+    };
 
-impl Dispatch<XdgToplevel, ()> for AppState {
-    fn event(
-        _state: &mut Self,
-        _proxy: &XdgToplevel,
-        _event: <XdgToplevel as Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
-        // TODO: Handle window state changes
-    }
+    Ok((Mode::Show(scene), options, async_loop))
 }
 
-impl Dispatch<WlRegistry, ()> for AppState {
-    fn event(
-        state: &mut Self,
-        registry: &WlRegistry,
-        event: <WlRegistry as Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        qh: &wayland_client::QueueHandle<Self>,
-    ) {
-        match event {
-            wl_registry::Event::Global {
-                name,
-                interface,
-                version,
-            } => {
-                if interface.starts_with("wl") {
-                    info!(?name, ?interface, version, "new global event")
-                }
+fn main() -> anyhow::Result<ExitCode> {
+    tracing_subscriber::fmt::init();
+    info!("Starting the application");
 
-                state.handle_global_add(registry, name, &interface, version, qh);
-            }
-            wl_registry::Event::GlobalRemove { name } => todo!(),
-            _ => unreachable!(),
+    // Prints the clipboard instead of showing a window
+    if env::args().nth(1).as_deref() == Some("--clipboard-watch") {
+        if env::args().count() > 2 {
+            bail!("--clipboard-watch takes no other arguments\n{USAGE}");
         }
+        return Ok(clipboard_watch::run()?);
     }
-}
 
-impl Dispatch<ZxdgDecorationManagerV1, ()> for AppState {
-    fn event(
-        _state: &mut Self,
-        _proxy: &ZxdgDecorationManagerV1,
-        _event: <ZxdgDecorationManagerV1 as Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
-        // AFAIK, this interface does not emit any events
+    // Tells the instance listening on a control socket what to do
+    if env::args().nth(1).as_deref() == Some("--send") {
+        let args: Vec<_> = env::args().skip(2).collect();
+        let [name, command] = args.as_slice() else {
+            bail!("--send takes a socket name and a command\n{USAGE}");
+        };
+        let answer = ipc::send(name, command)
+            .with_context(|| format!("failed to send {command:?} to {name}"))?;
+        print!("{answer}");
+        return Ok(ExitCode::SUCCESS);
     }
-}
 
-impl Dispatch<ZxdgToplevelDecorationV1, ()> for AppState {
-    fn event(
-        _state: &mut Self,
-        _proxy: &ZxdgToplevelDecorationV1,
-        event: <ZxdgToplevelDecorationV1 as Proxy>::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-    ) {
-        match event {
-            zxdg_toplevel_decoration_v1::Event::Configure { mode } => {
-                info!(?mode, "decoration configure event");
-            }
-            _ => unreachable!(),
+    let (scene, options, async_loop) = match parse_args()? {
+        (Mode::Show(scene), options, async_loop) => (scene, options, async_loop),
+        (Mode::Bench(duration), options, _) => return Ok(bench::run(duration, options)?),
+        (Mode::BenchPresent(duration), options, _) => {
+            return Ok(bench::run_present(duration, options)?)
         }
+    };
+    #[cfg(feature = "tokio")]
+    if async_loop {
+        return Ok(rust_wayland::async_loop::run(scene, options)?);
     }
+    // Refused by parse_args without the tokio feature
+    debug_assert!(!async_loop);
+    Ok(event_loop::run(scene, options)?)
 }
@@ -0,0 +1,219 @@
+//! The context menu that opens on a right click in the window. Entries are
+//! shown as icons, there is no text rendering yet.
+
+use tracing::{debug, error};
+use wayland_client::protocol::{wl_buffer::WlBuffer, wl_surface::WlSurface};
+
+use crate::{
+    color::Color,
+    decorations::{self, Button},
+    pixel_buffer::PixelBuffer,
+    popup::{Popup, Positioner},
+    rect::Rect,
+    seat::BTN_LEFT,
+    state::{required, AppState},
+    window::WindowId,
+};
+
+const ITEM_HEIGHT: usize = 28;
+const MENU_WIDTH: usize = 160;
+const ICON_MARGIN: usize = 4;
+
+const BACKGROUND: Color = Color::rgb(0x2E, 0x2E, 0x2E);
+const HOVERED: Color = Color::rgb(0x4A, 0x4A, 0x4A);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuItem {
+    Fullscreen,
+    Quit,
+}
+
+impl MenuItem {
+    const ALL: [Self; 2] = [Self::Fullscreen, Self::Quit];
+
+    // Stand-in for the label until we can draw text
+    fn icon(self) -> Button {
+        match self {
+            Self::Fullscreen => Button::Maximize,
+            Self::Quit => Button::Close,
+        }
+    }
+}
+
+/// An open context menu.
+pub struct Menu {
+    pub(crate) popup: Popup,
+    // The window it was opened on
+    pub(crate) parent: WindowId,
+    // The entry under the pointer
+    hovered: Option<MenuItem>,
+}
+
+fn menu_size() -> (usize, usize) {
+    (MENU_WIDTH, ITEM_HEIGHT * MenuItem::ALL.len())
+}
+
+/// The entry at `(x, y)` in the menu, if any.
+fn item_at((x, y): (f64, f64)) -> Option<MenuItem> {
+    let (width, height) = menu_size();
+    if !(0.0..width as f64).contains(&x) || !(0.0..height as f64).contains(&y) {
+        return None;
+    }
+    MenuItem::ALL.get(y as usize / ITEM_HEIGHT).copied()
+}
+
+fn draw(pixels: &mut PixelBuffer, hovered: Option<MenuItem>) {
+    pixels.fill(Color::rgb(0x1E, 0x1E, 0x1E));
+
+    let width = pixels.bounds().width;
+    for (i, item) in MenuItem::ALL.into_iter().enumerate() {
+        let row = Rect::new(1, i * ITEM_HEIGHT + 1, width - 2, ITEM_HEIGHT - 2);
+        let background = if hovered == Some(item) {
+            HOVERED
+        } else {
+            BACKGROUND
+        };
+        pixels.fill_rect(row, background);
+
+        let size = ITEM_HEIGHT - 2 * ICON_MARGIN;
+        let icon = Rect::new(row.x + ICON_MARGIN, row.y + ICON_MARGIN - 1, size, size);
+        decorations::draw_button(pixels, item.icon(), icon);
+    }
+}
+
+/// Opens the menu at `position` in the window `parent`, for the button
+/// press with `serial`. Replaces the menu already open, if any.
+pub(crate) fn open(state: &mut AppState, parent: WindowId, (x, y): (f64, f64), serial: u32) {
+    close(state);
+
+    let qh = &state.queue_handle;
+    let Some(window) = state.window(parent) else {
+        return;
+    };
+    let (Ok(compositor), Ok(xdg_wm_base)) =
+        (required(&state.compositor), required(&state.xdg_wm_base))
+    else {
+        return;
+    };
+
+    let positioner = Positioner::at_point((x.max(0.0) as usize, y.max(0.0) as usize), menu_size());
+    let grab = state.seat.as_ref().map(|seat| (&seat.seat, serial));
+    let popup = Popup::new(
+        compositor,
+        xdg_wm_base,
+        window,
+        &positioner,
+        state.format,
+        grab,
+        qh,
+    );
+    debug!(x, y, "opened the context menu");
+    state.menu = Some(Menu {
+        popup,
+        parent,
+        hovered: None,
+    });
+}
+
+pub(crate) fn close(state: &mut AppState) {
+    if let Some(menu) = state.menu.take() {
+        debug!("closing the context menu");
+        menu.popup.destroy();
+    }
+}
+
+/// Draws the menu again, e.g. after the hovered entry changed.
+pub(crate) fn redraw(state: &mut AppState) {
+    let qh = &state.queue_handle;
+    let (Some(menu), Ok(shm)) = (state.menu.as_mut(), required(&state.shm)) else {
+        return;
+    };
+
+    let hovered = menu.hovered;
+    if let Err(err) = menu.popup.present(shm, qh, |pixels| draw(pixels, hovered)) {
+        error!(%err, "failed to draw the context menu");
+    }
+}
+
+/// The compositor is done reading from `buffer`, if it is one of the
+/// menu's. A redraw may have been waiting for it.
+pub(crate) fn buffer_released(state: &mut AppState, buffer: &WlBuffer) {
+    let Some(menu) = state.menu.as_mut() else {
+        return;
+    };
+    menu.popup.release(buffer);
+    if menu.popup.needs_redraw {
+        redraw(state);
+    }
+}
+
+pub(crate) fn is_menu_surface(state: &AppState, surface: &WlSurface) -> bool {
+    state
+        .menu
+        .as_ref()
+        .is_some_and(|menu| menu.popup.surface() == surface)
+}
+
+/// Whether the pointer is over the menu rather than the window.
+pub(crate) fn has_pointer(state: &AppState) -> bool {
+    let surface = state
+        .seat
+        .as_ref()
+        .and_then(|s| s.pointer.as_ref()?.surface.as_ref());
+    surface.is_some_and(|surface| is_menu_surface(state, surface))
+}
+
+/// Highlights the entry under the pointer, `None` once it left the menu.
+pub(crate) fn pointer_moved(state: &mut AppState, position: Option<(f64, f64)>) {
+    let Some(menu) = state.menu.as_mut() else {
+        return;
+    };
+
+    let hovered = position.and_then(item_at);
+    if hovered != menu.hovered {
+        menu.hovered = hovered;
+        redraw(state);
+    }
+}
+
+/// Runs the entry under the pointer on a left click.
+pub(crate) fn pointer_button(state: &mut AppState, button: u32, pressed: bool) {
+    if !pressed || button != BTN_LEFT {
+        return;
+    }
+    let Some((item, parent)) = state
+        .menu
+        .as_ref()
+        .and_then(|menu| Some((menu.hovered?, menu.parent)))
+    else {
+        return;
+    };
+
+    debug!(?item, "context menu entry picked");
+    close(state);
+    match item {
+        MenuItem::Fullscreen => {
+            if let Some(window) = state.window(parent) {
+                window.toggle_fullscreen();
+            }
+        }
+        MenuItem::Quit => state.running = false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_stacked_top_to_bottom() {
+        assert_eq!(item_at((10.0, 1.0)), Some(MenuItem::Fullscreen));
+        assert_eq!(
+            item_at((10.0, ITEM_HEIGHT as f64 + 1.0)),
+            Some(MenuItem::Quit)
+        );
+        assert_eq!(item_at((10.0, -1.0)), None);
+        assert_eq!(item_at((MENU_WIDTH as f64, 1.0)), None);
+        assert_eq!(item_at((10.0, menu_size().1 as f64)), None);
+    }
+}
@@ -0,0 +1,237 @@
+//! A system monitor bar: CPU, memory, battery and the time in a panel
+//! along the top of the output, refreshed every second. Only the readings
+//! whose text changed are redrawn.
+
+use std::{fs, sync::Arc, time::Duration};
+
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::Layer, zwlr_layer_surface_v1::KeyboardInteractivity,
+};
+
+use crate::{
+    clock::local_time,
+    color::Color,
+    layer::LayerOptions,
+    pixel_buffer::PixelBuffer,
+    rect::Rect,
+    render::present_if_needed,
+    scale::Scale,
+    scene::Scene,
+    state::AppState,
+    text::{self, Font, FontError},
+};
+
+/// How often the readings are refreshed
+pub const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub const BACKGROUND: Color = Color::rgb(0x20, 0x20, 0x20);
+const TEXT: Color = Color::rgb(0xE0, 0xE0, 0xE0);
+
+const FONT_SIZE: f32 = 14.0;
+const PADDING: usize = 8;
+// Wide enough for the longest text of each reading, the clock goes on the
+// right
+const SLOT_WIDTHS: [usize; SLOTS] = [96, 160, 112, 72];
+const SLOTS: usize = 4;
+const CLOCK: usize = 3;
+
+/// The bar the compositor keeps other windows clear of, which never takes
+/// the keyboard.
+pub fn layer_options() -> LayerOptions {
+    LayerOptions {
+        keyboard_interactivity: KeyboardInteractivity::None,
+        ..LayerOptions::for_layer(Layer::Top)
+    }
+}
+
+/// Time the CPUs spent, from the first line of /proc/stat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+/// Parses the `cpu` line of /proc/stat.
+fn parse_cpu_times(stat: &str) -> Option<CpuTimes> {
+    let line = stat.lines().next()?.strip_prefix("cpu ")?;
+    let times: Vec<u64> = line
+        .split_whitespace()
+        .map(|time| time.parse().ok())
+        .collect::<Option<_>>()?;
+    // user, nice, system, idle, iowait, irq, softirq, steal. Guests are
+    // already counted in user and nice.
+    let total = times.iter().take(8).sum();
+    let idle = times.get(3)? + times.get(4).unwrap_or(&0);
+    Some(CpuTimes {
+        busy: total - idle,
+        total,
+    })
+}
+
+/// How busy the CPUs were between two readings, in percent.
+fn cpu_usage(from: CpuTimes, to: CpuTimes) -> Option<u64> {
+    let total = to.total.checked_sub(from.total).filter(|&t| t > 0)?;
+    let busy = to.busy.saturating_sub(from.busy);
+    Some(busy * 100 / total)
+}
+
+/// Total and available memory in KiB, from /proc/meminfo.
+fn parse_meminfo(meminfo: &str) -> Option<(u64, u64)> {
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))?
+            .trim()
+            .strip_suffix(" kB")?
+            .parse()
+            .ok()
+    };
+    Some((field("MemTotal")?, field("MemAvailable")?))
+}
+
+/// The charge of the first battery and whether it is charging.
+fn battery() -> Option<(u32, bool)> {
+    let dir = fs::read_dir("/sys/class/power_supply")
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| {
+            fs::read_to_string(path.join("type")).is_ok_and(|kind| kind.trim() == "Battery")
+        })?;
+    let capacity = fs::read_to_string(dir.join("capacity")).ok()?;
+    let status = fs::read_to_string(dir.join("status")).unwrap_or_default();
+    Some((capacity.trim().parse().ok()?, status.trim() == "Charging"))
+}
+
+/// What the bar shows, and the font it is written in.
+#[derive(Clone)]
+pub struct Monitor {
+    font: Arc<Font>,
+    texts: [String; SLOTS],
+    // The last reading, to tell how busy the CPUs were since
+    cpu: Option<CpuTimes>,
+}
+
+impl Monitor {
+    pub fn new() -> Result<Self, FontError> {
+        let mut monitor = Self {
+            font: Arc::new(Font::find(text::SANS)?),
+            texts: Default::default(),
+            cpu: None,
+        };
+        monitor.refresh();
+        Ok(monitor)
+    }
+
+    /// Reads everything again. Returns the slots whose text changed.
+    fn refresh(&mut self) -> Vec<usize> {
+        let cpu = fs::read_to_string("/proc/stat")
+            .ok()
+            .and_then(|stat| parse_cpu_times(&stat));
+        let usage = self.cpu.zip(cpu).and_then(|(from, to)| cpu_usage(from, to));
+        self.cpu = cpu;
+
+        let gib = |kib: u64| kib as f64 / (1024.0 * 1024.0);
+        let memory = fs::read_to_string("/proc/meminfo")
+            .ok()
+            .and_then(|meminfo| parse_meminfo(&meminfo));
+
+        let texts = [
+            usage.map_or("CPU --".to_owned(), |usage| format!("CPU {usage}%")),
+            memory.map_or("MEM --".to_owned(), |(total, available)| {
+                format!("MEM {:.1}/{:.1}G", gib(total - available), gib(total))
+            }),
+            battery().map_or(String::new(), |(capacity, charging)| {
+                format!("BAT {capacity}%{}", if charging { " +" } else { "" })
+            }),
+            local_time().map_or(String::new(), |tm| {
+                format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+            }),
+        ];
+
+        let changed = (0..SLOTS).filter(|&i| texts[i] != self.texts[i]).collect();
+        self.texts = texts;
+        changed
+    }
+
+    /// Draws the bar into `pixels`, laid out in window pixels.
+    pub(crate) fn draw(&self, pixels: &mut PixelBuffer, scale: Scale) {
+        pixels.fill(BACKGROUND);
+        let bounds = pixels.bounds();
+        let size = (
+            scale.to_surface(bounds.width),
+            scale.to_surface(bounds.height),
+        );
+
+        let font_size = FONT_SIZE * scale.as_f64() as f32;
+        for (i, text) in self.texts.iter().enumerate() {
+            let slot = scale.rect_to_buffer(slot_rect(i, size)).intersect(&bounds);
+            let y = slot.y + slot.height.saturating_sub(self.font.line_height(font_size)) / 2;
+            self.font.draw(pixels, text, (slot.x, y), font_size, TEXT);
+        }
+    }
+}
+
+/// Where a reading goes in a bar of `width`x`height` window pixels: from
+/// the left, the clock on the right.
+fn slot_rect(slot: usize, (width, height): (usize, usize)) -> Rect {
+    let x = if slot == CLOCK {
+        width.saturating_sub(PADDING + SLOT_WIDTHS[CLOCK])
+    } else {
+        PADDING + SLOT_WIDTHS[..slot].iter().sum::<usize>()
+    };
+    Rect::new(x, 0, SLOT_WIDTHS[slot], height).intersect(&Rect::new(0, 0, width, height))
+}
+
+/// Refreshes the readings of the monitor scene and redraws those that
+/// changed.
+pub(crate) fn update(state: &mut AppState) {
+    let Scene::Monitor(monitor) = &mut state.scene else {
+        return;
+    };
+
+    let changed = monitor.refresh();
+    for window in &mut state.windows {
+        let size = window.size();
+        window.damage_area(changed.iter().map(|&slot| slot_rect(slot, size)));
+    }
+    present_if_needed(state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proc_files_are_parsed() {
+        let stat = "cpu  100 0 50 800 50 0 0 0 0 0\ncpu0 100 0 50 800 50 0 0 0 0 0\n";
+        let from = parse_cpu_times(stat).unwrap();
+        assert_eq!(
+            from,
+            CpuTimes {
+                busy: 150,
+                total: 1000
+            }
+        );
+        let to = CpuTimes {
+            busy: 200,
+            total: 1200,
+        };
+        assert_eq!(cpu_usage(from, to), Some(25));
+        assert_eq!(cpu_usage(to, to), None);
+        assert_eq!(parse_cpu_times("intr 1 2 3"), None);
+
+        let meminfo = "MemTotal:        6147400 kB\nMemFree:          366564 kB\n\
+            MemAvailable:    5511668 kB\n";
+        assert_eq!(parse_meminfo(meminfo), Some((6147400, 5511668)));
+        assert_eq!(parse_meminfo("MemTotal: 1 kB\n"), None);
+    }
+
+    #[test]
+    fn the_clock_goes_on_the_right() {
+        let size = (1000, 32);
+        assert_eq!(slot_rect(0, size), Rect::new(8, 0, 96, 32));
+        assert_eq!(slot_rect(2, size), Rect::new(264, 0, 112, 32));
+        assert_eq!(slot_rect(CLOCK, size), Rect::new(920, 0, 72, 32));
+    }
+}
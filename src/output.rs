@@ -0,0 +1,338 @@
+//! The outputs (monitors) the compositor advertises, and what it tells us
+//! about them.
+
+use tracing::{debug, info};
+use wayland_client::{
+    protocol::wl_output::{self, Mode, Transform, WlOutput},
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+use wayland_protocols::xdg::xdg_output::zv1::client::{
+    zxdg_output_manager_v1::ZxdgOutputManagerV1,
+    zxdg_output_v1::{self, ZxdgOutputV1},
+};
+
+use crate::{state::AppState, window};
+
+/// What we know about an output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputInfo {
+    /// Like `DP-1`, only sent since version 4
+    pub name: Option<String>,
+    /// Human readable, like `Dell Inc. U2720Q`, only sent since version 4
+    pub description: Option<String>,
+    pub make: String,
+    pub model: String,
+    /// Position in the global compositor space
+    pub position: (i32, i32),
+    /// In millimeters, 0 if unknown
+    pub physical_size: (i32, i32),
+    /// Size of the current mode in pixels
+    pub resolution: (i32, i32),
+    /// Of the current mode in mHz, 0 if unknown
+    pub refresh: i32,
+    /// How many buffer pixels cover a surface pixel, for HiDPI
+    pub scale: i32,
+    /// How the output is rotated or flipped
+    pub transform: Transform,
+    /// Where the output is in the compositor's logical space, from
+    /// xdg-output. Tells outputs with different scales apart properly.
+    pub logical: Option<LogicalGeometry>,
+}
+
+/// The area of an output in the compositor's logical coordinates, those of
+/// surface pixels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogicalGeometry {
+    pub position: (i32, i32),
+    pub size: (i32, i32),
+}
+
+impl Default for OutputInfo {
+    fn default() -> Self {
+        Self {
+            name: None,
+            description: None,
+            make: String::new(),
+            model: String::new(),
+            position: (0, 0),
+            physical_size: (0, 0),
+            resolution: (0, 0),
+            refresh: 0,
+            scale: 1,
+            transform: Transform::Normal,
+            logical: None,
+        }
+    }
+}
+
+impl OutputInfo {
+    /// The refresh rate in Hz, if the compositor told us.
+    pub fn refresh_hz(&self) -> Option<f64> {
+        (self.refresh > 0).then(|| self.refresh as f64 / 1000.0)
+    }
+
+    /// The size surfaces see. Known from xdg-output if the compositor
+    /// supports it, guessed from the resolution rotated like the output and
+    /// divided by its scale otherwise, which is off with fractional scales.
+    pub fn logical_size(&self) -> (i32, i32) {
+        if let Some(logical) = self.logical {
+            return logical.size;
+        }
+
+        let (width, height) = self.resolution;
+        let (width, height) = if is_rotated(self.transform) {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let scale = self.scale.max(1);
+        (width / scale, height / scale)
+    }
+}
+
+/// Whether `transform` turns the output by 90 or 270 degrees, which swaps
+/// its width and height.
+pub fn is_rotated(transform: Transform) -> bool {
+    matches!(
+        transform,
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270
+    )
+}
+
+/// An output we bound.
+#[derive(Debug)]
+pub struct Output {
+    /// Name of its global, tells us when it is unplugged
+    pub global: u32,
+    pub wl_output: WlOutput,
+    // None if the compositor doesn't support xdg-output
+    xdg_output: Option<ZxdgOutputV1>,
+    /// As of the last `done` event
+    pub info: OutputInfo,
+    // Changes that the next `done` event applies all at once
+    pending: OutputInfo,
+}
+
+impl Output {
+    /// Starts tracking `wl_output`, along with its logical geometry if
+    /// there is an xdg-output `manager`.
+    pub fn new<D>(
+        global: u32,
+        wl_output: WlOutput,
+        manager: Option<&ZxdgOutputManagerV1>,
+        qh: &QueueHandle<D>,
+    ) -> Self
+    where
+        D: Dispatch<ZxdgOutputV1, ()> + 'static,
+    {
+        let xdg_output = manager.map(|manager| manager.get_xdg_output(&wl_output, qh, ()));
+        Self {
+            global,
+            wl_output,
+            xdg_output,
+            info: OutputInfo::default(),
+            pending: OutputInfo::default(),
+        }
+    }
+
+    /// Applies the changes received since the last `done` event. Returns
+    /// whether the scale changed.
+    fn apply_pending(&mut self) -> bool {
+        if self.info == self.pending {
+            return false;
+        }
+        let rescaled = self.info.scale != self.pending.scale;
+
+        self.info = self.pending.clone();
+        let info = &self.info;
+        info!(
+            name = ?info.name,
+            resolution = ?info.resolution,
+            refresh = ?info.refresh_hz(),
+            scale = info.scale,
+            transform = ?info.transform,
+            logical = ?info.logical,
+            "output changed"
+        );
+        rescaled
+    }
+}
+
+/// Every output we know about, e.g. to pick what to render for.
+pub fn outputs(state: &AppState) -> impl Iterator<Item = &Output> {
+    state.outputs.iter()
+}
+
+/// What we know about `output`, if it is one we bound.
+pub fn output_info<'a>(state: &'a AppState, output: &WlOutput) -> Option<&'a OutputInfo> {
+    state
+        .outputs
+        .iter()
+        .find(|o| &o.wl_output == output)
+        .map(|o| &o.info)
+}
+
+/// Releases `output`, if the compositor lets us.
+pub(crate) fn release_output(output: Output) {
+    if let Some(xdg_output) = output.xdg_output {
+        xdg_output.destroy();
+    }
+    // Only has a destructor since version 3
+    if output.wl_output.version() >= 3 {
+        output.wl_output.release();
+    }
+}
+
+impl Dispatch<WlOutput, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlOutput,
+        event: <WlOutput as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(output) = state.outputs.iter_mut().find(|o| &o.wl_output == proxy) else {
+            return;
+        };
+        let pending = &mut output.pending;
+        let done = matches!(event, wl_output::Event::Done);
+
+        match event {
+            wl_output::Event::Geometry {
+                x,
+                y,
+                physical_width,
+                physical_height,
+                make,
+                model,
+                transform,
+                ..
+            } => {
+                pending.position = (x, y);
+                pending.physical_size = (physical_width, physical_height);
+                pending.make = make;
+                pending.model = model;
+                if let WEnum::Value(transform) = transform {
+                    pending.transform = transform;
+                }
+            }
+            wl_output::Event::Mode {
+                flags: WEnum::Value(flags),
+                width,
+                height,
+                refresh,
+            } => {
+                // The other modes it supports are of no use to us
+                if flags.contains(Mode::Current) {
+                    pending.resolution = (width, height);
+                    pending.refresh = refresh;
+                }
+            }
+            wl_output::Event::Scale { factor } => pending.scale = factor,
+            wl_output::Event::Name { name } => pending.name = Some(name),
+            wl_output::Event::Description { description } => {
+                pending.description = Some(description);
+            }
+            wl_output::Event::Done => {}
+            event => {
+                debug!(?event, "ignoring unknown output event");
+                return;
+            }
+        }
+
+        // Version 1 has no done event, every change applies right away
+        if (done || proxy.version() < 2) && output.apply_pending() {
+            // The windows on it have to follow
+            window::update_scales(state);
+        }
+    }
+}
+
+impl Dispatch<ZxdgOutputManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZxdgOutputManagerV1,
+        _event: <ZxdgOutputManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &ZxdgOutputV1,
+        event: <ZxdgOutputV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(output) = state
+            .outputs
+            .iter_mut()
+            .find(|o| o.xdg_output.as_ref() == Some(proxy))
+        else {
+            return;
+        };
+        let logical = output.pending.logical.get_or_insert_with(Default::default);
+
+        match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => logical.position = (x, y),
+            zxdg_output_v1::Event::LogicalSize { width, height } => {
+                logical.size = (width, height);
+            }
+            // wl_output version 4 sends these too, we keep its own
+            zxdg_output_v1::Event::Name { name } => {
+                output.pending.name.get_or_insert(name);
+            }
+            zxdg_output_v1::Event::Description { description } => {
+                output.pending.description.get_or_insert(description);
+            }
+            // Since version 3 the changes come with wl_output.done instead
+            zxdg_output_v1::Event::Done => {
+                if output.apply_pending() {
+                    window::update_scales(state);
+                }
+            }
+            event => debug!(?event, "ignoring unknown xdg output event"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logical_size_follows_rotation_and_scale() {
+        let info = OutputInfo {
+            resolution: (3840, 2160),
+            scale: 2,
+            refresh: 59_940,
+            ..Default::default()
+        };
+        assert_eq!(info.logical_size(), (1920, 1080));
+        assert_eq!(info.refresh_hz(), Some(59.94));
+
+        let rotated = OutputInfo {
+            transform: Transform::_90,
+            ..info.clone()
+        };
+        assert_eq!(rotated.logical_size(), (1080, 1920));
+        assert_eq!(OutputInfo::default().refresh_hz(), None);
+
+        // Scaled by 1.5, which the scale of 2 doesn't tell
+        let fractional = OutputInfo {
+            logical: Some(LogicalGeometry {
+                position: (0, 0),
+                size: (2560, 1440),
+            }),
+            ..info
+        };
+        assert_eq!(fractional.logical_size(), (2560, 1440));
+    }
+}
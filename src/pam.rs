@@ -0,0 +1,339 @@
+//! Password checks through PAM, for the lock screen.
+//!
+//! libpam is loaded at runtime with dlopen(), like libxkbcommon, so we still
+//! build without its development files. Without it there is no way to check
+//! a password, and the session isn't locked in the first place.
+
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    mem,
+    path::Path,
+    ptr,
+};
+
+use libloading::Library;
+use thiserror::Error;
+use tracing::{debug, info, warn};
+
+const LIBRARY: &str = "libpam.so.0";
+
+// From security/_pam_types.h
+const PAM_SUCCESS: c_int = 0;
+const PAM_BUF_ERR: c_int = 5;
+const PAM_CONV_ERR: c_int = 19;
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+const PAM_ERROR_MSG: c_int = 3;
+const PAM_TEXT_INFO: c_int = 4;
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+type Conversation = unsafe extern "C" fn(
+    num_msg: c_int,
+    msg: *mut *const PamMessage,
+    resp: *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int;
+
+#[repr(C)]
+struct PamConv {
+    conv: Conversation,
+    appdata_ptr: *mut c_void,
+}
+
+type PamStart = unsafe extern "C" fn(
+    service_name: *const c_char,
+    user: *const c_char,
+    pam_conversation: *const PamConv,
+    pamh: *mut *mut c_void,
+) -> c_int;
+type PamAuthenticate = unsafe extern "C" fn(pamh: *mut c_void, flags: c_int) -> c_int;
+type PamEnd = unsafe extern "C" fn(pamh: *mut c_void, pam_status: c_int) -> c_int;
+type PamStrerror = unsafe extern "C" fn(pamh: *mut c_void, errnum: c_int) -> *const c_char;
+
+#[derive(Debug, Error)]
+pub enum PamError {
+    #[error("failed to load {LIBRARY}: {0}")]
+    Load(#[from] libloading::Error),
+    #[error("failed to find out who we are")]
+    UnknownUser,
+    #[error("failed to start PAM: {0}")]
+    Start(String),
+    #[error("authentication failed: {0}")]
+    Denied(String),
+}
+
+/// libpam, and the user whose password it checks.
+pub struct Pam {
+    start: PamStart,
+    authenticate: PamAuthenticate,
+    end: PamEnd,
+    strerror: PamStrerror,
+    service: &'static CStr,
+    user: CString,
+    // Keeps the functions above loaded
+    _library: Library,
+}
+
+impl Pam {
+    /// Loads libpam, to check the passwords of the user running us.
+    pub fn load() -> Result<Self, PamError> {
+        let user = current_user().ok_or(PamError::UnknownUser)?;
+        // SAFETY: libpam has no initialisation routines that could misbehave
+        // when loaded
+        let library = unsafe { Library::new(LIBRARY)? };
+        // SAFETY: the symbols have the signatures of the Linux-PAM headers.
+        // The pointers are copied out of `library`, which they live
+        // alongside of.
+        let (start, authenticate, end, strerror) = unsafe {
+            (
+                *library.get::<PamStart>(b"pam_start\0")?,
+                *library.get::<PamAuthenticate>(b"pam_authenticate\0")?,
+                *library.get::<PamEnd>(b"pam_end\0")?,
+                *library.get::<PamStrerror>(b"pam_strerror\0")?,
+            )
+        };
+
+        // Our own if someone installed one, otherwise the one for logging
+        // in, which every system has
+        let service = if Path::new("/etc/pam.d/rust-wayland").exists() {
+            c"rust-wayland"
+        } else {
+            c"login"
+        };
+        info!(service = ?service, user = ?user, "loaded PAM");
+        Ok(Self {
+            start,
+            authenticate,
+            end,
+            strerror,
+            service,
+            user,
+            _library: library,
+        })
+    }
+
+    /// Checks `password`, blocking for as long as PAM takes, which is a
+    /// couple of seconds on purpose when it is wrong.
+    pub fn authenticate(&self, password: &str) -> Result<(), PamError> {
+        // Typed text never holds a NUL, cut it off there if it does
+        let mut password = password.as_bytes().to_vec();
+        if let Some(nul) = password.iter().position(|&b| b == 0) {
+            password.truncate(nul);
+        }
+        let password = CString::new(password).expect("NULs were cut off");
+        let conversation = PamConv {
+            conv: converse,
+            appdata_ptr: password.as_ptr() as *mut c_void,
+        };
+
+        let mut handle = ptr::null_mut();
+        // SAFETY: the service and user are NUL terminated, and the
+        // conversation and the password it points to outlive the handle,
+        // which pam_end() frees below.
+        let res = unsafe {
+            (self.start)(
+                self.service.as_ptr(),
+                self.user.as_ptr(),
+                &conversation,
+                &mut handle,
+            )
+        };
+        if res != PAM_SUCCESS {
+            return Err(PamError::Start(self.describe(handle, res)));
+        }
+        // SAFETY: `handle` was started above and is only ended after this.
+        let res = unsafe { (self.authenticate)(handle, 0) };
+        let outcome = match res {
+            PAM_SUCCESS => Ok(()),
+            res => Err(PamError::Denied(self.describe(handle, res))),
+        };
+        // SAFETY: as above, and never used again.
+        unsafe { (self.end)(handle, res) };
+
+        // Leaves no copy of the password behind in our memory
+        let mut bytes = password.into_bytes_with_nul();
+        bytes.fill(0);
+        outcome
+    }
+
+    /// What PAM says `errnum` means.
+    fn describe(&self, handle: *mut c_void, errnum: c_int) -> String {
+        // SAFETY: pam_strerror() takes any handle, NULL included, and
+        // returns a static string.
+        let message = unsafe { (self.strerror)(handle, errnum) };
+        if message.is_null() {
+            return format!("error {errnum}");
+        }
+        // SAFETY: checked for NULL above, NUL terminated by libpam.
+        unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// The name of the user running us, from the password database.
+fn current_user() -> Option<CString> {
+    let mut buffer = vec![0 as c_char; 4096];
+    // SAFETY: a zeroed passwd is a valid value for getpwuid_r() to fill in
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut result = ptr::null_mut();
+    // SAFETY: every pointer is valid for as long as the call, with the size
+    // of the buffer the strings go to.
+    let res = unsafe {
+        libc::getpwuid_r(
+            libc::getuid(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if res != 0 || result.is_null() || passwd.pw_name.is_null() {
+        return None;
+    }
+    // SAFETY: filled in by getpwuid_r(), pointing into `buffer`
+    Some(unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned())
+}
+
+/// Answers PAM's prompts with the password in `appdata_ptr`, and logs what
+/// it tells the user.
+unsafe extern "C" fn converse(
+    num_msg: c_int,
+    msg: *mut *const PamMessage,
+    resp: *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int {
+    let Ok(count) = usize::try_from(num_msg) else {
+        return PAM_CONV_ERR;
+    };
+    // Freed by PAM, so allocated the way it frees
+    // SAFETY: calloc() can be called with any size, we check for NULL.
+    let responses =
+        unsafe { libc::calloc(count, mem::size_of::<PamResponse>()) } as *mut PamResponse;
+    if responses.is_null() {
+        return PAM_BUF_ERR;
+    }
+    // SAFETY: PAM hands us the password we gave it, NUL terminated
+    let password = unsafe { CStr::from_ptr(appdata_ptr as *const c_char) };
+
+    for i in 0..count {
+        // SAFETY: Linux-PAM passes an array of `num_msg` pointers to
+        // messages, and `responses` has room for as many.
+        let (message, response) = unsafe { (&**msg.add(i), &mut *responses.add(i)) };
+        let text = if message.msg.is_null() {
+            Default::default()
+        } else {
+            // SAFETY: checked for NULL, NUL terminated by PAM
+            unsafe { CStr::from_ptr(message.msg) }.to_string_lossy()
+        };
+        match message.msg_style {
+            PAM_PROMPT_ECHO_OFF | PAM_PROMPT_ECHO_ON => {
+                debug!(prompt = %text, "PAM asks for the password");
+                // SAFETY: `password` is NUL terminated
+                response.resp = unsafe { libc::strdup(password.as_ptr()) };
+                if response.resp.is_null() {
+                    // SAFETY: the responses so far were allocated above
+                    unsafe { free_responses(responses, i) };
+                    return PAM_BUF_ERR;
+                }
+            }
+            PAM_ERROR_MSG => warn!(message = %text, "PAM"),
+            PAM_TEXT_INFO => info!(message = %text, "PAM"),
+            style => {
+                warn!(style, "unknown PAM message style");
+                // SAFETY: as above
+                unsafe { free_responses(responses, i) };
+                return PAM_CONV_ERR;
+            }
+        }
+    }
+
+    // SAFETY: PAM gives us somewhere to put them
+    unsafe { *resp = responses };
+    PAM_SUCCESS
+}
+
+/// Frees the first `count` of `responses` and the array, zeroing the
+/// passwords in them first.
+///
+/// # Safety
+///
+/// `responses` comes from calloc(), with `count` responses filled in whose
+/// strings come from strdup().
+unsafe fn free_responses(responses: *mut PamResponse, count: usize) {
+    for i in 0..count {
+        // SAFETY: within the array, as the caller promises
+        let response = unsafe { &mut *responses.add(i) };
+        if !response.resp.is_null() {
+            // SAFETY: a NUL terminated string of that many bytes
+            unsafe {
+                let len = libc::strlen(response.resp);
+                ptr::write_bytes(response.resp, 0, len);
+                libc::free(response.resp as *mut c_void);
+            }
+        }
+    }
+    // SAFETY: allocated with calloc()
+    unsafe { libc::free(responses as *mut c_void) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A conversation with two prompts and an informational message in
+    // between, the way pam_unix and friends talk
+    #[test]
+    fn prompts_get_the_password() {
+        let password = c"hunter2";
+        let messages = [
+            PamMessage {
+                msg_style: PAM_PROMPT_ECHO_OFF,
+                msg: c"Password: ".as_ptr(),
+            },
+            PamMessage {
+                msg_style: PAM_TEXT_INFO,
+                msg: c"Hello".as_ptr(),
+            },
+            PamMessage {
+                msg_style: PAM_PROMPT_ECHO_ON,
+                msg: ptr::null(),
+            },
+        ];
+        let mut pointers: Vec<*const PamMessage> = messages.iter().map(ptr::from_ref).collect();
+        let mut responses = ptr::null_mut();
+
+        // SAFETY: laid out the way PAM calls it
+        let res = unsafe {
+            converse(
+                pointers.len() as c_int,
+                pointers.as_mut_ptr(),
+                &mut responses,
+                password.as_ptr() as *mut c_void,
+            )
+        };
+        assert_eq!(res, PAM_SUCCESS);
+
+        // SAFETY: filled in by converse() above
+        let answer = |i: usize| unsafe {
+            let resp = (*responses.add(i)).resp;
+            (!resp.is_null()).then(|| CStr::from_ptr(resp).to_owned())
+        };
+        assert_eq!(answer(0).as_deref(), Some(password));
+        assert_eq!(answer(1), None);
+        assert_eq!(answer(2).as_deref(), Some(password));
+        // SAFETY: as PAM would free them
+        unsafe { free_responses(responses, messages.len()) };
+    }
+}
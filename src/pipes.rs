@@ -0,0 +1,215 @@
+//! Pipes read and written alongside the Wayland socket, like the contents
+//! of the clipboard another client sends us, or the ones we send.
+//!
+//! Each pipe is a source of the main loop for as long as it is open. It
+//! reads what arrived and hands the whole of it over once the other end
+//! closed the pipe, and writes as much as fits.
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction};
+use tracing::{trace, warn};
+
+// Nobody should need more than that, and we keep it all in memory
+const MAX_LEN: usize = 16 << 20;
+
+/// Reads what is there without blocking into `data`. Returns `None` while
+/// the other end is still open.
+fn read_available(mut file: &File, data: &mut Vec<u8>) -> Option<io::Result<()>> {
+    let mut chunk = [0; 4096];
+    loop {
+        match file.read(&mut chunk) {
+            Ok(0) => return Some(Ok(())),
+            Ok(n) if data.len() + n > MAX_LEN => {
+                return Some(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("more than {MAX_LEN} bytes"),
+                )));
+            }
+            Ok(n) => data.extend_from_slice(&chunk[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return None,
+            Err(err) => return Some(Err(err)),
+        }
+    }
+}
+
+/// Writes what fits of `data` past `written` without blocking. Returns
+/// `None` until all of it is written.
+fn write_available(mut file: &File, data: &[u8], written: &mut usize) -> Option<io::Result<()>> {
+    while *written < data.len() {
+        match file.write(&data[*written..]) {
+            Ok(n) => *written += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return None,
+            Err(err) => return Some(Err(err)),
+        }
+    }
+    Some(Ok(()))
+}
+
+/// Adds pipes to the main loop of a state `S`.
+pub struct Pipes<S: 'static> {
+    // None until the main loop exists
+    handle: Option<LoopHandle<'static, S>>,
+}
+
+impl<S> Default for Pipes<S> {
+    fn default() -> Self {
+        Self { handle: None }
+    }
+}
+
+impl<S> Pipes<S> {
+    pub fn new(handle: LoopHandle<'static, S>) -> Self {
+        Self {
+            handle: Some(handle),
+        }
+    }
+
+    /// Runs `callback` whenever `file` is ready for `interest`, until it
+    /// returns `true`. The file is closed then.
+    fn insert(
+        &self,
+        fd: OwnedFd,
+        interest: Interest,
+        mut callback: impl FnMut(&mut S, &File) -> bool + 'static,
+    ) -> io::Result<()> {
+        let Some(handle) = &self.handle else {
+            return Err(io::Error::other("no main loop to read and write pipes in"));
+        };
+        set_nonblocking(&fd)?;
+
+        let source = Generic::new(File::from(fd), interest, Mode::Level);
+        handle
+            .insert_source(source, move |_, file, state| {
+                Ok(if callback(state, file) {
+                    PostAction::Remove
+                } else {
+                    PostAction::Continue
+                })
+            })
+            .map_err(|err| err.error)?;
+        Ok(())
+    }
+
+    /// Reads `fd` until the other end closes it, then calls `callback` with
+    /// everything read, or the error that stopped it.
+    pub fn read_to_end(
+        &mut self,
+        fd: OwnedFd,
+        callback: impl FnOnce(&mut S, io::Result<Vec<u8>>) + 'static,
+    ) -> io::Result<()> {
+        let mut data = Vec::new();
+        // Only ever called once, by the last call below
+        let mut callback = Some(callback);
+        self.insert(fd, Interest::READ, move |state, file| {
+            let Some(res) = read_available(file, &mut data) else {
+                return false;
+            };
+            trace!(fd = file.as_raw_fd(), len = data.len(), "pipe closed");
+            if let Some(callback) = callback.take() {
+                callback(state, res.map(|()| mem::take(&mut data)));
+            }
+            true
+        })
+    }
+
+    /// Writes `data` to `fd` and closes it, which tells the other end that
+    /// it has everything. Errors are only logged, the other end can tell
+    /// something went wrong anyway.
+    pub fn write_all(&mut self, fd: OwnedFd, data: Vec<u8>) -> io::Result<()> {
+        let mut written = 0;
+        self.insert(fd, Interest::WRITE, move |_, file| {
+            let Some(res) = write_available(file, &data, &mut written) else {
+                return false;
+            };
+            match res {
+                Ok(()) => trace!(fd = file.as_raw_fd(), len = data.len(), "pipe written"),
+                Err(err) => warn!(%err, "failed to write to a pipe"),
+            }
+            true
+        })
+    }
+}
+
+/// A pipe that is closed on exec, as (read end, write end).
+pub fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two fds pipe2 writes
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: both were just opened, and nothing else owns them
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+fn set_nonblocking(fd: &OwnedFd) -> io::Result<()> {
+    let fd = fd.as_raw_fd();
+    // SAFETY: `fd` is open for as long as the OwnedFd lives
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Duration};
+
+    use calloop::EventLoop;
+
+    use super::*;
+
+    struct State {
+        pipes: Pipes<State>,
+        got: Option<Vec<u8>>,
+    }
+
+    fn event_loop() -> (EventLoop<'static, State>, State) {
+        let event_loop = EventLoop::try_new().unwrap();
+        let state = State {
+            pipes: Pipes::new(event_loop.handle()),
+            got: None,
+        };
+        (event_loop, state)
+    }
+
+    #[test]
+    fn reads_until_the_other_end_closes() {
+        let (mut event_loop, mut state) = event_loop();
+        let (read, write) = pipe().unwrap();
+        state
+            .pipes
+            .read_to_end(read, |state, res| state.got = Some(res.unwrap()))
+            .unwrap();
+
+        let mut write = File::from(write);
+        write.write_all(b"hello").unwrap();
+        event_loop.dispatch(Duration::ZERO, &mut state).unwrap();
+        assert_eq!(state.got, None);
+
+        drop(write);
+        event_loop.dispatch(Duration::ZERO, &mut state).unwrap();
+        assert_eq!(state.got.as_deref(), Some(&b"hello"[..]));
+    }
+
+    #[test]
+    fn writes_everything_then_closes() {
+        let (mut event_loop, mut state) = event_loop();
+        let (read, write) = pipe().unwrap();
+        state.pipes.write_all(write, b"hello".to_vec()).unwrap();
+        event_loop.dispatch(Duration::ZERO, &mut state).unwrap();
+
+        // Only reaches the end once the write end is closed
+        let mut got = Vec::new();
+        File::from(read).read_to_end(&mut got).unwrap();
+        assert_eq!(got, b"hello");
+    }
+}
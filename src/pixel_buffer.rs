@@ -0,0 +1,291 @@
+use wayland_client::protocol::wl_output::Transform;
+
+use crate::{
+    color::{Color, ColorSpace},
+    pixel_format::PixelFormat,
+    rect::Rect,
+    transform::{transform_rect, transform_size},
+};
+
+/// A 32 bits per pixel view over some memory, e.g. a shm buffer.
+///
+/// Drawing outside of the buffer is a bug: it panics in debug builds and is
+/// clipped away in release builds, so a wrong coordinate can never write past
+/// the end of a row or of the mapping.
+///
+/// Coordinates are those of the content, which ends up rotated or flipped
+/// in memory if the buffer has a transform.
+pub struct PixelBuffer<'a> {
+    width: usize,
+    height: usize,
+    // In bytes, like wl_shm
+    stride: usize,
+    format: PixelFormat,
+    data: &'a mut [u32],
+    // In memory coordinates
+    clip: Rect,
+    transform: Transform,
+}
+
+impl<'a> PixelBuffer<'a> {
+    pub fn new(
+        data: &'a mut [u32],
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: PixelFormat,
+    ) -> Self {
+        assert!(
+            stride.is_multiple_of(4),
+            "stride must be a whole number of pixels"
+        );
+        assert!(stride >= width * 4, "stride is smaller than a row");
+        assert!(
+            data.len() >= stride / 4 * height,
+            "buffer is too small for {width}x{height} with a stride of {stride}"
+        );
+
+        Self {
+            width,
+            height,
+            stride,
+            format,
+            data,
+            clip: Rect::new(0, 0, width, height),
+            transform: Transform::Normal,
+        }
+    }
+
+    /// Draws the content with `transform` applied, for a buffer the
+    /// compositor turns back with `set_buffer_transform`.
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// The area of the content, its width and height swapped in memory if
+    /// the buffer is rotated.
+    pub fn bounds(&self) -> Rect {
+        let (width, height) = transform_size((self.width, self.height), self.transform);
+        Rect::new(0, 0, width, height)
+    }
+
+    /// Restricts drawing to `clip`, e.g. to redraw only a damaged area.
+    pub fn set_clip(&mut self, clip: Rect) {
+        let clip = clip.intersect(&self.bounds());
+        self.clip = self.to_memory(clip);
+    }
+
+    /// Replaces a single pixel.
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let visible = self.visible(Rect::new(x, y, 1, 1));
+        if !visible.is_empty() {
+            let row = self.stride / 4 * visible.y;
+            self.data[row + visible.x] = self.format.pack(color.premultiply());
+        }
+    }
+
+    /// Draws `color` on top of a single pixel.
+    pub fn blend_pixel(&mut self, x: usize, y: usize, color: Color, space: ColorSpace) {
+        let visible = self.visible(Rect::new(x, y, 1, 1));
+        if !visible.is_empty() {
+            let pixel = &mut self.data[self.stride / 4 * visible.y + visible.x];
+            *pixel = self
+                .format
+                .pack(color.premultiply().over(self.format.unpack(*pixel), space));
+        }
+    }
+
+    /// Replaces the pixels of a rectangle with `color`.
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let pixel = self.format.pack(color.premultiply());
+        let visible = self.visible(rect);
+        for row in self.rows(visible) {
+            row.fill(pixel);
+        }
+    }
+
+    /// Draws `color` on top of the pixels of a rectangle.
+    pub fn blend_rect(&mut self, rect: Rect, color: Color, space: ColorSpace) {
+        let src = color.premultiply();
+        let format = self.format;
+        let visible = self.visible(rect);
+        for row in self.rows(visible) {
+            for pixel in row {
+                *pixel = format.pack(src.over(format.unpack(*pixel), space));
+            }
+        }
+    }
+
+    /// Fills a rectangle with a left to right gradient.
+    pub fn fill_gradient(&mut self, rect: Rect, from: Color, to: Color, space: ColorSpace) {
+        let steps = rect.width.saturating_sub(1).max(1) as f32;
+        if self.transform != Transform::Normal {
+            // Left to right may be any direction in memory, go a column at
+            // a time
+            for i in 0..rect.width {
+                let color = from.lerp(to, i as f32 / steps, space);
+                self.fill_rect(Rect::new(rect.x + i, rect.y, 1, rect.height), color);
+            }
+            return;
+        }
+
+        let visible = self.visible(rect);
+        if visible.is_empty() {
+            return;
+        }
+
+        // Every row is the same, so compute it once
+        let gradient: Vec<u32> = (visible.x - rect.x..visible.right() - rect.x)
+            .map(|i| {
+                self.format
+                    .pack(from.lerp(to, i as f32 / steps, space).premultiply())
+            })
+            .collect();
+
+        for row in self.rows(visible) {
+            row.copy_from_slice(&gradient);
+        }
+    }
+
+    pub fn fill(&mut self, color: Color) {
+        self.fill_rect(self.bounds(), color);
+    }
+
+    /// All of the memory, rows of pixels in `format`, for other code to
+    /// draw straight into. Only if it is drawn as is: without a transform,
+    /// clip or padding at the end of the rows.
+    pub fn as_mut_slice(&mut self) -> Option<&mut [u32]> {
+        let whole = self.clip == Rect::new(0, 0, self.width, self.height);
+        if self.transform != Transform::Normal || !whole || self.stride != self.width * 4 {
+            return None;
+        }
+        Some(&mut self.data[..self.width * self.height])
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// The part of `rect` we may draw to, in memory coordinates.
+    fn visible(&self, rect: Rect) -> Rect {
+        let bounds = self.bounds();
+        debug_assert!(
+            bounds.contains_rect(&rect),
+            "{rect:?} is outside of {}x{}",
+            bounds.width,
+            bounds.height
+        );
+
+        let rect = rect.intersect(&bounds);
+        self.to_memory(rect).intersect(&self.clip)
+    }
+
+    /// Where `rect` of the content is in memory.
+    fn to_memory(&self, rect: Rect) -> Rect {
+        let bounds = self.bounds();
+        transform_rect(rect, self.transform, (bounds.width, bounds.height))
+    }
+
+    /// The rows of a rectangle that is known to be inside the buffer.
+    fn rows(&mut self, rect: Rect) -> impl Iterator<Item = &mut [u32]> {
+        self.data
+            .chunks_mut(self.stride / 4)
+            .take(rect.bottom())
+            .skip(rect.y)
+            .map(move |row| &mut row[rect.x..rect.right()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+
+    fn filled(data: &[u32], format: PixelFormat) -> usize {
+        let white = format.pack(WHITE.premultiply());
+        data.iter().filter(|&&pixel| pixel == white).count()
+    }
+
+    #[test]
+    fn rects_inside_are_filled() {
+        let format = PixelFormat::Argb8888;
+        // A stride wider than the rows, whose padding is never drawn to
+        let mut data = [0; 6 * 4];
+        let mut pixels = PixelBuffer::new(&mut data, 4, 4, 6 * 4, format);
+        pixels.fill_rect(Rect::new(1, 1, 3, 2), WHITE);
+        assert_eq!(filled(&data, format), 6);
+        assert_eq!(data[6 + 1..6 + 4], [format.pack(WHITE.premultiply()); 3]);
+        assert_eq!(data[6 + 4..6 + 6], [0, 0]);
+    }
+
+    #[test]
+    fn zero_size_rects_draw_nothing() {
+        let format = PixelFormat::Argb8888;
+        let mut data = [0; 4 * 4];
+        let mut pixels = PixelBuffer::new(&mut data, 4, 4, 4 * 4, format);
+        pixels.fill_rect(Rect::new(1, 1, 0, 2), WHITE);
+        pixels.fill_rect(Rect::new(1, 1, 2, 0), WHITE);
+        // Right at the edge, still within the buffer
+        pixels.fill_rect(Rect::new(4, 4, 0, 0), WHITE);
+        assert_eq!(pixels.visible(Rect::new(2, 2, 0, 0)), Rect::default());
+        assert_eq!(filled(&data, format), 0);
+    }
+
+    #[test]
+    fn the_clip_limits_drawing() {
+        let format = PixelFormat::Argb8888;
+        let mut data = [0; 4 * 4];
+        let mut pixels = PixelBuffer::new(&mut data, 4, 4, 4 * 4, format);
+        pixels.set_clip(Rect::new(2, 0, 8, 1));
+        assert_eq!(pixels.visible(Rect::new(0, 0, 4, 4)), Rect::new(2, 0, 2, 1));
+        pixels.fill(WHITE);
+        assert_eq!(filled(&data, format), 2);
+    }
+
+    #[test]
+    fn only_plain_buffers_are_handed_out() {
+        let format = PixelFormat::Argb8888;
+        let mut data = [0; 6 * 4];
+        assert_eq!(
+            PixelBuffer::new(&mut data, 6, 4, 6 * 4, format)
+                .as_mut_slice()
+                .map(|data| data.len()),
+            Some(24)
+        );
+        assert!(PixelBuffer::new(&mut data, 4, 4, 6 * 4, format)
+            .as_mut_slice()
+            .is_none());
+        let mut rotated =
+            PixelBuffer::new(&mut data, 6, 4, 6 * 4, format).with_transform(Transform::_90);
+        assert!(rotated.as_mut_slice().is_none());
+        let mut clipped = PixelBuffer::new(&mut data, 6, 4, 6 * 4, format);
+        clipped.set_clip(Rect::new(0, 0, 2, 2));
+        assert!(clipped.as_mut_slice().is_none());
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "is outside of 4x4"))]
+    fn partly_outside_rects_are_clipped() {
+        let format = PixelFormat::Argb8888;
+        let mut data = [0; 4 * 4];
+        let mut pixels = PixelBuffer::new(&mut data, 4, 4, 4 * 4, format);
+        // A bug in debug builds, clipped in release builds
+        assert_eq!(pixels.visible(Rect::new(2, 3, 4, 4)), Rect::new(2, 3, 2, 1));
+        pixels.fill_rect(Rect::new(2, 3, 4, 4), WHITE);
+        assert_eq!(filled(&data, format), 2);
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "is outside of 4x4"))]
+    fn rects_outside_are_left_out() {
+        let format = PixelFormat::Argb8888;
+        let mut data = [0; 4 * 4];
+        let mut pixels = PixelBuffer::new(&mut data, 4, 4, 4 * 4, format);
+        assert!(pixels.visible(Rect::new(10, 0, 2, 2)).is_empty());
+        pixels.fill_rect(Rect::new(10, 0, 2, 2), WHITE);
+        pixels.fill_rect(Rect::new(0, 4, 2, 0), WHITE);
+        assert_eq!(filled(&data, format), 0);
+    }
+}
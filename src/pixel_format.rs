@@ -0,0 +1,154 @@
+use wayland_client::protocol::wl_shm::Format;
+
+use crate::color::PremulColor;
+
+/// The wl_shm formats we know how to draw into.
+///
+/// wl_shm formats name the channels of a 32-bit little-endian word, from the
+/// most to the least significant byte. So an Argb8888 pixel is stored as the
+/// bytes B, G, R, A no matter what the CPU's byte order is.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    // wl_shm only guarantees this one and Xrgb8888
+    #[default]
+    Argb8888,
+    /// Like Argb8888, but the alpha byte is ignored
+    Xrgb8888,
+}
+
+impl PixelFormat {
+    /// Picks the format to draw in out of those the compositor advertised.
+    /// Without alpha Xrgb8888 is preferred, compositors can skip blending
+    /// what is below the window then.
+    pub fn choose(supported: &[Format], needs_alpha: bool) -> Option<Self> {
+        let preferred = if needs_alpha {
+            [Self::Argb8888, Self::Xrgb8888]
+        } else {
+            [Self::Xrgb8888, Self::Argb8888]
+        };
+
+        preferred
+            .into_iter()
+            .find(|format| supported.contains(&format.wl_format()))
+    }
+
+    /// Whether every pixel drawn in this format is opaque, whatever alpha
+    /// we draw with.
+    pub fn is_opaque(self) -> bool {
+        self == Self::Xrgb8888
+    }
+
+    pub fn wl_format(self) -> Format {
+        match self {
+            Self::Argb8888 => Format::Argb8888,
+            Self::Xrgb8888 => Format::Xrgb8888,
+        }
+    }
+
+    /// Packs `color` into a word whose in-memory bytes are what the
+    /// compositor expects, on little and big-endian CPUs alike.
+    pub fn pack(self, color: PremulColor) -> u32 {
+        let a = match self {
+            Self::Argb8888 => color.a,
+            // Unused, but keep it opaque in case someone reads it as ARGB
+            Self::Xrgb8888 => 0xFF,
+        };
+
+        u32::from_le_bytes([color.b, color.g, color.r, a])
+    }
+
+    pub fn unpack(self, pixel: u32) -> PremulColor {
+        let [b, g, r, a] = pixel.to_le_bytes();
+        let a = match self {
+            Self::Argb8888 => a,
+            Self::Xrgb8888 => 0xFF,
+        };
+
+        PremulColor { r, g, b, a }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn bytes(format: PixelFormat, color: Color) -> [u8; 4] {
+        format.pack(color.premultiply()).to_ne_bytes()
+    }
+
+    #[test]
+    fn argb8888_byte_order() {
+        let format = PixelFormat::Argb8888;
+        assert_eq!(
+            bytes(format, Color::rgb(0xFF, 0, 0)),
+            [0x00, 0x00, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            bytes(format, Color::rgb(0, 0xFF, 0)),
+            [0x00, 0xFF, 0x00, 0xFF]
+        );
+        assert_eq!(
+            bytes(format, Color::rgb(0, 0, 0xFF)),
+            [0xFF, 0x00, 0x00, 0xFF]
+        );
+        assert_eq!(
+            bytes(format, Color::rgb(0x12, 0x34, 0x56)),
+            [0x56, 0x34, 0x12, 0xFF]
+        );
+    }
+
+    #[test]
+    fn argb8888_is_premultiplied() {
+        let format = PixelFormat::Argb8888;
+        let half_white = Color::rgba(0xFF, 0xFF, 0xFF, 0x80);
+        assert_eq!(bytes(format, half_white), [0x80, 0x80, 0x80, 0x80]);
+        assert_eq!(bytes(format, Color::rgba(0xFF, 0, 0, 0)), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn xrgb8888_byte_order() {
+        let format = PixelFormat::Xrgb8888;
+        assert_eq!(
+            bytes(format, Color::rgb(0xFF, 0, 0)),
+            [0x00, 0x00, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            bytes(format, Color::rgb(0x12, 0x34, 0x56)),
+            [0x56, 0x34, 0x12, 0xFF]
+        );
+        // Alpha is dropped, but the color stays premultiplied
+        let half_white = Color::rgba(0xFF, 0xFF, 0xFF, 0x80);
+        assert_eq!(bytes(format, half_white), [0x80, 0x80, 0x80, 0xFF]);
+    }
+
+    #[test]
+    fn choose_prefers_xrgb_when_opaque() {
+        let both = [Format::Argb8888, Format::Xrgb8888];
+        assert_eq!(
+            PixelFormat::choose(&both, false),
+            Some(PixelFormat::Xrgb8888)
+        );
+        assert_eq!(
+            PixelFormat::choose(&both, true),
+            Some(PixelFormat::Argb8888)
+        );
+        // Falls back to whatever is there
+        assert_eq!(
+            PixelFormat::choose(&[Format::Argb8888], false),
+            Some(PixelFormat::Argb8888)
+        );
+        assert_eq!(PixelFormat::choose(&[Format::Rgb565], false), None);
+    }
+
+    #[test]
+    fn unpack_roundtrip() {
+        let color = Color::rgba(0xFF, 0x80, 0x00, 0xC0).premultiply();
+        let argb = PixelFormat::Argb8888;
+        assert_eq!(argb.unpack(argb.pack(color)), color);
+
+        let opaque = Color::rgb(0x12, 0x34, 0x56).premultiply();
+        let xrgb = PixelFormat::Xrgb8888;
+        assert_eq!(xrgb.unpack(xrgb.pack(opaque)), opaque);
+    }
+}
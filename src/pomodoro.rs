@@ -0,0 +1,300 @@
+//! A pomodoro timer: a ring in the corner of the screen that empties as the
+//! time runs out, with what is left written in the middle. Started, paused
+//! and reset over the control socket, and says so with a notification and
+//! the bell once the time is up.
+
+use std::{
+    f32::consts::TAU,
+    io::{self, Write},
+    process::Command,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{info, warn};
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::Layer,
+    zwlr_layer_surface_v1::{Anchor, KeyboardInteractivity},
+};
+
+use crate::{
+    color::{Color, ColorSpace},
+    layer::LayerOptions,
+    pixel_buffer::PixelBuffer,
+    render::present_if_needed,
+    scale::Scale,
+    scene::Scene,
+    state::AppState,
+    text::{self, Font, FontError},
+};
+
+/// How long a pomodoro lasts if not told otherwise
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(25 * 60);
+/// How often the time left is looked at, often enough for the seconds
+/// shown to keep in step with it
+pub const TICK_INTERVAL: Duration = Duration::from_millis(200);
+/// What the control socket is called, for `--send`
+pub const SOCKET_NAME: &str = "pomodoro";
+
+const SIZE: u32 = 160;
+const RING_WIDTH: f32 = 10.0;
+const FONT_SIZE: f32 = 28.0;
+
+const DISC: Color = Color::rgba(0x20, 0x20, 0x20, 0xD0);
+const TRACK: Color = Color::rgb(0x48, 0x48, 0x48);
+const RING: Color = Color::rgb(0xE0, 0x50, 0x40);
+const TEXT: Color = Color::rgb(0xE0, 0xE0, 0xE0);
+
+/// A square in the top right corner, over everything, which never takes
+/// the keyboard.
+pub fn layer_options() -> LayerOptions {
+    LayerOptions {
+        anchor: Anchor::Top | Anchor::Right,
+        keyboard_interactivity: KeyboardInteractivity::None,
+        size: (SIZE, SIZE),
+        ..LayerOptions::for_layer(Layer::Overlay)
+    }
+}
+
+/// The time a pomodoro takes, and how much of it went by.
+#[derive(Debug, Clone, Copy)]
+struct Countdown {
+    duration: Duration,
+    // Up to the last pause
+    elapsed: Duration,
+    // Since when it runs, `None` while paused
+    started: Option<Instant>,
+}
+
+impl Countdown {
+    fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            elapsed: Duration::ZERO,
+            started: None,
+        }
+    }
+
+    fn elapsed(&self, now: Instant) -> Duration {
+        let running = self.started.map_or(Duration::ZERO, |started| now - started);
+        (self.elapsed + running).min(self.duration)
+    }
+
+    fn remaining(&self, now: Instant) -> Duration {
+        self.duration - self.elapsed(now)
+    }
+
+    fn is_over(&self, now: Instant) -> bool {
+        self.remaining(now).is_zero()
+    }
+
+    fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// Starts counting down, from the top again if the time was up.
+    fn start(&mut self, now: Instant) {
+        if self.is_over(now) {
+            self.reset();
+        }
+        self.started.get_or_insert(now);
+    }
+
+    fn pause(&mut self, now: Instant) {
+        self.elapsed = self.elapsed(now);
+        self.started = None;
+    }
+
+    /// Back to the whole duration, paused.
+    fn reset(&mut self) {
+        self.elapsed = Duration::ZERO;
+        self.started = None;
+    }
+
+    /// The time left as `MM:SS`, rounded up so that 00:00 means it is over.
+    fn label(&self, now: Instant) -> String {
+        let remaining = self.remaining(now);
+        let seconds = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// The countdown, and the font the time left is written in.
+#[derive(Clone)]
+pub struct Pomodoro {
+    font: Arc<Font>,
+    countdown: Countdown,
+    // What was last drawn, to redraw only once it changes
+    shown: String,
+}
+
+impl Pomodoro {
+    /// A pomodoro of `duration`, already running.
+    pub fn new(duration: Duration) -> Result<Self, FontError> {
+        let mut countdown = Countdown::new(duration);
+        countdown.start(Instant::now());
+        Ok(Self {
+            font: Arc::new(Font::find(text::SANS)?),
+            countdown,
+            shown: String::new(),
+        })
+    }
+
+    /// Draws the ring and the time left into `pixels`, laid out in window
+    /// pixels.
+    pub(crate) fn draw(&self, pixels: &mut PixelBuffer, scale: Scale) {
+        let now = Instant::now();
+        pixels.fill(Color::rgba(0x00, 0x00, 0x00, 0x00));
+        let bounds = pixels.bounds();
+        let (center_x, center_y) = (bounds.width as f32 / 2.0, bounds.height as f32 / 2.0);
+        let radius = center_x.min(center_y);
+        let ring_width = RING_WIDTH * scale.as_f64() as f32;
+        // What is left, as a part of the turn clockwise from the top
+        let countdown = &self.countdown;
+        let left = countdown.remaining(now).as_secs_f32()
+            / countdown.duration.as_secs_f32().max(f32::EPSILON);
+
+        for y in 0..bounds.height {
+            for x in 0..bounds.width {
+                let (dx, dy) = (x as f32 + 0.5 - center_x, y as f32 + 0.5 - center_y);
+                let distance = (dx * dx + dy * dy).sqrt();
+                // Smoothed over a pixel on either edge
+                let inside = (radius - distance + 0.5).clamp(0.0, 1.0);
+                if inside == 0.0 {
+                    continue;
+                }
+                pixels.blend_pixel(x, y, DISC.with_coverage(inside), ColorSpace::Srgb);
+
+                let on_ring = inside.min((distance - (radius - ring_width) + 0.5).clamp(0.0, 1.0));
+                if on_ring == 0.0 {
+                    continue;
+                }
+                let turn = (dx.atan2(-dy) / TAU).rem_euclid(1.0);
+                let color = if turn < left { RING } else { TRACK };
+                pixels.blend_pixel(x, y, color.with_coverage(on_ring), ColorSpace::Srgb);
+            }
+        }
+
+        let label = countdown.label(now);
+        let font_size = FONT_SIZE * scale.as_f64() as f32;
+        let x = bounds
+            .width
+            .saturating_sub(self.font.width(&label, font_size))
+            / 2;
+        let y = bounds
+            .height
+            .saturating_sub(self.font.line_height(font_size))
+            / 2;
+        self.font.draw(pixels, &label, (x, y), font_size, TEXT);
+    }
+}
+
+/// Looks at the time left in the pomodoro scene, redraws it once the
+/// seconds shown change and rings once it is up.
+pub(crate) fn update(state: &mut AppState) {
+    let Scene::Pomodoro(pomodoro) = &mut state.scene else {
+        return;
+    };
+
+    let now = Instant::now();
+    let countdown = &mut pomodoro.countdown;
+    let label = countdown.label(now);
+    if countdown.is_running() && countdown.is_over(now) {
+        countdown.pause(now);
+        ring(countdown.duration);
+    }
+    if label != pomodoro.shown {
+        pomodoro.shown = label;
+        for window in &mut state.windows {
+            window.request_redraw();
+        }
+    }
+    present_if_needed(state);
+}
+
+/// Rings the terminal bell and sends a notification that the time is up.
+fn ring(duration: Duration) {
+    info!("the pomodoro is over");
+    let mut stderr = io::stderr();
+    // Nowhere to ring it if we aren't in a terminal, nothing to do about it
+    let _ = stderr.write_all(b"\x07").and_then(|()| stderr.flush());
+
+    let minutes = duration.as_secs() / 60;
+    let child = Command::new("notify-send")
+        .args(["--app-name", "rust-wayland", "--urgency", "critical"])
+        // Notification daemons that play sounds play this one
+        .args(["--hint", "string:sound-name:complete"])
+        .arg("Time is up")
+        .arg(format!("The {minutes} minute pomodoro is over"))
+        .spawn();
+    match child {
+        // Reaped in the background so that it doesn't linger as a zombie
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(err) => warn!(%err, "failed to send a notification"),
+    }
+}
+
+/// Answers a command sent over the control socket: `start`, `pause` or
+/// `reset`.
+pub(crate) fn command(state: &mut AppState, command: &str) -> String {
+    let Scene::Pomodoro(pomodoro) = &mut state.scene else {
+        return "not a pomodoro".to_owned();
+    };
+
+    let now = Instant::now();
+    let countdown = &mut pomodoro.countdown;
+    match command {
+        "start" => countdown.start(now),
+        "pause" => countdown.pause(now),
+        "reset" => countdown.reset(),
+        _ => return format!("unknown command {command:?}, expected start, pause or reset"),
+    }
+    let answer = format!(
+        "{} {}",
+        if countdown.is_running() {
+            "running"
+        } else {
+            "paused"
+        },
+        countdown.label(now)
+    );
+
+    // The ring moves even if the seconds shown don't
+    for window in &mut state.windows {
+        window.request_redraw();
+    }
+    present_if_needed(state);
+    answer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_down_while_running() {
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+        let mut countdown = Countdown::new(Duration::from_secs(90));
+        assert_eq!(countdown.label(at(10)), "01:30");
+
+        countdown.start(start);
+        assert_eq!(countdown.label(at(10)), "01:20");
+        countdown.pause(at(10));
+        assert_eq!(countdown.label(at(50)), "01:20");
+        countdown.start(at(50));
+        assert_eq!(countdown.label(at(60)), "01:10");
+        assert!(countdown.is_over(at(130)));
+        assert_eq!(countdown.label(at(200)), "00:00");
+
+        // From the top once it is over
+        countdown.start(at(200));
+        assert_eq!(countdown.label(at(200)), "01:30");
+        countdown.reset();
+        assert!(!countdown.is_running());
+        assert_eq!(countdown.label(at(300)), "01:30");
+    }
+}
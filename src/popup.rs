@@ -0,0 +1,282 @@
+//! Popups: short lived surfaces placed relative to the window, like menus
+//! and tooltips. The compositor keeps them on screen and dismisses them when
+//! the user clicks elsewhere.
+
+use tracing::debug;
+use wayland_client::{
+    protocol::{
+        wl_buffer::WlBuffer, wl_compositor::WlCompositor, wl_seat::WlSeat, wl_shm::WlShm,
+        wl_shm_pool::WlShmPool, wl_surface::WlSurface,
+    },
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+use wayland_protocols::xdg::shell::client::{
+    xdg_popup::{self, XdgPopup},
+    xdg_positioner::{Anchor, ConstraintAdjustment, Gravity, XdgPositioner},
+    xdg_surface::{self, XdgSurface},
+    xdg_wm_base::XdgWmBase,
+};
+
+use crate::{
+    buffers::Swapchain, error::Error, menu, pixel_buffer::PixelBuffer, pixel_format::PixelFormat,
+    rect::Rect, render, state::AppState, window::Window,
+};
+
+/// Where a popup goes, relative to the window geometry of its parent. The
+/// compositor moves it around within these rules to keep it on screen.
+#[derive(Debug, Clone)]
+pub struct Positioner {
+    pub size: (usize, usize),
+    /// The area of the parent the popup is attached to
+    pub anchor_rect: Rect,
+    /// Which point of `anchor_rect` the popup is attached to
+    pub anchor: Anchor,
+    /// Which way the popup extends from that point
+    pub gravity: Gravity,
+    /// How the compositor may move the popup when it doesn't fit
+    pub constraint_adjustment: ConstraintAdjustment,
+    pub offset: (i32, i32),
+}
+
+impl Positioner {
+    /// A popup that opens at `(x, y)` like a context menu: down and to the
+    /// right, flipped or slid over when that would go off screen.
+    pub fn at_point((x, y): (usize, usize), size: (usize, usize)) -> Self {
+        Self {
+            size,
+            anchor_rect: Rect::new(x, y, 1, 1),
+            anchor: Anchor::TopLeft,
+            gravity: Gravity::BottomRight,
+            constraint_adjustment: ConstraintAdjustment::FlipX
+                | ConstraintAdjustment::FlipY
+                | ConstraintAdjustment::SlideX
+                | ConstraintAdjustment::SlideY,
+            offset: (0, 0),
+        }
+    }
+
+    /// Creates the protocol object, it is only needed until the popup has
+    /// been created or repositioned.
+    fn create<D>(&self, xdg_wm_base: &XdgWmBase, qh: &QueueHandle<D>) -> XdgPositioner
+    where
+        D: Dispatch<XdgPositioner, ()> + 'static,
+    {
+        let positioner = xdg_wm_base.create_positioner(qh, ());
+        let r = &self.anchor_rect;
+        positioner.set_size(self.size.0 as i32, self.size.1 as i32);
+        positioner.set_anchor_rect(r.x as i32, r.y as i32, r.width as i32, r.height as i32);
+        positioner.set_anchor(self.anchor);
+        positioner.set_gravity(self.gravity);
+        positioner.set_constraint_adjustment(self.constraint_adjustment);
+        positioner.set_offset(self.offset.0, self.offset.1);
+        positioner
+    }
+}
+
+/// User data of the xdg_surface of a popup, tells it from the one of the
+/// window.
+pub struct PopupRole;
+
+/// A popup surface with its own buffers.
+pub struct Popup {
+    surface: WlSurface,
+    xdg_surface: XdgSurface,
+    popup: XdgPopup,
+    buffers: Swapchain,
+    // Size the compositor gave us, the one we asked for until then
+    size: (usize, usize),
+    // Whether we got our first configure and may attach buffers
+    pub(crate) configured: bool,
+    // Something changed while no buffer was free
+    pub(crate) needs_redraw: bool,
+}
+
+impl Popup {
+    /// Creates a popup attached to `parent` and commits it, which asks the
+    /// compositor for the first configure. With `grab`, the seat and serial
+    /// of the input event that opened it, the popup gets the keyboard and is
+    /// dismissed by a click anywhere else.
+    pub fn new<D>(
+        compositor: &WlCompositor,
+        xdg_wm_base: &XdgWmBase,
+        parent: &Window,
+        positioner: &Positioner,
+        format: PixelFormat,
+        grab: Option<(&WlSeat, u32)>,
+        qh: &QueueHandle<D>,
+    ) -> Self
+    where
+        D: Dispatch<WlSurface, ()>
+            + Dispatch<XdgSurface, PopupRole>
+            + Dispatch<XdgPopup, ()>
+            + Dispatch<XdgPositioner, ()>
+            + 'static,
+    {
+        let surface = compositor.create_surface(qh, ());
+        let xdg_surface = xdg_wm_base.get_xdg_surface(&surface, qh, PopupRole);
+        let xdg_positioner = positioner.create(xdg_wm_base, qh);
+        let popup = xdg_surface.get_popup(parent.xdg_surface(), &xdg_positioner, qh, ());
+        xdg_positioner.destroy();
+        // Layer surfaces take their popups in after the fact
+        if let Some(layer_surface) = parent.layer_surface() {
+            layer_surface.get_popup(&popup);
+        }
+
+        // Only allowed before the popup is mapped
+        if let Some((seat, serial)) = grab {
+            popup.grab(seat, serial);
+        }
+        surface.commit();
+
+        Self {
+            surface,
+            xdg_surface,
+            popup,
+            buffers: Swapchain::new(format),
+            size: positioner.size,
+            configured: false,
+            needs_redraw: false,
+        }
+    }
+
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    /// Moves the popup somewhere else, e.g. to follow what it points at.
+    /// `token` comes back with the `repositioned` event. Needs xdg_wm_base
+    /// version 3, older compositors leave the popup where it is.
+    pub fn reposition<D>(
+        &self,
+        xdg_wm_base: &XdgWmBase,
+        positioner: &Positioner,
+        token: u32,
+        qh: &QueueHandle<D>,
+    ) where
+        D: Dispatch<XdgPositioner, ()> + 'static,
+    {
+        if self.popup.version() < 3 {
+            debug!("the compositor can't reposition popups");
+            return;
+        }
+
+        let xdg_positioner = positioner.create(xdg_wm_base, qh);
+        // Keeps it from being hidden while the new position is worked out
+        xdg_positioner.set_reactive();
+        self.popup.reposition(&xdg_positioner, token);
+        xdg_positioner.destroy();
+    }
+
+    /// Applies a popup configure. A size of 0 keeps the one we asked for.
+    fn configure(&mut self, width: i32, height: i32) {
+        if width > 0 && height > 0 {
+            self.size = (width as usize, height as usize);
+        }
+    }
+
+    /// The compositor is done reading from `buffer`, if it is one of ours.
+    pub(crate) fn release(&mut self, buffer: &WlBuffer) {
+        self.buffers.release(buffer);
+    }
+
+    /// Draws the whole popup with `draw` and commits it. Does nothing before
+    /// the first configure, and only marks the popup for redrawing if all of
+    /// its buffers are with the compositor.
+    pub(crate) fn present<D>(
+        &mut self,
+        shm: &WlShm,
+        qh: &QueueHandle<D>,
+        draw: impl FnOnce(&mut PixelBuffer),
+    ) -> Result<(), Error>
+    where
+        D: Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, ()> + 'static,
+    {
+        if !self.configured {
+            return Ok(());
+        }
+
+        // Small enough to always be drawn whole
+        let drawn =
+            render::present_whole(&self.surface, &mut self.buffers, self.size, shm, qh, draw)?;
+        self.needs_redraw = !drawn;
+        Ok(())
+    }
+
+    /// Destroys the roles before the surface, as the protocol requires.
+    pub(crate) fn destroy(self) {
+        self.popup.destroy();
+        self.xdg_surface.destroy();
+        self.surface.destroy();
+    }
+}
+
+impl Dispatch<XdgSurface, PopupRole> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &XdgSurface,
+        event: <XdgSurface as Proxy>::Event,
+        _data: &PopupRole,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            debug!(?serial, "popup surface configure");
+            proxy.ack_configure(serial);
+
+            if let Some(menu) = state.menu.as_mut() {
+                menu.popup.configured = true;
+            }
+            menu::redraw(state);
+        }
+    }
+}
+
+impl Dispatch<XdgPopup, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &XdgPopup,
+        event: <XdgPopup as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            xdg_popup::Event::Configure {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                debug!(x, y, width, height, "popup configure");
+                if let Some(menu) = state.menu.as_mut() {
+                    menu.popup.configure(width, height);
+                }
+            }
+            // Clicked outside of it, or the grab was refused
+            xdg_popup::Event::PopupDone => {
+                debug!("popup dismissed");
+                menu::close(state);
+            }
+            // A configure with the new position follows
+            xdg_popup::Event::Repositioned { token } => debug!(token, "popup repositioned"),
+            event => debug!(?event, "ignoring unknown popup event"),
+        }
+    }
+}
+
+impl Dispatch<XdgPositioner, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &XdgPositioner,
+        _event: <XdgPositioner as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
@@ -0,0 +1,278 @@
+//! presentation-time: when our frames actually reach the screen, and how
+//! late, for frame timing statistics.
+
+use std::time::Duration;
+
+use tracing::{debug, trace, warn};
+use wayland_client::{
+    protocol::wl_surface::WlSurface, Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+use wayland_protocols::wp::presentation_time::client::{
+    wp_presentation::{self, WpPresentation},
+    wp_presentation_feedback::{self, Kind, WpPresentationFeedback},
+};
+
+use crate::{hud::LatencyGraph, state::AppState, window::WindowId};
+
+/// Counters describing how the frames of a window reach the screen.
+#[derive(Debug, Default)]
+pub struct PresentationStats {
+    pub presented: u64,
+    /// Replaced by a later frame before they were shown, or never shown
+    pub discarded: u64,
+    /// Shown more than a refresh cycle after they were committed, so later
+    /// than they could have been
+    pub missed: u64,
+    /// Refresh cycles an animation went without a frame because drawing
+    /// took too long, skipped rather than slowing down
+    pub dropped: u64,
+    /// Total time between committing frames and them being shown
+    pub latency: Duration,
+    /// The shortest and longest time a frame took to be shown
+    pub min_latency: Option<Duration>,
+    pub max_latency: Duration,
+    /// The time the last frames took to be shown
+    pub graph: LatencyGraph,
+    /// How often the output refreshed as of the last frame, zero if unknown
+    /// or variable
+    pub refresh: Duration,
+    /// How the last frame was shown
+    pub flags: Option<Kind>,
+    /// Refresh counter of the output as of the last frame, if it has one
+    pub sequence: Option<u64>,
+}
+
+impl PresentationStats {
+    /// A frame committed at `committed` was shown at `shown`, both on the
+    /// compositor's presentation clock.
+    pub fn presented(
+        &mut self,
+        committed: Duration,
+        shown: Duration,
+        refresh: Duration,
+        sequence: Option<u64>,
+        flags: Kind,
+    ) {
+        let latency = shown.saturating_sub(committed);
+        self.presented += 1;
+        self.latency += latency;
+        self.min_latency = Some(self.min_latency.map_or(latency, |min| min.min(latency)));
+        self.max_latency = self.max_latency.max(latency);
+        self.graph.push(latency, refresh);
+        self.refresh = refresh;
+        self.flags = Some(flags);
+        self.sequence = sequence;
+
+        if !refresh.is_zero() && latency > refresh {
+            self.missed += 1;
+        }
+    }
+
+    pub fn discarded(&mut self) {
+        self.discarded += 1;
+    }
+
+    /// An animation skipped ahead by `frames` refresh cycles.
+    pub fn dropped(&mut self, frames: u32) {
+        self.dropped += frames as u64;
+    }
+
+    /// The average time between committing a frame and it being shown.
+    pub fn average_latency(&self) -> Option<Duration> {
+        (self.presented > 0).then(|| self.latency / self.presented as u32)
+    }
+}
+
+/// How many refresh cycles passed in `elapsed` between two frames: the
+/// whole number closest to it, at least one. `None` if the refresh rate is
+/// unknown.
+pub fn refresh_cycles(elapsed: Duration, refresh: Option<Duration>) -> Option<u32> {
+    let refresh = refresh.filter(|r| !r.is_zero())?;
+    let cycles = (elapsed.as_secs_f64() / refresh.as_secs_f64())
+        .round()
+        .max(1.0);
+    Some(cycles as u32)
+}
+
+/// How far animations move on after `elapsed` passed between two frames:
+/// by [`refresh_cycles`], so that they move at the same speed whatever the
+/// refresh rate and skip ahead rather than slow down when frames are late.
+/// Just `elapsed` if the refresh rate is unknown.
+pub fn animation_step(elapsed: Duration, refresh: Option<Duration>) -> Duration {
+    match (refresh_cycles(elapsed, refresh), refresh) {
+        (Some(cycles), Some(refresh)) => refresh * cycles,
+        _ => elapsed,
+    }
+}
+
+/// The presentation global, along with the clock its timestamps are on.
+pub struct Presentation {
+    presentation: WpPresentation,
+    // Sent right after binding, before any frame could be committed
+    clock: Option<libc::clockid_t>,
+}
+
+/// User data of a feedback: the window and when the frame was committed.
+pub struct Feedback {
+    window: WindowId,
+    committed: Option<Duration>,
+}
+
+impl Presentation {
+    pub fn new(presentation: WpPresentation) -> Self {
+        Self {
+            presentation,
+            clock: None,
+        }
+    }
+
+    /// Asks to be told when the next commit of `surface`, which belongs to
+    /// the window `id`, reaches the screen.
+    pub(crate) fn feedback<D>(&self, surface: &WlSurface, id: WindowId, qh: &QueueHandle<D>)
+    where
+        D: Dispatch<WpPresentationFeedback, Feedback> + 'static,
+    {
+        let committed = self.clock.and_then(now);
+        self.presentation.feedback(
+            surface,
+            qh,
+            Feedback {
+                window: id,
+                committed,
+            },
+        );
+    }
+
+    pub(crate) fn destroy(self) {
+        self.presentation.destroy();
+    }
+}
+
+/// The current time on `clock`.
+fn now(clock: libc::clockid_t) -> Option<Duration> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `time` is a valid timespec to write to
+    if unsafe { libc::clock_gettime(clock, &mut time) } != 0 {
+        warn!(clock, "failed to read the presentation clock");
+        return None;
+    }
+    Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+impl Dispatch<WpPresentation, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpPresentation,
+        event: <WpPresentation as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wp_presentation::Event::ClockId { clk_id } => {
+                debug!(clk_id, "presentation clock");
+                if let Some(presentation) = state.presentation.as_mut() {
+                    presentation.clock = Some(clk_id as libc::clockid_t);
+                }
+            }
+            event => debug!(?event, "ignoring unknown presentation event"),
+        }
+    }
+}
+
+impl Dispatch<WpPresentationFeedback, Feedback> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpPresentationFeedback,
+        event: <WpPresentationFeedback as Proxy>::Event,
+        feedback: &Feedback,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let id = feedback.window;
+        let Some(window) = state.window_mut(id) else {
+            return;
+        };
+        let stats = &mut window.presentation_stats;
+
+        match event {
+            wp_presentation_feedback::Event::Presented {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+                refresh,
+                seq_hi,
+                seq_lo,
+                flags,
+            } => {
+                let shown = Duration::new((tv_sec_hi as u64) << 32 | tv_sec_lo as u64, tv_nsec);
+                let flags = match flags {
+                    WEnum::Value(flags) => flags,
+                    WEnum::Unknown(bits) => Kind::from_bits_truncate(bits),
+                };
+                // Only counts refreshes if the frame was synced to them
+                let sequence = (seq_hi as u64) << 32 | seq_lo as u64;
+                let sequence = flags.contains(Kind::Vsync).then_some(sequence);
+
+                // Without the commit time there is no latency to speak of
+                let Some(committed) = feedback.committed else {
+                    return;
+                };
+                trace!(?id, latency = ?shown.saturating_sub(committed), "frame shown");
+                stats.presented(
+                    committed,
+                    shown,
+                    Duration::from_nanos(refresh as u64),
+                    sequence,
+                    flags,
+                );
+            }
+            wp_presentation_feedback::Event::Discarded => stats.discarded(),
+            // Which output it was shown on, of no use to us
+            wp_presentation_feedback::Event::SyncOutput { .. } => {}
+            event => debug!(?event, "ignoring unknown presentation feedback event"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_frames_count_as_missed() {
+        let mut stats = PresentationStats::default();
+        let refresh = Duration::from_micros(16_667);
+        let at = Duration::from_millis;
+
+        stats.presented(at(100), at(110), refresh, Some(1), Kind::Vsync);
+        stats.presented(at(200), at(230), refresh, Some(4), Kind::Vsync);
+        stats.discarded();
+
+        assert_eq!((stats.presented, stats.missed, stats.discarded), (2, 1, 1));
+        assert_eq!(stats.average_latency(), Some(at(20)));
+        assert_eq!(
+            (stats.min_latency, stats.max_latency),
+            (Some(at(10)), at(30))
+        );
+        assert_eq!(PresentationStats::default().average_latency(), None);
+    }
+
+    #[test]
+    fn animations_step_by_refresh_cycles() {
+        let hz_144 = Duration::from_secs(1) / 144;
+        let at = Duration::from_millis;
+
+        // Timestamps in milliseconds jitter around the real interval
+        assert_eq!(animation_step(at(6), Some(hz_144)), hz_144);
+        assert_eq!(animation_step(at(8), Some(hz_144)), hz_144);
+        // A frame was skipped
+        assert_eq!(animation_step(at(14), Some(hz_144)), hz_144 * 2);
+        assert_eq!(refresh_cycles(at(14), Some(hz_144)), Some(2));
+        assert_eq!(animation_step(at(17), None), at(17));
+        assert_eq!(refresh_cycles(at(17), None), None);
+    }
+}
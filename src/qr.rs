@@ -0,0 +1,39 @@
+use qrcodegen::QrCode;
+
+use crate::{color::Color, pixel_buffer::PixelBuffer, rect::Rect};
+
+// The spec asks for a 4 module wide light border around the code
+const QUIET_ZONE: usize = 4;
+/// What is around and between the dark modules
+pub const LIGHT: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+
+/// Draws `code` centered in the buffer, as large as it fits with whole
+/// pixels per module so that the edges stay sharp.
+pub fn draw(pixels: &mut PixelBuffer, code: &QrCode) {
+    let bounds = pixels.bounds();
+    pixels.fill(LIGHT);
+
+    let modules = code.size() as usize;
+    let module_size = bounds.width.min(bounds.height) / (modules + 2 * QUIET_ZONE);
+    if module_size == 0 {
+        // Too small to be readable anyway
+        return;
+    }
+
+    let x0 = (bounds.width - modules * module_size) / 2;
+    let y0 = (bounds.height - modules * module_size) / 2;
+
+    for y in 0..modules {
+        for x in 0..modules {
+            if code.get_module(x as i32, y as i32) {
+                let rect = Rect::new(
+                    x0 + x * module_size,
+                    y0 + y * module_size,
+                    module_size,
+                    module_size,
+                );
+                pixels.fill_rect(rect, Color::rgb(0, 0, 0));
+            }
+        }
+    }
+}
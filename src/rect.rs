@@ -0,0 +1,99 @@
+/// An axis-aligned rectangle in buffer pixels.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn contains_rect(&self, other: &Self) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    /// The overlapping part of both rectangles, empty if they don't overlap.
+    pub fn intersect(&self, other: &Self) -> Self {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        if right <= x || bottom <= y {
+            return Self::default();
+        }
+
+        Self::new(x, y, right - x, bottom - y)
+    }
+
+    /// The smallest rectangle containing both.
+    pub fn union(&self, other: &Self) -> Self {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+
+        Self::new(x, y, right - x, bottom - y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intersections_are_empty_without_overlap() {
+        let a = Rect::new(0, 0, 10, 10);
+        assert_eq!(a.intersect(&Rect::new(5, 5, 10, 10)), Rect::new(5, 5, 5, 5));
+        assert_eq!(a.intersect(&Rect::new(2, 3, 4, 4)), Rect::new(2, 3, 4, 4));
+        // Touching edges don't overlap
+        assert!(a.intersect(&Rect::new(10, 0, 5, 5)).is_empty());
+        assert!(a.intersect(&Rect::new(0, 10, 5, 5)).is_empty());
+        assert!(a.intersect(&Rect::new(20, 20, 5, 5)).is_empty());
+        assert!(a.intersect(&Rect::new(5, 5, 0, 0)).is_empty());
+    }
+
+    #[test]
+    fn unions_ignore_empty_rects() {
+        let a = Rect::new(0, 0, 10, 10);
+        assert_eq!(a.union(&Rect::new(20, 5, 5, 10)), Rect::new(0, 0, 25, 15));
+        assert_eq!(a.union(&Rect::new(2, 2, 2, 2)), a);
+        // Wherever they are
+        assert_eq!(a.union(&Rect::new(50, 50, 0, 4)), a);
+        assert_eq!(Rect::default().union(&a), a);
+
+        assert!(a.contains_rect(&Rect::new(0, 0, 10, 10)));
+        assert!(!a.contains_rect(&Rect::new(5, 5, 10, 1)));
+    }
+}
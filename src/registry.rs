@@ -0,0 +1,575 @@
+//! Keeps track of the globals the compositor advertises and binds the ones
+//! we use.
+
+use std::ops::RangeInclusive;
+
+use thiserror::Error;
+use tracing::{debug, info, warn};
+use wayland_client::{
+    protocol::{
+        wl_compositor::WlCompositor,
+        wl_data_device_manager::WlDataDeviceManager,
+        wl_output::WlOutput,
+        wl_registry::{self, WlRegistry},
+        wl_seat::WlSeat,
+        wl_shm::{self, WlShm},
+        wl_shm_pool::WlShmPool,
+        wl_subcompositor::WlSubcompositor,
+    },
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+use wayland_protocols::{
+    ext::session_lock::v1::client::ext_session_lock_manager_v1::ExtSessionLockManagerV1,
+    wp::{
+        content_type::v1::client::wp_content_type_manager_v1::WpContentTypeManagerV1,
+        cursor_shape::v1::client::wp_cursor_shape_manager_v1::WpCursorShapeManagerV1,
+        fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        idle_inhibit::zv1::client::zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1,
+        presentation_time::client::wp_presentation::WpPresentation,
+        single_pixel_buffer::v1::client::wp_single_pixel_buffer_manager_v1::WpSinglePixelBufferManagerV1,
+        tearing_control::v1::client::wp_tearing_control_manager_v1::WpTearingControlManagerV1,
+        viewporter::client::wp_viewporter::WpViewporter,
+    },
+    xdg::{
+        activation::v1::client::xdg_activation_v1::XdgActivationV1,
+        decoration::zv1::client::zxdg_decoration_manager_v1::ZxdgDecorationManagerV1,
+        shell::client::xdg_wm_base::XdgWmBase,
+        xdg_output::zv1::client::zxdg_output_manager_v1::ZxdgOutputManagerV1,
+    },
+};
+
+use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::ZwlrLayerShellV1;
+
+use crate::{
+    clipboard::DataDevice,
+    error::Error,
+    input, lock,
+    output::{release_output, Output},
+    presentation::Presentation,
+    seat::Seat,
+    state::AppState,
+    window,
+};
+
+/// A global as advertised by `wl_registry.global`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Global {
+    pub name: u32,
+    pub interface: String,
+    pub version: u32,
+}
+
+#[derive(Debug, Error)]
+pub enum BindError {
+    #[error("the compositor doesn't support {interface}")]
+    Missing { interface: &'static str },
+    #[error("the compositor only supports {interface} version {version}, we need {min}")]
+    TooOld {
+        interface: &'static str,
+        version: u32,
+        min: u32,
+    },
+}
+
+#[derive(Debug, Error)]
+#[error("the compositor lacks globals we need: {}", .0.join(", "))]
+pub struct MissingGlobals(pub Vec<&'static str>);
+
+/// Every global the compositor advertised, and the registry to bind them
+/// with.
+#[derive(Debug, Default)]
+pub struct GlobalManager {
+    registry: Option<WlRegistry>,
+    globals: Vec<Global>,
+    // Names of the globals we bound
+    bound: Vec<u32>,
+}
+
+impl GlobalManager {
+    pub fn new(registry: WlRegistry) -> Self {
+        Self {
+            registry: Some(registry),
+            ..Default::default()
+        }
+    }
+
+    pub fn globals(&self) -> &[Global] {
+        &self.globals
+    }
+
+    pub(crate) fn add(&mut self, name: u32, interface: String, version: u32) {
+        self.globals.push(Global {
+            name,
+            interface,
+            version,
+        });
+    }
+
+    /// Forgets about a global the compositor removed. Returns it if we had
+    /// bound it, whatever we got from it has to go too then.
+    pub(crate) fn remove(&mut self, name: u32) -> Option<Global> {
+        let i = self.globals.iter().position(|g| g.name == name)?;
+        let global = self.globals.remove(i);
+
+        let bound = self.bound.iter().position(|&n| n == name)?;
+        self.bound.swap_remove(bound);
+        Some(global)
+    }
+
+    /// Checks that all of `interfaces` are advertised, listing every one
+    /// that isn't.
+    pub fn require(&self, interfaces: &[&'static str]) -> Result<(), MissingGlobals> {
+        let missing: Vec<_> = interfaces
+            .iter()
+            .copied()
+            .filter(|&interface| !self.globals.iter().any(|g| g.interface == interface))
+            .collect();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(MissingGlobals(missing))
+        }
+    }
+
+    /// Binds the first advertised `I` at the highest version in `versions`
+    /// that the compositor supports.
+    pub fn bind<I, U, D>(
+        &mut self,
+        versions: RangeInclusive<u32>,
+        qh: &QueueHandle<D>,
+        udata: U,
+    ) -> Result<I, BindError>
+    where
+        I: Proxy + 'static,
+        U: Send + Sync + 'static,
+        D: Dispatch<I, U> + 'static,
+    {
+        let interface = I::interface().name;
+        let name = self
+            .globals
+            .iter()
+            .find(|g| g.interface == interface)
+            .ok_or(BindError::Missing { interface })?
+            .name;
+        self.bind_name(name, versions, qh, udata)
+    }
+
+    /// Names of the advertised globals of type `I`, for those the
+    /// compositor may have several of.
+    pub fn names<I: Proxy>(&self) -> Vec<u32> {
+        let interface = I::interface().name;
+        self.globals
+            .iter()
+            .filter(|g| g.interface == interface)
+            .map(|g| g.name)
+            .collect()
+    }
+
+    /// Binds the global `name` of type `I` at the highest version in
+    /// `versions` that the compositor supports.
+    pub fn bind_name<I, U, D>(
+        &mut self,
+        name: u32,
+        versions: RangeInclusive<u32>,
+        qh: &QueueHandle<D>,
+        udata: U,
+    ) -> Result<I, BindError>
+    where
+        I: Proxy + 'static,
+        U: Send + Sync + 'static,
+        D: Dispatch<I, U> + 'static,
+    {
+        let interface = I::interface().name;
+        let global = self
+            .globals
+            .iter()
+            .find(|g| g.name == name && g.interface == interface)
+            .ok_or(BindError::Missing { interface })?;
+
+        if global.version < *versions.start() {
+            return Err(BindError::TooOld {
+                interface,
+                version: global.version,
+                min: *versions.start(),
+            });
+        }
+
+        let version = global.version.min(*versions.end());
+        debug!(interface, name = global.name, version, "binding global");
+        self.bound.push(global.name);
+        // Only None after destroy, nothing binds after that
+        let registry = self.registry.as_ref().unwrap();
+        Ok(registry.bind(global.name, version, qh, udata))
+    }
+}
+
+// The versions of each global we know how to handle. Binding anything newer
+// than that could get us events and behavior we don't expect.
+// damage_buffer needs wl_compositor version 4
+const COMPOSITOR_VERSIONS: RangeInclusive<u32> = 1..=4;
+const SHM_VERSIONS: RangeInclusive<u32> = 1..=1;
+const SUBCOMPOSITOR_VERSIONS: RangeInclusive<u32> = 1..=1;
+// configure_bounds needs xdg_wm_base version 4, the suspended state 6
+const XDG_WM_BASE_VERSIONS: RangeInclusive<u32> = 1..=6;
+const SEAT_VERSIONS: RangeInclusive<u32> = 1..=7;
+// release needs wl_data_device_manager version 2
+const DATA_DEVICE_MANAGER_VERSIONS: RangeInclusive<u32> = 1..=3;
+const CURSOR_SHAPE_VERSIONS: RangeInclusive<u32> = 1..=1;
+const DECORATION_VERSIONS: RangeInclusive<u32> = 1..=1;
+const ACTIVATION_VERSIONS: RangeInclusive<u32> = 1..=1;
+// release needs wl_output version 3, name and description 4
+const OUTPUT_VERSIONS: RangeInclusive<u32> = 1..=4;
+const SESSION_LOCK_VERSIONS: RangeInclusive<u32> = 1..=1;
+// Since version 3 the changes come with wl_output.done
+const XDG_OUTPUT_VERSIONS: RangeInclusive<u32> = 1..=3;
+const IDLE_INHIBIT_VERSIONS: RangeInclusive<u32> = 1..=1;
+const FRACTIONAL_SCALE_VERSIONS: RangeInclusive<u32> = 1..=1;
+const VIEWPORTER_VERSIONS: RangeInclusive<u32> = 1..=1;
+const SINGLE_PIXEL_BUFFER_VERSIONS: RangeInclusive<u32> = 1..=1;
+// Version 2 only allows a refresh of 0 for variable refresh rates
+const PRESENTATION_VERSIONS: RangeInclusive<u32> = 1..=2;
+const TEARING_CONTROL_VERSIONS: RangeInclusive<u32> = 1..=1;
+const CONTENT_TYPE_VERSIONS: RangeInclusive<u32> = 1..=1;
+// on_demand keyboard interactivity needs version 4
+const LAYER_SHELL_VERSIONS: RangeInclusive<u32> = 1..=4;
+
+/// Binds the globals we use out of those advertised so far.
+pub(crate) fn bind_globals(state: &mut AppState, qh: &QueueHandle<AppState>) -> Result<(), Error> {
+    let globals = &mut state.globals;
+    globals.require(&[
+        WlCompositor::interface().name,
+        WlShm::interface().name,
+        XdgWmBase::interface().name,
+    ])?;
+
+    state.compositor = Some(globals.bind(COMPOSITOR_VERSIONS, qh, ())?);
+    state.shm = Some(globals.bind(SHM_VERSIONS, qh, ())?);
+    state.xdg_wm_base = Some(globals.bind(XDG_WM_BASE_VERSIONS, qh, ())?);
+
+    state.subcompositor = globals
+        .bind::<WlSubcompositor, _, _>(SUBCOMPOSITOR_VERSIONS, qh, ())
+        .ok();
+    state.cursor_shape_manager = globals
+        .bind::<WpCursorShapeManagerV1, _, _>(CURSOR_SHAPE_VERSIONS, qh, ())
+        .ok();
+    state.xdg_decoration_manager = globals
+        .bind::<ZxdgDecorationManagerV1, _, _>(DECORATION_VERSIONS, qh, ())
+        .ok();
+    state.activation = globals
+        .bind::<XdgActivationV1, _, _>(ACTIVATION_VERSIONS, qh, ())
+        .ok();
+    state.idle_inhibit_manager = globals
+        .bind::<ZwpIdleInhibitManagerV1, _, _>(IDLE_INHIBIT_VERSIONS, qh, ())
+        .ok();
+    // Fractional scales need viewports too
+    state.fractional_scale_manager = globals
+        .bind::<WpFractionalScaleManagerV1, _, _>(FRACTIONAL_SCALE_VERSIONS, qh, ())
+        .ok();
+    state.viewporter = globals
+        .bind::<WpViewporter, _, _>(VIEWPORTER_VERSIONS, qh, ())
+        .ok();
+    // Only of use with viewports, to scale the pixel up
+    state.single_pixel_buffer_manager = globals
+        .bind::<WpSinglePixelBufferManagerV1, _, _>(SINGLE_PIXEL_BUFFER_VERSIONS, qh, ())
+        .ok();
+    state.presentation = globals
+        .bind::<WpPresentation, _, _>(PRESENTATION_VERSIONS, qh, ())
+        .ok()
+        .map(Presentation::new);
+    state.tearing_control_manager = globals
+        .bind::<WpTearingControlManagerV1, _, _>(TEARING_CONTROL_VERSIONS, qh, ())
+        .ok();
+    state.content_type_manager = globals
+        .bind::<WpContentTypeManagerV1, _, _>(CONTENT_TYPE_VERSIONS, qh, ())
+        .ok();
+    state.layer_shell = globals
+        .bind::<ZwlrLayerShellV1, _, _>(LAYER_SHELL_VERSIONS, qh, ())
+        .ok();
+    state.session_lock_manager = globals
+        .bind::<ExtSessionLockManagerV1, _, _>(SESSION_LOCK_VERSIONS, qh, ())
+        .ok();
+    // Before the outputs, so that they get their xdg_output right away
+    state.xdg_output_manager = globals
+        .bind::<ZxdgOutputManagerV1, _, _>(XDG_OUTPUT_VERSIONS, qh, ())
+        .ok();
+    // Before the seat, which gets its data device from it
+    state.data_device_manager = globals
+        .bind::<WlDataDeviceManager, _, _>(DATA_DEVICE_MANAGER_VERSIONS, qh, ())
+        .ok();
+    bind_seat(state, qh);
+    for name in state.globals.names::<WlOutput>() {
+        bind_output(state, name, qh);
+    }
+
+    Ok(())
+}
+
+/// Binds the first seat advertised, we only handle a single one.
+fn bind_seat(state: &mut AppState, qh: &QueueHandle<AppState>) {
+    state.seat = state
+        .globals
+        .bind::<WlSeat, _, _>(SEAT_VERSIONS, qh, ())
+        .ok()
+        .map(Seat::new);
+    if let (Some(seat), Some(manager)) = (state.seat.as_mut(), &state.data_device_manager) {
+        seat.data_device = Some(DataDevice::new(manager, &seat.seat, qh));
+    }
+}
+
+/// Binds the output `name`, at startup or when it is plugged in.
+fn bind_output(state: &mut AppState, name: u32, qh: &QueueHandle<AppState>) {
+    let wl_output = match state
+        .globals
+        .bind_name::<WlOutput, _, _>(name, OUTPUT_VERSIONS, qh, ())
+    {
+        Ok(wl_output) => wl_output,
+        Err(err) => {
+            warn!(%err, "failed to bind an output");
+            return;
+        }
+    };
+
+    lock::output_added(state, &wl_output);
+    let output = Output::new(name, wl_output, state.xdg_output_manager.as_ref(), qh);
+    state.outputs.push(output);
+}
+
+/// Drops what we got from a global that went away, e.g. a seat whose
+/// devices were unplugged.
+fn global_removed(
+    state: &mut AppState,
+    global: &Global,
+    conn: &Connection,
+    qh: &QueueHandle<AppState>,
+) {
+    match global.interface.as_str() {
+        "wl_output" => {
+            let Some(i) = state.outputs.iter().position(|o| o.global == global.name) else {
+                return;
+            };
+            let output = state.outputs.remove(i);
+            lock::output_removed(state, &output.wl_output);
+            window::output_left(state, None, &output.wl_output);
+            release_output(output);
+        }
+        "wl_seat" => {
+            input::remove_seat(state);
+            // Carry on with another one, if there is one
+            bind_seat(state, qh);
+        }
+        "wp_cursor_shape_manager_v1" => input::fall_back_to_theme_cursor(state, conn, qh),
+        // The data device we got from it keeps working until released. It
+        // has no destructor.
+        "wl_data_device_manager" => state.data_device_manager = None,
+        // The subsurfaces we got from it keep working until destroyed
+        "wl_subcompositor" => {
+            if let Some(subcompositor) = state.subcompositor.take() {
+                subcompositor.destroy();
+            }
+        }
+        "zxdg_decoration_manager_v1" => {
+            // The decoration we got from it keeps working until destroyed
+            if let Some(manager) = state.xdg_decoration_manager.take() {
+                manager.destroy();
+            }
+        }
+        "xdg_activation_v1" => {
+            if let Some(activation) = state.activation.take() {
+                activation.destroy();
+            }
+        }
+        // The inhibitor we got from it keeps working until destroyed
+        "zwp_idle_inhibit_manager_v1" => {
+            if let Some(manager) = state.idle_inhibit_manager.take() {
+                manager.destroy();
+            }
+        }
+        // Same for the fractional scales and viewports of the windows
+        "wp_fractional_scale_manager_v1" => {
+            if let Some(manager) = state.fractional_scale_manager.take() {
+                manager.destroy();
+            }
+        }
+        "wp_viewporter" => {
+            if let Some(viewporter) = state.viewporter.take() {
+                viewporter.destroy();
+            }
+        }
+        // Same for the buffers already made
+        "wp_single_pixel_buffer_manager_v1" => {
+            if let Some(manager) = state.single_pixel_buffer_manager.take() {
+                manager.destroy();
+            }
+        }
+        // Same for the feedback of frames already committed
+        "wp_presentation" => {
+            if let Some(presentation) = state.presentation.take() {
+                presentation.destroy();
+            }
+        }
+        // Same for the hints of the windows
+        "wp_tearing_control_manager_v1" => {
+            if let Some(manager) = state.tearing_control_manager.take() {
+                manager.destroy();
+            }
+        }
+        "wp_content_type_manager_v1" => {
+            if let Some(manager) = state.content_type_manager.take() {
+                manager.destroy();
+            }
+        }
+        // Same for the xdg_outputs
+        "zxdg_output_manager_v1" => {
+            if let Some(manager) = state.xdg_output_manager.take() {
+                manager.destroy();
+            }
+        }
+        // Same for a session lock
+        "ext_session_lock_manager_v1" => {
+            if let Some(manager) = state.session_lock_manager.take() {
+                manager.destroy();
+            }
+        }
+        // Same for a layer surface
+        "zwlr_layer_shell_v1" => {
+            if let Some(layer_shell) = state.layer_shell.take().filter(|l| l.version() >= 3) {
+                layer_shell.destroy();
+            }
+        }
+        // Objects created from these stay valid, but we can't do much
+        // without them
+        interface => warn!(interface, "a global we depend on was removed"),
+    }
+}
+
+impl Dispatch<WlRegistry, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        _registry: &WlRegistry,
+        event: <WlRegistry as Proxy>::Event,
+        _data: &(),
+        conn: &Connection,
+        qh: &wayland_client::QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } => {
+                if interface.starts_with("wl") {
+                    info!(?name, ?interface, version, "new global event")
+                }
+
+                let started = state.compositor.is_some();
+                let late_seat =
+                    interface == WlSeat::interface().name && started && state.seat.is_none();
+                let late_output = interface == WlOutput::interface().name && started;
+                state.globals.add(name, interface, version);
+
+                // Plugged in after we started, the others are bound once at
+                // startup
+                if late_seat {
+                    bind_seat(state, qh);
+                }
+                if late_output {
+                    bind_output(state, name, qh);
+                }
+            }
+            wl_registry::Event::GlobalRemove { name } => {
+                let Some(global) = state.globals.remove(name) else {
+                    debug!(name, "unused global removed");
+                    return;
+                };
+
+                info!(name, interface = global.interface, "global removed");
+                global_removed(state, &global, conn, qh);
+            }
+            // Only sent by versions newer than the one we bound
+            event => debug!(?event, "ignoring unknown registry event"),
+        }
+    }
+}
+
+impl Dispatch<WlCompositor, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlCompositor,
+        _event: <WlCompositor as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        // This interface does not generates any events AFAIK
+    }
+}
+
+impl Dispatch<WlShm, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlShm,
+        event: <WlShm as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let wl_shm::Event::Format { format } = event else {
+            return;
+        };
+        debug!(?format, "wl_shm format advertised");
+        // Formats we don't know by name are of no use to us anyway
+        if let WEnum::Value(format) = format {
+            state.shm_formats.push(format);
+        }
+    }
+}
+
+impl Dispatch<WlShmPool, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlShmPool,
+        _event: <WlShmPool as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // AFAIK, this interface does not emit any events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn require_lists_every_missing_global() {
+        let mut globals = GlobalManager::default();
+        globals.add(1, "wl_compositor".into(), 6);
+        globals.add(2, "wl_shm".into(), 2);
+
+        assert!(globals.require(&["wl_compositor", "wl_shm"]).is_ok());
+
+        let err = globals
+            .require(&["wl_compositor", "xdg_wm_base", "wl_seat"])
+            .unwrap_err();
+        assert_eq!(err.0, ["xdg_wm_base", "wl_seat"]);
+        assert_eq!(
+            err.to_string(),
+            "the compositor lacks globals we need: xdg_wm_base, wl_seat"
+        );
+    }
+
+    #[test]
+    fn remove_reports_bound_globals_only() {
+        let mut globals = GlobalManager::default();
+        globals.add(1, "wl_seat".into(), 7);
+        globals.add(2, "wl_seat".into(), 7);
+        globals.bound.push(1);
+
+        assert_eq!(globals.remove(2), None);
+        assert_eq!(globals.remove(1).map(|g| g.name), Some(1));
+        assert!(globals.globals().is_empty());
+        assert_eq!(globals.remove(1), None);
+    }
+}
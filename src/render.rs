@@ -0,0 +1,530 @@
+//! Draws frames and hands them to the compositor, paced by frame callbacks.
+
+use std::{mem, time::Duration};
+
+use tracing::{debug, error};
+use wayland_client::{
+    protocol::{
+        wl_buffer::{self, WlBuffer},
+        wl_callback::{self, WlCallback},
+        wl_output::Transform,
+        wl_shm::WlShm,
+        wl_shm_pool::WlShmPool,
+        wl_surface::{self, WlSurface},
+    },
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+
+use crate::{
+    app::{App, Canvas},
+    buffers::{Frame, Swapchain},
+    decorations, dnd,
+    error::Error,
+    hud::LatencyGraph,
+    lock, menu,
+    pixel_buffer::PixelBuffer,
+    pixel_format::PixelFormat,
+    presentation,
+    rect::Rect,
+    render_thread,
+    scale::Scale,
+    scene::{draw_scene, Scene, Unfocused},
+    single_pixel,
+    state::{required, AppState},
+    subsurface,
+    transform::{inverse, transform_rect, transform_size},
+    window::{self, Window, WindowId, WindowState, WmCapabilities},
+};
+
+/// Everything needed to draw a frame of a window but the scene, away from
+/// the window, e.g. on the render thread.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FrameContent {
+    // In memory, swapped if rotated
+    pub(crate) size: (usize, usize),
+    pub(crate) format: PixelFormat,
+    pub(crate) transform: Transform,
+    pub(crate) scale: Scale,
+    pub(crate) highlighted: bool,
+    pub(crate) dimmed: bool,
+    // The title bar and border, if we draw them
+    pub(crate) decorations: Option<(WindowState, WmCapabilities)>,
+    pub(crate) time: u32,
+    // The latency graph, if the window shows it
+    pub(crate) hud: Option<LatencyGraph>,
+}
+
+impl FrameContent {
+    /// Draws `rect` of `pixels`, in memory coordinates, a buffer of `size`
+    /// with `transform` applied. `app` draws instead of `scene` if there is
+    /// one.
+    pub(crate) fn draw(
+        &self,
+        pixels: &mut PixelBuffer,
+        scene: &Scene,
+        app: Option<&mut dyn App>,
+        rect: Rect,
+    ) {
+        // The scene is drawn as if the buffer wasn't transformed
+        let rect = transform_rect(rect, inverse(self.transform), self.size);
+        pixels.set_clip(rect);
+        match app {
+            Some(app) => app.on_draw(&mut Canvas::new(pixels, self.scale, self.time)),
+            None => draw_scene(
+                pixels,
+                scene,
+                self.highlighted,
+                self.dimmed,
+                self.time,
+                self.scale,
+            ),
+        }
+        if let Some((state, capabilities)) = self.decorations {
+            decorations::draw(pixels, state, capabilities, self.scale);
+        }
+        if let Some(graph) = &self.hud {
+            graph.draw(pixels, self.scale);
+        }
+    }
+
+    /// The same content drawn smaller, into a buffer of `size` we fell
+    /// back to. The viewport scales it back up to the window if there is
+    /// one, otherwise the window shrinks.
+    pub(crate) fn shrunk(&self, size: (usize, usize)) -> Self {
+        Self {
+            size,
+            scale: self.scale.shrunk(size.0, self.size.0),
+            ..*self
+        }
+    }
+}
+
+/// The next frame of a window: what it looks like and what changed since
+/// the last one.
+pub(crate) struct FramePlan {
+    pub(crate) content: FrameContent,
+    // How big a buffer we expect to need at most
+    pool_size: usize,
+    // In surface pixels, to report to the compositor
+    pub(crate) damage: Vec<Rect>,
+    // In memory coordinates, what the buffers have to catch up on
+    buffer_damage: Vec<Rect>,
+}
+
+/// Takes what was damaged in `window` since the last frame, to draw it.
+pub(crate) fn plan_frame(window: &mut Window, scene: &Scene) -> FramePlan {
+    let (width, height) = window.size();
+    let scale = window.scale();
+    // Cropped and scaled by the compositor from then on, nothing to redraw
+    // but what the buffer missed
+    let fixed_size = scene.buffer_size().filter(|_| window.has_viewport());
+    let content_size = fixed_size.unwrap_or_else(|| window.content_size());
+    // Rotated buffers have their width and height swapped
+    let transform = window.transform();
+    let (buffer_width, buffer_height) = transform_size(content_size, transform);
+    let size = buffer_width * 4 * buffer_height;
+
+    if window.buffers.size() != (buffer_width, buffer_height) {
+        window.damage.add(Rect::new(0, 0, width, height));
+    }
+
+    // Make room for the biggest window we may be asked for up front, so
+    // that interactive resizes don't have to grow the pool. No bigger than
+    // the maximum size though, unless we already are.
+    let pool_size = window.configure_bounds.map_or(size, |bounds| {
+        let (w, h) = window.options().clamp(bounds);
+        (scale.to_buffer(w) * 4 * scale.to_buffer(h)).max(size)
+    });
+
+    // Neither would follow the window once drawn into a fixed size buffer
+    let dimmed = !window.state().contains(WindowState::ACTIVATED) && fixed_size.is_none();
+    let decorations = (window.client_side_decorations() && fixed_size.is_none())
+        .then(|| (window.state(), window.capabilities()));
+
+    // Moves on with every frame, whatever else changed
+    let hud = (window.options().hud && fixed_size.is_none()).then(|| {
+        window.damage.add(LatencyGraph::rect((width, height)));
+        window.presentation_stats.graph
+    });
+
+    // Tracked in surface pixels, drawn in buffer pixels
+    let damage = window.damage.take();
+    let buffer_damage = match fixed_size {
+        Some(_) => Vec::new(),
+        None => damage.iter().map(|r| window.rect_to_buffer(*r)).collect(),
+    };
+
+    FramePlan {
+        content: FrameContent {
+            size: (buffer_width, buffer_height),
+            format: window.buffers.format(),
+            transform,
+            scale,
+            highlighted: window.highlighted,
+            dimmed,
+            decorations,
+            time: window.animation_time.as_millis() as u32,
+            hud,
+        },
+        pool_size,
+        damage,
+        buffer_damage,
+    }
+}
+
+/// A buffer to draw `plan` into, or `None` if there is no free buffer. The
+/// damage is kept for the next frame then.
+pub(crate) fn acquire_buffer<'a, D>(
+    window: &'a mut Window,
+    plan: &FramePlan,
+    shm: &WlShm,
+    qh: &QueueHandle<D>,
+) -> Result<Option<Frame<'a>>, Error>
+where
+    D: Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, ()> + 'static,
+{
+    let (width, height) = plan.content.size;
+    let frame =
+        window
+            .buffers
+            .acquire(shm, width, height, plan.pool_size, &plan.buffer_damage, qh)?;
+    if frame.is_none() {
+        // Keep the damage for when a buffer is free again
+        for rect in &plan.damage {
+            window.damage.add(*rect);
+        }
+        window.needs_redraw = true;
+    }
+    Ok(frame)
+}
+
+/// What to draw into `frame` to show `content`, and where. Also catches up
+/// on what changed while it was with the compositor.
+pub(crate) fn catch_up(frame: &mut Frame, content: FrameContent) -> (FrameContent, Vec<Rect>) {
+    let redraw = frame.take_damage();
+    if frame.size() == content.size {
+        return (content, redraw);
+    }
+
+    // The damage is that of the size we asked for
+    let content = content.shrunk(frame.size());
+    let all = Rect::new(0, 0, content.size.0, content.size.1);
+    (content, vec![all])
+}
+
+/// Redraws whatever was damaged since the last frame. Returns the buffer to
+/// attach and the damaged rectangles in surface pixels to report to the
+/// compositor, or `None` if there is no free buffer to draw into.
+pub(crate) fn draw_frame<D>(
+    window: &mut Window,
+    scene: &Scene,
+    mut app: Option<&mut dyn App>,
+    shm: &WlShm,
+    qh: &QueueHandle<D>,
+) -> Result<Option<(WlBuffer, Vec<Rect>)>, Error>
+where
+    D: Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, ()> + 'static,
+{
+    let plan = plan_frame(window, scene);
+    let Some(mut frame) = acquire_buffer(window, &plan, shm, qh)? else {
+        return Ok(None);
+    };
+
+    let (content, redraw) = catch_up(&mut frame, plan.content);
+    let mut pixels = frame.pixels().with_transform(content.transform);
+    for rect in redraw {
+        content.draw(&mut pixels, scene, app.as_deref_mut(), rect);
+    }
+
+    Ok(Some((frame.wl_buffer().clone(), plan.damage)))
+}
+
+/// Draws a new frame of the window `id` and commits it to its surface, or
+/// has the render thread draw it if there is one.
+pub(crate) fn present(state: &mut AppState, id: WindowId) {
+    if single_pixel::present(state, id) {
+        return;
+    }
+    if state.render_thread.is_some() {
+        render_thread::request_frame(state, id);
+        return;
+    }
+
+    let qh = &state.queue_handle;
+    let (Some(window), Ok(shm)) = (
+        state.windows.iter_mut().find(|w| w.id() == id),
+        required(&state.shm),
+    ) else {
+        return;
+    };
+
+    let frame = draw_frame(window, &state.scene, state.app.as_deref_mut(), shm, qh);
+    commit_frame(state, id, frame);
+}
+
+/// Attaches the buffer of a frame drawn for the window `id`, reports its
+/// damage and commits it.
+pub(crate) fn commit_frame(
+    state: &mut AppState,
+    id: WindowId,
+    frame: Result<Option<(WlBuffer, Vec<Rect>)>, Error>,
+) {
+    let qh = &state.queue_handle;
+    let Some(window) = state.windows.iter_mut().find(|w| w.id() == id) else {
+        return;
+    };
+
+    let surface = window.surface().clone();
+    let (buffer, damage) = match frame {
+        Ok(Some(frame)) => frame,
+        Ok(None) => {
+            // Tried again once a buffer is released
+            debug!("no free buffer, delaying frame");
+            return;
+        }
+        Err(err) => {
+            // Keep whatever was on screen, maybe the next frame has more luck
+            error!(%err, "failed to draw frame");
+            surface.commit();
+            return;
+        }
+    };
+
+    // Ask to be told when it is a good time to draw the next frame
+    surface.frame(qh, id);
+    window.frame_pending = true;
+    window.needs_redraw = false;
+
+    // Applies along with the buffer drawn that way
+    let source = window
+        .has_viewport()
+        .then(|| state.scene.source(window.size()))
+        .flatten();
+    window.apply_viewport(source);
+    if let Some(compositor) = &state.compositor {
+        window.apply_opaque_region(compositor, qh);
+        window.apply_input_region(compositor, qh);
+    }
+
+    surface.attach(Some(&buffer), 0, 0);
+    window.buffers.committed(&buffer, window.transform());
+    for rect in damage {
+        // damage_buffer is only available since version 4, and takes
+        // buffer pixels. Those of a cropped buffer don't line up with the
+        // window.
+        if surface.version() >= 4 && source.is_none() {
+            let rect = window.rect_to_buffer(rect);
+            let (x, y) = (rect.x as i32, rect.y as i32);
+            surface.damage_buffer(x, y, rect.width as i32, rect.height as i32);
+        } else {
+            let (x, y) = (rect.x as i32, rect.y as i32);
+            surface.damage(x, y, rect.width as i32, rect.height as i32);
+        }
+    }
+    if let Some(presentation) = &state.presentation {
+        presentation.feedback(&surface, id, qh);
+    }
+    subsurface::place_badge(window);
+    surface.commit();
+    // Starts the animation along with the first frame
+    subsurface::present_badge(state, id);
+}
+
+/// Draws the whole of a small surface like a popup with `draw` and commits
+/// it, without frame callbacks. Returns `false` if all of `buffers` are with
+/// the compositor, nothing is drawn then.
+pub(crate) fn present_whole<D>(
+    surface: &WlSurface,
+    buffers: &mut Swapchain,
+    (width, height): (usize, usize),
+    shm: &WlShm,
+    qh: &QueueHandle<D>,
+    draw: impl FnOnce(&mut PixelBuffer),
+) -> Result<bool, Error>
+where
+    D: Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, ()> + 'static,
+{
+    let all = Rect::new(0, 0, width, height);
+    let Some(mut frame) = buffers.acquire(shm, width, height, width * 4 * height, &[all], qh)?
+    else {
+        return Ok(false);
+    };
+
+    frame.take_damage();
+    draw(&mut frame.pixels());
+
+    surface.attach(Some(frame.wl_buffer()), 0, 0);
+    if surface.version() >= 4 {
+        surface.damage_buffer(0, 0, width as i32, height as i32);
+    } else {
+        surface.damage(0, 0, width as i32, height as i32);
+    }
+    surface.commit();
+    Ok(true)
+}
+
+/// Presents the windows that have something new to show, if the
+/// compositor is ready for it.
+pub(crate) fn present_if_needed(state: &mut AppState) {
+    // Attaching a buffer before the first configure is a protocol error.
+    // While suspended nothing is drawn and no frame callback is asked for,
+    // the configure that clears the state redraws everything.
+    let ready: Vec<_> = state
+        .windows
+        .iter()
+        .filter(|w| {
+            w.configured
+                && w.needs_redraw
+                && !w.frame_pending
+                && !w.state().contains(WindowState::SUSPENDED)
+        })
+        .map(Window::id)
+        .collect();
+    for id in ready {
+        present(state, id);
+    }
+}
+
+/// Redraws the parts of the window `id` that changed, only those are sent
+/// to the compositor. Waits for it to be ready if the last frame is still
+/// pending.
+pub(crate) fn redraw_area(
+    state: &mut AppState,
+    id: WindowId,
+    rects: impl IntoIterator<Item = Rect>,
+) {
+    if let Some(window) = state.window_mut(id) {
+        window.damage_area(rects);
+    }
+    present_if_needed(state);
+}
+
+impl Dispatch<WlSurface, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlSurface,
+        event: <WlSurface as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // Only windows render at the scale of their outputs so far
+        match event {
+            wl_surface::Event::Enter { output } => window::output_entered(state, proxy, &output),
+            wl_surface::Event::Leave { output } => window::output_left(state, Some(proxy), &output),
+            event => debug!(?event, "ignoring unknown surface event"),
+        }
+    }
+}
+
+impl Dispatch<WlBuffer, ()> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlBuffer,
+        event: <WlBuffer as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The compositor is done reading from the buffer
+        if let wl_buffer::Event::Release = event {
+            for window in &mut state.windows {
+                window.buffers.release(proxy);
+            }
+            menu::buffer_released(state, proxy);
+            lock::buffer_released(state, proxy);
+            subsurface::buffer_released(state, proxy);
+            dnd::buffer_released(state, proxy);
+
+            // A frame may have been waiting for a free buffer
+            present_if_needed(state);
+        }
+    }
+}
+
+impl Dispatch<WlCallback, WindowId> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlCallback,
+        event: <WlCallback as Proxy>::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The compositor is ready for the next frame
+        if let wl_callback::Event::Done { callback_data } = event {
+            let refresh = window::refresh_interval(state, *id);
+            let Some(window) = state.windows.iter_mut().find(|w| w.id() == *id) else {
+                return;
+            };
+            window.frame_pending = false;
+
+            // Animations move on by the refresh cycles that passed, whatever
+            // the refresh rate, without the jitter of the timestamps
+            let elapsed = window
+                .last_callback
+                .replace(callback_data)
+                .map_or(0, |last| callback_data.wrapping_sub(last));
+            let elapsed = Duration::from_millis(elapsed as u64);
+            let step = presentation::animation_step(elapsed, refresh);
+            // Drawing the last frame took longer than a refresh cycle
+            let cycles = presentation::refresh_cycles(elapsed, refresh).unwrap_or(1);
+            let delayed = mem::take(&mut window.frame_delayed);
+            if cycles > 1 && !delayed {
+                debug!(id = ?*id, dropped = cycles - 1, "frames dropped, skipping ahead");
+                window.presentation_stats.dropped(cycles - 1);
+            }
+            let previous = window.animation_time.as_millis() as u32;
+            window.animation_time += step;
+            let now = window.animation_time.as_millis() as u32;
+            // Nobody would see the animation, and the next frame waits for
+            // the state to clear anyway
+            if window.state().contains(WindowState::SUSPENDED) {
+                window.last_callback = None;
+                return;
+            }
+
+            let size = window.size();
+            let activated = window.state().contains(WindowState::ACTIVATED);
+            let (mut changes, unfocused) = match &state.app {
+                Some(app) if app.animates() => {
+                    (vec![Rect::new(0, 0, size.0, size.1)], app.when_unfocused())
+                }
+                Some(_) => (Vec::new(), Unfocused::Animate),
+                None => (
+                    state.scene.changes(size, previous, now),
+                    state.scene.when_unfocused(),
+                ),
+            };
+
+            let id = *id;
+            if changes.is_empty() {
+                // Not animating anymore, whenever the next frame comes it
+                // isn't late
+                window.last_callback = None;
+            }
+            match unfocused {
+                _ if activated || changes.is_empty() => redraw_area(state, id, changes),
+                Unfocused::Animate => redraw_area(state, id, changes),
+                Unfocused::Throttle(interval) => {
+                    // This callback already came a refresh cycle after the
+                    // last frame
+                    let delay = interval.saturating_sub(refresh.unwrap_or_default());
+                    window.frame_delayed = true;
+                    state.timers.after(delay, move |state| {
+                        redraw_area(state, id, mem::take(&mut changes))
+                    });
+                }
+                // No frame means no more callbacks, until the configure
+                // that focuses the window redraws it
+                Unfocused::Pause => {
+                    debug!(?id, "pausing the animation while unfocused");
+                    if let Some(window) = state.window_mut(id) {
+                        // Or the time spent unfocused would be skipped
+                        window.last_callback = None;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Draws the windows on a thread of their own with `--render-thread`, so
+//! that an expensive frame doesn't hold up the events behind it.
+//!
+//! The main loop picks a free buffer, sends what to draw over a channel and
+//! carries on. The thread draws straight into the buffer, through a mapping
+//! of its own, and sends it back through a channel of the main loop, which
+//! attaches and commits it. Everything Wayland stays on the main thread.
+//! As the mapping is the thread's, a window closing or its pool growing
+//! meanwhile is harmless.
+
+use std::{
+    io,
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+use calloop::{
+    channel::{self, Event},
+    LoopHandle,
+};
+use tracing::{debug, trace, warn};
+use wayland_client::protocol::wl_buffer::WlBuffer;
+
+use crate::{
+    pixel_buffer::PixelBuffer,
+    rect::Rect,
+    render::{self, FrameContent},
+    scene::Scene,
+    shm::SharedMapping,
+    state::{required, AppState},
+    window::WindowId,
+};
+
+/// A frame for the thread to draw.
+struct Request {
+    id: WindowId,
+    content: FrameContent,
+    // As of this frame, the thread can't look at the state
+    scene: Scene,
+    // In memory coordinates
+    redraw: Vec<Rect>,
+    // The memory of `buffer`, mapped for the thread
+    target: SharedMapping,
+    buffer: WlBuffer,
+    // In surface pixels, to report to the compositor
+    damage: Vec<Rect>,
+}
+
+impl Request {
+    fn draw(&mut self) {
+        let content = self.content;
+        let (width, height) = content.size;
+        let mut pixels = PixelBuffer::new(
+            self.target.as_mut_slice(),
+            width,
+            height,
+            width * 4,
+            content.format,
+        )
+        .with_transform(content.transform);
+        for rect in &self.redraw {
+            content.draw(&mut pixels, &self.scene, None, *rect);
+        }
+    }
+}
+
+/// A frame the thread drew, ready to attach.
+struct Drawn {
+    id: WindowId,
+    buffer: WlBuffer,
+    damage: Vec<Rect>,
+}
+
+pub struct RenderThread {
+    // Dropped first, which ends the thread
+    requests: Option<Sender<Request>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RenderThread {
+    /// Starts the thread. The frames it draws are committed from the loop
+    /// of `handle`.
+    pub(crate) fn spawn(handle: &LoopHandle<'static, AppState>) -> io::Result<Self> {
+        let (drawn_sender, drawn) = channel::channel();
+        handle
+            .insert_source(drawn, |event, _, state| {
+                if let Event::Msg(drawn) = event {
+                    frame_drawn(state, drawn);
+                }
+            })
+            .map_err(|err| io::Error::from(err.error))?;
+
+        let (requests, received) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("render".to_owned())
+            .spawn(move || draw_requests(received, drawn_sender))?;
+        Ok(Self {
+            requests: Some(requests),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for RenderThread {
+    fn drop(&mut self) {
+        self.requests = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                debug!("the render thread panicked");
+            }
+        }
+    }
+}
+
+/// Runs on the thread until the main loop is gone.
+fn draw_requests(requests: Receiver<Request>, drawn: channel::Sender<Drawn>) {
+    for mut request in requests {
+        request.draw();
+        trace!(id = ?request.id, "frame drawn");
+
+        let Request {
+            id, buffer, damage, ..
+        } = request;
+        if drawn.send(Drawn { id, buffer, damage }).is_err() {
+            return;
+        }
+    }
+}
+
+/// Has the thread draw the next frame of the window `id` into a free
+/// buffer.
+pub(crate) fn request_frame(state: &mut AppState, id: WindowId) {
+    let qh = &state.queue_handle;
+    let (Some(window), Ok(shm)) = (
+        state.windows.iter_mut().find(|w| w.id() == id),
+        required(&state.shm),
+    ) else {
+        return;
+    };
+
+    let plan = render::plan_frame(window, &state.scene);
+    let mut frame = match render::acquire_buffer(window, &plan, shm, qh) {
+        Ok(Some(frame)) => frame,
+        Ok(None) => return render::commit_frame(state, id, Ok(None)),
+        Err(err) => return render::commit_frame(state, id, Err(err)),
+    };
+    let (content, redraw) = render::catch_up(&mut frame, plan.content);
+    let buffer = frame.wl_buffer().clone();
+    let target = match frame.share() {
+        Ok(target) => target,
+        Err(err) => {
+            warn!(%err, "failed to map the buffer for the render thread");
+            let mut pixels = frame.pixels().with_transform(content.transform);
+            for rect in redraw {
+                content.draw(&mut pixels, &state.scene, None, rect);
+            }
+            return render::commit_frame(state, id, Ok(Some((buffer, plan.damage))));
+        }
+    };
+
+    let request = Request {
+        id,
+        content,
+        scene: state.scene.clone(),
+        redraw,
+        target,
+        buffer,
+        damage: plan.damage,
+    };
+    let sent = match state
+        .render_thread
+        .as_ref()
+        .and_then(|t| t.requests.as_ref())
+    {
+        Some(requests) => requests.send(request).map_err(|err| err.0),
+        None => Err(request),
+    };
+    match sent {
+        Ok(()) => {
+            // Nothing else is drawn until this one is committed
+            if let Some(window) = state.window_mut(id) {
+                window.frame_pending = true;
+                window.needs_redraw = false;
+            }
+        }
+        Err(mut request) => {
+            debug!("the render thread is gone, drawing on the main thread");
+            state.render_thread = None;
+            request.draw();
+            let frame = Some((request.buffer, request.damage));
+            render::commit_frame(state, id, Ok(frame));
+        }
+    }
+}
+
+/// Commits a frame the thread drew.
+fn frame_drawn(state: &mut AppState, drawn: Drawn) {
+    let Some(window) = state.window_mut(drawn.id) else {
+        // Closed meanwhile, the buffer went with it
+        return;
+    };
+    window.frame_pending = false;
+    render::commit_frame(state, drawn.id, Ok(Some((drawn.buffer, drawn.damage))));
+}
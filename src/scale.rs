@@ -0,0 +1,112 @@
+//! How many buffer pixels make up a surface pixel, integer on outputs with
+//! a HiDPI scale, fractional with wp-fractional-scale.
+
+use std::fmt;
+
+use crate::rect::Rect;
+
+// wp_fractional_scale_v1 sends scales in 120ths
+const DENOMINATOR: usize = 120;
+
+/// A scale factor, in 120ths like wp_fractional_scale_v1 sends it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Scale(u32);
+
+impl Scale {
+    pub const ONE: Self = Self(DENOMINATOR as u32);
+
+    /// An integer scale, as `wl_output.scale` sends it.
+    pub const fn integer(scale: u32) -> Self {
+        Self(scale * DENOMINATOR as u32)
+    }
+
+    /// A scale in 120ths, as `wp_fractional_scale_v1.preferred_scale` sends
+    /// it. 0 is taken as 1.
+    pub const fn from_120ths(scale: u32) -> Self {
+        if scale == 0 {
+            Self::ONE
+        } else {
+            Self(scale)
+        }
+    }
+
+    pub fn as_f64(self) -> f64 {
+        self.0 as f64 / DENOMINATOR as f64
+    }
+
+    pub fn is_integer(self) -> bool {
+        self.0.is_multiple_of(DENOMINATOR as u32)
+    }
+
+    /// The integer scale at least as large, for `set_buffer_scale`.
+    pub fn ceil(self) -> u32 {
+        (self.0 as usize).div_ceil(DENOMINATOR) as u32
+    }
+
+    /// Surface to buffer pixels, rounded half away from zero as the
+    /// fractional scale protocol asks for buffer sizes.
+    pub fn to_buffer(self, length: usize) -> usize {
+        (length * self.0 as usize + DENOMINATOR / 2) / DENOMINATOR
+    }
+
+    /// Buffer to surface pixels, rounded the same way.
+    pub fn to_surface(self, length: usize) -> usize {
+        (length * DENOMINATOR + self.0 as usize / 2) / self.0 as usize
+    }
+
+    /// The scale to draw at into a buffer `to` pixels wide rather than
+    /// `from`, e.g. a smaller one we fell back to.
+    pub fn shrunk(self, to: usize, from: usize) -> Self {
+        Self::from_120ths((self.0 as usize * to / from.max(1)) as u32)
+    }
+
+    /// `rect` in buffer pixels, grown to whole pixels so that it covers all
+    /// of the surface pixels in it.
+    pub fn rect_to_buffer(self, rect: Rect) -> Rect {
+        let scale = self.0 as usize;
+        let x = rect.x * scale / DENOMINATOR;
+        let y = rect.y * scale / DENOMINATOR;
+        let right = (rect.right() * scale).div_ceil(DENOMINATOR);
+        let bottom = (rect.bottom() * scale).div_ceil(DENOMINATOR);
+        Rect::new(x, y, right - x, bottom - y)
+    }
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self::ONE
+    }
+}
+
+impl fmt::Debug for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fractional_rects_cover_whole_pixels() {
+        let scale = Scale::from_120ths(180);
+        assert!(!scale.is_integer());
+        assert_eq!(scale.ceil(), 2);
+        assert_eq!(scale.to_buffer(101), 152);
+        assert_eq!(scale.to_surface(152), 101);
+        assert_eq!(
+            scale.rect_to_buffer(Rect::new(1, 1, 3, 1)),
+            Rect::new(1, 1, 5, 2)
+        );
+
+        let scale = Scale::integer(2);
+        assert!(scale.is_integer());
+        assert_eq!(
+            scale.rect_to_buffer(Rect::new(1, 2, 3, 4)),
+            Rect::new(2, 4, 6, 8)
+        );
+        assert_eq!(Scale::from_120ths(0), Scale::ONE);
+        assert_eq!(Scale::integer(2).shrunk(500, 1000), Scale::ONE);
+    }
+}
@@ -0,0 +1,291 @@
+//! What the demo window shows.
+
+use std::time::Duration;
+
+use qrcodegen::QrCode;
+
+use crate::{
+    animation::{self, Animation},
+    annotate::{self, Annotation},
+    color::{Color, ColorSpace},
+    dock::Dock,
+    fontview::{self, FontView},
+    monitor::{self, Monitor},
+    pixel_buffer::PixelBuffer,
+    pomodoro::Pomodoro,
+    qr,
+    rect::Rect,
+    scale::Scale,
+    select::Selection,
+    snake::{self, Snake},
+    svg::{self, SvgImage},
+    viewport::{self, PanZoom, Source},
+};
+
+/// What the window shows.
+#[derive(Default, Clone)]
+pub enum Scene {
+    #[default]
+    TestPattern,
+    Qr(QrCode),
+    /// A square moving across a still background
+    Square,
+    /// Pans and zooms over a large image, only drawn once
+    Pan(PanZoom),
+    /// A still window with an animated badge in a corner, in a subsurface
+    Badge,
+    /// The snake game, moved along by a timer rather than every frame
+    Snake(Snake),
+    /// A see-through overlay to drag a rectangle on
+    Select(Selection),
+    /// A bar with system readings, refreshed by a timer
+    Monitor(Monitor),
+    /// A specimen of a font family
+    FontView(FontView),
+    /// An SVG file, drawn again at each size
+    Svg(SvgImage),
+    /// A GIF, PNG or WebP file, stepped through its frames by a timer
+    Animation(Animation),
+    /// A countdown over everything, controlled over the socket
+    Pomodoro(Pomodoro),
+    /// Application icons along the bottom of the output
+    Dock(Dock),
+    /// A screenshot of the window, drawn on
+    Annotate(Annotation),
+}
+
+/// What an animation does while its window isn't focused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unfocused {
+    /// Goes on as usual
+    Animate,
+    /// Draws a frame every so often at most
+    Throttle(Duration),
+    /// Stops, and picks up where it left off once focused again
+    Pause,
+}
+
+impl Default for Unfocused {
+    fn default() -> Self {
+        Self::Throttle(THROTTLED_FRAME_INTERVAL)
+    }
+}
+
+// 10 FPS, enough to see that something moves
+const THROTTLED_FRAME_INTERVAL: Duration = Duration::from_millis(100);
+
+impl Scene {
+    /// The parts of a `width`x`height` window that change between the
+    /// frames at `from` and `to`, empty if the scene isn't animated.
+    pub(crate) fn changes(&self, (width, height): (usize, usize), from: u32, to: u32) -> Vec<Rect> {
+        match self {
+            Self::TestPattern => vec![Rect::new(0, 0, width, height)],
+            // The badge draws itself, the snake moves on its own timer
+            Self::Qr(_)
+            | Self::Pan(_)
+            | Self::Badge
+            | Self::Snake(_)
+            | Self::Select(_)
+            | Self::Monitor(_)
+            | Self::FontView(_)
+            | Self::Svg(_)
+            | Self::Animation(_)
+            | Self::Pomodoro(_)
+            | Self::Dock(_)
+            | Self::Annotate(_) => Vec::new(),
+            // Where the square was and where it is now
+            Self::Square => vec![
+                square_rect(width, height, from),
+                square_rect(width, height, to),
+            ],
+        }
+    }
+
+    /// What the animation does while the window isn't focused.
+    pub(crate) fn when_unfocused(&self) -> Unfocused {
+        match self {
+            // Only the color changes, slowly
+            Self::TestPattern => Unfocused::default(),
+            // Choppy motion is worse than none
+            Self::Square => Unfocused::Pause,
+            Self::Qr(_)
+            | Self::Pan(_)
+            | Self::Badge
+            | Self::Snake(_)
+            | Self::Select(_)
+            | Self::Monitor(_)
+            | Self::FontView(_)
+            | Self::Svg(_)
+            | Self::Animation(_)
+            | Self::Pomodoro(_)
+            | Self::Dock(_)
+            | Self::Annotate(_) => Unfocused::Animate,
+        }
+    }
+
+    /// Whether the scene has see-through parts.
+    pub(crate) fn needs_alpha(&self) -> bool {
+        match self {
+            Self::Select(_) | Self::Pomodoro(_) | Self::Dock(_) => true,
+            Self::TestPattern
+            | Self::Qr(_)
+            | Self::Square
+            | Self::Pan(_)
+            | Self::Badge
+            | Self::Snake(_)
+            | Self::Monitor(_)
+            | Self::FontView(_)
+            | Self::Svg(_)
+            | Self::Animation(_)
+            | Self::Annotate(_) => false,
+        }
+    }
+
+    /// The color most of the window is, as of `time`.
+    pub(crate) fn background(&self, highlighted: bool, time: u32) -> Color {
+        match self {
+            Self::TestPattern if highlighted => HIGHLIGHT,
+            Self::TestPattern => cycle_color(time),
+            Self::Square if highlighted => HIGHLIGHT,
+            Self::Square | Self::Badge => STILL_BACKGROUND,
+            Self::Qr(_) => qr::LIGHT,
+            Self::Pan(_) => viewport::GRID,
+            Self::Snake(_) => snake::BACKGROUND,
+            Self::Select(_) | Self::Pomodoro(_) | Self::Dock(_) => {
+                Color::rgba(0x00, 0x00, 0x00, 0x00)
+            }
+            Self::Monitor(_) => monitor::BACKGROUND,
+            Self::FontView(_) => fontview::BACKGROUND,
+            Self::Svg(_) => svg::BACKGROUND,
+            Self::Animation(_) => animation::BACKGROUND,
+            Self::Annotate(_) => annotate::BACKGROUND,
+        }
+    }
+
+    /// The size of the buffer, for scenes drawn once at a fixed size and
+    /// then cropped and scaled to the window. `None` draws at the window
+    /// size.
+    pub(crate) fn buffer_size(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Pan(_) => Some(viewport::IMAGE_SIZE),
+            _ => None,
+        }
+    }
+
+    /// The part of such a buffer a `width`x`height` window shows.
+    pub(crate) fn source(&self, size: (usize, usize)) -> Option<Source> {
+        match self {
+            Self::Pan(pan) => Some(pan.source(size)),
+            _ => None,
+        }
+    }
+}
+
+// Drawn over everything while the window isn't focused
+const DIM: Color = Color::rgba(0x00, 0x00, 0x00, 0x50);
+// The background while the left button is held down
+const HIGHLIGHT: Color = Color::rgb(0xFF, 0x80, 0x00);
+// Behind the scenes that don't change color
+const STILL_BACKGROUND: Color = Color::rgb(0x30, 0x30, 0x30);
+
+pub(crate) fn draw_scene(
+    pixels: &mut PixelBuffer,
+    scene: &Scene,
+    highlighted: bool,
+    dimmed: bool,
+    time: u32,
+    scale: Scale,
+) {
+    match scene {
+        Scene::TestPattern => {
+            draw_test_pattern(pixels, scene.background(highlighted, time), scale);
+        }
+        Scene::Qr(code) => qr::draw(pixels, code),
+        Scene::Square => {
+            let background = scene.background(highlighted, time);
+            // Laid out in window pixels, like the changes
+            let bounds = pixels.bounds();
+            let (width, height) = (
+                scale.to_surface(bounds.width),
+                scale.to_surface(bounds.height),
+            );
+            let square = square_rect(width, height, time);
+            pixels.fill(background);
+            pixels.fill_rect(scale.rect_to_buffer(square), Color::rgb(0x40, 0xA0, 0xFF));
+        }
+        Scene::Pan(_) => viewport::draw_image(pixels),
+        Scene::Badge => draw_test_pattern(pixels, scene.background(false, time), scale),
+        Scene::Snake(snake) => snake.draw(pixels, scale),
+        Scene::Select(selection) => selection.draw(pixels, scale),
+        Scene::Monitor(monitor) => monitor.draw(pixels, scale),
+        Scene::FontView(view) => view.draw(pixels, scale),
+        Scene::Svg(image) => image.draw(pixels),
+        Scene::Animation(animation) => animation.draw(pixels),
+        Scene::Pomodoro(pomodoro) => pomodoro.draw(pixels, scale),
+        Scene::Dock(dock) => dock.draw(pixels, scale),
+        Scene::Annotate(annotation) => annotation.draw(pixels, scale),
+    }
+
+    if dimmed {
+        pixels.blend_rect(pixels.bounds(), DIM, ColorSpace::Srgb);
+    }
+}
+
+const SQUARE_SIZE: usize = 64;
+// In pixels per second
+const SQUARE_SPEED: u64 = 200;
+
+/// Where the square is at `time` in milliseconds. It goes back and forth
+/// along the middle of the window.
+fn square_rect(width: usize, height: usize, time: u32) -> Rect {
+    let travel = width.saturating_sub(SQUARE_SIZE) as u64;
+    let distance = time as u64 * SQUARE_SPEED / 1000;
+    let x = match travel {
+        0 => 0,
+        _ if distance % (2 * travel) < travel => distance % travel,
+        _ => travel - distance % travel,
+    };
+    let y = height.saturating_sub(SQUARE_SIZE) / 2;
+
+    Rect::new(x as usize, y, SQUARE_SIZE, SQUARE_SIZE)
+}
+
+// How long it takes the background to go through every hue
+const COLOR_CYCLE_MS: u32 = 10_000;
+
+/// A slowly cycling background color for `time` in milliseconds.
+pub fn cycle_color(time: u32) -> Color {
+    // HSV to RGB with a fixed saturation and value
+    let hue = (time % COLOR_CYCLE_MS) as f32 / COLOR_CYCLE_MS as f32 * 6.0;
+    let (value, saturation) = (0.8, 0.7);
+    let chroma = value * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    let min = value - chroma;
+    let byte = |c: f32| ((c + min) * 255.0).round() as u8;
+    Color::rgb(byte(r), byte(g), byte(b))
+}
+
+fn draw_test_pattern(pixels: &mut PixelBuffer, background: Color, scale: Scale) {
+    pixels.fill(background);
+
+    // Gradient test pattern: naive sRGB mixing on top, linear light below.
+    // The top one has a visibly darker, muddier middle.
+    let bounds = pixels.bounds();
+    let (from, to) = (Color::rgb(0xFF, 0x00, 0x00), Color::rgb(0x00, 0xFF, 0x00));
+    let px = |length| scale.to_buffer(length);
+    let width = bounds.width.saturating_sub(px(100));
+    let y = (bounds.height / 2).saturating_sub(px(50));
+    let srgb = Rect::new(px(50), y, width, px(40)).intersect(&bounds);
+    let linear = Rect::new(px(50), y + px(60), width, px(40)).intersect(&bounds);
+    pixels.fill_gradient(srgb, from, to, ColorSpace::Srgb);
+    pixels.fill_gradient(linear, from, to, ColorSpace::Linear);
+}
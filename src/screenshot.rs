@@ -0,0 +1,213 @@
+//! Screenshots of our own windows: the buffer last committed, saved as a
+//! PNG in the pictures directory. No compositor support needed.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+use tracing::{info, warn};
+
+use crate::{
+    clock::local_time, pixel_format::PixelFormat, state::AppState, transform::untransform_pixels,
+    window::WindowId,
+};
+
+#[derive(Debug, Error)]
+pub enum ScreenshotError {
+    #[error("the window has no frame to save yet")]
+    NoFrame,
+    #[error("failed to write {path}: {source}")]
+    Write { path: PathBuf, source: io::Error },
+    #[error("failed to encode the PNG: {0}")]
+    Encode(#[from] png::EncodingError),
+}
+
+/// Where pictures go: `XDG_PICTURES_DIR` from the environment or from
+/// user-dirs.dirs, `~/Pictures` if neither says.
+pub fn pictures_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_PICTURES_DIR") {
+        return dir.into();
+    }
+
+    let home = env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    let config = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".config"));
+    fs::read_to_string(config.join("user-dirs.dirs"))
+        .ok()
+        .and_then(|dirs| parse_user_dir(&dirs, "XDG_PICTURES_DIR", &home))
+        .unwrap_or_else(|| home.join("Pictures"))
+}
+
+/// Looks `name` up in the contents of a user-dirs.dirs file, lines like
+/// `XDG_PICTURES_DIR="$HOME/Pictures"`.
+fn parse_user_dir(dirs: &str, name: &str, home: &Path) -> Option<PathBuf> {
+    let value = dirs
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix('='))?;
+    let value = value.trim_matches('"');
+    // Only ever relative to the home directory, or absolute
+    match value.strip_prefix("$HOME") {
+        Some(rest) => Some(home.join(rest.trim_start_matches('/'))),
+        None => Some(PathBuf::from(value)).filter(|path| path.is_absolute()),
+    }
+}
+
+/// Writes `pixels`, `width`x`height` premultiplied pixels in `format`, as
+/// a PNG to `writer`.
+pub fn encode_png(
+    writer: impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[u32],
+    format: PixelFormat,
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+
+    let rgba: Vec<u8> = pixels
+        .iter()
+        .flat_map(|pixel| {
+            let color = format.unpack(*pixel).unpremultiply();
+            [color.r, color.g, color.b, color.a]
+        })
+        .collect();
+    writer.write_image_data(&rgba)?;
+    writer.finish()
+}
+
+/// The local time as `YYYYMMDD-HHMMSS`, to name files by.
+fn timestamp() -> String {
+    let Some(tm) = local_time() else {
+        return "unknown-time".to_owned();
+    };
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// A frame one of our windows showed, the way it shows on screen.
+pub(crate) struct Capture {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+    pub format: PixelFormat,
+}
+
+/// Copies the frame the window `id` last showed.
+pub(crate) fn capture(state: &mut AppState, id: WindowId) -> Result<Capture, ScreenshotError> {
+    let window = state.window_mut(id).ok_or(ScreenshotError::NoFrame)?;
+    let format = window.buffers.format();
+    let (width, height, pixels, transform) = window
+        .buffers
+        .last_frame()
+        .ok_or(ScreenshotError::NoFrame)?;
+    // The way it shows on screen
+    let (width, height, pixels) = untransform_pixels(pixels, (width, height), transform);
+    Ok(Capture {
+        width,
+        height,
+        pixels,
+        format,
+    })
+}
+
+/// Saves `capture` to a new file in the pictures directory, named after the
+/// time and `suffix`. Returns its path.
+pub(crate) fn save_png(capture: &Capture, suffix: &str) -> Result<PathBuf, ScreenshotError> {
+    let dir = pictures_dir();
+    let path = dir.join(format!("rust-wayland-{}{suffix}.png", timestamp()));
+    let write_error = |source| ScreenshotError::Write {
+        path: path.clone(),
+        source,
+    };
+    fs::create_dir_all(&dir).map_err(write_error)?;
+    // Never overwrites an earlier one taken in the same second
+    let file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .map_err(write_error)?;
+
+    let Capture {
+        width,
+        height,
+        ref pixels,
+        format,
+    } = *capture;
+    encode_png(BufWriter::new(file), width, height, pixels, format)?;
+    Ok(path)
+}
+
+/// Saves what the window `id` shows to a new file in the pictures
+/// directory. Returns its path.
+pub(crate) fn save(state: &mut AppState, id: WindowId) -> Result<PathBuf, ScreenshotError> {
+    save_png(&capture(state, id)?, "")
+}
+
+/// Saves a screenshot of the window `id` and says where.
+pub(crate) fn take(state: &mut AppState, id: WindowId) {
+    match save(state, id) {
+        Ok(path) => info!(path = %path.display(), "saved a screenshot"),
+        Err(err) => warn!(%err, "failed to save a screenshot"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn user_dirs_are_parsed() {
+        let home = Path::new("/home/me");
+        let dirs = "# written by xdg-user-dirs-update\n\
+            XDG_DESKTOP_DIR=\"$HOME/Desktop\"\n\
+            XDG_PICTURES_DIR=\"$HOME/Bilder\"\n";
+        assert_eq!(
+            parse_user_dir(dirs, "XDG_PICTURES_DIR", home),
+            Some(PathBuf::from("/home/me/Bilder"))
+        );
+        let absolute = "XDG_PICTURES_DIR=\"/data/pictures\"";
+        assert_eq!(
+            parse_user_dir(absolute, "XDG_PICTURES_DIR", home),
+            Some(PathBuf::from("/data/pictures"))
+        );
+        assert_eq!(
+            parse_user_dir("XDG_PICTURES_DIR=\"pics\"", "XDG_PICTURES_DIR", home),
+            None
+        );
+        assert_eq!(parse_user_dir(dirs, "XDG_MUSIC_DIR", home), None);
+    }
+
+    #[test]
+    fn pngs_hold_straight_alpha() {
+        let format = PixelFormat::Argb8888;
+        let pixels = [
+            format.pack(Color::rgba(0xFF, 0x80, 0x00, 0x80).premultiply()),
+            format.pack(Color::rgb(0x00, 0x00, 0xFF).premultiply()),
+        ];
+        let mut png = Vec::new();
+        encode_png(&mut png, 2, 1, &pixels, format).unwrap();
+
+        let decoder = png::Decoder::new(png.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut rgba).unwrap();
+        assert_eq!(rgba, [0xFF, 0x80, 0x00, 0x80, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+}
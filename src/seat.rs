@@ -0,0 +1,78 @@
+//! The seat: the group of input devices (pointer, keyboard, touch) in front
+//! of a single user.
+
+use crate::{clipboard::DataDevice, keyboard::Keyboard};
+use wayland_client::protocol::{
+    wl_pointer::WlPointer,
+    wl_seat::{Capability, WlSeat},
+    wl_surface::WlSurface,
+};
+
+/// `BTN_LEFT` from linux/input-event-codes.h
+pub const BTN_LEFT: u32 = 0x110;
+/// `BTN_RIGHT` from linux/input-event-codes.h
+pub const BTN_RIGHT: u32 = 0x111;
+
+pub struct Seat {
+    pub seat: WlSeat,
+    // Only sent since version 2
+    pub name: Option<String>,
+    pub capabilities: Capability,
+    pub pointer: Option<Pointer>,
+    pub keyboard: Option<Keyboard>,
+    // For copy and paste, None if the compositor doesn't support it
+    pub data_device: Option<DataDevice>,
+    // Of the last key or button press, proves that the user did something
+    pub last_serial: Option<u32>,
+}
+
+impl Seat {
+    pub fn new(seat: WlSeat) -> Self {
+        Self {
+            seat,
+            name: None,
+            capabilities: Capability::empty(),
+            pointer: None,
+            keyboard: None,
+            data_device: None,
+            last_serial: None,
+        }
+    }
+
+    /// Records the capabilities the compositor advertised. Returns the ones
+    /// that were added and the ones that were removed since last time.
+    pub fn set_capabilities(&mut self, capabilities: Capability) -> (Capability, Capability) {
+        let added = capabilities - self.capabilities;
+        let removed = self.capabilities - capabilities;
+        self.capabilities = capabilities;
+
+        (added, removed)
+    }
+}
+
+/// What we know about the pointer, in surface-local coordinates.
+#[derive(Debug)]
+pub struct Pointer {
+    pub pointer: WlPointer,
+    // The surface the pointer is over, the window or a popup
+    pub surface: Option<WlSurface>,
+    // `None` while the pointer is outside of our surfaces
+    pub position: Option<(f64, f64)>,
+    // Buttons held down, as linux input event codes
+    pub pressed: Vec<u32>,
+}
+
+impl Pointer {
+    pub fn new(pointer: WlPointer) -> Self {
+        Self {
+            pointer,
+            surface: None,
+            position: None,
+            pressed: Vec::new(),
+        }
+    }
+
+    pub fn is_pressed(&self, button: u32) -> bool {
+        self.pressed.contains(&button)
+    }
+}
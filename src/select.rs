@@ -0,0 +1,248 @@
+//! Region selection: a fullscreen overlay the user drags a rectangle on,
+//! dimmed outside of it and labelled with its size. The geometry is printed
+//! once the button is released, Escape cancels.
+
+use std::sync::Arc;
+
+use tracing::warn;
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::Layer,
+    zwlr_layer_surface_v1::{Anchor, KeyboardInteractivity},
+};
+
+use crate::{
+    color::Color,
+    layer::LayerOptions,
+    pixel_buffer::PixelBuffer,
+    rect::Rect,
+    render::present_if_needed,
+    scale::Scale,
+    scene::Scene,
+    seat::BTN_LEFT,
+    state::AppState,
+    text::{self, Font},
+    window::WindowId,
+};
+
+const DIM: Color = Color::rgba(0x00, 0x00, 0x00, 0x80);
+const CLEAR: Color = Color::rgba(0x00, 0x00, 0x00, 0x00);
+const BORDER: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+const LABEL_BACKGROUND: Color = Color::rgba(0x00, 0x00, 0x00, 0xC0);
+const LABEL_TEXT: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+
+// The label with the size goes below the bottom right corner, or inside of
+// it if there is no room below
+const LABEL_SIZE: (usize, usize) = (112, 24);
+const LABEL_FONT_SIZE: f32 = 14.0;
+const LABEL_PADDING: usize = 4;
+
+/// The overlay covering a whole output, above everything, that takes the
+/// keyboard so that Escape works.
+pub fn layer_options() -> LayerOptions {
+    LayerOptions {
+        layer: Layer::Overlay,
+        anchor: Anchor::Top | Anchor::Bottom | Anchor::Left | Anchor::Right,
+        exclusive_zone: -1,
+        keyboard_interactivity: KeyboardInteractivity::Exclusive,
+        size: (0, 0),
+    }
+}
+
+/// The rectangle being dragged, in window pixels.
+#[derive(Clone, Default)]
+pub struct Selection {
+    // Where the button was pressed, None until then
+    start: Option<(f64, f64)>,
+    end: (f64, f64),
+    // No label without it
+    font: Option<Arc<Font>>,
+}
+
+impl Selection {
+    /// A selection yet to be started, with the font for its label.
+    pub fn new() -> Self {
+        let font = Font::find(text::SANS)
+            .inspect_err(|err| warn!(%err, "no font for the size of the selection"))
+            .ok()
+            .map(Arc::new);
+        Self {
+            font,
+            ..Self::default()
+        }
+    }
+
+    /// The selected rectangle, within a `width`x`height` window.
+    fn rect(&self, (width, height): (usize, usize)) -> Option<Rect> {
+        let (x0, y0) = self.start?;
+        let (x1, y1) = self.end;
+        let clamp = |v: f64, max: usize| (v.max(0.0) as usize).min(max);
+        let (left, right) = (clamp(x0.min(x1), width), clamp(x0.max(x1), width));
+        let (top, bottom) = (clamp(y0.min(y1), height), clamp(y0.max(y1), height));
+        Some(Rect::new(left, top, right - left, bottom - top))
+    }
+
+    /// Where the label of `rect` goes in a window of `size`.
+    fn label_rect(rect: Rect, (width, height): (usize, usize)) -> Rect {
+        let (label_width, label_height) = LABEL_SIZE;
+        let x = rect.right().saturating_sub(label_width);
+        let y = if rect.bottom() + label_height <= height {
+            rect.bottom()
+        } else {
+            rect.bottom().saturating_sub(label_height)
+        };
+        Rect::new(x, y, label_width, label_height).intersect(&Rect::new(0, 0, width, height))
+    }
+
+    /// What the selection covers in a window of `size`, border and label
+    /// included, to redraw when it changes.
+    fn area(&self, size: (usize, usize)) -> Option<Rect> {
+        let rect = self.rect(size)?;
+        // The border is drawn around the selection
+        let outline = Rect::new(
+            rect.x.saturating_sub(1),
+            rect.y.saturating_sub(1),
+            rect.width + 2,
+            rect.height + 2,
+        );
+        let window = Rect::new(0, 0, size.0, size.1);
+        Some(
+            outline
+                .union(&Self::label_rect(rect, size))
+                .intersect(&window),
+        )
+    }
+
+    /// Draws the overlay into `pixels`, laid out in window pixels.
+    pub(crate) fn draw(&self, pixels: &mut PixelBuffer, scale: Scale) {
+        let bounds = pixels.bounds();
+        pixels.fill(DIM);
+        let size = (
+            scale.to_surface(bounds.width),
+            scale.to_surface(bounds.height),
+        );
+        let Some(rect) = self.rect(size) else {
+            return;
+        };
+
+        let to_buffer = |rect| scale.rect_to_buffer(rect).intersect(&bounds);
+        let outline = to_buffer(Rect::new(
+            rect.x.saturating_sub(1),
+            rect.y.saturating_sub(1),
+            rect.width + 2,
+            rect.height + 2,
+        ));
+        pixels.fill_rect(outline, BORDER);
+        pixels.fill_rect(to_buffer(rect), CLEAR);
+
+        let Some(font) = &self.font else {
+            return;
+        };
+        let label = to_buffer(Self::label_rect(rect, size));
+        pixels.fill_rect(label, LABEL_BACKGROUND);
+        let padding = scale.to_buffer(LABEL_PADDING);
+        font.draw(
+            pixels,
+            &format!("{} x {}", rect.width, rect.height),
+            (label.x + padding, label.y + padding),
+            LABEL_FONT_SIZE * scale.as_f64() as f32,
+            LABEL_TEXT,
+        );
+    }
+}
+
+/// Changes the selection of the window `id` with `change`, and redraws
+/// where it was and where it is now.
+fn update(state: &mut AppState, id: WindowId, change: impl FnOnce(&mut Selection)) {
+    let (Scene::Select(selection), Some(window)) = (
+        &mut state.scene,
+        state.windows.iter_mut().find(|w| w.id() == id),
+    ) else {
+        return;
+    };
+
+    let size = window.size();
+    let before = selection.area(size);
+    change(selection);
+    let after = selection.area(size);
+    window.damage_area(before.into_iter().chain(after));
+    present_if_needed(state);
+}
+
+/// The pointer moved to `x`, `y` over the window `id`.
+pub(crate) fn pointer_moved(state: &mut AppState, id: Option<WindowId>, x: f64, y: f64) {
+    if let Some(id) = id {
+        update(state, id, |selection| selection.end = (x, y));
+    }
+}
+
+/// Starts the selection when the left button is pressed over the window
+/// `id` and prints it once released. Returns whether the button was the
+/// left one, in the selection mode.
+pub(crate) fn pointer_button(
+    state: &mut AppState,
+    id: Option<WindowId>,
+    button: u32,
+    pressed: bool,
+) -> bool {
+    let Some(id) = id.filter(|_| matches!(state.scene, Scene::Select(_))) else {
+        return false;
+    };
+    if button != BTN_LEFT {
+        return false;
+    }
+
+    if pressed {
+        update(state, id, |selection| selection.start = Some(selection.end));
+        return true;
+    }
+    let rect = match (&state.scene, state.window(id)) {
+        (Scene::Select(selection), Some(window)) => selection.rect(window.size()),
+        _ => None,
+    };
+    let Some(rect) = rect else {
+        return true;
+    };
+    if rect.is_empty() {
+        // A click rather than a drag, start over
+        update(state, id, |selection| selection.start = None);
+        return true;
+    }
+
+    // Like slurp, relative to the output the overlay covers
+    println!("{},{} {}x{}", rect.x, rect.y, rect.width, rect.height);
+    state.running = false;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selections_stay_in_the_window() {
+        let mut selection = Selection {
+            start: Some((300.5, 200.0)),
+            end: (100.0, 50.2),
+            font: None,
+        };
+        let size = (640, 480);
+        assert_eq!(selection.rect(size), Some(Rect::new(100, 50, 200, 150)));
+        assert_eq!(
+            Selection::label_rect(Rect::new(100, 50, 200, 150), size),
+            Rect::new(188, 200, 112, 24)
+        );
+
+        // Dragged past the edges, the label doesn't fit below
+        selection.end = (-10.0, 900.0);
+        let rect = selection.rect(size).unwrap();
+        assert_eq!(rect, Rect::new(0, 200, 300, 280));
+        assert_eq!(
+            Selection::label_rect(rect, size),
+            Rect::new(188, 456, 112, 24)
+        );
+        assert_eq!(selection.area(size), Some(Rect::new(0, 199, 302, 281)));
+
+        selection.start = None;
+        assert_eq!(selection.area(size), None);
+    }
+}
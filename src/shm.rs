@@ -0,0 +1,451 @@
+use std::{
+    fs::File,
+    io,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd},
+    ptr, slice,
+};
+
+use thiserror::Error;
+use tracing::debug;
+use wayland_client::{
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_shm::{Format, WlShm},
+        wl_shm_pool::WlShmPool,
+    },
+    Dispatch, QueueHandle,
+};
+
+#[derive(Debug, Error)]
+pub enum ShmError {
+    /// Creating, sizing or sealing the memfd failed, e.g. we ran out of fds
+    /// or memory.
+    #[error("failed to create the shm file: {0}")]
+    CreateFile(#[source] io::Error),
+    #[error("failed to map {size} bytes of shm: {source}")]
+    Map { size: usize, source: io::Error },
+    #[error("failed to grow the shm file to {size} bytes: {source}")]
+    Grow { size: usize, source: io::Error },
+    #[error("{size} bytes is too large for a wl_shm pool")]
+    TooLarge { size: usize },
+    #[error("a stride of {stride} bytes is too small for a {width} pixels wide buffer")]
+    StrideTooSmall { width: usize, stride: usize },
+    #[error(
+        "a {width}x{height} buffer at offset {offset} does not fit in a {pool_size} bytes pool"
+    )]
+    OutOfBounds {
+        width: usize,
+        height: usize,
+        offset: usize,
+        pool_size: usize,
+    },
+}
+
+/// The memory behind a pool: an anonymous memfd, so nothing touches the file
+/// system. It is sealed against shrinking, nobody can truncate it under our
+/// or the compositor's mapping and have the other side die of SIGBUS.
+pub struct ShmBacking {
+    file: File,
+    size: usize,
+}
+
+impl ShmBacking {
+    pub fn new(size: usize) -> Result<Self, ShmError> {
+        let fd = unsafe {
+            libc::memfd_create(
+                c"rust-wayland-shm".as_ptr(),
+                libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+            )
+        };
+        if fd < 0 {
+            return Err(ShmError::CreateFile(io::Error::last_os_error()));
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(size as u64).map_err(ShmError::CreateFile)?;
+
+        // Growing stays allowed, the pool grows with the window
+        let res = unsafe {
+            libc::fcntl(
+                fd,
+                libc::F_ADD_SEALS,
+                libc::F_SEAL_SHRINK | libc::F_SEAL_SEAL,
+            )
+        };
+        if res < 0 {
+            return Err(ShmError::CreateFile(io::Error::last_os_error()));
+        }
+
+        Ok(Self { file, size })
+    }
+
+    /// Grows the file to `size` bytes, it can't shrink.
+    pub fn grow(&mut self, size: usize) -> Result<(), ShmError> {
+        if size <= self.size {
+            return Ok(());
+        }
+
+        self.file
+            .set_len(size as u64)
+            .map_err(|source| ShmError::Grow { size, source })?;
+        self.size = size;
+
+        Ok(())
+    }
+}
+
+impl AsFd for ShmBacking {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+/// A shared, writable mapping of a memfd. Owns both, the mapping is undone
+/// and the fd closed when it is dropped.
+pub struct MappedMemory {
+    backing: ShmBacking,
+    ptr: *mut u8,
+    size: usize,
+}
+
+impl MappedMemory {
+    /// Maps a new memfd of `size` bytes. Its pages are only allocated once
+    /// written to: pools are sized for the largest window we may get, most
+    /// of that is never touched.
+    pub fn new(size: usize) -> Result<Self, ShmError> {
+        let backing = ShmBacking::new(size)?;
+
+        let ptr = unsafe {
+            let res = libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                backing.as_fd().as_raw_fd(),
+                0,
+            );
+
+            if res == libc::MAP_FAILED {
+                let source = io::Error::last_os_error();
+                return Err(ShmError::Map { size, source });
+            }
+
+            res as *mut u8
+        };
+
+        Ok(Self { backing, ptr, size })
+    }
+
+    /// The whole mapping as 32-bit pixels.
+    pub fn as_mut_slice(&mut self) -> &mut [u32] {
+        // mmap hands out page aligned memory, so this is aligned for u32 too
+        unsafe { slice::from_raw_parts_mut(self.ptr as *mut u32, self.size / 4) }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Grows the memfd and the mapping to `size` bytes, the mapping may
+    /// move.
+    pub fn grow(&mut self, size: usize) -> Result<(), ShmError> {
+        if size <= self.size {
+            return Ok(());
+        }
+
+        self.backing.grow(size)?;
+
+        let ptr = unsafe {
+            libc::mremap(
+                self.ptr as *mut libc::c_void,
+                self.size,
+                size,
+                libc::MREMAP_MAYMOVE,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            let source = io::Error::last_os_error();
+            return Err(ShmError::Map { size, source });
+        }
+
+        self.ptr = ptr as *mut u8;
+        self.size = size;
+
+        Ok(())
+    }
+
+    /// Has the kernel fault in the pages among `len` bytes at `offset` ahead
+    /// of time, instead of taking a page fault per page while drawing.
+    pub fn prefault(&self, offset: usize, len: usize) {
+        let start = offset / page_size() * page_size();
+        let end = offset.saturating_add(len).min(self.size);
+        if start >= end {
+            return;
+        }
+
+        // Only a hint, drawing faults the pages in anyway if it fails
+        unsafe {
+            libc::madvise(
+                self.ptr.add(start) as *mut libc::c_void,
+                end - start,
+                libc::MADV_WILLNEED,
+            );
+        }
+    }
+
+    /// Frees the whole pages among `len` bytes at `offset`. They read as
+    /// zeros afterwards, and only take memory again once written to.
+    ///
+    /// The pages of a shared mapping belong to the memfd, dropping them from
+    /// our mapping with MADV_DONTNEED wouldn't free anything: the hole has
+    /// to be punched into the file.
+    pub fn discard(&self, offset: usize, len: usize) -> io::Result<()> {
+        let page_size = page_size();
+        let start = offset.next_multiple_of(page_size);
+        let end = offset.saturating_add(len).min(self.size) / page_size * page_size;
+        if start >= end {
+            return Ok(());
+        }
+
+        let res = unsafe {
+            libc::fallocate(
+                self.backing.as_fd().as_raw_fd(),
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                start as libc::off_t,
+                (end - start) as libc::off_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl AsFd for MappedMemory {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.backing.as_fd()
+    }
+}
+
+impl Drop for MappedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.size);
+        }
+        // The memfd is closed right after this
+    }
+}
+
+/// A mapping of its own of part of a pool, e.g. for another thread to draw
+/// a buffer into. It stays valid whatever happens to the pool meanwhile,
+/// even once the pool is gone: the pages belong to the memfd, which can't
+/// shrink.
+pub struct SharedMapping {
+    // Of the whole pages the part is in
+    ptr: *mut u8,
+    len: usize,
+    // Where the part starts in them
+    skip: usize,
+    size: usize,
+}
+
+// Nothing else writes to the part while it is mapped, see `Frame::share`
+unsafe impl Send for SharedMapping {}
+
+impl SharedMapping {
+    fn new(fd: BorrowedFd<'_>, offset: usize, size: usize) -> Result<Self, ShmError> {
+        // mmap only takes whole pages
+        let start = offset / page_size() * page_size();
+        let (skip, len) = (offset - start, offset - start + size);
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                start as libc::off_t,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            let source = io::Error::last_os_error();
+            return Err(ShmError::Map { size, source });
+        }
+
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+            skip,
+            size,
+        })
+    }
+
+    /// The part as 32-bit pixels.
+    pub fn as_mut_slice(&mut self) -> &mut [u32] {
+        // Buffers start at a multiple of 4 bytes into the pool
+        unsafe { slice::from_raw_parts_mut(self.ptr.add(self.skip) as *mut u32, self.size / 4) }
+    }
+}
+
+impl Drop for SharedMapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// Shared memory backing a `wl_shm_pool`.
+///
+/// `create_pool` hands the compositor its own duplicate of the fd, ours is
+/// kept around in the mapping to grow the pool.
+pub struct ShmPool {
+    memory: MappedMemory,
+    pool: WlShmPool,
+}
+
+impl ShmPool {
+    pub fn new<D>(shm: &WlShm, size: usize, qh: &QueueHandle<D>) -> Result<Self, ShmError>
+    where
+        D: Dispatch<WlShmPool, ()> + 'static,
+    {
+        // The protocol uses an int for the size
+        let wl_size = i32::try_from(size).map_err(|_| ShmError::TooLarge { size })?;
+
+        let memory = MappedMemory::new(size)?;
+        let pool = shm.create_pool(memory.as_fd(), wl_size, qh, ());
+
+        Ok(Self { memory, pool })
+    }
+
+    pub fn create_buffer<D>(
+        &self,
+        offset: usize,
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: Format,
+        qh: &QueueHandle<D>,
+    ) -> Result<WlBuffer, ShmError>
+    where
+        D: Dispatch<WlBuffer, ()> + 'static,
+    {
+        // The compositor would kill us with invalid_stride
+        if width.checked_mul(4).is_none_or(|row| stride < row) {
+            return Err(ShmError::StrideTooSmall { width, stride });
+        }
+        // The pool size fits in an i32, so everything that fits in the pool
+        // does too
+        let pool_size = self.memory.size();
+        let end = stride
+            .checked_mul(height)
+            .and_then(|len| len.checked_add(offset));
+        if end.is_none_or(|end| end > pool_size) {
+            return Err(ShmError::OutOfBounds {
+                width,
+                height,
+                offset,
+                pool_size,
+            });
+        }
+
+        Ok(self.pool.create_buffer(
+            offset as i32,
+            width as i32,
+            height as i32,
+            stride as i32,
+            format,
+            qh,
+            (),
+        ))
+    }
+
+    pub fn memory(&mut self) -> &mut MappedMemory {
+        &mut self.memory
+    }
+
+    /// Maps `size` bytes at `offset` once more, see [`SharedMapping`].
+    pub fn map_shared(&self, offset: usize, size: usize) -> Result<SharedMapping, ShmError> {
+        SharedMapping::new(self.memory.as_fd(), offset, size)
+    }
+
+    pub fn size(&self) -> usize {
+        self.memory.size()
+    }
+
+    /// Grows the pool to `size` bytes. Buffers created from it stay valid.
+    pub fn grow(&mut self, size: usize) -> Result<(), ShmError> {
+        if size <= self.size() {
+            return Ok(());
+        }
+
+        let wl_size = i32::try_from(size).map_err(|_| ShmError::TooLarge { size })?;
+        self.memory.grow(size)?;
+        // The compositor remaps its side when it sees this
+        self.pool.resize(wl_size);
+
+        Ok(())
+    }
+
+    /// See [`MappedMemory::prefault`].
+    pub fn prefault(&self, offset: usize, len: usize) {
+        self.memory.prefault(offset, len);
+    }
+
+    /// Gives back the memory of `len` bytes at `offset`, e.g. after a buffer
+    /// shrank or went away. wl_shm pools can't shrink, so this is the next
+    /// best thing.
+    pub fn discard(&self, offset: usize, len: usize) {
+        // Harmless, the memory is only given back later, with the pool
+        if let Err(err) = self.memory.discard(offset, len) {
+            debug!(%err, offset, len, "failed to discard shm memory");
+        }
+    }
+}
+
+pub fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+impl Drop for ShmPool {
+    fn drop(&mut self) {
+        self.pool.destroy();
+        // The mapping goes away right after this
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How much memory the memfd behind `memory` takes.
+    fn allocated_blocks(memory: &MappedMemory) -> i64 {
+        let mut stat = unsafe { std::mem::zeroed::<libc::stat>() };
+        let res = unsafe { libc::fstat(memory.as_fd().as_raw_fd(), &mut stat) };
+        assert_eq!(res, 0, "{}", io::Error::last_os_error());
+        stat.st_blocks
+    }
+
+    #[test]
+    fn discarding_frees_the_pages() {
+        let size = 16 * page_size();
+        let mut memory = MappedMemory::new(size).unwrap();
+        memory.as_mut_slice().fill(0xFFFF_FFFF);
+        let written = allocated_blocks(&memory);
+        assert!(written > 0);
+
+        // Half of it, and half a page on either side that must stay
+        memory
+            .discard(page_size() / 2, size / 2 + page_size())
+            .unwrap();
+        let discarded = allocated_blocks(&memory);
+        assert!(discarded < written, "{discarded} blocks, {written} before");
+        assert_eq!(memory.size(), size);
+
+        let pixels = memory.as_mut_slice();
+        let words_per_page = page_size() / 4;
+        assert_eq!(pixels[words_per_page - 1], 0xFFFF_FFFF);
+        assert_eq!(pixels[words_per_page], 0);
+        assert_eq!(pixels[size / 4 - 1], 0xFFFF_FFFF);
+    }
+}
@@ -0,0 +1,105 @@
+//! Frames of a single color, as wp_single_pixel_buffer_v1 buffers the
+//! viewport scales up to the window. Nothing is drawn nor copied, which
+//! makes them the cheapest frames there are to compare the others with.
+
+use tracing::debug;
+use wayland_client::{
+    protocol::wl_buffer::{self, WlBuffer},
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+use wayland_protocols::wp::single_pixel_buffer::v1::client::wp_single_pixel_buffer_manager_v1::WpSinglePixelBufferManagerV1;
+
+use crate::{
+    color::PremulColor,
+    state::AppState,
+    window::{PresentPath, WindowId},
+};
+
+/// Marks the buffers made here, destroyed as soon as the compositor is done
+/// with them since every frame has a color of its own.
+pub(crate) struct SinglePixelBuffer;
+
+/// Shows the background of the scene in the window `id` if it asks for
+/// single pixel buffers. Returns `false` if it doesn't, or if the compositor
+/// can't do it, the frame is to be drawn as usual then.
+pub(crate) fn present(state: &mut AppState, id: WindowId) -> bool {
+    let qh = &state.queue_handle;
+    let Some(manager) = &state.single_pixel_buffer_manager else {
+        return false;
+    };
+    let Some(window) = state.windows.iter_mut().find(|w| w.id() == id) else {
+        return false;
+    };
+    if window.options().present_path != PresentPath::SinglePixel || !window.has_viewport() {
+        return false;
+    }
+
+    let time = window.animation_time.as_millis() as u32;
+    let color = state
+        .scene
+        .background(window.highlighted, time)
+        .premultiply();
+    let buffer = create_buffer(manager, color, qh);
+
+    let surface = window.surface().clone();
+    surface.frame(qh, id);
+    window.frame_pending = true;
+    window.needs_redraw = false;
+    // The whole buffer, stretched over the window
+    window.apply_viewport(None);
+
+    surface.attach(Some(&buffer), 0, 0);
+    let (width, height) = window.size();
+    surface.damage(0, 0, width as i32, height as i32);
+    if let Some(presentation) = &state.presentation {
+        presentation.feedback(&surface, id, qh);
+    }
+    surface.commit();
+    true
+}
+
+fn create_buffer(
+    manager: &WpSinglePixelBufferManagerV1,
+    color: PremulColor,
+    qh: &QueueHandle<AppState>,
+) -> WlBuffer {
+    // From 0 to u32::MAX for 0 to 100%, premultiplied like the rest
+    let channel = |value: u8| u32::from(value) * 0x0101_0101;
+    manager.create_u32_rgba_buffer(
+        channel(color.r),
+        channel(color.g),
+        channel(color.b),
+        channel(color.a),
+        qh,
+        SinglePixelBuffer,
+    )
+}
+
+impl Dispatch<WpSinglePixelBufferManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpSinglePixelBufferManagerV1,
+        _event: <WpSinglePixelBufferManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<WlBuffer, SinglePixelBuffer> for AppState {
+    fn event(
+        _state: &mut Self,
+        proxy: &WlBuffer,
+        event: <WlBuffer as Proxy>::Event,
+        _data: &SinglePixelBuffer,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_buffer::Event::Release => proxy.destroy(),
+            event => debug!(?event, "ignoring unknown buffer event"),
+        }
+    }
+}
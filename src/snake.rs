@@ -0,0 +1,410 @@
+//! The snake game: the arrow keys steer, a timer moves the snake one cell
+//! at a time and only the cells that changed are redrawn.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use xkbcommon_dl::keysyms;
+
+use crate::{
+    color::{Color, ColorSpace},
+    pixel_buffer::PixelBuffer,
+    rect::Rect,
+    render::present_if_needed,
+    scale::Scale,
+    scene::Scene,
+    state::AppState,
+};
+
+/// How long the snake takes to move by one cell
+pub const STEP_INTERVAL: Duration = Duration::from_millis(120);
+
+const COLUMNS: usize = 24;
+const ROWS: usize = 18;
+const START_LENGTH: usize = 3;
+// Turns typed faster than the snake moves wait for the next steps
+const MAX_QUEUED_TURNS: usize = 2;
+
+pub const BACKGROUND: Color = Color::rgb(0x20, 0x20, 0x20);
+const GRID: Color = Color::rgb(0x30, 0x30, 0x30);
+const BODY: Color = Color::rgb(0x40, 0xA0, 0x40);
+const HEAD: Color = Color::rgb(0x80, 0xE0, 0x80);
+const FOOD: Color = Color::rgb(0xE0, 0x40, 0x40);
+const GAME_OVER: Color = Color::rgba(0xC0, 0x00, 0x00, 0x60);
+const SCORE: Color = Color::rgb(0xFF, 0xFF, 0xFF);
+const SCORE_BACKING: Color = Color::rgba(0x00, 0x00, 0x00, 0x80);
+
+/// A cell of the grid, column first.
+type Cell = (usize, usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    fn opposite(self) -> Self {
+        match self {
+            Self::Up => Self::Down,
+            Self::Down => Self::Up,
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+        }
+    }
+
+    /// The cell next to `cell` this way, `None` past the edge of the grid.
+    fn step(self, (column, row): Cell) -> Option<Cell> {
+        let (column, row) = match self {
+            Self::Up => (Some(column), row.checked_sub(1)),
+            Self::Down => (Some(column), Some(row + 1).filter(|&r| r < ROWS)),
+            Self::Left => (column.checked_sub(1), Some(row)),
+            Self::Right => (Some(column + 1).filter(|&c| c < COLUMNS), Some(row)),
+        };
+        Some((column?, row?))
+    }
+}
+
+/// The state of a game.
+#[derive(Debug, Clone)]
+pub struct Snake {
+    // Head first
+    body: VecDeque<Cell>,
+    direction: Direction,
+    turns: VecDeque<Direction>,
+    food: Cell,
+    score: u32,
+    over: bool,
+    // xorshift64, to place the food
+    rng: u64,
+}
+
+impl Default for Snake {
+    fn default() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |time| time.as_nanos() as u64);
+        Self::new(seed)
+    }
+}
+
+impl Snake {
+    /// A new game, the food placed by `seed`.
+    fn new(seed: u64) -> Self {
+        let row = ROWS / 2;
+        let mut snake = Self {
+            body: (0..START_LENGTH)
+                .rev()
+                .map(|column| (column, row))
+                .collect(),
+            direction: Direction::Right,
+            turns: VecDeque::new(),
+            food: (0, 0),
+            score: 0,
+            over: false,
+            // xorshift gets stuck on zero
+            rng: seed | 1,
+        };
+        snake.place_food();
+        snake
+    }
+
+    fn random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    /// Puts the food on a free cell. The game is won if there is none.
+    fn place_food(&mut self) {
+        let free: Vec<Cell> = (0..ROWS)
+            .flat_map(|row| (0..COLUMNS).map(move |column| (column, row)))
+            .filter(|cell| !self.body.contains(cell))
+            .collect();
+        if free.is_empty() {
+            self.over = true;
+            return;
+        }
+        self.food = free[(self.random() % free.len() as u64) as usize];
+    }
+
+    /// Steers towards `direction` from the next step on, after the turns
+    /// already asked for. Turning back onto itself is ignored.
+    fn turn(&mut self, direction: Direction) {
+        let last = self.turns.back().copied().unwrap_or(self.direction);
+        if direction != last && direction != last.opposite() && self.turns.len() < MAX_QUEUED_TURNS
+        {
+            self.turns.push_back(direction);
+        }
+    }
+
+    /// Moves the snake by one cell. Returns the cells that changed, or
+    /// `None` if the game just ended and everything has to be redrawn.
+    fn step(&mut self) -> Option<Vec<Cell>> {
+        if self.over {
+            return Some(Vec::new());
+        }
+        if let Some(direction) = self.turns.pop_front() {
+            self.direction = direction;
+        }
+
+        let head = self.body[0];
+        let Some(next) = self.direction.step(head) else {
+            self.over = true;
+            return None;
+        };
+        let mut changed = vec![head, next];
+        let eating = next == self.food;
+        // The tail moves out of the way first, unless the snake grows
+        if !eating {
+            changed.extend(self.body.pop_back());
+        }
+        if self.body.contains(&next) {
+            self.over = true;
+            return None;
+        }
+        self.body.push_front(next);
+
+        if eating {
+            self.score += 1;
+            self.place_food();
+            if self.over {
+                return None;
+            }
+            changed.push(self.food);
+        }
+        Some(changed)
+    }
+
+    /// Draws the game into `pixels`, laid out in window pixels.
+    pub(crate) fn draw(&self, pixels: &mut PixelBuffer, scale: Scale) {
+        let bounds = pixels.bounds();
+        let layout = Layout::new((
+            scale.to_surface(bounds.width),
+            scale.to_surface(bounds.height),
+        ));
+        pixels.fill(BACKGROUND);
+        let mut fill = |rect: Rect, color: Color| {
+            pixels.fill_rect(scale.rect_to_buffer(rect).intersect(&bounds), color);
+        };
+
+        fill(layout.grid(), GRID);
+        fill(layout.cell(self.food), FOOD);
+        for (i, cell) in self.body.iter().enumerate() {
+            fill(layout.cell(*cell), if i == 0 { HEAD } else { BODY });
+        }
+
+        let score = scale.rect_to_buffer(layout.score()).intersect(&bounds);
+        pixels.blend_rect(score, SCORE_BACKING, ColorSpace::Srgb);
+        draw_number(pixels, self.score, layout, scale);
+
+        if self.over {
+            let grid = scale.rect_to_buffer(layout.grid()).intersect(&bounds);
+            pixels.blend_rect(grid, GAME_OVER, ColorSpace::Srgb);
+        }
+    }
+}
+
+/// Where the grid goes in a window: centered, with square cells as large
+/// as fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    x: usize,
+    y: usize,
+    cell: usize,
+}
+
+impl Layout {
+    fn new((width, height): (usize, usize)) -> Self {
+        let cell = (width / COLUMNS).min(height / ROWS).max(1);
+        Self {
+            x: width.saturating_sub(cell * COLUMNS) / 2,
+            y: height.saturating_sub(cell * ROWS) / 2,
+            cell,
+        }
+    }
+
+    fn grid(&self) -> Rect {
+        Rect::new(self.x, self.y, self.cell * COLUMNS, self.cell * ROWS)
+    }
+
+    fn cell(&self, (column, row): Cell) -> Rect {
+        let (x, y) = (self.x + column * self.cell, self.y + row * self.cell);
+        // A gap between the cells, if they are large enough for one
+        let gap = usize::from(self.cell > 4);
+        Rect::new(x + gap, y + gap, self.cell - gap * 2, self.cell - gap * 2)
+    }
+
+    /// Where the score goes, in the top left corner of the grid. Room for
+    /// three digits, the grid holds fewer cells than 1000.
+    fn score(&self) -> Rect {
+        let dot = self.dot();
+        let width = (DIGITS * (DIGIT_WIDTH + 1) + 1) * dot;
+        Rect::new(self.x, self.y, width, (DIGIT_HEIGHT + 2) * dot)
+    }
+
+    /// How large the squares the digits are made of are.
+    fn dot(&self) -> usize {
+        (self.cell / 4).max(1)
+    }
+}
+
+const DIGITS: usize = 3;
+const DIGIT_WIDTH: usize = 3;
+const DIGIT_HEIGHT: usize = 5;
+
+// 3x5 dots per digit, a row per three bits from the top, most significant
+// bit on the left
+const FONT: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_010_010_010,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+
+/// Writes `number` in the score area of `layout`.
+fn draw_number(pixels: &mut PixelBuffer, number: u32, layout: Layout, scale: Scale) {
+    let bounds = pixels.bounds();
+    let area = layout.score();
+    let dot = layout.dot();
+    let digits = number.to_string();
+    for (i, digit) in digits.bytes().take(DIGITS).enumerate() {
+        let glyph = FONT[(digit - b'0') as usize];
+        let x = area.x + dot + i * (DIGIT_WIDTH + 1) * dot;
+        for row in 0..DIGIT_HEIGHT {
+            for column in 0..DIGIT_WIDTH {
+                let bit = (DIGIT_HEIGHT - row) * DIGIT_WIDTH - column - 1;
+                if glyph & (1 << bit) != 0 {
+                    let rect = Rect::new(x + column * dot, area.y + (row + 1) * dot, dot, dot);
+                    pixels.fill_rect(scale.rect_to_buffer(rect).intersect(&bounds), SCORE);
+                }
+            }
+        }
+    }
+}
+
+/// Moves the snake of the snake scene and redraws what changed. Waits while
+/// no window has the keyboard focus.
+pub(crate) fn step(state: &mut AppState) {
+    if state.focused_window().is_none() {
+        return;
+    }
+    let Scene::Snake(snake) = &mut state.scene else {
+        return;
+    };
+
+    let score = snake.score;
+    let changed = snake.step();
+    let score_changed = snake.score != score;
+    for window in &mut state.windows {
+        let layout = Layout::new(window.size());
+        match &changed {
+            Some(cells) => {
+                window.damage_area(cells.iter().map(|cell| layout.cell(*cell)));
+                // The snake may pass under the score
+                let under_score =
+                    |cell: &Cell| !layout.cell(*cell).intersect(&layout.score()).is_empty();
+                if score_changed || cells.iter().any(under_score) {
+                    window.damage_area([layout.score()]);
+                }
+            }
+            None => window.damage_area([layout.grid()]),
+        }
+    }
+    present_if_needed(state);
+}
+
+/// Steers with the arrow keys, and starts a new game with space once it is
+/// over. Returns whether `keysym` was one of those.
+pub(crate) fn handle_key(state: &mut AppState, keysym: u32) -> bool {
+    let Scene::Snake(snake) = &mut state.scene else {
+        return false;
+    };
+
+    match keysym {
+        keysyms::Up => snake.turn(Direction::Up),
+        keysyms::Down => snake.turn(Direction::Down),
+        keysyms::Left => snake.turn(Direction::Left),
+        keysyms::Right => snake.turn(Direction::Right),
+        keysyms::space if snake.over => {
+            *snake = Snake::new(snake.random());
+            for window in &mut state.windows {
+                window.request_redraw();
+            }
+            present_if_needed(state);
+        }
+        _ => return false,
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_when_eating() {
+        let mut snake = Snake::new(1);
+        let row = ROWS / 2;
+        snake.food = (START_LENGTH, row);
+
+        let changed = snake.step().unwrap();
+        assert_eq!(snake.score, 1);
+        assert_eq!(snake.body.len(), START_LENGTH + 1);
+        // The old and new head, and the new food, but not the tail
+        assert_eq!(changed[..2], [(START_LENGTH - 1, row), (START_LENGTH, row)]);
+        assert_eq!(changed[2], snake.food);
+        assert!(!snake.body.contains(&snake.food));
+
+        snake.food = (0, 0);
+        let changed = snake.step().unwrap();
+        // The tail moved along
+        assert_eq!(changed[2], (0, row));
+        assert_eq!(snake.body.len(), START_LENGTH + 1);
+    }
+
+    #[test]
+    fn ends_at_the_walls_and_on_itself() {
+        let mut snake = Snake::new(1);
+        snake.turn(Direction::Left);
+        assert!(snake.turns.is_empty(), "can't turn back onto itself");
+        snake.turn(Direction::Up);
+        while !snake.over {
+            snake.step();
+        }
+        assert_eq!(snake.body[0], (START_LENGTH - 1, 0));
+
+        let mut snake = Snake::new(1);
+        snake.food = (COLUMNS - 1, ROWS - 1);
+        snake.body = [(2, 2), (3, 2), (3, 3), (2, 3), (1, 3)].into();
+        snake.direction = Direction::Down;
+        assert_eq!(snake.step(), None);
+        assert!(snake.over);
+    }
+
+    #[test]
+    fn cells_fit_in_the_window() {
+        let layout = Layout::new((640, 480));
+        assert_eq!(
+            layout,
+            Layout {
+                x: 8,
+                y: 6,
+                cell: 26
+            }
+        );
+        assert_eq!(layout.cell((1, 0)), Rect::new(35, 7, 24, 24));
+        assert!(layout.grid().contains_rect(&layout.score()));
+    }
+}
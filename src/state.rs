@@ -0,0 +1,311 @@
+//! Everything the event handlers share.
+
+use wayland_client::{
+    protocol::{
+        wl_compositor::WlCompositor,
+        wl_data_device_manager::WlDataDeviceManager,
+        wl_display::WlDisplay,
+        wl_shm::{self, WlShm},
+        wl_subcompositor::WlSubcompositor,
+        wl_surface::WlSurface,
+    },
+    Proxy, QueueHandle,
+};
+use wayland_protocols::ext::session_lock::v1::client::ext_session_lock_manager_v1::ExtSessionLockManagerV1;
+use wayland_protocols::wp::{
+    content_type::v1::client::wp_content_type_manager_v1::WpContentTypeManagerV1,
+    cursor_shape::v1::client::wp_cursor_shape_manager_v1::WpCursorShapeManagerV1,
+    fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+    idle_inhibit::zv1::client::{
+        zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1,
+        zwp_idle_inhibitor_v1::ZwpIdleInhibitorV1,
+    },
+    single_pixel_buffer::v1::client::wp_single_pixel_buffer_manager_v1::WpSinglePixelBufferManagerV1,
+    tearing_control::v1::client::wp_tearing_control_manager_v1::WpTearingControlManagerV1,
+    viewporter::client::wp_viewporter::WpViewporter,
+};
+use wayland_protocols::xdg::{
+    activation::v1::client::{
+        xdg_activation_token_v1::XdgActivationTokenV1, xdg_activation_v1::XdgActivationV1,
+    },
+    decoration::zv1::client::zxdg_decoration_manager_v1::ZxdgDecorationManagerV1,
+    shell::client::xdg_wm_base::XdgWmBase,
+    xdg_output::zv1::client::zxdg_output_manager_v1::ZxdgOutputManagerV1,
+};
+use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::ZwlrLayerShellV1;
+
+use crate::{
+    activation::TokenCallback,
+    app::App,
+    cursor::Cursor,
+    error::Error,
+    input::release_seat,
+    ipc::ControlSocket,
+    keyboard::Keyboard,
+    lock::SessionLock,
+    menu::Menu,
+    output::{release_output, Output},
+    pipes::Pipes,
+    pixel_format::PixelFormat,
+    presentation::Presentation,
+    registry::GlobalManager,
+    render_thread::RenderThread,
+    scene::Scene,
+    seat::{Pointer, Seat},
+    timer::{TimerId, Timers},
+    watchdog::Watchdog,
+    window::{Window, WindowId},
+};
+
+/// A global bound at startup, or an error if the compositor lacks it.
+pub(crate) fn required<T: Proxy>(global: &Option<T>) -> Result<&T, Error> {
+    global
+        .as_ref()
+        .ok_or_else(|| Error::missing(T::interface().name))
+}
+
+/// The state of the whole client, every Dispatch impl gets it.
+pub struct AppState {
+    // Globals
+    pub(crate) display: Option<WlDisplay>,
+    pub(crate) compositor: Option<WlCompositor>,
+    pub(crate) globals: GlobalManager,
+    pub(crate) shm: Option<WlShm>,
+    // Pixel formats wl_shm supports
+    pub(crate) shm_formats: Vec<wl_shm::Format>,
+    pub(crate) subcompositor: Option<WlSubcompositor>,
+    pub(crate) data_device_manager: Option<WlDataDeviceManager>,
+    pub(crate) xdg_wm_base: Option<XdgWmBase>,
+    pub(crate) xdg_decoration_manager: Option<ZxdgDecorationManagerV1>,
+    pub(crate) cursor_shape_manager: Option<WpCursorShapeManagerV1>,
+    pub(crate) layer_shell: Option<ZwlrLayerShellV1>,
+    pub(crate) activation: Option<XdgActivationV1>,
+    pub(crate) idle_inhibit_manager: Option<ZwpIdleInhibitManagerV1>,
+    pub(crate) session_lock_manager: Option<ExtSessionLockManagerV1>,
+    pub(crate) xdg_output_manager: Option<ZxdgOutputManagerV1>,
+    pub(crate) fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
+    pub(crate) viewporter: Option<WpViewporter>,
+    pub(crate) single_pixel_buffer_manager: Option<WpSinglePixelBufferManagerV1>,
+    pub(crate) presentation: Option<Presentation>,
+    pub(crate) tearing_control_manager: Option<WpTearingControlManagerV1>,
+    pub(crate) content_type_manager: Option<WpContentTypeManagerV1>,
+    // Every output, in the order they were advertised
+    pub(crate) outputs: Vec<Output>,
+    // We only handle a single seat, the first one advertised
+    pub(crate) seat: Option<Seat>,
+
+    // Objects
+    // In the order they were opened
+    pub(crate) windows: Vec<Window>,
+    // Only in --lock mode, instead of the windows
+    pub(crate) session_lock: Option<SessionLock>,
+    // The context menu, while it is open
+    pub(crate) menu: Option<Menu>,
+    // Keeps the screen on while the window it is attached to is visible
+    pub(crate) idle_inhibitor: Option<(WindowId, ZwpIdleInhibitorV1)>,
+    // Activation tokens asked for and what to do with them
+    pub(crate) activation_tokens: Vec<(XdgActivationTokenV1, TokenCallback)>,
+
+    pub(crate) queue_handle: QueueHandle<Self>,
+
+    // Rendering
+    pub(crate) scene: Scene,
+    // Draws instead of the scene when run through app::run
+    pub(crate) app: Option<Box<dyn App>>,
+    // The pixel format of every buffer we draw into
+    pub(crate) format: PixelFormat,
+    // How close to the edge of the window a button press resizes it
+    pub(crate) resize_border: f64,
+
+    // None if the cursor theme failed to load
+    pub(crate) cursor: Option<Cursor>,
+    // Advances animated cursors
+    pub(crate) cursor_animation: Option<TimerId>,
+
+    pub(crate) timers: Timers<AppState>,
+    // Pipes read along with the socket, like pasted data
+    pub(crate) pipes: Pipes<AppState>,
+    // Where commands for the scene arrive, for those that take any
+    pub(crate) control_socket: Option<ControlSocket>,
+    // Draws the windows with --render-thread, None if it couldn't start
+    pub(crate) render_thread: Option<RenderThread>,
+    // Stops watching the connection along with the rest of the state
+    pub(crate) watchdog: Option<Watchdog>,
+
+    // Whether systemd has been told that we are up and running
+    pub(crate) ready_notified: bool,
+    // Cleared when the last window is closed or on SIGINT and SIGTERM,
+    // which may come before the loop even started
+    pub(crate) running: bool,
+}
+
+impl AppState {
+    /// Nothing bound yet, showing `scene`. Every object is created on the
+    /// queue of `qh`.
+    pub(crate) fn new(scene: Scene, qh: QueueHandle<Self>) -> Self {
+        Self {
+            display: None,
+            compositor: None,
+            globals: GlobalManager::default(),
+            shm: None,
+            shm_formats: Vec::new(),
+            subcompositor: None,
+            data_device_manager: None,
+            xdg_wm_base: None,
+            xdg_decoration_manager: None,
+            cursor_shape_manager: None,
+            layer_shell: None,
+            activation: None,
+            idle_inhibit_manager: None,
+            session_lock_manager: None,
+            xdg_output_manager: None,
+            fractional_scale_manager: None,
+            viewporter: None,
+            single_pixel_buffer_manager: None,
+            presentation: None,
+            tearing_control_manager: None,
+            content_type_manager: None,
+            outputs: Vec::new(),
+            seat: None,
+            windows: Vec::new(),
+            session_lock: None,
+            menu: None,
+            idle_inhibitor: None,
+            activation_tokens: Vec::new(),
+            queue_handle: qh,
+            scene,
+            app: None,
+            format: PixelFormat::default(),
+            resize_border: 0.0,
+            cursor: None,
+            cursor_animation: None,
+            timers: Timers::default(),
+            pipes: Pipes::default(),
+            control_socket: None,
+            render_thread: None,
+            watchdog: None,
+            ready_notified: false,
+            running: true,
+        }
+    }
+
+    pub(crate) fn set_display(&mut self, display: WlDisplay) {
+        self.display = Some(display);
+    }
+
+    /// Destroys our objects in the order the protocol requires: roles
+    /// before the objects they are attached to.
+    pub(crate) fn destroy(&mut self) {
+        // Frames it is still drawing are for windows about to go
+        self.render_thread = None;
+        // Attached to the window's surface
+        if let Some((_, inhibitor)) = self.idle_inhibitor.take() {
+            inhibitor.destroy();
+        }
+        if let Some(session_lock) = self.session_lock.take() {
+            session_lock.destroy();
+        }
+        // Popups go before their parent
+        if let Some(menu) = self.menu.take() {
+            menu.popup.destroy();
+        }
+        // Along with their buffers
+        for window in self.windows.drain(..) {
+            window.destroy();
+        }
+        if let Some(cursor) = self.cursor.take() {
+            cursor.destroy();
+        }
+
+        if let Some(subcompositor) = self.subcompositor.take() {
+            subcompositor.destroy();
+        }
+        if let Some(xdg_wm_base) = self.xdg_wm_base.take() {
+            xdg_wm_base.destroy();
+        }
+        if let Some(decoration_manager) = self.xdg_decoration_manager.take() {
+            decoration_manager.destroy();
+        }
+        for (token, _) in self.activation_tokens.drain(..) {
+            token.destroy();
+        }
+        if let Some(activation) = self.activation.take() {
+            activation.destroy();
+        }
+        if let Some(manager) = self.idle_inhibit_manager.take() {
+            manager.destroy();
+        }
+        if let Some(manager) = self.session_lock_manager.take() {
+            manager.destroy();
+        }
+        if let Some(manager) = self.fractional_scale_manager.take() {
+            manager.destroy();
+        }
+        if let Some(viewporter) = self.viewporter.take() {
+            viewporter.destroy();
+        }
+        if let Some(manager) = self.single_pixel_buffer_manager.take() {
+            manager.destroy();
+        }
+        if let Some(presentation) = self.presentation.take() {
+            presentation.destroy();
+        }
+        if let Some(manager) = self.tearing_control_manager.take() {
+            manager.destroy();
+        }
+        if let Some(manager) = self.content_type_manager.take() {
+            manager.destroy();
+        }
+        for output in self.outputs.drain(..) {
+            release_output(output);
+        }
+        if let Some(manager) = self.xdg_output_manager.take() {
+            manager.destroy();
+        }
+        // Only has a destructor since version 3
+        if let Some(layer_shell) = self.layer_shell.take().filter(|l| l.version() >= 3) {
+            layer_shell.destroy();
+        }
+        if let Some(seat) = self.seat.take() {
+            release_seat(seat);
+        }
+        // wl_registry has no destructor, we can only forget about it
+        self.globals = GlobalManager::default();
+    }
+
+    pub(crate) fn window(&self, id: WindowId) -> Option<&Window> {
+        self.windows.iter().find(|w| w.id() == id)
+    }
+
+    pub(crate) fn window_mut(&mut self, id: WindowId) -> Option<&mut Window> {
+        self.windows.iter_mut().find(|w| w.id() == id)
+    }
+
+    /// The window `surface` belongs to, if it is one of ours.
+    pub(crate) fn window_for_surface(&self, surface: &WlSurface) -> Option<WindowId> {
+        self.windows
+            .iter()
+            .find(|w| w.surface() == surface)
+            .map(Window::id)
+    }
+
+    /// The window the pointer is over, not counting popups.
+    pub(crate) fn pointer_window(&self) -> Option<WindowId> {
+        let surface = self.seat.as_ref()?.pointer.as_ref()?.surface.as_ref()?;
+        self.window_for_surface(surface)
+    }
+
+    /// The window with keyboard focus, which key presses apply to.
+    pub(crate) fn focused_window(&self) -> Option<WindowId> {
+        let surface = self.seat.as_ref()?.keyboard.as_ref()?.focus.as_ref()?;
+        self.window_for_surface(surface)
+    }
+
+    pub(crate) fn pointer_mut(&mut self) -> Option<&mut Pointer> {
+        self.seat.as_mut()?.pointer.as_mut()
+    }
+
+    pub(crate) fn keyboard_mut(&mut self) -> Option<&mut Keyboard> {
+        self.seat.as_mut()?.keyboard.as_mut()
+    }
+}
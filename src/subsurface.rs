@@ -0,0 +1,338 @@
+//! Subsurfaces: surfaces of their own placed within a window, each with its
+//! own buffers, that the compositor draws on top of it. The `badge` demo
+//! animates a small badge in a corner while the window itself stays still.
+
+use tracing::error;
+use wayland_client::{
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_callback::{self, WlCallback},
+        wl_compositor::WlCompositor,
+        wl_region::WlRegion,
+        wl_shm::WlShm,
+        wl_shm_pool::WlShmPool,
+        wl_subcompositor::WlSubcompositor,
+        wl_subsurface::WlSubsurface,
+        wl_surface::WlSurface,
+    },
+    Connection, Dispatch, Proxy, QueueHandle,
+};
+
+use crate::{
+    buffers::Swapchain,
+    color::Color,
+    error::Error,
+    pixel_buffer::PixelBuffer,
+    pixel_format::PixelFormat,
+    rect::Rect,
+    render,
+    scene::cycle_color,
+    state::{required, AppState},
+    window::{Window, WindowId, WindowState},
+};
+
+/// A surface drawn at an offset within its parent, with its own buffers.
+pub struct Subsurface {
+    surface: WlSurface,
+    subsurface: WlSubsurface,
+    buffers: Swapchain,
+    // In surface pixels
+    size: (usize, usize),
+    // What we last told the compositor
+    sent_position: Option<(i32, i32)>,
+    sent_scale: u32,
+    // Something changed while no buffer was free
+    pub(crate) needs_redraw: bool,
+}
+
+impl Subsurface {
+    /// Creates a `size` large subsurface of `parent`. It is shown once both
+    /// have a buffer and the parent commits.
+    pub fn new<D>(
+        compositor: &WlCompositor,
+        subcompositor: &WlSubcompositor,
+        parent: &WlSurface,
+        size: (usize, usize),
+        format: PixelFormat,
+        qh: &QueueHandle<D>,
+    ) -> Self
+    where
+        D: Dispatch<WlSurface, ()> + Dispatch<WlSubsurface, ()> + 'static,
+    {
+        let surface = compositor.create_surface(qh, ());
+        let subsurface = subcompositor.get_subsurface(&surface, parent, qh, ());
+        Self {
+            surface,
+            subsurface,
+            buffers: Swapchain::new(format),
+            size,
+            sent_position: None,
+            sent_scale: 1,
+            needs_redraw: false,
+        }
+    }
+
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    /// Places the top left corner at `(x, y)` of the parent, in surface
+    /// pixels. Applies on the next commit of the parent, whatever the mode.
+    pub fn set_position(&mut self, x: i32, y: i32) {
+        if self.sent_position != Some((x, y)) {
+            self.subsurface.set_position(x, y);
+            self.sent_position = Some((x, y));
+        }
+    }
+
+    /// In sync mode, the default, commits only apply along with the next
+    /// commit of the parent, so that both change at once. Otherwise they
+    /// apply right away, e.g. to animate on their own.
+    pub fn set_sync(&self, sync: bool) {
+        if sync {
+            self.subsurface.set_sync();
+        } else {
+            self.subsurface.set_desync();
+        }
+    }
+
+    /// The compositor is done reading from `buffer`, if it is one of ours.
+    pub(crate) fn release(&mut self, buffer: &WlBuffer) {
+        self.buffers.release(buffer);
+    }
+
+    /// Draws the whole subsurface at the integer `scale` with `draw` and
+    /// commits it. Only marks it for redrawing if all of its buffers are
+    /// with the compositor.
+    pub(crate) fn present<D>(
+        &mut self,
+        shm: &WlShm,
+        scale: u32,
+        qh: &QueueHandle<D>,
+        draw: impl FnOnce(&mut PixelBuffer),
+    ) -> Result<(), Error>
+    where
+        D: Dispatch<WlShmPool, ()> + Dispatch<WlBuffer, ()> + 'static,
+    {
+        // set_buffer_scale needs wl_surface version 3
+        let scale = if self.surface.version() >= 3 {
+            scale.max(1)
+        } else {
+            1
+        };
+        if self.sent_scale != scale {
+            self.surface.set_buffer_scale(scale as i32);
+            self.sent_scale = scale;
+        }
+
+        let size = (self.size.0 * scale as usize, self.size.1 * scale as usize);
+        let drawn = render::present_whole(&self.surface, &mut self.buffers, size, shm, qh, draw)?;
+        self.needs_redraw = !drawn;
+        Ok(())
+    }
+
+    /// Destroys the role before the surface, as the protocol requires.
+    pub(crate) fn destroy(self) {
+        self.subsurface.destroy();
+        self.surface.destroy();
+    }
+}
+
+const BADGE_SIZE: usize = 48;
+// Between the badge and the edges of the window
+const BADGE_MARGIN: usize = 16;
+const BADGE_BORDER: Color = Color::rgb(0x10, 0x10, 0x10);
+// How long the badge takes to grow and shrink back, in milliseconds
+const PULSE_MS: u32 = 1200;
+
+/// User data of the frame callbacks of a badge: the window it is on.
+pub struct BadgeFrame(WindowId);
+
+/// A small animated badge in the bottom right corner of a window. It
+/// commits on its own, the window isn't drawn again for it.
+pub struct Badge {
+    subsurface: Subsurface,
+    frame_pending: bool,
+    // Timestamp of the last frame callback in milliseconds
+    last_callback: Option<u32>,
+    // Drives the animation
+    time: u32,
+}
+
+impl Badge {
+    pub fn new<D>(
+        compositor: &WlCompositor,
+        subcompositor: &WlSubcompositor,
+        parent: &WlSurface,
+        format: PixelFormat,
+        qh: &QueueHandle<D>,
+    ) -> Self
+    where
+        D: Dispatch<WlSurface, ()> + Dispatch<WlSubsurface, ()> + Dispatch<WlRegion, ()> + 'static,
+    {
+        let subsurface = Subsurface::new(
+            compositor,
+            subcompositor,
+            parent,
+            (BADGE_SIZE, BADGE_SIZE),
+            format,
+            qh,
+        );
+        // Animates whether or not the window commits
+        subsurface.set_sync(false);
+        // Clicks go through to the window
+        let region = compositor.create_region(qh, ());
+        subsurface.surface().set_input_region(Some(&region));
+        region.destroy();
+
+        Self {
+            subsurface,
+            frame_pending: false,
+            last_callback: None,
+            time: 0,
+        }
+    }
+
+    /// Keeps the badge in the corner of a `width`x`height` window. Applies
+    /// on the next commit of the window.
+    pub(crate) fn place(&mut self, (width, height): (usize, usize)) {
+        let x = width.saturating_sub(BADGE_SIZE + BADGE_MARGIN);
+        let y = height.saturating_sub(BADGE_SIZE + BADGE_MARGIN);
+        self.subsurface.set_position(x as i32, y as i32);
+    }
+
+    pub(crate) fn destroy(self) {
+        self.subsurface.destroy();
+    }
+}
+
+/// A square of the color of the test pattern that grows and shrinks.
+fn draw_badge(pixels: &mut PixelBuffer, time: u32) {
+    pixels.fill(BADGE_BORDER);
+
+    let bounds = pixels.bounds();
+    // From a third of the badge to all of it and back
+    let phase = (time % PULSE_MS) as f32 / PULSE_MS as f32;
+    let grown = 1.0 - (phase * 2.0 - 1.0).abs();
+    let inset = (bounds.width as f32 / 3.0 * (1.0 - grown)) as usize + 2;
+    let inner = Rect::new(
+        inset,
+        inset,
+        bounds.width.saturating_sub(2 * inset),
+        bounds.height.saturating_sub(2 * inset),
+    );
+    pixels.fill_rect(inner, cycle_color(time));
+}
+
+/// Draws the next frame of the badge of the window `id`, if it has one
+/// and the compositor is ready for it.
+pub(crate) fn present_badge(state: &mut AppState, id: WindowId) {
+    let qh = &state.queue_handle;
+    let (Some(window), Ok(shm)) = (
+        state.windows.iter_mut().find(|w| w.id() == id),
+        required(&state.shm),
+    ) else {
+        return;
+    };
+    if window.state().contains(WindowState::SUSPENDED) {
+        return;
+    }
+    let scale = window.scale().ceil();
+    let Some(badge) = window.badge.as_mut() else {
+        return;
+    };
+    if badge.frame_pending && !badge.subsurface.needs_redraw {
+        return;
+    }
+
+    // Left pending if no buffer is free, the redraw once one is commits it
+    if !badge.frame_pending {
+        badge.subsurface.surface().frame(qh, BadgeFrame(id));
+        badge.frame_pending = true;
+    }
+    let time = badge.time;
+    if let Err(err) = badge
+        .subsurface
+        .present(shm, scale, qh, |pixels| draw_badge(pixels, time))
+    {
+        error!(%err, "failed to draw the badge");
+    }
+}
+
+/// Hands `buffer` back to the badge it belongs to, if any.
+pub(crate) fn buffer_released(state: &mut AppState, buffer: &WlBuffer) {
+    let mut waiting = Vec::new();
+    for window in &mut state.windows {
+        if let Some(badge) = window.badge.as_mut() {
+            badge.subsurface.release(buffer);
+            if badge.subsurface.needs_redraw {
+                waiting.push(window.id());
+            }
+        }
+    }
+    for id in waiting {
+        present_badge(state, id);
+    }
+}
+
+/// Places the badge of `window`, if it has one, before the window commits.
+pub(crate) fn place_badge(window: &mut Window) {
+    let size = window.size();
+    if let Some(badge) = window.badge.as_mut() {
+        badge.place(size);
+    }
+}
+
+impl Dispatch<WlCallback, BadgeFrame> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlCallback,
+        event: <WlCallback as Proxy>::Event,
+        frame: &BadgeFrame,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { callback_data } = event {
+            let Some(badge) = state.window_mut(frame.0).and_then(|w| w.badge.as_mut()) else {
+                return;
+            };
+            badge.frame_pending = false;
+            let elapsed = badge
+                .last_callback
+                .replace(callback_data)
+                .map_or(0, |last| callback_data.wrapping_sub(last));
+            badge.time = badge.time.wrapping_add(elapsed);
+            present_badge(state, frame.0);
+        }
+    }
+}
+
+impl Dispatch<WlSubcompositor, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlSubcompositor,
+        _event: <WlSubcompositor as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<WlSubsurface, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlSubsurface,
+        _event: <WlSubsurface as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
@@ -0,0 +1,192 @@
+//! SVG images, rasterized with resvg at the size of the buffer rather than
+//! scaled by the compositor, so that they stay sharp at any scale.
+
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use resvg::{tiny_skia, usvg};
+use thiserror::Error;
+
+use crate::{
+    color::{Color, ColorSpace, PremulColor},
+    pixel_buffer::PixelBuffer,
+};
+
+pub const BACKGROUND: Color = Color::rgb(0x30, 0x30, 0x30);
+
+#[derive(Debug, Error)]
+pub enum ImageError {
+    /// Told apart by the extension
+    #[error("{0} is not an SVG, GIF, PNG or WebP file")]
+    Unsupported(PathBuf),
+    #[error("failed to read {path}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to parse {path}: {source}")]
+    Parse { path: PathBuf, source: usvg::Error },
+    #[error("failed to decode {path}: {source}")]
+    Decode {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+}
+
+/// A parsed SVG document, drawn as large as fits in the window.
+#[derive(Clone)]
+pub struct SvgImage {
+    tree: Arc<usvg::Tree>,
+}
+
+impl SvgImage {
+    pub fn open(path: &Path) -> Result<Self, ImageError> {
+        let is_svg = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"));
+        if !is_svg {
+            return Err(ImageError::Unsupported(path.to_owned()));
+        }
+
+        let data = fs::read(path).map_err(|source| ImageError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        let mut options = usvg::Options {
+            // For the images it links to
+            resources_dir: path.parent().map(Path::to_owned),
+            ..usvg::Options::default()
+        };
+        // For the text in it
+        options.fontdb_mut().load_system_fonts();
+        Self::parse(&data, &options).map_err(|source| ImageError::Parse {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    fn parse(data: &[u8], options: &usvg::Options) -> Result<Self, usvg::Error> {
+        Ok(Self {
+            tree: Arc::new(usvg::Tree::from_data(data, options)?),
+        })
+    }
+
+    /// Draws the image into `pixels`, scaled to fit and centered. Drawn
+    /// from the outlines each time, at the size of the buffer.
+    pub(crate) fn draw(&self, pixels: &mut PixelBuffer) {
+        let bounds = pixels.bounds();
+        let (width, height) = (bounds.width as u32, bounds.height as u32);
+        let size = self.tree.size();
+        let (scale, dx, dy) = fit((size.width(), size.height()), (bounds.width, bounds.height));
+        let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(dx, dy);
+
+        let format = pixels.format();
+        if let Some(data) = pixels.as_mut_slice() {
+            // Straight into the buffer, and only its byte order to fix up
+            // afterwards rather than a copy to make
+            let Some(mut pixmap) =
+                tiny_skia::PixmapMut::from_bytes(bytemuck::cast_slice_mut(data), width, height)
+            else {
+                return;
+            };
+            let background = BACKGROUND;
+            pixmap.fill(tiny_skia::Color::from_rgba8(
+                background.r,
+                background.g,
+                background.b,
+                background.a,
+            ));
+            resvg::render(&self.tree, transform, &mut pixmap);
+            for pixel in data {
+                // tiny-skia keeps premultiplied R, G, B, A bytes
+                let [r, g, b, a] = pixel.to_ne_bytes();
+                *pixel = format.pack(PremulColor { r, g, b, a });
+            }
+            return;
+        }
+
+        // Rotated or partly redrawn, through a pixmap of its own
+        pixels.fill(BACKGROUND);
+        for (i, color) in self
+            .rasterize(bounds.width, bounds.height)
+            .into_iter()
+            .enumerate()
+        {
+            if color.a == 0 {
+                continue;
+            }
+            let (x, y) = (i % bounds.width, i / bounds.width);
+            pixels.blend_pixel(x, y, color.unpremultiply(), ColorSpace::Srgb);
+        }
+    }
+
+    /// The image scaled to fit in `width`x`height` and centered, as rows of
+    /// pixels on a transparent background.
+    pub(crate) fn rasterize(&self, width: usize, height: usize) -> Vec<PremulColor> {
+        let size = self.tree.size();
+        let (scale, dx, dy) = fit((size.width(), size.height()), (width, height));
+        let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(dx, dy);
+        let Some(mut pixmap) = tiny_skia::Pixmap::new(width as u32, height as u32) else {
+            return vec![PremulColor::default(); width * height];
+        };
+        resvg::render(&self.tree, transform, &mut pixmap.as_mut());
+
+        pixmap
+            .pixels()
+            .iter()
+            .map(|pixel| PremulColor {
+                r: pixel.red(),
+                g: pixel.green(),
+                b: pixel.blue(),
+                a: pixel.alpha(),
+            })
+            .collect()
+    }
+}
+
+/// How much an image of `size` is scaled to fit in `width`x`height`, and
+/// where it goes to be centered.
+pub(crate) fn fit(
+    (image_width, image_height): (f32, f32),
+    (width, height): (usize, usize),
+) -> (f32, f32, f32) {
+    let (width, height) = (width as f32, height as f32);
+    let scale = (width / image_width).min(height / image_height);
+    (
+        scale,
+        (width - image_width * scale) / 2.0,
+        (height - image_height * scale) / 2.0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pixel_format::PixelFormat;
+
+    #[test]
+    fn images_fit_in_the_middle() {
+        assert_eq!(fit((100.0, 50.0), (400, 400)), (4.0, 0.0, 100.0));
+        assert_eq!(fit((100.0, 100.0), (300, 150)), (1.5, 75.0, 0.0));
+
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="2" height="2">
+            <rect width="2" height="2" fill="red"/>
+        </svg>"#;
+        let image = SvgImage::parse(svg, &usvg::Options::default()).unwrap();
+        let format = PixelFormat::Argb8888;
+        let mut data = [0; 8 * 4];
+        image.draw(&mut PixelBuffer::new(&mut data, 8, 4, 8 * 4, format));
+        let red = format.pack(Color::rgb(0xFF, 0x00, 0x00).premultiply());
+        let background = format.pack(BACKGROUND.premultiply());
+        let row = [
+            background, background, red, red, red, red, background, background,
+        ];
+        assert_eq!(data[..8], row);
+
+        // The same through a pixmap of its own, with padding on the rows
+        let mut padded = [0; 10 * 4];
+        image.draw(&mut PixelBuffer::new(&mut padded, 8, 4, 10 * 4, format));
+        assert_eq!(padded[..8], row);
+    }
+}
@@ -0,0 +1,74 @@
+//! A tiny sd_notify(3) client so the app can run as a systemd service with
+//! `Type=notify` and `WatchdogSec=`. Everything here is a no-op when we are not
+//! started by systemd (i.e. `NOTIFY_SOCKET` is not set).
+
+use std::{
+    env, io,
+    os::unix::{ffi::OsStrExt, net::UnixDatagram},
+    process,
+    time::Duration,
+};
+
+#[cfg(target_os = "linux")]
+use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+use tracing::{debug, warn};
+
+fn notify(message: &str) -> io::Result<bool> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        // Abstract socket namespace, only available on Linux
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            let addr = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(message.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(message.as_bytes(), &path)?;
+        }
+    }
+
+    Ok(true)
+}
+
+fn notify_or_warn(message: &str) {
+    match notify(message) {
+        Ok(true) => debug!(?message, "sent sd_notify message"),
+        Ok(false) => {}
+        Err(err) => warn!(?message, ?err, "failed to send sd_notify message"),
+    }
+}
+
+/// Tells systemd that the start-up is finished.
+pub fn notify_ready() {
+    notify_or_warn("READY=1");
+}
+
+/// Keep-alive ping for `WatchdogSec=`.
+pub fn notify_watchdog() {
+    notify_or_warn("WATCHDOG=1");
+}
+
+/// Returns the watchdog timeout configured for this process, if any.
+///
+/// systemd recommends pinging at half of this interval.
+pub fn watchdog_interval() -> Option<Duration> {
+    // WATCHDOG_PID is optional, but when it is set the watchdog is meant for
+    // that process only.
+    if let Some(pid) = env::var_os("WATCHDOG_PID") {
+        if pid.to_str()?.parse::<u32>().ok()? != process::id() {
+            return None;
+        }
+    }
+
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    if usec == 0 {
+        return None;
+    }
+
+    Some(Duration::from_micros(usec))
+}
@@ -0,0 +1,263 @@
+//! Text drawn with the system fonts: fontconfig picks the file, fontdue
+//! rasterizes the glyphs.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use thiserror::Error;
+
+use crate::{
+    color::{Color, ColorSpace},
+    pixel_buffer::PixelBuffer,
+    rect::Rect,
+};
+
+/// The family asked for when any readable font will do
+pub const SANS: &str = "sans-serif";
+
+#[derive(Debug, Error)]
+pub enum FontError {
+    /// fc-match is missing or knows no font for the family
+    #[error("no font found for {0:?}")]
+    NotFound(String),
+    #[error("failed to read {path}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to parse {path}: {reason}")]
+    Parse { path: PathBuf, reason: &'static str },
+}
+
+/// A font file, ready to draw text at any size.
+pub struct Font {
+    font: fontdue::Font,
+    path: PathBuf,
+}
+
+impl Font {
+    pub fn load(path: &Path) -> Result<Self, FontError> {
+        let read_error = |source| FontError::Read {
+            path: path.to_owned(),
+            source,
+        };
+        let data = fs::read(path).map_err(read_error)?;
+        let font = fontdue::Font::from_bytes(data, fontdue::FontSettings::default()).map_err(
+            |reason| FontError::Parse {
+                path: path.to_owned(),
+                reason,
+            },
+        )?;
+
+        Ok(Self {
+            font,
+            path: path.to_owned(),
+        })
+    }
+
+    /// The font fontconfig picks for `pattern`, e.g. `DejaVu Sans:bold`.
+    pub fn find(pattern: &str) -> Result<Self, FontError> {
+        let output = Command::new("fc-match")
+            .args(["--format=%{file}", pattern])
+            .output()
+            .ok()
+            .filter(|output| output.status.success() && !output.stdout.is_empty())
+            .ok_or_else(|| FontError::NotFound(pattern.to_owned()))?;
+        let path = String::from_utf8_lossy(&output.stdout);
+        Self::load(Path::new(path.trim()))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The full name of the font, e.g. `DejaVu Sans Bold`.
+    pub fn name(&self) -> Option<&str> {
+        self.font.name()
+    }
+
+    /// How far apart lines of text `size` pixels tall are.
+    pub fn line_height(&self, size: f32) -> usize {
+        self.font
+            .horizontal_line_metrics(size)
+            .map_or(size, |metrics| metrics.new_line_size)
+            .ceil() as usize
+    }
+
+    /// How wide `text` is at `size` pixels, on a single line.
+    pub fn width(&self, text: &str, size: f32) -> usize {
+        self.line_width(text, size, false)
+    }
+
+    fn line_width(&self, text: &str, size: f32, grid_fit: bool) -> usize {
+        self.layout(text, size, grid_fit)
+            .last()
+            .map_or(0.0, |(x, c)| x + self.advance(*c, size, grid_fit))
+            .ceil() as usize
+    }
+
+    /// How far the pen moves on after `c`.
+    fn advance(&self, c: char, size: f32, grid_fit: bool) -> f32 {
+        let advance = self.font.metrics(c, size).advance_width;
+        if grid_fit {
+            advance.round()
+        } else {
+            advance
+        }
+    }
+
+    /// Where each character of `text` starts on the line, along with it.
+    /// Grid fitted, every glyph starts on a whole pixel.
+    fn layout(&self, text: &str, size: f32, grid_fit: bool) -> Vec<(f32, char)> {
+        let mut x = 0.0;
+        let mut previous = None;
+        let mut glyphs = Vec::new();
+        for c in text.chars() {
+            if let Some(kern) = previous.and_then(|p| self.font.horizontal_kern(p, c, size)) {
+                x += if grid_fit { kern.round() } else { kern };
+            }
+            glyphs.push((x, c));
+            x += self.advance(c, size, grid_fit);
+            previous = Some(c);
+        }
+        glyphs
+    }
+
+    /// Draws `text` on a single line `size` pixels tall, its top left
+    /// corner at `x`, `y`. Returns how wide it is. Whatever falls outside
+    /// of `pixels` is left out.
+    pub fn draw(
+        &self,
+        pixels: &mut PixelBuffer,
+        text: &str,
+        position: (usize, usize),
+        size: f32,
+        color: Color,
+    ) -> usize {
+        self.draw_line(pixels, text, position, size, color, false)
+    }
+
+    /// Like [`Font::draw`], optionally grid fitted. fontdue has no hinter,
+    /// this is the closest to it: the size and glyph positions are
+    /// rounded to whole pixels, so that stems line up with the pixels at
+    /// the cost of uneven spacing.
+    pub fn draw_line(
+        &self,
+        pixels: &mut PixelBuffer,
+        text: &str,
+        (x, y): (usize, usize),
+        size: f32,
+        color: Color,
+        grid_fit: bool,
+    ) -> usize {
+        let size = if grid_fit { size.round() } else { size };
+        let bounds = pixels.bounds();
+        let ascent = self
+            .font
+            .horizontal_line_metrics(size)
+            .map_or(size, |metrics| metrics.ascent);
+        let baseline = y as f32 + ascent;
+
+        for (offset, c) in self.layout(text, size, grid_fit) {
+            let (metrics, coverage) = self.font.rasterize(c, size);
+            let left = (x as f32 + offset).round() as i64 + metrics.xmin as i64;
+            let top = (baseline - metrics.ymin as f32 - metrics.height as f32).round() as i64;
+            for (i, alpha) in coverage.iter().enumerate() {
+                let (px, py) = (
+                    left + (i % metrics.width) as i64,
+                    top + (i / metrics.width) as i64,
+                );
+                let inside = px >= 0
+                    && py >= 0
+                    && (px as usize) < bounds.width
+                    && (py as usize) < bounds.height;
+                if *alpha == 0 || !inside {
+                    continue;
+                }
+                let alpha = (color.a as u32 * *alpha as u32 / 255) as u8;
+                let color = Color::rgba(color.r, color.g, color.b, alpha);
+                pixels.blend_pixel(px as usize, py as usize, color, ColorSpace::Srgb);
+            }
+        }
+        self.line_width(text, size, grid_fit)
+    }
+
+    /// Draws `text` wrapped to lines as wide as `rect`, the lines that
+    /// don't fit in it left out. Returns how tall what was drawn is.
+    pub fn draw_paragraph(
+        &self,
+        pixels: &mut PixelBuffer,
+        text: &str,
+        rect: Rect,
+        size: f32,
+        color: Color,
+        grid_fit: bool,
+    ) -> usize {
+        let size = if grid_fit { size.round() } else { size };
+        let line_height = self.line_height(size);
+        let lines = wrap(text, rect.width, |line| {
+            self.line_width(line, size, grid_fit)
+        });
+
+        let mut y = rect.y;
+        for line in lines {
+            if y + line_height > rect.bottom() {
+                break;
+            }
+            self.draw_line(pixels, line, (rect.x, y), size, color, grid_fit);
+            y += line_height;
+        }
+        y - rect.y
+    }
+}
+
+/// Breaks `text` into lines at most `max_width` wide as measured by
+/// `width`, between words. A word too long for a line gets one of its own.
+pub fn wrap(text: &str, max_width: usize, width: impl Fn(&str) -> usize) -> Vec<&str> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut start = None;
+        let mut end = 0;
+        // Along with where they start in the paragraph
+        let words = paragraph
+            .split_whitespace()
+            .map(|word| (word.as_ptr() as usize - paragraph.as_ptr() as usize, word));
+        for (i, word) in words {
+            let line_start = *start.get_or_insert(i);
+            let candidate = &paragraph[line_start..i + word.len()];
+            if end > line_start && width(candidate) > max_width {
+                lines.push(&paragraph[line_start..end]);
+                start = Some(i);
+            }
+            end = i + word.len();
+        }
+        match start {
+            Some(start) => lines.push(&paragraph[start..end]),
+            // Keeps empty lines between paragraphs
+            None => lines.push(""),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_between_words() {
+        let width = |line: &str| line.chars().count();
+        assert_eq!(
+            wrap("the quick brown fox jumps", 10, width),
+            ["the quick", "brown fox", "jumps"]
+        );
+        assert_eq!(
+            wrap("a extraordinarily b", 5, width),
+            ["a", "extraordinarily", "b"]
+        );
+        assert_eq!(
+            wrap("one\n\ntwo  words", 20, width),
+            ["one", "", "two  words"]
+        );
+    }
+}
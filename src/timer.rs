@@ -0,0 +1,221 @@
+//! One-shot and repeating timers driven by the main loop.
+//!
+//! The loop asks [`Timers::next_timeout`] how long it may block waiting for
+//! Wayland events, and calls [`Timers::dispatch`] afterwards to run whatever
+//! became due in the meantime.
+
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use tracing::trace;
+
+type Callback<S> = Box<dyn FnMut(&mut S)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+struct Timer<S> {
+    id: TimerId,
+    deadline: Instant,
+    // Repeating timers are re-armed with this after firing
+    interval: Option<Duration>,
+    callback: Callback<S>,
+}
+
+pub struct Timers<S> {
+    next_id: u64,
+    timers: Vec<Timer<S>>,
+    // Timers cancelled while they are being dispatched, from inside their
+    // own callback or that of another timer due at the same time
+    cancelled: HashSet<TimerId>,
+}
+
+impl<S> Default for Timers<S> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            timers: Vec::new(),
+            cancelled: HashSet::new(),
+        }
+    }
+}
+
+impl<S> Timers<S> {
+    /// Runs `callback` once, `delay` from now.
+    pub fn after(&mut self, delay: Duration, callback: impl FnMut(&mut S) + 'static) -> TimerId {
+        self.insert(delay, None, Box::new(callback))
+    }
+
+    /// Runs `callback` every `interval`, starting one `interval` from now.
+    pub fn every(&mut self, interval: Duration, callback: impl FnMut(&mut S) + 'static) -> TimerId {
+        self.insert(interval, Some(interval), Box::new(callback))
+    }
+
+    pub fn cancel(&mut self, id: TimerId) {
+        let len = self.timers.len();
+        self.timers.retain(|t| t.id != id);
+
+        // Not armed, it may be running right now
+        if self.timers.len() == len {
+            self.cancelled.insert(id);
+        }
+    }
+
+    /// How long until the next timer is due, `None` if there are no timers.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        self.timers
+            .iter()
+            .map(|t| t.deadline)
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Runs every timer that is due. `timers` gets the `Timers` back out of
+    /// the state, since the callbacks need the whole state mutably.
+    pub fn dispatch(state: &mut S, timers: fn(&mut S) -> &mut Self) {
+        let now = Instant::now();
+
+        let this = timers(state);
+        let (due, pending) = this.timers.drain(..).partition(|t| t.deadline <= now);
+        this.timers = pending;
+
+        for mut timer in due {
+            // Cancelled by one of the callbacks that ran before
+            if timers(state).cancelled.remove(&timer.id) {
+                continue;
+            }
+            trace!(id = ?timer.id, "timer fired");
+            (timer.callback)(state);
+
+            let this = timers(state);
+            if this.cancelled.remove(&timer.id) {
+                continue;
+            }
+
+            if let Some(interval) = timer.interval {
+                // Don't try to catch up on missed ticks
+                timer.deadline = (timer.deadline + interval).max(now);
+                this.timers.push(timer);
+            }
+        }
+
+        timers(state).cancelled.clear();
+    }
+
+    fn insert(
+        &mut self,
+        delay: Duration,
+        interval: Option<Duration>,
+        callback: Callback<S>,
+    ) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+
+        self.timers.push(Timer {
+            id,
+            deadline: Instant::now() + delay,
+            interval,
+            callback,
+        });
+
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct State {
+        timers: Timers<State>,
+        fired: Vec<&'static str>,
+    }
+
+    fn dispatch(state: &mut State) {
+        Timers::dispatch(state, |state| &mut state.timers);
+    }
+
+    #[test]
+    fn one_shot_timers_fire_once() {
+        let mut state = State::default();
+        state.timers.after(Duration::ZERO, |s| s.fired.push("now"));
+        state
+            .timers
+            .after(Duration::from_secs(60), |s| s.fired.push("later"));
+
+        dispatch(&mut state);
+        dispatch(&mut state);
+        assert_eq!(state.fired, ["now"]);
+    }
+
+    #[test]
+    fn repeating_timers_fire_until_cancelled() {
+        let mut state = State::default();
+        let id = state.timers.every(Duration::ZERO, |s| s.fired.push("tick"));
+
+        dispatch(&mut state);
+        dispatch(&mut state);
+        assert_eq!(state.fired, ["tick", "tick"]);
+
+        state.timers.cancel(id);
+        dispatch(&mut state);
+        assert_eq!(state.fired.len(), 2);
+        assert_eq!(state.timers.next_timeout(), None);
+    }
+
+    #[test]
+    fn timers_can_cancel_themselves() {
+        let mut state = State::default();
+        let id = Rc::new(RefCell::new(None));
+        let own_id = id.clone();
+        let timer = state.timers.every(Duration::ZERO, move |s| {
+            s.fired.push("once");
+            s.timers.cancel(own_id.borrow().unwrap());
+        });
+        *id.borrow_mut() = Some(timer);
+
+        dispatch(&mut state);
+        dispatch(&mut state);
+        assert_eq!(state.fired, ["once"]);
+        assert_eq!(state.timers.next_timeout(), None);
+    }
+
+    #[test]
+    fn cancelling_another_due_timer_keeps_it_from_firing() {
+        let mut state = State::default();
+        let other = Rc::new(RefCell::new(None));
+        let to_cancel = other.clone();
+        state.timers.after(Duration::ZERO, move |s| {
+            s.fired.push("first");
+            s.timers.cancel(to_cancel.borrow().unwrap());
+        });
+        let id = state
+            .timers
+            .every(Duration::ZERO, |s| s.fired.push("second"));
+        *other.borrow_mut() = Some(id);
+
+        dispatch(&mut state);
+        dispatch(&mut state);
+        assert_eq!(state.fired, ["first"]);
+    }
+
+    #[test]
+    fn next_timeout_is_that_of_the_earliest_timer() {
+        let mut state = State::default();
+        assert_eq!(state.timers.next_timeout(), None);
+
+        state.timers.after(Duration::from_secs(60), |_| {});
+        let id = state.timers.every(Duration::from_secs(10), |_| {});
+        let timeout = state.timers.next_timeout().unwrap();
+        assert!(timeout <= Duration::from_secs(10));
+        assert!(timeout > Duration::from_secs(9));
+
+        state.timers.cancel(id);
+        assert!(state.timers.next_timeout().unwrap() > Duration::from_secs(59));
+    }
+}
@@ -0,0 +1,157 @@
+//! Buffers drawn rotated or flipped, which the compositor turns back with
+//! `wl_surface.set_buffer_transform`. Saves it the work on a rotated output.
+
+use wayland_client::protocol::wl_output::Transform;
+
+use crate::{output::is_rotated, rect::Rect};
+
+/// Parses the name of a transform: `normal`, `90`, `180`, `270`, `flipped`
+/// or `flipped-90` and so on.
+pub fn parse_transform(name: &str) -> Option<Transform> {
+    match name {
+        "normal" => Some(Transform::Normal),
+        "90" => Some(Transform::_90),
+        "180" => Some(Transform::_180),
+        "270" => Some(Transform::_270),
+        "flipped" => Some(Transform::Flipped),
+        "flipped-90" => Some(Transform::Flipped90),
+        "flipped-180" => Some(Transform::Flipped180),
+        "flipped-270" => Some(Transform::Flipped270),
+        _ => None,
+    }
+}
+
+/// The size of a buffer holding `size` worth of content drawn with
+/// `transform`.
+pub fn transform_size((width, height): (usize, usize), transform: Transform) -> (usize, usize) {
+    if is_rotated(transform) {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+/// The transform that undoes `transform`.
+pub fn inverse(transform: Transform) -> Transform {
+    match transform {
+        Transform::_90 => Transform::_270,
+        Transform::_270 => Transform::_90,
+        // Flips, and turning by 180 degrees, undo themselves
+        transform => transform,
+    }
+}
+
+/// Where `rect` of content of `size` ends up in a buffer drawn with
+/// `transform`.
+pub fn transform_rect(rect: Rect, transform: Transform, (width, height): (usize, usize)) -> Rect {
+    // Measured from the opposite edges
+    let left = rect.x;
+    let top = rect.y;
+    let right = width - rect.right();
+    let bottom = height - rect.bottom();
+    let (w, h) = (rect.width, rect.height);
+
+    match transform {
+        Transform::Flipped => Rect::new(right, top, w, h),
+        Transform::_90 => Rect::new(top, right, h, w),
+        Transform::Flipped90 => Rect::new(top, left, h, w),
+        Transform::_180 => Rect::new(right, bottom, w, h),
+        Transform::Flipped180 => Rect::new(left, bottom, w, h),
+        Transform::_270 => Rect::new(bottom, left, h, w),
+        Transform::Flipped270 => Rect::new(bottom, right, h, w),
+        _ => rect,
+    }
+}
+
+/// Turns `pixels`, rows of a `width`x`height` buffer drawn with
+/// `transform`, back the way the compositor shows them. Returns the width
+/// and height of the result along with it.
+pub fn untransform_pixels(
+    pixels: &[u32],
+    (width, height): (usize, usize),
+    transform: Transform,
+) -> (usize, usize, Vec<u32>) {
+    let size = transform_size((width, height), transform);
+    let mut content = Vec::with_capacity(width * height);
+    for y in 0..size.1 {
+        for x in 0..size.0 {
+            let at = transform_rect(Rect::new(x, y, 1, 1), transform, size);
+            content.push(pixels[at.y * width + at.x]);
+        }
+    }
+    (size.0, size.1, content)
+}
+
+/// The next transform turning by another 90 degrees, flips left alone.
+pub fn rotate(transform: Transform) -> Transform {
+    match transform {
+        Transform::Normal => Transform::_90,
+        Transform::_90 => Transform::_180,
+        Transform::_180 => Transform::_270,
+        Transform::_270 => Transform::Normal,
+        Transform::Flipped => Transform::Flipped90,
+        Transform::Flipped90 => Transform::Flipped180,
+        Transform::Flipped180 => Transform::Flipped270,
+        _ => Transform::Flipped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Transform; 8] = [
+        Transform::Normal,
+        Transform::_90,
+        Transform::_180,
+        Transform::_270,
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ];
+
+    #[test]
+    fn rects_map_back_with_the_inverse() {
+        let size = (100, 50);
+        let rect = Rect::new(10, 5, 20, 30);
+        for transform in ALL {
+            let buffer_size = transform_size(size, transform);
+            let mapped = transform_rect(rect, transform, size);
+            assert!(Rect::new(0, 0, buffer_size.0, buffer_size.1).contains_rect(&mapped));
+            assert_eq!(
+                transform_rect(mapped, inverse(transform), buffer_size),
+                rect,
+                "{transform:?}"
+            );
+        }
+
+        // The top left corner ends up at the bottom left, turned
+        // counter-clockwise
+        assert_eq!(
+            transform_rect(Rect::new(0, 0, 1, 1), Transform::_90, size),
+            Rect::new(0, 99, 1, 1)
+        );
+        assert_eq!(parse_transform("flipped-270"), Some(Transform::Flipped270));
+        assert_eq!(parse_transform("45"), None);
+    }
+
+    #[test]
+    fn pixels_turn_back() {
+        let size = (3, 2);
+        let content: Vec<u32> = (0..6).collect();
+        for transform in ALL {
+            let (width, height) = transform_size(size, transform);
+            let mut buffer = vec![0; width * height];
+            for (i, pixel) in content.iter().enumerate() {
+                let at = transform_rect(Rect::new(i % 3, i / 3, 1, 1), transform, size);
+                buffer[at.y * width + at.x] = *pixel;
+            }
+            assert_eq!(
+                untransform_pixels(&buffer, (width, height), transform),
+                (3, 2, content.clone()),
+                "{transform:?}"
+            );
+        }
+    }
+}
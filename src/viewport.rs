@@ -0,0 +1,158 @@
+//! wp_viewporter: cropping and scaling buffers in the compositor, without
+//! drawing them again. The `pan` demo pans and zooms over an image that is
+//! only drawn once.
+
+use xkbcommon_dl::keysyms;
+
+use crate::{
+    color::Color, pixel_buffer::PixelBuffer, rect::Rect, render::present_if_needed, scene::Scene,
+    state::AppState,
+};
+
+/// The size the image of the demo is drawn at, whatever the window size.
+pub const IMAGE_SIZE: (usize, usize) = (2048, 2048);
+// Of the squares the image is made of
+const CELL_SIZE: usize = 128;
+pub(crate) const GRID: Color = Color::rgb(0x20, 0x20, 0x20);
+
+const MAX_ZOOM: f64 = 8.0;
+const ZOOM_STEP: f64 = 1.25;
+// How far the arrow keys move the image, in window pixels
+const PAN_STEP: f64 = 64.0;
+
+/// The part of the buffer a window shows, in buffer pixels. The compositor
+/// scales it to the window size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Source {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Which part of the image the demo shows, and how large.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PanZoom {
+    /// The point of the image in the middle of the window
+    pub center: (f64, f64),
+    /// Window pixels per image pixel
+    pub zoom: f64,
+}
+
+impl Default for PanZoom {
+    fn default() -> Self {
+        Self {
+            center: (IMAGE_SIZE.0 as f64 / 2.0, IMAGE_SIZE.1 as f64 / 2.0),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl PanZoom {
+    /// The part of the image a `width`x`height` window shows. Never zoomed
+    /// out further than the whole image, nor past its edges.
+    pub fn source(&self, (width, height): (usize, usize)) -> Source {
+        let (image_width, image_height) = (IMAGE_SIZE.0 as f64, IMAGE_SIZE.1 as f64);
+        let (width, height) = (width.max(1) as f64, height.max(1) as f64);
+        let zoom = self
+            .zoom
+            .max(width / image_width)
+            .max(height / image_height);
+
+        let (source_width, source_height) = (width / zoom, height / zoom);
+        let x = (self.center.0 - source_width / 2.0).clamp(0.0, image_width - source_width);
+        let y = (self.center.1 - source_height / 2.0).clamp(0.0, image_height - source_height);
+        Source {
+            x,
+            y,
+            width: source_width,
+            height: source_height,
+        }
+    }
+
+    /// Moves the image by `(dx, dy)` window pixels.
+    pub fn pan(&mut self, dx: f64, dy: f64) {
+        self.center.0 = (self.center.0 + dx / self.zoom).clamp(0.0, IMAGE_SIZE.0 as f64);
+        self.center.1 = (self.center.1 + dy / self.zoom).clamp(0.0, IMAGE_SIZE.1 as f64);
+    }
+
+    /// Zooms in by `factor`, or out if it is below 1. How far out depends
+    /// on the window size, see `source`.
+    pub fn zoom_by(&mut self, factor: f64) {
+        self.zoom = (self.zoom * factor).clamp(1.0 / MAX_ZOOM, MAX_ZOOM);
+    }
+}
+
+/// Draws the image of the demo: a grid of squares, each its own color, so
+/// that it is easy to tell where we are.
+pub(crate) fn draw_image(pixels: &mut PixelBuffer) {
+    pixels.fill(GRID);
+
+    let (columns, rows) = (IMAGE_SIZE.0 / CELL_SIZE, IMAGE_SIZE.1 / CELL_SIZE);
+    for row in 0..rows {
+        for column in 0..columns {
+            let blue = if (row + column) % 2 == 0 { 0xC0 } else { 0x60 };
+            let color = Color::rgb(
+                (column * 255 / columns) as u8,
+                (row * 255 / rows) as u8,
+                blue,
+            );
+            let (x, y) = (column * CELL_SIZE, row * CELL_SIZE);
+            pixels.fill_rect(Rect::new(x + 1, y + 1, CELL_SIZE - 2, CELL_SIZE - 2), color);
+        }
+    }
+}
+
+/// Pans with the arrow keys, zooms with plus and minus and goes back to
+/// the start with 0. Returns whether `keysym` was one of those. Only the
+/// viewport changes, the image isn't drawn again.
+pub(crate) fn handle_key(state: &mut AppState, keysym: u32) -> bool {
+    let Scene::Pan(pan) = &mut state.scene else {
+        return false;
+    };
+
+    match keysym {
+        keysyms::Left => pan.pan(-PAN_STEP, 0.0),
+        keysyms::Right => pan.pan(PAN_STEP, 0.0),
+        keysyms::Up => pan.pan(0.0, -PAN_STEP),
+        keysyms::Down => pan.pan(0.0, PAN_STEP),
+        keysyms::plus | keysyms::equal | keysyms::KP_Add => pan.zoom_by(ZOOM_STEP),
+        keysyms::minus | keysyms::KP_Subtract => pan.zoom_by(1.0 / ZOOM_STEP),
+        keysyms::_0 => *pan = PanZoom::default(),
+        _ => return false,
+    }
+
+    for window in &mut state.windows {
+        window.request_redraw();
+    }
+    present_if_needed(state);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_stays_within_the_image() {
+        let pan = PanZoom::default();
+        let source = pan.source((500, 250));
+        assert_eq!(source.width, 500.0);
+        assert_eq!(source.x, 1024.0 - 250.0);
+
+        // Can't zoom out past the whole image
+        let mut pan = PanZoom {
+            center: (0.0, 0.0),
+            zoom: 1.0,
+        };
+        pan.zoom_by(0.01);
+        let source = pan.source((1024, 512));
+        assert_eq!((source.x, source.y), (0.0, 0.0));
+        assert_eq!((source.width, source.height), (2048.0, 1024.0));
+
+        pan.zoom = 2.0;
+        pan.pan(4096.0, 0.0);
+        let source = pan.source((100, 100));
+        assert_eq!(source.x, 2048.0 - 50.0);
+    }
+}
@@ -0,0 +1,269 @@
+//! Detects a main loop that stopped making progress.
+//!
+//! A background thread looks at what the main thread reported last. We are
+//! considered stalled when we have been busy handling events for too long
+//! (a deadlock or a runaway handler on our side), or when we have been
+//! waiting for the compositor to answer during start-up for too long (the
+//! compositor is frozen). Idling in the main loop is fine.
+//!
+//! Configured through the environment:
+//! - `RUST_WAYLAND_STALL_TIMEOUT`: seconds before a stall is reported,
+//!   `0` disables the watchdog. Defaults to 10.
+//! - `RUST_WAYLAND_STALL_ABORT`: if set, abort (and dump core) on a stall.
+
+use std::{
+    env,
+    os::fd::{AsRawFd, BorrowedFd, OwnedFd},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use tracing::{error, warn};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Phase {
+    Startup,
+    Roundtrip,
+    WaitingForConfigure,
+    Running,
+}
+
+impl Phase {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Startup,
+            1 => Self::Roundtrip,
+            2 => Self::WaitingForConfigure,
+            _ => Self::Running,
+        }
+    }
+
+    /// Whether we are blocked on the compositor in this phase
+    fn awaits_compositor(self) -> bool {
+        matches!(self, Self::Roundtrip | Self::WaitingForConfigure)
+    }
+}
+
+/// What the main thread reported last, shared with the watchdog thread.
+struct Shared {
+    epoch: Instant,
+    timeout: Duration,
+    abort: bool,
+    phase: AtomicU8,
+    phase_since: AtomicU64,
+    // Milliseconds since `epoch` + 1 when we started handling events, 0 while
+    // waiting for new ones.
+    busy_since: AtomicU64,
+    reported: AtomicBool,
+}
+
+// That of the running watchdog, replaced on each run so that a new
+// connection starts over
+static CURRENT: Mutex<Option<Arc<Shared>>> = Mutex::new(None);
+
+impl Shared {
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+
+    fn check(&self, fd: &OwnedFd) {
+        let now = self.now();
+        let timeout = self.timeout.as_millis() as u64;
+        let phase = Phase::from_u8(self.phase.load(Ordering::Relaxed));
+        let phase_since = self.phase_since.load(Ordering::Relaxed);
+        let busy_since = self.busy_since.load(Ordering::Relaxed);
+
+        let busy_for = (busy_since != 0).then(|| now.saturating_sub(busy_since - 1));
+        let stalled = busy_for.is_some_and(|busy_for| busy_for > timeout)
+            || (phase.awaits_compositor() && now.saturating_sub(phase_since) > timeout);
+
+        if !stalled {
+            self.reported.store(false, Ordering::Relaxed);
+            return;
+        }
+
+        // Only report each stall once
+        if self.reported.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        error!(
+            ?phase,
+            phase_ms = now.saturating_sub(phase_since),
+            busy_ms = ?busy_for,
+            events_pending = events_pending(fd),
+            "main loop stalled"
+        );
+
+        if self.abort {
+            process::abort();
+        }
+    }
+}
+
+fn events_pending(fd: &OwnedFd) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    unsafe { libc::poll(&mut pollfd, 1, 0) > 0 }
+}
+
+/// The running watchdog thread, stopped when dropped.
+pub struct Watchdog {
+    shared: Arc<Shared>,
+    // Dropping it wakes the thread up to stop
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let mut current = CURRENT.lock().unwrap();
+        if current
+            .as_ref()
+            .is_some_and(|shared| Arc::ptr_eq(shared, &self.shared))
+        {
+            *current = None;
+        }
+        drop(current);
+
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Starts the watchdog thread for the connection behind `fd`, with a copy
+/// of it so that it never polls a closed or reused fd. None if disabled or
+/// if it couldn't start.
+pub fn spawn(fd: BorrowedFd<'_>) -> Option<Watchdog> {
+    let timeout = match env::var("RUST_WAYLAND_STALL_TIMEOUT") {
+        Ok(secs) => match secs.parse::<u64>() {
+            Ok(0) => return None,
+            Ok(secs) => Duration::from_secs(secs),
+            Err(err) => {
+                warn!(
+                    ?secs,
+                    ?err,
+                    "invalid RUST_WAYLAND_STALL_TIMEOUT, using default"
+                );
+                DEFAULT_TIMEOUT
+            }
+        },
+        Err(_) => DEFAULT_TIMEOUT,
+    };
+
+    let fd = match fd.try_clone_to_owned() {
+        Ok(fd) => fd,
+        Err(err) => {
+            warn!(%err, "failed to start the watchdog thread");
+            return None;
+        }
+    };
+    let shared = Arc::new(Shared {
+        epoch: Instant::now(),
+        timeout,
+        abort: env::var_os("RUST_WAYLAND_STALL_ABORT").is_some(),
+        phase: AtomicU8::new(Phase::Startup as u8),
+        phase_since: AtomicU64::new(0),
+        busy_since: AtomicU64::new(0),
+        reported: AtomicBool::new(false),
+    });
+
+    let interval = (timeout / 4).max(Duration::from_millis(100));
+    let (stop, stopped) = mpsc::channel::<()>();
+    let watched = shared.clone();
+    let res = thread::Builder::new()
+        .name("watchdog".into())
+        .spawn(move || {
+            // Until the sender is dropped
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                watched.check(&fd);
+            }
+        });
+
+    let thread = match res {
+        Ok(thread) => thread,
+        Err(err) => {
+            warn!(?err, "failed to start the watchdog thread");
+            return None;
+        }
+    };
+    *CURRENT.lock().unwrap() = Some(shared.clone());
+    Some(Watchdog {
+        shared,
+        stop: Some(stop),
+        thread: Some(thread),
+    })
+}
+
+/// Calls `f` with the state of the running watchdog, if any.
+fn with_current(f: impl FnOnce(&Shared)) {
+    if let Some(shared) = CURRENT.lock().unwrap().as_deref() {
+        f(shared);
+    }
+}
+
+pub fn set_phase(phase: Phase) {
+    with_current(|watchdog| {
+        watchdog.phase.store(phase as u8, Ordering::Relaxed);
+        watchdog
+            .phase_since
+            .store(watchdog.now(), Ordering::Relaxed);
+    });
+}
+
+/// Marks the start of event handling.
+pub fn busy() {
+    with_current(|watchdog| {
+        watchdog
+            .busy_since
+            .store(watchdog.now() + 1, Ordering::Relaxed);
+    });
+}
+
+/// Marks that we are waiting for new events.
+pub fn idle() {
+    with_current(|watchdog| watchdog.busy_since.store(0, Ordering::Relaxed));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, os::fd::AsFd};
+
+    use super::*;
+
+    #[test]
+    fn each_run_starts_over() {
+        let file = File::open("/dev/null").unwrap();
+        let first = spawn(file.as_fd()).unwrap();
+        set_phase(Phase::Running);
+        busy();
+        drop(first);
+        assert!(CURRENT.lock().unwrap().is_none());
+
+        // Watches its own copy of the fd
+        let second = spawn(file.as_fd()).unwrap();
+        drop(file);
+        let current = CURRENT.lock().unwrap().clone().unwrap();
+        assert!(Arc::ptr_eq(&current, &second.shared));
+        assert_eq!(
+            Phase::from_u8(current.phase.load(Ordering::Relaxed)),
+            Phase::Startup
+        );
+        assert_eq!(current.busy_since.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,1725 @@
+//! The toplevel windows and how the compositor configures them.
+
+use std::{
+    env,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use bitflags::bitflags;
+use tracing::{debug, info, warn};
+use wayland_client::{
+    protocol::{
+        wl_compositor::WlCompositor,
+        wl_output::{Transform, WlOutput},
+        wl_region::WlRegion,
+        wl_seat::WlSeat,
+        wl_surface::WlSurface,
+    },
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+};
+use wayland_protocols::{
+    wp::{
+        content_type::v1::client::{
+            wp_content_type_manager_v1::WpContentTypeManagerV1,
+            wp_content_type_v1::{self, WpContentTypeV1},
+        },
+        fractional_scale::v1::client::{
+            wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+            wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+        },
+        tearing_control::v1::client::{
+            wp_tearing_control_manager_v1::WpTearingControlManagerV1,
+            wp_tearing_control_v1::{PresentationHint, WpTearingControlV1},
+        },
+        viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+    },
+    xdg::{
+        decoration::zv1::client::{
+            zxdg_decoration_manager_v1::ZxdgDecorationManagerV1,
+            zxdg_toplevel_decoration_v1::{self, Mode, ZxdgToplevelDecorationV1},
+        },
+        shell::client::{
+            xdg_surface::{self, XdgSurface},
+            xdg_toplevel::{self, XdgToplevel},
+            xdg_wm_base::{self, XdgWmBase},
+        },
+    },
+};
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::ZwlrLayerShellV1, zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+};
+
+use crate::{
+    activation,
+    app::OpenWindow,
+    buffers::Swapchain,
+    damage::Damage,
+    error::Error,
+    hit_test::Edge,
+    idle_inhibit,
+    layer::LayerOptions,
+    menu, output,
+    pixel_format::PixelFormat,
+    presentation::PresentationStats,
+    rect::Rect,
+    render::present_if_needed,
+    scale::Scale,
+    scene::Scene,
+    state::{required, AppState},
+    subsurface::Badge,
+    systemd,
+    transform::{transform_rect, transform_size},
+    viewport::Source,
+    watchdog::{self, Phase},
+};
+
+bitflags! {
+    /// What the compositor says about the window, from the states of the
+    /// last toplevel configure.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct WindowState: u32 {
+        /// Has keyboard focus, or is otherwise the one the user works with
+        const ACTIVATED = 1 << 0;
+        const MAXIMIZED = 1 << 1;
+        const FULLSCREEN = 1 << 2;
+        /// Being resized interactively
+        const RESIZING = 1 << 3;
+        const TILED_LEFT = 1 << 4;
+        const TILED_RIGHT = 1 << 5;
+        const TILED_TOP = 1 << 6;
+        const TILED_BOTTOM = 1 << 7;
+        /// Not visible at all, e.g. minimized or on another workspace
+        const SUSPENDED = 1 << 8;
+    }
+}
+
+bitflags! {
+    /// What the compositor lets the user do with the window, from
+    /// `xdg_toplevel.wm_capabilities`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct WmCapabilities: u32 {
+        /// Can show a menu of window actions, see `show_window_menu`
+        const WINDOW_MENU = 1 << 0;
+        const MAXIMIZE = 1 << 1;
+        const FULLSCREEN = 1 << 2;
+        const MINIMIZE = 1 << 3;
+    }
+}
+
+impl WmCapabilities {
+    /// Parses the capabilities array of `xdg_toplevel.wm_capabilities`.
+    /// Capabilities we don't know are skipped.
+    pub fn from_wire(capabilities: &[u8]) -> Self {
+        wire_array(capabilities)
+            .filter_map(|c| xdg_toplevel::WmCapabilities::try_from(c).ok())
+            .map(|c| match c {
+                xdg_toplevel::WmCapabilities::WindowMenu => Self::WINDOW_MENU,
+                xdg_toplevel::WmCapabilities::Maximize => Self::MAXIMIZE,
+                xdg_toplevel::WmCapabilities::Fullscreen => Self::FULLSCREEN,
+                xdg_toplevel::WmCapabilities::Minimize => Self::MINIMIZE,
+                _ => Self::empty(),
+            })
+            .collect()
+    }
+}
+
+/// Compositors that don't send `wm_capabilities`, e.g. because they only
+/// support older versions, are assumed to support everything.
+impl Default for WmCapabilities {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// The values of a Wayland array of native endian u32s.
+fn wire_array(array: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    array
+        .chunks_exact(4)
+        .map(|v| u32::from_ne_bytes([v[0], v[1], v[2], v[3]]))
+}
+
+impl WindowState {
+    /// Parses the states array of `xdg_toplevel.configure`. States we don't
+    /// know are skipped.
+    pub fn from_wire(states: &[u8]) -> Self {
+        wire_array(states)
+            .filter_map(|s| xdg_toplevel::State::try_from(s).ok())
+            .map(|s| match s {
+                xdg_toplevel::State::Activated => Self::ACTIVATED,
+                xdg_toplevel::State::Maximized => Self::MAXIMIZED,
+                xdg_toplevel::State::Fullscreen => Self::FULLSCREEN,
+                xdg_toplevel::State::Resizing => Self::RESIZING,
+                xdg_toplevel::State::TiledLeft => Self::TILED_LEFT,
+                xdg_toplevel::State::TiledRight => Self::TILED_RIGHT,
+                xdg_toplevel::State::TiledTop => Self::TILED_TOP,
+                xdg_toplevel::State::TiledBottom => Self::TILED_BOTTOM,
+                xdg_toplevel::State::Suspended => Self::SUSPENDED,
+                _ => Self::empty(),
+            })
+            .collect()
+    }
+
+    /// Neither maximized, fullscreen nor tiled: the size is up to us.
+    pub fn is_floating(self) -> bool {
+        !self.intersects(
+            Self::MAXIMIZED
+                | Self::FULLSCREEN
+                | Self::TILED_LEFT
+                | Self::TILED_RIGHT
+                | Self::TILED_TOP
+                | Self::TILED_BOTTOM,
+        )
+    }
+}
+
+// Used until the compositor tells us otherwise
+pub(crate) const DEFAULT_SIZE: (usize, usize) = (500, 500);
+/// Compositors match it against the name of a .desktop file to find the
+/// icon and group windows
+pub const DEFAULT_APP_ID: &str = "rust-wayland";
+/// Shown in the title bar and task switchers if no title is set
+pub const DEFAULT_TITLE: &str = "Hello, world!";
+
+/// Who draws the title bar and border of a window. The compositor has the
+/// last word, e.g. client side is all there is without xdg-decoration.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Prefer {
+    #[default]
+    ServerSide,
+    ClientSide,
+}
+
+impl Prefer {
+    fn mode(self) -> Mode {
+        match self {
+            Self::ServerSide => Mode::ServerSide,
+            Self::ClientSide => Mode::ClientSide,
+        }
+    }
+}
+
+/// How the frames of a window reach the compositor.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PresentPath {
+    /// Drawn into shm buffers, another one while the compositor still
+    /// reads from the last
+    #[default]
+    Swapchain,
+    /// Drawn into a single shm buffer, once the compositor is done with it
+    SingleBuffer,
+    /// Only the background color of the scene, as a single pixel buffer
+    /// scaled to the window. Needs wp_single_pixel_buffer_manager_v1 and
+    /// wp_viewporter, drawn into shm buffers otherwise.
+    SinglePixel,
+}
+
+/// How the window is set up when it is created.
+#[derive(Debug, Default, Clone)]
+pub struct WindowOptions {
+    /// [`DEFAULT_TITLE`] if not set
+    pub title: Option<String>,
+    /// The size to start with, until the compositor picks one
+    pub size: Option<(usize, usize)>,
+    /// Smallest size the window may be resized to
+    pub min_size: Option<(usize, usize)>,
+    /// Largest size the window may be resized to
+    pub max_size: Option<(usize, usize)>,
+    /// Identifies the application to the compositor, [`DEFAULT_APP_ID`] if
+    /// not set
+    pub app_id: Option<String>,
+    /// Creates a layer surface rather than a regular window
+    pub layer: Option<LayerOptions>,
+    /// Locks the session and covers every output instead of opening a
+    /// window, until a key is pressed
+    pub lock: bool,
+    /// Draws the window rotated or flipped and lets the compositor turn it
+    /// back, e.g. to match a rotated output. Normal if not set.
+    pub transform: Option<Transform>,
+    /// Lets the compositor show frames right away rather than waiting for
+    /// the next refresh, at the cost of tearing, for lower latency
+    pub tearing: bool,
+    /// What the window shows, so that the compositor can e.g. lower latency
+    /// for games or pick a fitting display mode for videos. None if not set.
+    pub content_type: Option<wp_content_type_v1::Type>,
+    /// Lets pointer and touch events through to whatever is below, e.g.
+    /// for an overlay
+    pub click_through: bool,
+    /// Draws the windows on a thread of their own
+    pub render_thread: bool,
+    /// Who should draw the decorations
+    pub decorations: Prefer,
+    /// Draws with an alpha channel, so that what is below shows through
+    /// where the window isn't opaque
+    pub transparent: bool,
+    /// Graphs how long the last frames took to reach the screen, in the
+    /// bottom left corner
+    pub hud: bool,
+    /// Swapchain unless set, the others are there to be compared with it
+    pub present_path: PresentPath,
+}
+
+/// Parses the name of a content type: `none`, `photo`, `video` or `game`.
+pub fn parse_content_type(name: &str) -> Option<wp_content_type_v1::Type> {
+    match name {
+        "none" => Some(wp_content_type_v1::Type::None),
+        "photo" => Some(wp_content_type_v1::Type::Photo),
+        "video" => Some(wp_content_type_v1::Type::Video),
+        "game" => Some(wp_content_type_v1::Type::Game),
+        _ => None,
+    }
+}
+
+impl WindowOptions {
+    pub fn app_id(&self) -> &str {
+        self.app_id.as_deref().unwrap_or(DEFAULT_APP_ID)
+    }
+
+    pub fn title(&self) -> &str {
+        self.title.as_deref().unwrap_or(DEFAULT_TITLE)
+    }
+
+    /// Fits `size` within the minimum and maximum size.
+    pub fn clamp(&self, (mut width, mut height): (usize, usize)) -> (usize, usize) {
+        if let Some((max_width, max_height)) = self.max_size {
+            width = width.min(max_width);
+            height = height.min(max_height);
+        }
+        if let Some((min_width, min_height)) = self.min_size {
+            width = width.max(min_width);
+            height = height.max(min_height);
+        }
+        (width, height)
+    }
+}
+
+/// Sets up the [`WindowOptions`] of a window, see [`Window::builder`].
+#[derive(Debug, Clone)]
+pub struct WindowBuilder {
+    options: WindowOptions,
+    resizable: bool,
+}
+
+impl WindowBuilder {
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.options.title = Some(title.into());
+        self
+    }
+
+    /// The size to start with, in window pixels.
+    pub fn size(mut self, width: usize, height: usize) -> Self {
+        self.options.size = Some((width, height));
+        self
+    }
+
+    /// Whether the user may resize the window. Otherwise it keeps its
+    /// initial size while floating, and setting a minimum or maximum size
+    /// too is an error.
+    pub fn resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+
+    pub fn min_size(mut self, width: usize, height: usize) -> Self {
+        self.options.min_size = Some((width, height));
+        self
+    }
+
+    pub fn max_size(mut self, width: usize, height: usize) -> Self {
+        self.options.max_size = Some((width, height));
+        self
+    }
+
+    pub fn decorations(mut self, prefer: Prefer) -> Self {
+        self.options.decorations = prefer;
+        self
+    }
+
+    pub fn transparent(mut self, transparent: bool) -> Self {
+        self.options.transparent = transparent;
+        self
+    }
+
+    pub fn app_id(mut self, app_id: impl Into<String>) -> Self {
+        self.options.app_id = Some(app_id.into());
+        self
+    }
+
+    /// Makes it a layer surface rather than a regular window.
+    pub fn layer(mut self, layer: LayerOptions) -> Self {
+        self.options.layer = Some(layer);
+        self
+    }
+
+    /// Locks the session instead of opening a window.
+    pub fn lock(mut self, lock: bool) -> Self {
+        self.options.lock = lock;
+        self
+    }
+
+    pub fn transform(mut self, transform: Transform) -> Self {
+        self.options.transform = Some(transform);
+        self
+    }
+
+    pub fn tearing(mut self, tearing: bool) -> Self {
+        self.options.tearing = tearing;
+        self
+    }
+
+    pub fn content_type(mut self, content_type: wp_content_type_v1::Type) -> Self {
+        self.options.content_type = Some(content_type);
+        self
+    }
+
+    pub fn click_through(mut self, click_through: bool) -> Self {
+        self.options.click_through = click_through;
+        self
+    }
+
+    pub fn render_thread(mut self, render_thread: bool) -> Self {
+        self.options.render_thread = render_thread;
+        self
+    }
+
+    pub fn hud(mut self, hud: bool) -> Self {
+        self.options.hud = hud;
+        self
+    }
+
+    /// Opens the window on `conn`, for an [`App`](crate::app::App) to
+    /// run in. Fails if the options contradict each other, see
+    /// [`WindowBuilder::options`].
+    pub fn build(self, conn: &Connection) -> Result<OpenWindow, Error> {
+        OpenWindow::open(conn.clone(), self.options()?)
+    }
+
+    /// The options to open the window with, through [`event_loop::run`] or
+    /// [`app::run`]. Fails if they contradict each other, or make for a
+    /// window with nothing in it.
+    ///
+    /// [`event_loop::run`]: crate::event_loop::run
+    /// [`app::run`]: crate::app::run
+    pub fn options(self) -> Result<WindowOptions, Error> {
+        let mut options = self.options;
+        // A buffer that size is a protocol error, and a maximum size of 0
+        // means none to xdg-shell but not to us
+        let empty = |size: Option<(usize, usize)>| size.is_some_and(|(w, h)| w == 0 || h == 0);
+        if empty(options.size) || empty(options.max_size) {
+            return Err(Error::InvalidOptions(
+                "a window can't be 0 pixels wide or tall".to_owned(),
+            ));
+        }
+        if !self.resizable {
+            if options.min_size.is_some() || options.max_size.is_some() {
+                return Err(Error::InvalidOptions(
+                    "a window that isn't resizable can't have a minimum or maximum size".to_owned(),
+                ));
+            }
+            let size = options.size.unwrap_or(DEFAULT_SIZE);
+            options.min_size = Some(size);
+            options.max_size = Some(size);
+        }
+
+        if options.lock && options.layer.is_some() {
+            return Err(Error::InvalidOptions(
+                "a layer surface can't lock the session".to_owned(),
+            ));
+        }
+        if let (Some(min), Some(max)) = (options.min_size, options.max_size) {
+            if min.0 > max.0 || min.1 > max.1 {
+                return Err(Error::InvalidOptions(format!(
+                    "the minimum size {min:?} is larger than the maximum size {max:?}"
+                )));
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// What makes the surface a window.
+enum Role {
+    /// A regular window, managed by the compositor
+    Toplevel {
+        xdg_surface: XdgSurface,
+        toplevel: XdgToplevel,
+        // None if the compositor can't draw decorations for us
+        decoration: Option<ZxdgToplevelDecorationV1>,
+    },
+    /// A panel, overlay or wallpaper from wlr-layer-shell
+    Layer(ZwlrLayerSurfaceV1),
+}
+
+/// Tells our windows apart. The user data of their protocol objects, so
+/// that events find the window they are about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WindowId(u32);
+
+impl WindowId {
+    fn next() -> Self {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// A window: the surface and the role that makes it one, usually a
+/// toplevel.
+pub struct Window {
+    id: WindowId,
+    surface: WlSurface,
+    role: Role,
+    // Who draws the title bar and border, as chosen by the compositor
+    decoration_mode: Mode,
+    // The window geometry we last told the compositor about
+    sent_geometry: Option<Rect>,
+
+    // What needs to be redrawn in the next frame
+    pub(crate) damage: Damage,
+    // Whether we got our first configure and may attach buffers
+    pub(crate) configured: bool,
+    // A frame callback is outstanding, the compositor isn't ready for
+    // another frame yet
+    pub(crate) frame_pending: bool,
+    // Something changed and is waiting to be presented
+    pub(crate) needs_redraw: bool,
+    pub(crate) buffers: Swapchain,
+    // How the frames reach the screen, from presentation feedback
+    pub(crate) presentation_stats: PresentationStats,
+    // Timestamp of the last frame callback in milliseconds, while
+    // animating
+    pub(crate) last_callback: Option<u32>,
+    // The frame after the last callback was held back on purpose, e.g. to
+    // throttle the animation, rather than late
+    pub(crate) frame_delayed: bool,
+    // Drives animations, advanced by whole refresh cycles from one frame
+    // to the next
+    pub(crate) animation_time: Duration,
+    // Left button held down inside the window
+    pub(crate) highlighted: bool,
+    // Only in badge mode, if the compositor supports subsurfaces
+    pub(crate) badge: Option<Badge>,
+    // The outputs the window is on, as told by wl_surface enter and leave
+    outputs: Vec<WlOutput>,
+    // Crops and scales the buffer to the window size, None if the
+    // compositor doesn't support wp_viewporter
+    viewport: Option<WpViewport>,
+    // Where the fractional scale comes from, None unless the compositor
+    // supports it along with viewports
+    fractional_scale: Option<WpFractionalScaleV1>,
+    // The scale the compositor asked for through fractional_scale
+    preferred_scale: Option<Scale>,
+    // How many buffer pixels make up a surface pixel: the preferred scale,
+    // or the largest scale of those outputs
+    scale: Scale,
+    // What we last told the compositor about the buffer: its scale, or the
+    // part of it the viewport shows and the size it scales it to
+    sent_scale: Scale,
+    sent_source: Option<Source>,
+    sent_destination: Option<(usize, usize)>,
+    // The size of the opaque region we last set, None if we didn't
+    sent_opaque_size: Option<(usize, usize)>,
+    // Where the window takes pointer and touch input, all of it if None,
+    // and whether the compositor has yet to be told
+    input_region: Option<Vec<Rect>>,
+    input_region_changed: bool,
+    // How the buffers are rotated or flipped, and what we last told the
+    // compositor about it
+    transform: Transform,
+    sent_transform: Transform,
+    // Hints that the window may tear, only with --tearing
+    tearing_control: Option<WpTearingControlV1>,
+    // Tells the compositor what the window shows, only with --content-type
+    content_type: Option<WpContentTypeV1>,
+    // Size the compositor asked for, if it asked for one
+    pub(crate) configured_size: Option<(usize, usize)>,
+    // The largest size the compositor expects the window to have
+    pub(crate) configure_bounds: Option<(usize, usize)>,
+    // As of the last configure
+    state: WindowState,
+    capabilities: WmCapabilities,
+    // Size to go back to when leaving fullscreen, maximized or tiled, the
+    // compositor leaves that up to us
+    floating_size: Option<(usize, usize)>,
+    options: WindowOptions,
+}
+
+impl Window {
+    /// Starts setting up a window, resizable and with everything else left
+    /// to the compositor.
+    pub fn builder() -> WindowBuilder {
+        WindowBuilder {
+            options: WindowOptions::default(),
+            resizable: true,
+        }
+    }
+
+    /// Creates the window and commits it without a buffer, which asks the
+    /// compositor for the first configure.
+    pub fn new<D>(
+        compositor: &WlCompositor,
+        xdg_wm_base: &XdgWmBase,
+        decoration_manager: Option<&ZxdgDecorationManagerV1>,
+        options: WindowOptions,
+        format: PixelFormat,
+        qh: &QueueHandle<D>,
+    ) -> Self
+    where
+        D: Dispatch<WlSurface, ()>
+            + Dispatch<XdgSurface, WindowId>
+            + Dispatch<XdgToplevel, WindowId>
+            + Dispatch<ZxdgToplevelDecorationV1, WindowId>
+            + 'static,
+    {
+        let id = WindowId::next();
+        let surface = compositor.create_surface(qh, ());
+        let xdg_surface = xdg_wm_base.get_xdg_surface(&surface, qh, id);
+        let toplevel = xdg_surface.get_toplevel(qh, id);
+
+        let decoration = decoration_manager.map(|manager| {
+            let decoration = manager.get_toplevel_decoration(&toplevel, qh, id);
+            decoration.set_mode(options.decorations.mode());
+            decoration
+        });
+
+        // Part of the initial state, the first configure already respects
+        // them. 0 means no limit.
+        let wire =
+            |size: Option<(usize, usize)>| size.map_or((0, 0), |(w, h)| (w as i32, h as i32));
+        let (min_width, min_height) = wire(options.min_size);
+        let (max_width, max_height) = wire(options.max_size);
+        toplevel.set_min_size(min_width, min_height);
+        toplevel.set_max_size(max_width, max_height);
+        toplevel.set_app_id(options.app_id().to_owned());
+
+        surface.commit();
+
+        // What we asked for, until the compositor tells us otherwise
+        let decoration_mode = if decoration.is_some() {
+            options.decorations.mode()
+        } else {
+            Mode::ClientSide
+        };
+        let role = Role::Toplevel {
+            xdg_surface,
+            toplevel,
+            decoration,
+        };
+        Self::with_role(
+            id,
+            surface,
+            role,
+            decoration_mode,
+            WmCapabilities::default(),
+            options,
+            format,
+        )
+    }
+
+    /// Creates a layer surface instead of a toplevel, placed on the output
+    /// as `layer` says, and commits it.
+    pub fn new_layer<D>(
+        compositor: &WlCompositor,
+        layer_shell: &ZwlrLayerShellV1,
+        layer: &LayerOptions,
+        options: WindowOptions,
+        format: PixelFormat,
+        qh: &QueueHandle<D>,
+    ) -> Self
+    where
+        D: Dispatch<WlSurface, ()> + Dispatch<ZwlrLayerSurfaceV1, WindowId> + 'static,
+    {
+        let id = WindowId::next();
+        let surface = compositor.create_surface(qh, ());
+        // On the output the compositor picks
+        let layer_surface = layer_shell.get_layer_surface(
+            &surface,
+            None,
+            layer.layer,
+            options.app_id().to_owned(),
+            qh,
+            id,
+        );
+        layer.apply(&layer_surface);
+        surface.commit();
+
+        // There is nothing to decorate, and none of the toplevel requests
+        // apply
+        let role = Role::Layer(layer_surface);
+        Self::with_role(
+            id,
+            surface,
+            role,
+            Mode::ServerSide,
+            WmCapabilities::empty(),
+            options,
+            format,
+        )
+    }
+
+    fn with_role(
+        id: WindowId,
+        surface: WlSurface,
+        role: Role,
+        decoration_mode: Mode,
+        capabilities: WmCapabilities,
+        options: WindowOptions,
+        format: PixelFormat,
+    ) -> Self {
+        let mut buffers = Swapchain::new(format);
+        if options.present_path == PresentPath::SingleBuffer {
+            buffers.set_len(1);
+        }
+        Self {
+            id,
+            surface,
+            role,
+            decoration_mode,
+            sent_geometry: None,
+            damage: Damage::default(),
+            configured: false,
+            frame_pending: false,
+            needs_redraw: false,
+            buffers,
+            presentation_stats: PresentationStats::default(),
+            last_callback: None,
+            frame_delayed: false,
+            animation_time: Duration::ZERO,
+            highlighted: false,
+            badge: None,
+            outputs: Vec::new(),
+            viewport: None,
+            fractional_scale: None,
+            preferred_scale: None,
+            scale: Scale::ONE,
+            sent_scale: Scale::ONE,
+            sent_source: None,
+            sent_destination: None,
+            sent_opaque_size: None,
+            input_region: None,
+            input_region_changed: false,
+            transform: Transform::Normal,
+            sent_transform: Transform::Normal,
+            tearing_control: None,
+            content_type: None,
+            configured_size: None,
+            configure_bounds: None,
+            state: WindowState::empty(),
+            capabilities,
+            floating_size: None,
+            options,
+        }
+    }
+
+    /// Lets the compositor crop and scale the buffers of the window, rather
+    /// than showing them as they are.
+    pub(crate) fn use_viewport<D>(&mut self, viewporter: &WpViewporter, qh: &QueueHandle<D>)
+    where
+        D: Dispatch<WpViewport, ()> + 'static,
+    {
+        self.viewport = Some(viewporter.get_viewport(&self.surface, qh, ()));
+    }
+
+    /// Renders at the exact scale the compositor prefers, fractional ones
+    /// included, rather than at the integer scale of the outputs. Needs a
+    /// viewport to scale the buffer back down to the window size.
+    pub(crate) fn use_fractional_scale<D>(
+        &mut self,
+        manager: &WpFractionalScaleManagerV1,
+        qh: &QueueHandle<D>,
+    ) where
+        D: Dispatch<WpFractionalScaleV1, WindowId> + 'static,
+    {
+        if self.viewport.is_some() {
+            self.fractional_scale = Some(manager.get_fractional_scale(&self.surface, qh, self.id));
+        }
+    }
+
+    /// Asks the compositor to show the frames of the window as soon as they
+    /// are committed, even halfway through a refresh. Applies on the next
+    /// commit.
+    pub(crate) fn allow_tearing<D>(
+        &mut self,
+        manager: &WpTearingControlManagerV1,
+        qh: &QueueHandle<D>,
+    ) where
+        D: Dispatch<WpTearingControlV1, ()> + 'static,
+    {
+        let tearing_control = manager.get_tearing_control(&self.surface, qh, ());
+        tearing_control.set_presentation_hint(PresentationHint::Async);
+        self.tearing_control = Some(tearing_control);
+    }
+
+    /// Tells the compositor what kind of content the window shows. Applies
+    /// on the next commit.
+    pub(crate) fn set_content_type<D>(
+        &mut self,
+        manager: &WpContentTypeManagerV1,
+        content_type: wp_content_type_v1::Type,
+        qh: &QueueHandle<D>,
+    ) where
+        D: Dispatch<WpContentTypeV1, ()> + 'static,
+    {
+        // Only one per surface, further changes go through it
+        let object = self
+            .content_type
+            .get_or_insert_with(|| manager.get_surface_content_type(&self.surface, qh, ()));
+        object.set_content_type(content_type);
+    }
+
+    /// Whether the compositor crops and scales the buffers to the window
+    /// size, so that they may have any size.
+    pub fn has_viewport(&self) -> bool {
+        self.viewport.is_some()
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.id
+    }
+
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    /// What popups of the window are attached to, `None` for a layer
+    /// surface.
+    pub fn xdg_surface(&self) -> Option<&XdgSurface> {
+        match &self.role {
+            Role::Toplevel { xdg_surface, .. } => Some(xdg_surface),
+            Role::Layer(_) => None,
+        }
+    }
+
+    fn toplevel(&self) -> Option<&XdgToplevel> {
+        match &self.role {
+            Role::Toplevel { toplevel, .. } => Some(toplevel),
+            Role::Layer(_) => None,
+        }
+    }
+
+    pub fn layer_surface(&self) -> Option<&ZwlrLayerSurfaceV1> {
+        match &self.role {
+            Role::Toplevel { .. } => None,
+            Role::Layer(layer_surface) => Some(layer_surface),
+        }
+    }
+
+    /// Whether this is a regular window, which can be moved and resized.
+    pub fn is_toplevel(&self) -> bool {
+        self.toplevel().is_some()
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        self.configured_size.unwrap_or_else(|| {
+            self.options
+                .clamp(self.options.size.unwrap_or(DEFAULT_SIZE))
+        })
+    }
+
+    pub fn options(&self) -> &WindowOptions {
+        &self.options
+    }
+
+    /// How many buffer pixels make up a pixel of the window, more than 1 on
+    /// HiDPI outputs.
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    /// The size of the content of the buffers the window needs, in pixels.
+    /// Swapped in the buffers themselves if they are rotated.
+    pub fn content_size(&self) -> (usize, usize) {
+        let (width, height) = self.size();
+        (self.scale.to_buffer(width), self.scale.to_buffer(height))
+    }
+
+    /// The size of the buffers the window needs, in pixels.
+    pub fn buffer_size(&self) -> (usize, usize) {
+        transform_size(self.content_size(), self.transform)
+    }
+
+    /// Where `rect` of the window is in its buffers.
+    pub(crate) fn rect_to_buffer(&self, rect: Rect) -> Rect {
+        let (width, height) = self.content_size();
+        // Rounded out, which may go past the edges
+        let rect = self
+            .scale
+            .rect_to_buffer(rect)
+            .intersect(&Rect::new(0, 0, width, height));
+        transform_rect(rect, self.transform, (width, height))
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+
+    /// Draws the window with `transform` from the next frame on, which the
+    /// compositor undoes. Needs wl_surface version 2.
+    pub fn set_transform(&mut self, transform: Transform) {
+        if transform == self.transform {
+            return;
+        }
+        if self.surface.version() < 2 {
+            debug!("the compositor can't transform buffers");
+            return;
+        }
+
+        debug!(id = ?self.id, ?transform, "window transform changed");
+        self.transform = transform;
+        self.request_redraw();
+    }
+
+    /// Renders at `scale` from the next frame on.
+    fn set_scale(&mut self, scale: Scale) {
+        if scale == self.scale {
+            return;
+        }
+
+        debug!(id = ?self.id, ?scale, "window scale changed");
+        self.scale = scale;
+        self.request_redraw();
+    }
+
+    /// Tells the compositor how the next buffer maps onto the window,
+    /// before it is attached: how it is transformed, then through the
+    /// viewport if we have one, showing only `source` of the buffer if set,
+    /// with the buffer scale otherwise.
+    pub(crate) fn apply_viewport(&mut self, source: Option<Source>) {
+        if self.sent_transform != self.transform {
+            self.surface.set_buffer_transform(self.transform);
+            self.sent_transform = self.transform;
+        }
+
+        if let Some(viewport) = &self.viewport {
+            if self.sent_source != source {
+                // -1 everywhere shows the whole buffer again
+                let s = source.unwrap_or(Source {
+                    x: -1.0,
+                    y: -1.0,
+                    width: -1.0,
+                    height: -1.0,
+                });
+                viewport.set_source(s.x, s.y, s.width, s.height);
+                self.sent_source = source;
+            }
+
+            let size = self.size();
+            if self.sent_destination != Some(size) {
+                viewport.set_destination(size.0 as i32, size.1 as i32);
+                self.sent_destination = Some(size);
+            }
+        } else if self.sent_scale != self.scale {
+            // Only ever an integer scale without a viewport
+            self.surface.set_buffer_scale(self.scale.ceil() as i32);
+            self.sent_scale = self.scale;
+        }
+    }
+
+    /// Marks the whole window as opaque if its buffers have no alpha, so
+    /// that the compositor can skip drawing and blending what is below it.
+    /// Applies along with the next buffer, like the viewport.
+    pub(crate) fn apply_opaque_region<D>(&mut self, compositor: &WlCompositor, qh: &QueueHandle<D>)
+    where
+        D: Dispatch<WlRegion, ()> + 'static,
+    {
+        let opaque_size = self.buffers.format().is_opaque().then(|| self.size());
+        if self.sent_opaque_size == opaque_size {
+            return;
+        }
+
+        match opaque_size {
+            Some((width, height)) => {
+                let region = compositor.create_region(qh, ());
+                region.add(0, 0, width as i32, height as i32);
+                self.surface.set_opaque_region(Some(&region));
+                // The surface keeps a copy
+                region.destroy();
+            }
+            None => self.surface.set_opaque_region(None),
+        }
+        self.sent_opaque_size = opaque_size;
+    }
+
+    /// Limits where the window takes pointer and touch input to `rects`,
+    /// in surface pixels. Events elsewhere go to whatever is below, none at
+    /// all if `rects` is empty. `None` takes input everywhere again. Applies
+    /// along with the next frame.
+    pub fn set_input_region(&mut self, rects: Option<Vec<Rect>>) {
+        if self.input_region == rects {
+            return;
+        }
+        self.input_region = rects;
+        self.input_region_changed = true;
+        self.request_redraw();
+    }
+
+    /// Tells the compositor about the input region set last, if it hasn't
+    /// been told yet.
+    pub(crate) fn apply_input_region<D>(&mut self, compositor: &WlCompositor, qh: &QueueHandle<D>)
+    where
+        D: Dispatch<WlRegion, ()> + 'static,
+    {
+        if !self.input_region_changed {
+            return;
+        }
+
+        match &self.input_region {
+            Some(rects) => {
+                let region = compositor.create_region(qh, ());
+                for rect in rects {
+                    region.add(
+                        rect.x as i32,
+                        rect.y as i32,
+                        rect.width as i32,
+                        rect.height as i32,
+                    );
+                }
+                self.surface.set_input_region(Some(&region));
+                region.destroy();
+            }
+            None => self.surface.set_input_region(None),
+        }
+        self.input_region_changed = false;
+    }
+
+    pub fn set_title(&self, title: impl Into<String>) {
+        if let Some(toplevel) = self.toplevel() {
+            toplevel.set_title(title.into());
+        }
+    }
+
+    /// Asks the compositor to make the window fullscreen on an output of
+    /// its choice, or to leave fullscreen.
+    pub fn set_fullscreen(&self, fullscreen: bool) {
+        let Some(toplevel) = self.toplevel_with(WmCapabilities::FULLSCREEN) else {
+            debug!("the compositor doesn't support fullscreen windows");
+            return;
+        };
+
+        if fullscreen {
+            toplevel.set_fullscreen(None);
+        } else {
+            toplevel.unset_fullscreen();
+        }
+    }
+
+    pub fn toggle_fullscreen(&self) {
+        self.set_fullscreen(!self.state.contains(WindowState::FULLSCREEN));
+    }
+
+    pub fn set_maximized(&self, maximized: bool) {
+        let Some(toplevel) = self.toplevel_with(WmCapabilities::MAXIMIZE) else {
+            debug!("the compositor doesn't support maximizing windows");
+            return;
+        };
+
+        if maximized {
+            toplevel.set_maximized();
+        } else {
+            toplevel.unset_maximized();
+        }
+    }
+
+    pub fn toggle_maximized(&self) {
+        self.set_maximized(!self.state.contains(WindowState::MAXIMIZED));
+    }
+
+    /// Asks the compositor to minimize the window. There is no way to tell
+    /// when or whether it did, nor to undo it.
+    pub fn minimize(&self) {
+        let Some(toplevel) = self.toplevel_with(WmCapabilities::MINIMIZE) else {
+            debug!("the compositor doesn't support minimizing windows");
+            return;
+        };
+        toplevel.set_minimized();
+    }
+
+    /// The toplevel, if the compositor supports `capability` for it.
+    fn toplevel_with(&self, capability: WmCapabilities) -> Option<&XdgToplevel> {
+        self.toplevel()
+            .filter(|_| self.capabilities.contains(capability))
+    }
+
+    pub fn capabilities(&self) -> WmCapabilities {
+        self.capabilities
+    }
+
+    pub fn state(&self) -> WindowState {
+        self.state
+    }
+
+    pub fn decoration_mode(&self) -> Mode {
+        self.decoration_mode
+    }
+
+    /// Whether we have to draw the title bar and border ourselves.
+    pub fn client_side_decorations(&self) -> bool {
+        self.is_toplevel() && self.decoration_mode == Mode::ClientSide
+    }
+
+    /// Switches to the decoration mode the compositor picked.
+    fn set_decoration_mode(&mut self, mode: Mode) {
+        if mode == self.decoration_mode {
+            return;
+        }
+
+        self.decoration_mode = mode;
+        // The decorations appear or disappear
+        self.request_redraw();
+    }
+
+    /// The part of the surface that is the window proper, decorations
+    /// included. Anything outside of it, like a shadow, is left out when
+    /// the compositor lines windows up or sizes them. Ours have no shadow,
+    /// so it is the whole surface whoever draws the decorations, and
+    /// switching between them doesn't change it.
+    pub fn geometry(&self) -> Rect {
+        let (width, height) = self.size();
+        Rect::new(0, 0, width, height)
+    }
+
+    /// Tells the compositor about the window geometry if it changed. Once
+    /// set it isn't derived from the surface anymore, so this has to follow
+    /// every resize.
+    fn update_geometry(&mut self) {
+        let geometry = self.geometry();
+        if self.sent_geometry == Some(geometry) || geometry.is_empty() {
+            return;
+        }
+        // Layer surfaces are placed by their size alone
+        let Some(xdg_surface) = self.xdg_surface() else {
+            return;
+        };
+
+        xdg_surface.set_window_geometry(
+            geometry.x as i32,
+            geometry.y as i32,
+            geometry.width as i32,
+            geometry.height as i32,
+        );
+        self.sent_geometry = Some(geometry);
+    }
+
+    /// Applies a toplevel or layer surface configure. A size of 0 leaves it
+    /// up to us.
+    pub(crate) fn configure(&mut self, width: i32, height: i32, state: WindowState) {
+        let resumed =
+            self.state.contains(WindowState::SUSPENDED) && !state.contains(WindowState::SUSPENDED);
+        if resumed {
+            debug!("window no longer suspended");
+            // The compositor may have held back the callback of the last
+            // frame while suspended, don't wait for it to draw again
+            self.frame_pending = false;
+        }
+        self.state = state;
+        let floating = state.is_floating();
+
+        // Back to floating, the compositor doesn't remember the size we had
+        let (current_width, current_height) = match self.floating_size {
+            Some(size) if floating => size,
+            _ => self.size(),
+        };
+        let width = if width > 0 {
+            width as usize
+        } else {
+            current_width
+        };
+        let height = if height > 0 {
+            height as usize
+        } else {
+            current_height
+        };
+
+        // The compositor may ignore the limits when maximized, fullscreen or
+        // tiled, and then we have to use the size it picked
+        let (width, height) = if floating {
+            self.options.clamp((width, height))
+        } else {
+            (width, height)
+        };
+
+        self.configured_size = Some((width, height));
+        if floating {
+            self.floating_size = Some((width, height));
+        }
+        self.update_geometry();
+    }
+
+    /// Marks the whole window for redrawing, it is drawn once the
+    /// compositor is ready for a new frame.
+    pub fn request_redraw(&mut self) {
+        let (width, height) = self.size();
+        self.damage_area([Rect::new(0, 0, width, height)]);
+    }
+
+    /// Marks only `rects` for redrawing.
+    pub fn damage_area(&mut self, rects: impl IntoIterator<Item = Rect>) {
+        for rect in rects {
+            self.damage.add(rect);
+            self.needs_redraw = true;
+        }
+    }
+
+    /// Lets the compositor resize the window from `edge`, `serial` is the
+    /// one of the button press that started it.
+    pub(crate) fn start_resize(&self, seat: &WlSeat, serial: u32, edge: Edge) {
+        debug!(serial, ?edge, "starting interactive resize");
+        if let Some(toplevel) = self.toplevel() {
+            toplevel.resize(seat, serial, edge.resize_edge());
+        }
+    }
+
+    /// Asks the compositor to show its menu of window actions at `(x, y)`,
+    /// for the button press with `serial`. Returns false if it has none.
+    pub(crate) fn show_window_menu(&self, seat: &WlSeat, serial: u32, (x, y): (f64, f64)) -> bool {
+        let Some(toplevel) = self.toplevel_with(WmCapabilities::WINDOW_MENU) else {
+            return false;
+        };
+
+        debug!(serial, x, y, "showing the window menu");
+        toplevel.show_window_menu(seat, serial, x as i32, y as i32);
+        true
+    }
+
+    /// Lets the compositor move the window, `serial` is the one of the
+    /// button press that started it.
+    pub(crate) fn start_move(&self, seat: &WlSeat, serial: u32) {
+        debug!(serial, "starting interactive move");
+        if let Some(toplevel) = self.toplevel() {
+            toplevel._move(seat, serial);
+        }
+    }
+
+    /// Destroys the roles before the surface, as the protocol requires.
+    pub(crate) fn destroy(self) {
+        match self.role {
+            Role::Toplevel {
+                xdg_surface,
+                toplevel,
+                decoration,
+            } => {
+                if let Some(decoration) = decoration {
+                    decoration.destroy();
+                }
+                toplevel.destroy();
+                xdg_surface.destroy();
+            }
+            Role::Layer(layer_surface) => layer_surface.destroy(),
+        }
+        if let Some(fractional_scale) = self.fractional_scale {
+            fractional_scale.destroy();
+        }
+        if let Some(viewport) = self.viewport {
+            viewport.destroy();
+        }
+        if let Some(badge) = self.badge {
+            badge.destroy();
+        }
+        if let Some(tearing_control) = self.tearing_control {
+            tearing_control.destroy();
+        }
+        if let Some(content_type) = self.content_type {
+            content_type.destroy();
+        }
+        self.surface.destroy();
+    }
+}
+
+/// Picks the scale of every window from the outputs it is on, e.g. after
+/// it moved or an output changed its scale.
+pub(crate) fn update_scales(state: &mut AppState) {
+    let scales: Vec<_> = state
+        .windows
+        .iter()
+        .map(|window| {
+            if let Some(scale) = window.preferred_scale {
+                return scale;
+            }
+            // set_buffer_scale needs wl_surface version 3, a viewport works
+            // with any
+            if window.viewport.is_none() && window.surface.version() < 3 {
+                return Scale::ONE;
+            }
+            window
+                .outputs
+                .iter()
+                .filter_map(|output| output::output_info(state, output))
+                .map(|info| Scale::integer(info.scale.max(1) as u32))
+                .max()
+                .unwrap_or(Scale::ONE)
+        })
+        .collect();
+
+    for (window, scale) in state.windows.iter_mut().zip(scales) {
+        window.set_scale(scale);
+    }
+    present_if_needed(state);
+}
+
+/// How often the window is shown anew: as the compositor reports it in
+/// presentation feedback, or the refresh rate of the fastest output the
+/// window is on. `None` if neither is known.
+pub(crate) fn refresh_interval(state: &AppState, id: WindowId) -> Option<Duration> {
+    let window = state.window(id)?;
+    let presented = window.presentation_stats.refresh;
+    if !presented.is_zero() {
+        return Some(presented);
+    }
+
+    window
+        .outputs
+        .iter()
+        .filter_map(|output| output::output_info(state, output)?.refresh_hz())
+        .max_by(f64::total_cmp)
+        .map(|hz| Duration::from_secs_f64(1.0 / hz))
+}
+
+/// The window with `surface` is now also on `output`.
+pub(crate) fn output_entered(state: &mut AppState, surface: &WlSurface, output: &WlOutput) {
+    let Some(window) = state.windows.iter_mut().find(|w| &w.surface == surface) else {
+        return;
+    };
+    if !window.outputs.contains(output) {
+        window.outputs.push(output.clone());
+    }
+    update_scales(state);
+}
+
+/// The window with `surface`, or every window if `None`, is no longer on
+/// `output`.
+pub(crate) fn output_left(state: &mut AppState, surface: Option<&WlSurface>, output: &WlOutput) {
+    for window in &mut state.windows {
+        if surface.is_none_or(|surface| &window.surface == surface) {
+            window.outputs.retain(|o| o != output);
+        }
+    }
+    update_scales(state);
+}
+
+/// Creates a window set up with `options` and adds it to the others. Its
+/// first configure shows it.
+pub(crate) fn open(state: &mut AppState, options: WindowOptions) -> Result<WindowId, Error> {
+    let compositor = required(&state.compositor)?;
+    let qh = &state.queue_handle;
+    let mut window = match options.layer.clone() {
+        Some(layer) => {
+            let layer_shell = required(&state.layer_shell)?;
+            Window::new_layer(compositor, layer_shell, &layer, options, state.format, qh)
+        }
+        None => Window::new(
+            compositor,
+            required(&state.xdg_wm_base)?,
+            state.xdg_decoration_manager.as_ref(),
+            options,
+            state.format,
+            qh,
+        ),
+    };
+    window.set_title(window.options.title());
+    if let Some(transform) = window.options.transform {
+        window.set_transform(transform);
+    }
+    if let Some(viewporter) = &state.viewporter {
+        window.use_viewport(viewporter, qh);
+    }
+    if let Some(manager) = &state.fractional_scale_manager {
+        window.use_fractional_scale(manager, qh);
+    }
+    if let (true, Some(manager)) = (window.options.tearing, &state.tearing_control_manager) {
+        window.allow_tearing(manager, qh);
+    }
+    if let (Scene::Badge, Some(subcompositor)) = (&state.scene, &state.subcompositor) {
+        window.badge = Some(Badge::new(
+            compositor,
+            subcompositor,
+            window.surface(),
+            state.format,
+            qh,
+        ));
+    }
+    if window.options.click_through {
+        window.set_input_region(Some(Vec::new()));
+    }
+    if let (Some(content_type), Some(manager)) =
+        (window.options.content_type, &state.content_type_manager)
+    {
+        window.set_content_type(manager, content_type, qh);
+    }
+
+    let id = window.id();
+    info!(?id, windows = state.windows.len() + 1, "opened a window");
+    state.windows.push(window);
+    Ok(id)
+}
+
+/// The user asked to close the window `id`. Closes it unless the app
+/// keeps it open.
+pub(crate) fn request_close(state: &mut AppState, id: WindowId) {
+    if state.app.as_mut().is_some_and(|app| !app.on_close()) {
+        debug!(?id, "the app keeps the window open");
+        return;
+    }
+    close(state, id);
+}
+
+/// Closes the window `id`, along with what is attached to it. We are done
+/// once the last one is closed.
+pub(crate) fn close(state: &mut AppState, id: WindowId) {
+    let Some(i) = state.windows.iter().position(|w| w.id == id) else {
+        return;
+    };
+
+    if state.menu.as_ref().is_some_and(|m| m.parent == id) {
+        menu::close(state);
+    }
+    // Moves over to a window that stays, if there is one
+    let inhibited = idle_inhibit::is_idle_inhibited(state);
+    if state.idle_inhibitor.as_ref().is_some_and(|(w, _)| *w == id) {
+        idle_inhibit::set_idle_inhibited(state, false);
+    }
+
+    info!(?id, "closing a window");
+    state.windows.remove(i).destroy();
+    if state.windows.is_empty() {
+        state.running = false;
+    } else if inhibited {
+        idle_inhibit::set_idle_inhibited(state, true);
+    }
+}
+
+pub(crate) const DEFAULT_RESIZE_BORDER: f64 = 8.0;
+
+/// Reads the resize border width from `RUST_WAYLAND_RESIZE_BORDER`.
+pub(crate) fn resize_border() -> f64 {
+    let Ok(border) = env::var("RUST_WAYLAND_RESIZE_BORDER") else {
+        return DEFAULT_RESIZE_BORDER;
+    };
+
+    match border.parse::<f64>() {
+        Ok(border) if border >= 0.0 => border,
+        _ => {
+            warn!(?border, "invalid RUST_WAYLAND_RESIZE_BORDER, using default");
+            DEFAULT_RESIZE_BORDER
+        }
+    }
+}
+
+impl Dispatch<XdgWmBase, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        proxy: &XdgWmBase,
+        event: <XdgWmBase as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            debug!(?serial, "xdg ping");
+            proxy.pong(serial);
+        }
+    }
+}
+
+impl Dispatch<XdgSurface, WindowId> for AppState {
+    fn event(
+        state: &mut Self,
+        proxy: &XdgSurface,
+        event: <XdgSurface as Proxy>::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            info!(?serial, ?id, "xdg surface configure event");
+            proxy.ack_configure(serial);
+            configured(state, *id);
+        }
+    }
+}
+
+/// Draws the window once a configure was acked: for the first time, or
+/// again with the new size and state.
+pub(crate) fn configured(state: &mut AppState, id: WindowId) {
+    let Some(window) = state.window_mut(id) else {
+        return;
+    };
+    window.configured = true;
+    let (width, height) = window.size();
+    // Only panels and the like run as services, see `event_loop::start`
+    let daemon = window.options().layer.is_some();
+    window.request_redraw();
+    if let Some(app) = &mut state.app {
+        app.on_configure(width, height);
+    }
+    present_if_needed(state);
+
+    if !state.ready_notified {
+        // Mapped now, the focus can go to it
+        activation::activate_from_env(state, id);
+        watchdog::set_phase(Phase::Running);
+        if daemon {
+            systemd::notify_ready();
+        }
+        state.ready_notified = true;
+    }
+}
+
+impl Dispatch<XdgToplevel, WindowId> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &XdgToplevel,
+        event: <XdgToplevel as Proxy>::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(window) = state.window_mut(*id) else {
+            return;
+        };
+
+        match event {
+            xdg_toplevel::Event::Configure {
+                width,
+                height,
+                states,
+            } => {
+                let window_state = WindowState::from_wire(&states);
+                debug!(?width, ?height, ?window_state, "xdg toplevel configure");
+                window.configure(width, height, window_state);
+            }
+            xdg_toplevel::Event::Close => {
+                debug!(?id, "xdg toplevel close");
+                request_close(state, *id);
+            }
+            xdg_toplevel::Event::ConfigureBounds { width, height } => {
+                debug!(?width, ?height, "xdg toplevel configure bounds");
+                // 0 means the compositor doesn't know the bounds
+                window.configure_bounds = match (width, height) {
+                    (1.., 1..) => Some((width as usize, height as usize)),
+                    _ => None,
+                };
+            }
+            xdg_toplevel::Event::WmCapabilities { capabilities } => {
+                let capabilities = WmCapabilities::from_wire(&capabilities);
+                debug!(?capabilities, "xdg toplevel wm capabilities");
+                // Followed by a configure, which redraws the decorations
+                window.capabilities = capabilities;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZxdgDecorationManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZxdgDecorationManagerV1,
+        _event: <ZxdgDecorationManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // AFAIK, this interface does not emit any events
+    }
+}
+
+impl Dispatch<ZxdgToplevelDecorationV1, WindowId> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZxdgToplevelDecorationV1,
+        event: <ZxdgToplevelDecorationV1 as Proxy>::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zxdg_toplevel_decoration_v1::Event::Configure { mode } => {
+                info!(?mode, "decoration configure event");
+                // Applied with the surface configure that follows
+                if let (Some(window), WEnum::Value(mode)) = (state.window_mut(*id), mode) {
+                    window.set_decoration_mode(mode);
+                }
+            }
+            // Only sent by versions newer than the one we bound
+            event => debug!(?event, "ignoring unknown decoration event"),
+        }
+    }
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpFractionalScaleManagerV1,
+        _event: <WpFractionalScaleManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, WindowId> for AppState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: <WpFractionalScaleV1 as Proxy>::Event,
+        id: &WindowId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wp_fractional_scale_v1::Event::PreferredScale { scale } => {
+                let Some(window) = state.window_mut(*id) else {
+                    return;
+                };
+                let scale = Scale::from_120ths(scale);
+                debug!(?id, ?scale, "preferred fractional scale");
+                window.preferred_scale = Some(scale);
+                update_scales(state);
+            }
+            event => debug!(?event, "ignoring unknown fractional scale event"),
+        }
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewporter,
+        _event: <WpViewporter as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<WpViewport, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewport,
+        _event: <WpViewport as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<WlRegion, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlRegion,
+        _event: <WlRegion as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<WpContentTypeManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpContentTypeManagerV1,
+        _event: <WpContentTypeManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<WpContentTypeV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpContentTypeV1,
+        _event: <WpContentTypeV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<WpTearingControlManagerV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpTearingControlManagerV1,
+        _event: <WpTearingControlManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+impl Dispatch<WpTearingControlV1, ()> for AppState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpTearingControlV1,
+        _event: <WpTearingControlV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // This interface has no events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn states_are_parsed_from_the_wire() {
+        let wire: Vec<u8> = [4u32, 2, 9, 1000]
+            .iter()
+            .flat_map(|s| s.to_ne_bytes())
+            .collect();
+
+        let state = WindowState::from_wire(&wire);
+        assert_eq!(
+            state,
+            WindowState::ACTIVATED | WindowState::FULLSCREEN | WindowState::SUSPENDED
+        );
+        assert!(!state.is_floating());
+        assert!(WindowState::from_wire(&[]).is_floating());
+    }
+
+    #[test]
+    fn sizes_are_clamped_to_the_limits() {
+        let options = WindowOptions {
+            min_size: Some((200, 100)),
+            max_size: Some((800, 600)),
+            ..Default::default()
+        };
+        assert_eq!(options.clamp((500, 500)), (500, 500));
+        assert_eq!(options.clamp((100, 1000)), (200, 600));
+        assert_eq!(WindowOptions::default().clamp((1, 1)), (1, 1));
+    }
+
+    #[test]
+    fn builder_checks_the_limits() {
+        let options = Window::builder()
+            .size(300, 200)
+            .resizable(false)
+            .options()
+            .unwrap();
+        assert_eq!(options.min_size, Some((300, 200)));
+        assert_eq!(options.max_size, Some((300, 200)));
+
+        let contradicting = Window::builder().min_size(400, 400).max_size(300, 500);
+        assert!(contradicting.options().is_err());
+        let fixed_with_limits = Window::builder().resizable(false).min_size(100, 100);
+        assert!(fixed_with_limits.options().is_err());
+        assert!(Window::builder().size(0, 0).options().is_err());
+        assert!(Window::builder().max_size(640, 0).options().is_err());
+    }
+}